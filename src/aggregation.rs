@@ -0,0 +1,307 @@
+//! Time-bucketed aggregation of high-rate event classes.
+//!
+//! The events of an aggregated class are rolled up, per timeline, into fixed
+//! buckets of event time. Instead of the events themselves, one synthetic event
+//! per bucket is ingested once the bucket closes, named after the class and
+//! timestamped with the last event rolled into the bucket, with the event count
+//! and the sum, min, and max of each of the configured numeric fields. Stamping
+//! the bucket's start instead would put the synthetic event before the events
+//! the timeline already had ingested during the bucket.
+
+use crate::attrs::EventAttrKey;
+use crate::client::Client;
+use crate::config::AggregationConfig;
+use crate::error::Error;
+use crate::event::CtfEvent;
+use modality_api::{AttrVal, BigInt, Nanoseconds, TimelineId};
+use modality_ingest_protocol::InternedAttrKey;
+use std::collections::{BTreeMap, HashMap};
+
+pub const DEFAULT_AGGREGATION_BUCKET_MS: u64 = 1000;
+
+/// The aggregated event classes and their open buckets
+#[derive(Debug, Default)]
+pub struct Aggregator {
+    classes: BTreeMap<String, AggregationConfig>,
+    buckets: HashMap<(TimelineId, String), Bucket>,
+}
+
+impl Aggregator {
+    pub fn from_config(classes: &BTreeMap<String, AggregationConfig>) -> Self {
+        Self {
+            classes: classes.clone(),
+            buckets: Default::default(),
+        }
+    }
+
+    pub fn is_aggregated(&self, class: &str) -> bool {
+        self.classes.contains_key(class)
+    }
+
+    /// Roll a mapped event of an aggregated class into its bucket, returning the
+    /// bucket it closed, if any. Events without a timestamp go in the open bucket.
+    pub fn record(
+        &mut self,
+        timeline_id: TimelineId,
+        class: &str,
+        timestamp_ns: Option<u64>,
+        event: &CtfEvent,
+        client: &Client,
+    ) -> Option<Bucket> {
+        let cfg = self.classes.get(class)?;
+        let bucket_ns = cfg.bucket_ms().max(1).saturating_mul(1_000_000);
+        let values: Vec<(String, Number)> = event
+            .attr_kvs()
+            .iter()
+            .filter_map(|(k, v)| {
+                let key = client.event_key_name(k)?;
+                if !cfg.fields.contains(key) {
+                    return None;
+                }
+                Some((key.to_owned(), Number::from_attr_val(v)?))
+            })
+            .collect();
+        self.record_values(timeline_id, class, bucket_ns, timestamp_ns, values)
+    }
+
+    fn record_values(
+        &mut self,
+        timeline_id: TimelineId,
+        class: &str,
+        bucket_ns: u64,
+        timestamp_ns: Option<u64>,
+        values: Vec<(String, Number)>,
+    ) -> Option<Bucket> {
+        let start_ns = timestamp_ns.map(|ts| ts - ts % bucket_ns);
+        let key = (timeline_id, class.to_owned());
+        let closes = self
+            .buckets
+            .get(&key)
+            .map(|b| start_ns.is_some() && start_ns != b.start_ns)
+            .unwrap_or(false);
+        let closed = if closes {
+            self.buckets.remove(&key)
+        } else {
+            None
+        };
+        let bucket = self.buckets.entry(key).or_insert_with(|| Bucket {
+            class: class.to_owned(),
+            start_ns,
+            last_ns: None,
+            duration_ns: bucket_ns,
+            count: 0,
+            fields: Default::default(),
+        });
+        bucket.count += 1;
+        if timestamp_ns.is_some() {
+            bucket.last_ns = timestamp_ns;
+        }
+        for (field, v) in values.into_iter() {
+            bucket
+                .fields
+                .entry(field)
+                .and_modify(|s| s.record(v))
+                .or_insert_with(|| FieldStats::new(v));
+        }
+        closed
+    }
+
+    /// Close all the open buckets, e.g. at the end of the trace
+    pub fn finish(&mut self) -> Vec<(TimelineId, Bucket)> {
+        let mut buckets: Vec<_> = self.buckets.drain().map(|((tid, _), b)| (tid, b)).collect();
+        buckets.sort_by_key(|(_, b)| b.start_ns);
+        buckets
+    }
+}
+
+/// The events of a class rolled up over a bucket of event time
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bucket {
+    class: String,
+    start_ns: Option<u64>,
+    /// The timestamp of the last event rolled into the bucket
+    last_ns: Option<u64>,
+    duration_ns: u64,
+    count: u64,
+    fields: BTreeMap<String, FieldStats>,
+}
+
+impl Bucket {
+    /// The attrs of the bucket's synthetic event
+    pub async fn attr_kvs(
+        &self,
+        client: &mut Client,
+    ) -> Result<Vec<(InternedAttrKey, AttrVal)>, Error> {
+        let mut attrs = vec![
+            (
+                client.interned_event_key(EventAttrKey::Name).await?,
+                self.class.clone().into(),
+            ),
+            (
+                client
+                    .interned_event_key(EventAttrKey::AggregateCount)
+                    .await?,
+                BigInt::new_attr_val(self.count.into()),
+            ),
+            (
+                client
+                    .interned_event_key(EventAttrKey::AggregateBucketNs)
+                    .await?,
+                BigInt::new_attr_val(self.duration_ns.into()),
+            ),
+        ];
+        if let Some(ns) = self.last_ns {
+            attrs.push((
+                client.interned_event_key(EventAttrKey::Timestamp).await?,
+                Nanoseconds::from(ns).into(),
+            ));
+        }
+        for (field, stats) in self.fields.iter() {
+            let field = field.strip_prefix("event.").unwrap_or(field);
+            for (stat, v) in [("sum", stats.sum), ("min", stats.min), ("max", stats.max)] {
+                attrs.push((
+                    client
+                        .interned_event_key(EventAttrKey::Field(format!("{field}.{stat}")))
+                        .await?,
+                    v.into_attr_val(),
+                ));
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct FieldStats {
+    sum: Number,
+    min: Number,
+    max: Number,
+}
+
+impl FieldStats {
+    fn new(v: Number) -> Self {
+        Self {
+            sum: v,
+            min: v,
+            max: v,
+        }
+    }
+
+    fn record(&mut self, v: Number) {
+        self.sum = self.sum.add(v);
+        if v.as_f64() < self.min.as_f64() {
+            self.min = v;
+        }
+        if v.as_f64() > self.max.as_f64() {
+            self.max = v;
+        }
+    }
+}
+
+/// A numeric attr value, integers are summed exactly
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Number {
+    Int(i128),
+    Float(f64),
+}
+
+impl Number {
+    fn from_attr_val(v: &AttrVal) -> Option<Self> {
+        match v {
+            AttrVal::Integer(i) => Some(Number::Int((*i).into())),
+            AttrVal::BigInt(_) => v.to_string().parse().ok().map(Number::Int),
+            AttrVal::Float(_) => v.to_string().parse().ok().map(Number::Float),
+            _ => None,
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(i) => i as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    fn add(self, other: Number) -> Number {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => match a.checked_add(b) {
+                Some(sum) => Number::Int(sum),
+                None => Number::Float(a as f64 + b as f64),
+            },
+            (a, b) => Number::Float(a.as_f64() + b.as_f64()),
+        }
+    }
+
+    fn into_attr_val(self) -> AttrVal {
+        match self {
+            Number::Int(i) => match i64::try_from(i) {
+                Ok(i) => i.into(),
+                Err(_) => BigInt::new_attr_val(i),
+            },
+            Number::Float(f) => f.into(),
+        }
+    }
+}
+
+impl AggregationConfig {
+    pub fn bucket_ms(&self) -> u64 {
+        self.bucket_ms.unwrap_or(DEFAULT_AGGREGATION_BUCKET_MS)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use uuid::Uuid;
+
+    #[test]
+    fn buckets_close_on_the_next_bucket() {
+        let mut aggregator = Aggregator::default();
+        let tid = TimelineId::from(Uuid::from_u128(1));
+        let runtime = |v| vec![("event.runtime".to_owned(), Number::Int(v))];
+        let record = |aggregator: &mut Aggregator, ts, v| {
+            aggregator.record_values(tid, "sched_stat_runtime", 100, Some(ts), runtime(v))
+        };
+
+        assert_eq!(record(&mut aggregator, 105, 10), None);
+        assert_eq!(record(&mut aggregator, 150, -4), None);
+        assert_eq!(record(&mut aggregator, 199, 30), None);
+        let closed = record(&mut aggregator, 200, 1).unwrap();
+        assert_eq!(closed.start_ns, Some(100));
+        assert_eq!(closed.last_ns, Some(199));
+        assert_eq!(closed.count, 3);
+        assert_eq!(
+            closed.fields["event.runtime"],
+            FieldStats {
+                sum: Number::Int(36),
+                min: Number::Int(-4),
+                max: Number::Int(30),
+            }
+        );
+
+        // Events without a timestamp stay in the open bucket
+        assert_eq!(
+            aggregator.record_values(tid, "sched_stat_runtime", 100, None, runtime(2)),
+            None
+        );
+        let open = aggregator.finish();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].1.start_ns, Some(200));
+        assert_eq!(open[0].1.last_ns, Some(200));
+        assert_eq!(open[0].1.count, 2);
+        assert_eq!(open[0].1.fields["event.runtime"].sum, Number::Int(3));
+        assert!(aggregator.finish().is_empty());
+    }
+
+    #[test]
+    fn mixed_numbers() {
+        let sum = Number::Int(i128::MAX).add(Number::Int(1));
+        assert!(matches!(sum, Number::Float(_)));
+        assert_eq!(Number::Int(1).add(Number::Float(0.5)), Number::Float(1.5));
+        let mut stats = FieldStats::new(Number::Int(3));
+        stats.record(Number::Float(2.5));
+        assert_eq!(stats.min, Number::Float(2.5));
+        assert_eq!(stats.max, Number::Int(3));
+    }
+}
@@ -22,6 +22,12 @@ pub enum TimelineAttrKey {
     ClockStyle,
     #[display(fmt = "timeline.ingest_source")]
     IngestSource,
+    #[display(fmt = "timeline.tracer.name")]
+    TracerName,
+    #[display(fmt = "timeline.tracer.version")]
+    TracerVersion,
+    #[display(fmt = "timeline.cpu")]
+    Cpu,
 
     #[display(fmt = "timeline.internal.ctf.trace.name")]
     TraceName,
@@ -52,6 +58,45 @@ pub enum TimelineAttrKey {
     StreamClockDesc,
     #[display(fmt = "timeline.internal.ctf.stream.clock.uuid")]
     StreamClockUuid,
+    #[display(fmt = "timeline.internal.ctf.stream.clock.wall_clock_origin")]
+    StreamClockWallClockOrigin,
+    #[display(fmt = "timeline.internal.ctf.stream.clock.timestamp_shift_ns")]
+    StreamClockTimestampShift,
+    #[display(fmt = "timeline.internal.ctf.stream.clock.class_offset_ns")]
+    StreamClockClassOffset,
+    #[display(fmt = "timeline.internal.ctf.stream.clock.correction_ns")]
+    StreamClockCorrection,
+    #[display(fmt = "timeline.internal.ctf.stream.clock.correction_source")]
+    StreamClockCorrectionSource,
+    #[display(fmt = "timeline.internal.ctf.source_uri")]
+    SourceUri,
+    #[display(fmt = "timeline.internal.ctf.packet_index.packet_count")]
+    PacketIndexPacketCount,
+    #[display(fmt = "timeline.internal.ctf.packet_index.total_size")]
+    PacketIndexTotalSize,
+    #[display(fmt = "timeline.internal.ctf.packet_index.begin_ns")]
+    PacketIndexBeginNs,
+    #[display(fmt = "timeline.internal.ctf.packet_index.end_ns")]
+    PacketIndexEndNs,
+
+    #[display(fmt = "timeline.internal.ctf.event_count")]
+    EventCount,
+    #[display(fmt = "timeline.internal.ctf.capped_event_count")]
+    CappedEventCount,
+    #[display(fmt = "timeline.internal.ctf.min_timestamp")]
+    MinTimestamp,
+    #[display(fmt = "timeline.internal.ctf.max_timestamp")]
+    MaxTimestamp,
+    #[display(fmt = "timeline.internal.ctf.event_class.{_0}.count")]
+    EventClassCount(String),
+    #[display(fmt = "timeline.internal.ctf.event_class.{_0}.sample_rate")]
+    EventClassSampleRate(String),
+    #[display(fmt = "timeline.internal.ctf.event_class.{_0}.decaying_threshold")]
+    EventClassDecayingThreshold(String),
+    #[display(fmt = "timeline.internal.ctf.sample_rate")]
+    SampleRate,
+    #[display(fmt = "timeline.internal.ctf.sample_decaying_window_ms")]
+    SampleDecayingWindowMs,
 
     #[display(fmt = "timeline.internal.config.merge_stream_id")]
     MergeStreamId,
@@ -66,6 +111,36 @@ pub enum EventAttrKey {
     Name,
     #[display(fmt = "event.timestamp")]
     Timestamp,
+    #[display(fmt = "event.timestamp.precision_ns")]
+    TimestampPrecision,
+    #[display(fmt = "event.cpu")]
+    Cpu,
+    #[display(fmt = "event.run_id")]
+    RunId,
+    #[display(fmt = "event.ingest.source")]
+    IngestSource,
+    #[display(fmt = "event.ingest.plugin")]
+    IngestPlugin,
+    #[display(fmt = "event.ingest.version")]
+    IngestVersion,
+    #[display(fmt = "event.ingest.lag_ns")]
+    IngestLag,
+    #[display(fmt = "event.ingest.url")]
+    IngestUrl,
+    #[display(fmt = "event.ingest.event_count")]
+    IngestEventCount,
+    #[display(fmt = "event.ingest.acknowledged")]
+    IngestAcknowledged,
+    #[display(fmt = "event.ingest.drop_reason")]
+    IngestDropReason,
+    #[display(fmt = "event.ingest.idle_ms")]
+    IngestIdleMs,
+    #[display(fmt = "event.ingest.max_events_per_timeline")]
+    MaxEventsPerTimeline,
+    #[display(fmt = "event.interaction.remote_timeline_id")]
+    RemoteTimelineId,
+    #[display(fmt = "event.interaction.remote_timestamp")]
+    RemoteTimestamp,
 
     #[display(fmt = "event.internal.ctf.stream_id")]
     StreamId,
@@ -73,8 +148,28 @@ pub enum EventAttrKey {
     Id,
     #[display(fmt = "event.internal.ctf.log_level")]
     LogLevel,
+    #[display(fmt = "event.internal.ctf.log_level_value")]
+    LogLevelValue,
     #[display(fmt = "event.internal.ctf.clock_snapshot")]
     ClockSnapshot,
+    #[display(fmt = "event.internal.ctf.signed_clock_snapshot")]
+    SignedClockSnapshot,
+    #[display(fmt = "event.internal.ctf.packet.begin_ns")]
+    PacketBeginNs,
+    #[display(fmt = "event.internal.ctf.packet.end_ns")]
+    PacketEndNs,
+    #[display(fmt = "event.internal.ctf.source_uri")]
+    SourceUri,
+    #[display(fmt = "event.internal.ctf.trace.uuid")]
+    TraceUuid,
+    #[display(fmt = "event.internal.ctf.discarded_events")]
+    DiscardedEvents,
+    #[display(fmt = "event.internal.ctf.sample_rate")]
+    SampleRate,
+    #[display(fmt = "event.internal.ctf.aggregate.count")]
+    AggregateCount,
+    #[display(fmt = "event.internal.ctf.aggregate.bucket_ns")]
+    AggregateBucketNs,
 
     #[display(fmt = "event.internal.ctf.common_context.{_0}")]
     CommonContext(String),
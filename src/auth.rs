@@ -1,22 +1,61 @@
 use derive_more::{Deref, Into};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
 use thiserror::Error;
+use tokio::process::Command;
 use tracing::debug;
 
+/// How long the auth token command gets to print the token
+const TOKEN_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Deref, Into)]
 pub struct AuthTokenBytes(Vec<u8>);
 
-#[derive(Copy, Clone, Debug, Error)]
+#[derive(Debug, Error)]
 pub enum AuthTokenError {
     #[error("An auth token is required. Provide one at the command line or via the MODALITY_AUTH_TOKEN environment variable.")]
     AuthRequired,
 
+    #[error("Encountered an IO error while reading the auth token file '{0}'. {1}")]
+    File(PathBuf, #[source] io::Error),
+
+    #[error("Failed to run the auth token command '{0}'. {1}")]
+    CommandIo(String, #[source] io::Error),
+
+    #[error("The auth token command '{0}' failed ({1}). {2}")]
+    Command(String, ExitStatus, String),
+
+    #[error("The auth token command '{0}' didn't finish within {1:?}")]
+    CommandTimeout(String, Duration),
+
     #[error("Encountered an error decoding the auth token. {0}")]
     Hex(#[from] hex::FromHexError),
 }
 
 impl AuthTokenBytes {
-    pub fn resolve(maybe_provided_hex: Option<&str>) -> Result<Self, AuthTokenError> {
-        let hex = if let Some(provided_hex) = maybe_provided_hex {
+    /// Resolve the auth token from the stdout of the token command, then the token file,
+    /// then the provided hex string, then the default Modality auth token file.
+    /// An explicitly configured command or file takes precedence over the provided
+    /// token, which can come from the environment (`MODALITY_AUTH_TOKEN`).
+    /// The command is run and the files are read on every call so a refreshed token
+    /// is picked up.
+    pub async fn resolve(
+        maybe_provided_hex: Option<&str>,
+        token_command: Option<&str>,
+        token_file: Option<&Path>,
+    ) -> Result<Self, AuthTokenError> {
+        if maybe_provided_hex.is_some() && (token_command.is_some() || token_file.is_some()) {
+            debug!("Ignoring the provided auth token in favor of the configured token source");
+        }
+        let hex = if let Some(command) = token_command {
+            debug!("Resolving auth token from the auth token command");
+            run_token_command(command, TOKEN_COMMAND_TIMEOUT).await?
+        } else if let Some(path) = token_file {
+            debug!("Resolving auth token from '{}'", path.display());
+            std::fs::read_to_string(path).map_err(|e| AuthTokenError::File(path.to_owned(), e))?
+        } else if let Some(provided_hex) = maybe_provided_hex {
             provided_hex.to_string()
         } else {
             dirs::config_dir()
@@ -31,3 +70,102 @@ impl AuthTokenBytes {
         Ok(Self(hex::decode(hex.trim())?))
     }
 }
+
+/// Run the command with `sh -c`, its stdout is the token.
+/// The command is killed when it doesn't finish in time.
+async fn run_token_command(command: &str, timeout: Duration) -> Result<String, AuthTokenError> {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| AuthTokenError::CommandIo(command.to_owned(), e))?;
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| AuthTokenError::CommandTimeout(command.to_owned(), timeout))?
+        .map_err(|e| AuthTokenError::CommandIo(command.to_owned(), e))?;
+    if !output.status.success() {
+        return Err(AuthTokenError::Command(
+            command.to_owned(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| {
+        AuthTokenError::CommandIo(
+            command.to_owned(),
+            io::Error::new(io::ErrorKind::InvalidData, e),
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn token_file_is_re_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        std::fs::write(&path, "0102\n").unwrap();
+        let token = AuthTokenBytes::resolve(None, None, Some(&path))
+            .await
+            .unwrap();
+        assert_eq!(token.0, vec![1, 2]);
+
+        std::fs::write(&path, "0304").unwrap();
+        let token = AuthTokenBytes::resolve(None, None, Some(&path))
+            .await
+            .unwrap();
+        assert_eq!(token.0, vec![3, 4]);
+
+        // The configured file takes precedence over a provided token
+        let token = AuthTokenBytes::resolve(Some("05"), None, Some(&path))
+            .await
+            .unwrap();
+        assert_eq!(token.0, vec![3, 4]);
+        let token = AuthTokenBytes::resolve(Some("05"), None, None)
+            .await
+            .unwrap();
+        assert_eq!(token.0, vec![5]);
+
+        let missing = dir.path().join("missing");
+        assert!(matches!(
+            AuthTokenBytes::resolve(None, None, Some(&missing)).await,
+            Err(AuthTokenError::File(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn token_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        std::fs::write(&path, "0102").unwrap();
+
+        // The command takes precedence over the file
+        let token = AuthTokenBytes::resolve(None, Some("echo 0a0b"), Some(&path))
+            .await
+            .unwrap();
+        assert_eq!(token.0, vec![10, 11]);
+
+        // And over a provided token
+        let token = AuthTokenBytes::resolve(Some("05"), Some("echo 0a0b"), None)
+            .await
+            .unwrap();
+        assert_eq!(token.0, vec![10, 11]);
+
+        assert!(matches!(
+            AuthTokenBytes::resolve(None, Some("echo denied >&2; exit 3"), None).await,
+            Err(AuthTokenError::Command(_, _, stderr)) if stderr == "denied"
+        ));
+
+        assert!(matches!(
+            run_token_command("sleep 5", Duration::from_millis(50)).await,
+            Err(AuthTokenError::CommandTimeout(_, _))
+        ));
+    }
+}
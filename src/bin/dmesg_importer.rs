@@ -0,0 +1,225 @@
+#![deny(warnings, clippy::all)]
+
+use clap::Parser;
+use modality_api::types::TimelineId;
+use modality_ctf::config::{parse_attr_key_rename, AttrKeyRename};
+use modality_ctf::{
+    client::connect_ingest,
+    diagnostics::{DiagnosticEvent, Diagnostics, DropReason},
+    dmesg::{self, DmesgRecord, DMESG_TIMELINE_NAME},
+    prelude::*,
+    reflector,
+    tracing::try_init_tracing_subscriber,
+};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+/// Import kernel ring buffer logs, as printed by `dmesg` or `dmesg --raw`
+#[derive(Parser, Debug, Clone)]
+#[clap(version)]
+pub struct Opts {
+    #[clap(flatten)]
+    pub rf_opts: ReflectorOpts,
+
+    #[clap(flatten)]
+    pub bt_opts: BabeltraceOpts,
+
+    /// Name of the timeline the log records are imported to
+    #[clap(long, name = "timeline-name", help_heading = "IMPORT CONFIGURATION")]
+    pub timeline_name: Option<String>,
+
+    /// Rename an event attribute key as it is being imported. Specify as 'original_key,new_key'
+    #[clap(long, name = "original.event.attr,new.event.attr", help_heading = "IMPORT CONFIGURATION", value_parser = parse_attr_key_rename)]
+    pub rename_event_attr: Vec<AttrKeyRename>,
+
+    /// When the ingest endpoint can't be reached at startup, keep retrying
+    /// (with backoff) for up to this many milliseconds instead of exiting
+    #[clap(
+        long,
+        name = "wait-for-ingest-ms",
+        help_heading = "IMPORT CONFIGURATION"
+    )]
+    pub wait_for_ingest_ms: Option<u64>,
+
+    /// Paths to dmesg log files, standard input is read when none are given
+    #[clap(name = "input", help_heading = "IMPORT CONFIGURATION")]
+    pub inputs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Encountered an IO error while reading the dmesg input '{0}'. {1}")]
+    Io(String, #[source] io::Error),
+}
+
+#[tokio::main]
+async fn main() {
+    match do_main().await {
+        Ok(()) => (),
+        Err(e) => {
+            eprintln!("{e}");
+            let mut cause = e.source();
+            while let Some(err) = cause {
+                eprintln!("Caused by: {err}");
+                cause = err.source();
+            }
+            std::process::exit(exitcode::SOFTWARE);
+        }
+    }
+}
+
+async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
+    let opts = Opts::parse();
+    let log_level = opts.rf_opts.log_level_filter();
+
+    // The log file settings come from the configuration, so it's loaded first
+    let mut cfg = CtfConfig::load_merge_with_opts(opts.rf_opts, opts.bt_opts)?;
+    try_init_tracing_subscriber(&cfg.plugin, log_level)?;
+    cfg.plugin.warn_unknown_keys();
+    if cfg.is_reflector_child() {
+        reflector::exit_with_parent()?;
+        info!("Running as a reflector child plugin");
+    }
+    if let Some(ms) = opts.wait_for_ingest_ms {
+        cfg.plugin.wait_for_ingest_ms = ms.into();
+    }
+    let inputs = if opts.inputs.is_empty() {
+        cfg.plugin.import.inputs.clone()
+    } else {
+        opts.inputs
+    };
+
+    let mut rename_event_attrs = opts.rename_event_attr.clone();
+    rename_event_attrs.extend(cfg.plugin.rename_event_attrs.clone());
+
+    let (c_authed, ingest_url) = connect_ingest(&cfg).await?;
+    let mut client = Client::new(
+        c_authed,
+        cfg.plugin.rename_timeline_attrs.clone(),
+        rename_event_attrs,
+    );
+    if let Some(budget) = cfg.plugin.event_attr_key_budget() {
+        client.set_event_attr_key_budget(budget, cfg.plugin.strict_attr_key_budget);
+    }
+    client.set_reconnect(&cfg);
+    client.set_ingest_error_policy(
+        cfg.plugin.on_ingest_error,
+        cfg.plugin.ingest_error_retries(),
+    );
+    let mut diagnostics = Diagnostics::new(&cfg.plugin, env!("CARGO_BIN_NAME"), "");
+    diagnostics
+        .record(
+            DiagnosticEvent::Connected {
+                url: ingest_url.to_string(),
+            },
+            &mut client,
+        )
+        .await?;
+
+    // A trace UUID override makes for a deterministic timeline ID
+    let timeline_id = TimelineId::from(
+        cfg.plugin
+            .trace_uuid
+            .map(|u| Uuid::new_v5(&u, DMESG_TIMELINE_NAME.as_bytes()))
+            .unwrap_or_else(Uuid::new_v4),
+    );
+    let run_id = cfg.plugin.run_id.unwrap_or_else(Uuid::new_v4);
+    let wall_clock_origin_ns = cfg.plugin.wall_clock_origin_ns;
+    let timeline_name = opts
+        .timeline_name
+        .unwrap_or_else(|| DMESG_TIMELINE_NAME.to_owned());
+
+    let mut attrs = HashMap::new();
+    for (k, v) in
+        dmesg::timeline_attr_kvs(&timeline_name, &run_id, wall_clock_origin_ns, &mut client).await?
+    {
+        attrs.insert(k, v);
+    }
+    for (k, v) in cfg
+        .ingest
+        .timeline_attributes
+        .additional_timeline_attributes
+        .iter()
+        .chain(
+            cfg.ingest
+                .timeline_attributes
+                .override_timeline_attributes
+                .iter(),
+        )
+    {
+        let key = client
+            .interned_timeline_key(TimelineAttrKey::Custom(k.to_string()))
+            .await?;
+        attrs.insert(key, v.clone());
+    }
+    client.open_timeline(timeline_id).await?;
+    client.timeline_metadata(attrs).await?;
+
+    let readers: Vec<(String, Box<dyn BufRead>)> = if inputs.is_empty() {
+        vec![("stdin".to_owned(), Box::new(BufReader::new(io::stdin())))]
+    } else {
+        inputs
+            .iter()
+            .map(|p| {
+                let f = File::open(p).map_err(|e| Error::Io(p.display().to_string(), e))?;
+                Ok((
+                    p.display().to_string(),
+                    Box::new(BufReader::new(f)) as Box<dyn BufRead>,
+                ))
+            })
+            .collect::<Result<_, Error>>()?
+    };
+
+    let mut ordering: u128 = 0;
+    for (name, mut reader) in readers.into_iter() {
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            if reader
+                .read_until(b'\n', &mut buf)
+                .map_err(|e| Error::Io(name.clone(), e))?
+                == 0
+            {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buf);
+            let record = match DmesgRecord::parse(&line) {
+                Some(r) => r,
+                None => continue,
+            };
+            let attrs = record.attr_kvs(wall_clock_origin_ns, &mut client).await?;
+            if !client.send_event(ordering, attrs).await? {
+                diagnostics.dropped(DropReason::IngestError);
+            }
+            ordering += 1;
+        }
+    }
+    client.close_timeline();
+    info!("Imported {ordering} dmesg records");
+
+    if diagnostics.is_enabled() {
+        let acknowledged = client
+            .flush_with_timeout(cfg.plugin.drain_timeout())
+            .await?;
+        diagnostics
+            .record(
+                DiagnosticEvent::BatchFlushed {
+                    event_count: ordering as u64,
+                    acknowledged,
+                },
+                &mut client,
+            )
+            .await?;
+        client.close_timeline();
+        client
+            .flush_with_timeout(cfg.plugin.drain_timeout())
+            .await?;
+    }
+
+    Ok(())
+}
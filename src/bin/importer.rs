@@ -1,15 +1,51 @@
 #![deny(warnings, clippy::all)]
 
-use babeltrace2_sys::{CtfIterator, CtfPluginSourceFsInitParams};
+use babeltrace2_sys::{CtfIterator, OwnedEvent, StreamProperties, TraceProperties};
 use clap::Parser;
 use modality_api::types::TimelineId;
-use modality_ctf::config::AttrKeyRename;
-use modality_ctf::{prelude::*, tracing::try_init_tracing_subscriber};
-use modality_ingest_client::IngestClient;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use modality_ctf::config::{parse_attr_key_rename, AttrKeyRename, StreamEventMappings};
+use modality_ctf::{
+    aggregation::Aggregator,
+    client::connect_ingest,
+    clock::{self, ClockSyncTracker},
+    diagnostics::{DiagnosticEvent, Diagnostics, DropReason},
+    error::BabeltraceError,
+    event::{in_band_timeline_attr_kvs, packet_context_cpu_id, packet_context_seq_num},
+    healthcheck::{self, HealthCheckError},
+    index::{ImportProgress, PacketIndex},
+    inspect::TraceSchema,
+    interaction::{InteractionPairs, RemoteTimelines},
+    messages::{handle_messages, PacketBoundaryTracker},
+    metadata::group_inputs_by_trace_uuid,
+    prelude::*,
+    preview::TimelinesPreview,
+    profile::{
+        detect::{detect, detect_from_trace_properties, ProfileDetection},
+        ProfileRules,
+    },
+    properties::{fs_source_uri, fs_trace_name, shard_run_id, stream_file_path},
+    reflector,
+    report::MappingReport,
+    sampling::{Sampled, Sampler},
+    shard::ShardDir,
+    state::{
+        trace_digest, trace_size, IngestState, StreamMark, StreamRecord, TraceRecord, TraceWatcher,
+    },
+    stats::{PipelineBench, TimelineStats, TraceStats},
+    symbols::Symbolizer,
+    timeline::TimelineRegistry,
+    tracing::{try_init_tracing_subscriber, BabeltraceLogBridge},
+    transform::EventTransform,
+    types::{EventLogLevel, LoggingLevel, MessageAction, Profile, Shard, UnixEpochOrigin},
+};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tracing::warn;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
 
 /// Import CTF trace data from files
 #[derive(Parser, Debug, Clone)]
@@ -34,9 +70,10 @@ pub struct Opts {
     #[clap(long, name = "offset-s", help_heading = "IMPORT CONFIGURATION")]
     pub clock_class_offset_s: Option<i64>,
 
-    /// Force the origin of all clock classes that the component creates to have a Unix epoch origin
+    /// Force the origin of all clock classes that the component creates to have a Unix epoch origin.
+    /// Use 'auto' to decide from the trace's clock classes
     #[clap(long, name = "unix-epoch", help_heading = "IMPORT CONFIGURATION")]
-    pub force_clock_class_origin_unix_epoch: Option<bool>,
+    pub force_clock_class_origin_unix_epoch: Option<UnixEpochOrigin>,
 
     /// Rename a timeline attribute key as it is being imported. Specify as 'original_key,new_key'
     #[clap(long, name = "original.tl.attr,new.tl.attr", help_heading = "IMPORT CONFIGURATION", value_parser = parse_attr_key_rename)]
@@ -46,22 +83,105 @@ pub struct Opts {
     #[clap(long, name = "original.event.attr,new.event.attr", help_heading = "IMPORT CONFIGURATION", value_parser = parse_attr_key_rename)]
     pub rename_event_attr: Vec<AttrKeyRename>,
 
+    /// Treat each input path as an independent trace, importing up to this many
+    /// inputs in parallel
+    #[clap(long, name = "parallel-inputs", help_heading = "IMPORT CONFIGURATION")]
+    pub parallel_inputs: Option<usize>,
+
+    /// Import the inputs with different trace UUIDs as separate traces, instead of failing
+    #[clap(long, help_heading = "IMPORT CONFIGURATION")]
+    pub group_inputs_by_trace_uuid: bool,
+
+    /// Check babeltrace can open every input as a CTF trace before importing
+    /// anything, instead of warning about the ones that don't
+    #[clap(long, help_heading = "IMPORT CONFIGURATION")]
+    pub strict_inputs: bool,
+
+    /// Record the content digest of each imported trace in this file and skip
+    /// traces that were already imported
+    #[clap(long, name = "state-file", help_heading = "IMPORT CONFIGURATION")]
+    pub state_file: Option<PathBuf>,
+
+    /// Import the traces recorded in the state file again, reusing the
+    /// run ID and timeline IDs from the previous import
+    #[clap(long, help_heading = "IMPORT CONFIGURATION")]
+    pub reimport: bool,
+
+    /// Skip the events already imported from a previous snapshot of the
+    /// same session, as recorded in the state file
+    #[clap(long, help_heading = "IMPORT CONFIGURATION")]
+    pub dedup_snapshots: bool,
+
+    /// Only register the timelines of the streams that have events
+    #[clap(long, help_heading = "IMPORT CONFIGURATION")]
+    pub skip_empty_timelines: bool,
+
+    /// Write the field to attr key mapping of each event class to this JSON file
+    #[clap(long, name = "report.json", help_heading = "IMPORT CONFIGURATION")]
+    pub mapping_report: Option<PathBuf>,
+
+    /// Only import the streams of shard 'index' out of 'count' cooperating importers,
+    /// the stream files whose sorted position modulo 'count' is 'index'
+    #[clap(long, name = "index/count", help_heading = "IMPORT CONFIGURATION")]
+    pub shard: Option<Shard>,
+
+    /// Check the configuration loads and the ingest endpoint is reachable and
+    /// accepts the auth token, then exit
+    #[clap(long)]
+    pub healthcheck: bool,
+
+    /// Print the trace properties, streams, and event class fields
+    /// without connecting to Modality, then exit
+    #[clap(long)]
+    pub inspect: bool,
+
+    /// Print event class counts, event rates, stream time ranges, and
+    /// payload sizes without connecting to Modality, then exit
+    #[clap(long, conflicts_with = "inspect")]
+    pub stats: bool,
+
+    /// Print the timelines that would be created, with their names, IDs, and
+    /// clocks, without connecting to Modality, then exit
+    #[clap(long, conflicts_with_all = ["inspect", "stats"])]
+    pub list_timelines: bool,
+
+    /// Run the import without connecting to Modality, discarding the events,
+    /// then print the decode and mapping throughput
+    #[clap(long, conflicts_with_all = ["inspect", "stats", "list_timelines"])]
+    pub bench: bool,
+
+    /// When the ingest endpoint can't be reached at startup, keep retrying
+    /// (with backoff) for up to this many milliseconds instead of exiting
+    #[clap(
+        long,
+        name = "wait-for-ingest-ms",
+        help_heading = "IMPORT CONFIGURATION"
+    )]
+    pub wait_for_ingest_ms: Option<u64>,
+
+    /// Keep importing the traces copied into this directory instead of the inputs,
+    /// skipping the ones recorded in the state file
+    #[clap(
+        long,
+        name = "watch",
+        help_heading = "IMPORT CONFIGURATION",
+        conflicts_with_all = ["inspect", "stats", "list_timelines", "bench"]
+    )]
+    pub watch_dir: Option<PathBuf>,
+
+    /// How often the watched directory is scanned for new traces [default: 5000]
+    #[clap(
+        long,
+        name = "watch-interval-ms",
+        help_heading = "IMPORT CONFIGURATION"
+    )]
+    pub watch_interval_ms: Option<u64>,
+
     /// Path to trace directories
     #[clap(name = "input", help_heading = "IMPORT CONFIGURATION")]
     pub inputs: Vec<PathBuf>,
 }
 
-fn parse_attr_key_rename(
-    s: &str,
-) -> Result<AttrKeyRename, Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let pos = s
-        .find(',')
-        .ok_or_else(|| format!("invalid original,new: no `,` found in `{s}`"))?;
-    let original = s[..pos].parse()?;
-    let new = s[pos + 1..].parse()?;
-    Ok(AttrKeyRename { original, new })
-}
-
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
@@ -69,6 +189,27 @@ pub enum Error {
 
     #[error("At least one CTF containing input path is required.")]
     MissingInputs,
+
+    #[error("No events were ingested.")]
+    NoEventsIngested,
+
+    #[error("Sharding can't be combined with merge-stream-id, the streams would all share one timeline.")]
+    ShardWithMergedStreams,
+
+    #[error("Failed to set up the shard's stream files of input '{0}'. {1}")]
+    ShardInput(PathBuf, #[source] std::io::Error),
+
+    #[error("Watching a directory requires a state-file to record the imported traces.")]
+    WatchWithoutStateFile,
+}
+
+impl Error {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Error::NoEventsIngested => exitcode::DATAERR,
+            _ => exitcode::SOFTWARE,
+        }
+    }
 }
 
 #[tokio::main]
@@ -82,15 +223,39 @@ async fn main() {
                 eprintln!("Caused by: {err}");
                 cause = err.source();
             }
-            std::process::exit(exitcode::SOFTWARE);
+            std::process::exit(
+                e.downcast_ref::<Error>()
+                    .map(Error::exit_code)
+                    .or_else(|| {
+                        e.downcast_ref::<HealthCheckError>()
+                            .map(HealthCheckError::exit_code)
+                    })
+                    .unwrap_or(exitcode::SOFTWARE),
+            );
         }
     }
 }
 
 async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
     let opts = Opts::parse();
+    let log_level = opts.rf_opts.log_level_filter();
 
-    try_init_tracing_subscriber()?;
+    if opts.healthcheck {
+        let cfg = CtfConfig::load_merge_with_opts(opts.rf_opts, opts.bt_opts)
+            .map_err(|e| HealthCheckError::Config(e.to_string()))?;
+        try_init_tracing_subscriber(&cfg.plugin, log_level)?;
+        cfg.plugin.warn_unknown_keys();
+        println!("config: ok");
+        healthcheck::check_ingest(&cfg).await?;
+        return Ok(());
+    }
+
+    // The log file settings come from the configuration, so it's loaded first
+    let mut cfg = CtfConfig::load_merge_with_opts(opts.rf_opts, opts.bt_opts)?;
+    try_init_tracing_subscriber(&cfg.plugin, log_level)?;
+    cfg.plugin.warn_unknown_keys();
+
+    let _bt_log_bridge = BabeltraceLogBridge::start(cfg.plugin.log_level)?;
 
     let intr = Interruptor::new();
     let interruptor = intr.clone();
@@ -103,144 +268,564 @@ async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
         }
     })?;
 
-    let mut cfg = CtfConfig::load_merge_with_opts(opts.rf_opts, opts.bt_opts)?;
+    if cfg.is_reflector_child() {
+        reflector::exit_with_parent()?;
+        info!("Running as a reflector child plugin");
+    }
     if let Some(tn) = opts.trace_name {
         cfg.plugin.import.trace_name = tn.into();
     }
     if let Some(ns) = opts.clock_class_offset_ns {
-        cfg.plugin.import.clock_class_offset_ns = ns.into();
+        cfg.plugin.clock_class.clock_class_offset_ns = ns.into();
     }
     if let Some(s) = opts.clock_class_offset_s {
-        cfg.plugin.import.clock_class_offset_s = s.into();
+        cfg.plugin.clock_class.clock_class_offset_s = s.into();
     }
     if let Some(ue) = opts.force_clock_class_origin_unix_epoch {
-        cfg.plugin.import.force_clock_class_origin_unix_epoch = ue.into();
+        cfg.plugin.clock_class.force_clock_class_origin_unix_epoch = ue.into();
+    }
+    if let Some(n) = opts.parallel_inputs {
+        cfg.plugin.import.parallel_inputs = n.into();
+    }
+    if let Some(p) = opts.state_file {
+        cfg.plugin.import.state_file = p.into();
+    }
+    if opts.group_inputs_by_trace_uuid {
+        cfg.plugin.import.group_inputs_by_trace_uuid = true;
+    }
+    if opts.strict_inputs {
+        cfg.plugin.import.strict_inputs = true;
+    }
+    if opts.reimport {
+        cfg.plugin.import.reimport = true;
+    }
+    if opts.dedup_snapshots {
+        cfg.plugin.import.dedup_snapshots = true;
+    }
+    if opts.skip_empty_timelines {
+        cfg.plugin.import.skip_empty_timelines = true;
+    }
+    if let Some(p) = opts.mapping_report {
+        cfg.plugin.import.mapping_report = p.into();
+    }
+    if let Some(shard) = opts.shard {
+        cfg.plugin.import.shard = shard.into();
+    }
+    if let Some(dir) = opts.watch_dir {
+        cfg.plugin.import.watch_dir = dir.into();
+    }
+    if let Some(ms) = opts.watch_interval_ms {
+        cfg.plugin.import.watch_interval_ms = ms.into();
+    }
+    if let Some(ms) = opts.wait_for_ingest_ms {
+        cfg.plugin.wait_for_ingest_ms = ms.into();
     }
     if !opts.inputs.is_empty() {
         cfg.plugin.import.inputs = opts.inputs;
     }
 
-    let mut rename_timeline_attrs = opts.rename_timeline_attr.clone();
-    rename_timeline_attrs.extend(cfg.plugin.rename_timeline_attrs.clone());
-
-    let mut rename_event_attrs = opts.rename_event_attr.clone();
-    rename_event_attrs.extend(cfg.plugin.rename_event_attrs.clone());
-
-    if cfg.plugin.import.inputs.is_empty() {
+    // The profile of the watched traces is detected as they're imported
+    if cfg.plugin.import.watch_dir.is_some() {
+        if cfg.plugin.import.state_file.is_none() {
+            return Err(Error::WatchWithoutStateFile.into());
+        }
+        if !cfg.plugin.import.inputs.is_empty() {
+            warn!("The inputs are ignored when watching a directory");
+            cfg.plugin.import.inputs.clear();
+        }
+    } else if cfg.plugin.import.inputs.is_empty() {
         return Err(Error::MissingInputs.into());
+    } else {
+        detect_profile(&mut cfg)?;
+    }
+    if cfg.plugin.import.shard.is_some() && cfg.plugin.merge_stream_id.is_some() {
+        return Err(Error::ShardWithMergedStreams.into());
+    }
+    if let Some(shard) = cfg.plugin.import.shard {
+        info!("Importing shard {shard} of the streams");
+        // A configured trace UUID settles the shards' run ID up front, so the
+        // diagnostics timeline agrees with the trace timelines
+        if let Some(trace_uuid) = cfg
+            .plugin
+            .trace_uuid
+            .filter(|_| cfg.plugin.generated_run_id)
+        {
+            cfg.plugin.run_id = shard_run_id(&trace_uuid).into();
+            cfg.plugin.generated_run_id = false;
+        }
     }
-    for p in cfg.plugin.import.inputs.iter() {
-        if !p.join("metadata").exists() {
-            warn!(
-                "Input path '{}' does not contain a metadata file",
-                p.display()
+    if cfg.plugin.import.dedup_snapshots && cfg.plugin.import.state_file.is_none() {
+        warn!("Snapshot deduplication requires a state file, all events will be imported");
+    }
+    if cfg.plugin.import.strict_inputs {
+        let invalid_inputs: Vec<String> = cfg
+            .plugin
+            .import
+            .inputs
+            .iter()
+            .filter_map(|p| {
+                input_problem(
+                    p,
+                    &cfg.plugin.import,
+                    &cfg.plugin.clock_class,
+                    cfg.plugin.log_level,
+                )
+                .map(|why| format!("'{}': {why}", p.display()))
+            })
+            .collect();
+        if !invalid_inputs.is_empty() {
+            return Err(
+                modality_ctf::error::Error::InvalidInputs(invalid_inputs.join(", ")).into(),
             );
         }
+    } else {
+        for p in cfg.plugin.import.inputs.iter() {
+            if !p.join("metadata").exists() {
+                warn!(
+                    "Input path '{}' does not contain a metadata file",
+                    p.display()
+                );
+            }
+        }
     }
 
-    let c =
-        IngestClient::connect(&cfg.protocol_parent_url()?, cfg.ingest.allow_insecure_tls).await?;
-    let c_authed = c.authenticate(cfg.resolve_auth()?.into()).await?;
-    let mut client = Client::new(c_authed, rename_timeline_attrs, rename_event_attrs);
+    if opts.inspect {
+        return inspect(&cfg, &opts.rename_timeline_attr, &opts.rename_event_attr);
+    }
+    if opts.stats {
+        return stats(
+            &cfg.plugin.import,
+            &cfg.plugin.clock_class,
+            cfg.plugin.log_level,
+        );
+    }
 
-    let ctf_params = CtfPluginSourceFsInitParams::try_from(&cfg.plugin.import)?;
-    let trace_iter = CtfIterator::new(cfg.plugin.log_level.into(), &ctf_params)?;
-    let props = CtfProperties::new(
-        cfg.plugin.run_id,
-        cfg.plugin.trace_uuid,
-        trace_iter.trace_properties(),
-        trace_iter.stream_properties(),
-        &mut client,
-    )
-    .await?;
+    if opts.list_timelines {
+        let (sources, _) = import_sources(&cfg)?;
+        return list_timelines(&cfg.plugin, &sources);
+    }
 
-    let mut last_timeline_ordering_val: HashMap<TimelineId, u128> = Default::default();
+    match cfg.plugin.import.watch_dir.clone() {
+        Some(dir) => {
+            watch(
+                &cfg,
+                dir,
+                &opts.rename_timeline_attr,
+                &opts.rename_event_attr,
+                &interruptor,
+            )
+            .await
+        }
+        None => {
+            import(
+                &cfg,
+                opts.bench,
+                &opts.rename_timeline_attr,
+                &opts.rename_event_attr,
+                &interruptor,
+            )
+            .await
+        }
+    }
+}
+
+fn detect_profile(cfg: &mut CtfConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if cfg.plugin.profile == Profile::Auto {
+        let detection = detect_import_profile(&cfg.plugin)?;
+        detection.log();
+        ProfileRules::for_profile(detection.profile).merge_into(&mut cfg.plugin);
+    }
+    Ok(())
+}
+
+/// The number of events of each input whose event class names are used for
+/// the profile detection
+const PROFILE_DETECTION_EVENTS: usize = 10_000;
 
-    if props.streams.is_empty() {
-        warn!("The CTF containing input path(s) don't contain any trace data");
+/// Detect the profile from the trace properties and the event class names of
+/// the first events of each input, the first input with a recognized profile wins
+fn detect_import_profile(
+    plugin_cfg: &PluginConfig,
+) -> Result<ProfileDetection, Box<dyn std::error::Error>> {
+    let mut detection = None;
+    for input in plugin_cfg.import.inputs.iter() {
+        let source_cfg = ImportConfig {
+            inputs: vec![input.clone()],
+            ..plugin_cfg.import.clone()
+        };
+        let ctf_params = source_cfg.ctf_fs_params(&plugin_cfg.clock_class)?;
+        let trace_iter = CtfIterator::new(plugin_cfg.log_level.into(), &ctf_params)
+            .map_err(|e| source_error(e, &source_cfg, &plugin_cfg.clock_class))?;
+        let trace_props = trace_iter.trace_properties().clone();
+        let mut event_names = BTreeSet::new();
+        for event in trace_iter.take(PROFILE_DETECTION_EVENTS) {
+            if let Some(name) = event?.class_properties.name {
+                event_names.insert(name);
+            }
+        }
+        let d = detect_from_trace_properties(&trace_props, &event_names);
+        if d.profile != Profile::None {
+            return Ok(d);
+        }
+        detection = Some(d);
     }
+    Ok(detection.unwrap_or_else(|| detect(None, None, &Default::default())))
+}
+
+/// Import the traces settling in the watched directory until interrupted,
+/// each one as an independent trace
+async fn watch(
+    cfg: &CtfConfig,
+    dir: PathBuf,
+    rename_timeline_attrs: &[AttrKeyRename],
+    rename_event_attrs: &[AttrKeyRename],
+    interruptor: &Interruptor,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state_file = cfg
+        .plugin
+        .import
+        .state_file
+        .as_deref()
+        .ok_or(Error::WatchWithoutStateFile)?;
+    let interval = cfg.plugin.import.watch_interval();
+    info!("Watching '{}' for new traces", dir.display());
+    let mut watcher = TraceWatcher::new(dir);
+    while !interruptor.is_set() {
+        let state = IngestState::load(state_file)?;
+        let trace_dirs = watcher.scan(&state)?;
+        if !trace_dirs.is_empty() {
+            info!("Importing the new trace(s) {trace_dirs:?}");
+            let mut trace_cfg = cfg.clone();
+            trace_cfg.plugin.import.inputs = trace_dirs;
+            trace_cfg.plugin.import.parallel_inputs.get_or_insert(1);
+            detect_profile(&mut trace_cfg)?;
+            import(
+                &trace_cfg,
+                false,
+                rename_timeline_attrs,
+                rename_event_attrs,
+                interruptor,
+            )
+            .await?;
+        }
 
-    if let Some(stream_id) = cfg.plugin.merge_stream_id {
-        if !props.streams.contains_key(&stream_id) {
-            return Err(modality_ctf::error::Error::MergeStreamIdNotFound.into());
+        let next_scan = Instant::now() + interval;
+        while !interruptor.is_set() && Instant::now() < next_scan {
+            tokio::time::sleep(WATCH_INTERRUPT_POLL_INTERVAL).await;
         }
     }
+    Ok(())
+}
 
-    let mut additional_timeline_attributes = Vec::with_capacity(
-        cfg.ingest
-            .timeline_attributes
-            .additional_timeline_attributes
-            .len(),
-    );
-    for kv in cfg
-        .ingest
-        .timeline_attributes
-        .additional_timeline_attributes
-        .iter()
-    {
-        additional_timeline_attributes.push((
-            client
-                .interned_timeline_key(TimelineAttrKey::Custom(kv.0.to_string()))
-                .await?,
-            kv.1.clone(),
-        ));
-    }
-
-    if let Some(stream_id) = cfg.plugin.merge_stream_id {
-        additional_timeline_attributes.push((
-            client
-                .interned_timeline_key(TimelineAttrKey::MergeStreamId)
-                .await?,
-            modality_api::BigInt::new_attr_val(stream_id.into()),
-        ));
-    }
-
-    let mut override_timeline_attributes = Vec::with_capacity(
-        cfg.ingest
-            .timeline_attributes
-            .override_timeline_attributes
-            .len(),
+const WATCH_INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+async fn import(
+    cfg: &CtfConfig,
+    bench: bool,
+    rename_timeline_attr: &[AttrKeyRename],
+    rename_event_attr: &[AttrKeyRename],
+    interruptor: &Interruptor,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rename_timeline_attrs = rename_timeline_attr.to_vec();
+    rename_timeline_attrs.extend(cfg.plugin.rename_timeline_attrs.clone());
+
+    let mut rename_event_attrs = rename_event_attr.to_vec();
+    rename_event_attrs.extend(cfg.plugin.rename_event_attrs.clone());
+
+    let (sources, parallelism) = import_sources(cfg)?;
+
+    // The bench runs the import without connecting, discarding what it would send
+    let (mut client, ingest_url) = if bench {
+        (
+            Client::offline(rename_timeline_attrs, rename_event_attrs),
+            None,
+        )
+    } else {
+        let (c_authed, ingest_url) = connect_ingest(cfg).await?;
+        (
+            Client::new(c_authed, rename_timeline_attrs, rename_event_attrs),
+            Some(ingest_url),
+        )
+    };
+    if let Some(budget) = cfg.plugin.event_attr_key_budget() {
+        client.set_event_attr_key_budget(budget, cfg.plugin.strict_attr_key_budget);
+    }
+    client.set_reconnect(cfg);
+    client.set_ingest_error_policy(
+        cfg.plugin.on_ingest_error,
+        cfg.plugin.ingest_error_retries(),
     );
-    for kv in cfg
-        .ingest
-        .timeline_attributes
-        .override_timeline_attributes
-        .iter()
-    {
-        override_timeline_attributes.push((
-            client
-                .interned_timeline_key(TimelineAttrKey::Custom(kv.0.to_string()))
-                .await?,
-            kv.1.clone(),
-        ));
-    }
-
-    for (stream_id, tid, attr_kvs) in props.timelines() {
-        if let Some(merge_stream_id) = cfg.plugin.merge_stream_id {
-            if stream_id != merge_stream_id {
-                continue;
+    let mut bench = bench.then(PipelineBench::default);
+    let mut diagnostics = Diagnostics::new(&cfg.plugin, env!("CARGO_BIN_NAME"), "");
+    if let Some(url) = &ingest_url {
+        diagnostics
+            .record(
+                DiagnosticEvent::Connected {
+                    url: url.to_string(),
+                },
+                &mut client,
+            )
+            .await?;
+    }
+
+    let mut last_timeline_ordering_val: HashMap<TimelineId, u128> = Default::default();
+    let mut timeline_stats: HashMap<TimelineId, TimelineStats> = Default::default();
+    let mut timelines_with_cpu: HashSet<TimelineId> = Default::default();
+    let mut clock_sync = ClockSyncTracker::new(&cfg.plugin);
+    let track_messages = cfg.plugin.message_handling.tracks_packet_boundaries();
+    let mut message_tracker = PacketBoundaryTracker::default();
+    let symbolizer = Symbolizer::from_config(&cfg.plugin.event_mapping)?;
+    let field_mappers = FieldMappers::registered();
+    let timeline_attrs_event = cfg.plugin.timeline_attrs_event.as_deref();
+    let mut remote_timelines = RemoteTimelines::from_config(&cfg.plugin.event_mapping);
+    let mut interaction_pairs = InteractionPairs::from_config(&cfg.plugin);
+    let mut event_transform = EventTransform::from_config(&cfg.plugin.event_mapping)?;
+    let mut sampler = Sampler::from_config(&cfg.plugin.sampling);
+    let mut aggregator = Aggregator::from_config(&cfg.plugin.aggregations);
+    let mut stream_event_mappings = StreamEventMappings::default();
+    // Packet sequence numbers count per stream, so the marks are keyed by source
+    // and stream ID rather than by timeline, merged streams share a timeline
+    let mut dedup_marks: HashMap<(usize, u64), StreamMark> = Default::default();
+    let mut stream_marks: HashMap<(usize, u64), StreamMark> = Default::default();
+    let mut dedup_skipped_events: u64 = 0;
+    // Logged as the events get through the time ranges of the stream packet indexes
+    let mut progress = ImportProgress::default();
+    let mut mapping_report = cfg
+        .plugin
+        .import
+        .mapping_report
+        .as_ref()
+        .map(|_| MappingReport::new(&field_mappers));
+
+    let mut timeline_registry =
+        TimelineRegistry::new(env!("CARGO_BIN_NAME"), cfg, &sampler, &mut client)
+            .await?
+            .with_skip_empty_timelines(cfg.plugin.import.skip_empty_timelines);
+
+    // Each shard only decodes its own stream files, from views of the input
+    // trace directories that are removed when the import is done
+    let mut shard_dirs = Vec::new();
+    let sources = match cfg.plugin.import.shard {
+        Some(shard) => {
+            let mut shard_sources = Vec::with_capacity(sources.len());
+            for source in sources.into_iter() {
+                let mut inputs = Vec::with_capacity(source.inputs.len());
+                for input in source.inputs.iter() {
+                    match ShardDir::new(shard, input)
+                        .map_err(|e| Error::ShardInput(input.clone(), e))?
+                    {
+                        Some(dir) => {
+                            inputs.push(dir.path().to_owned());
+                            shard_dirs.push(dir);
+                        }
+                        None => info!(
+                            "Input '{}' has no stream files in shard {shard}",
+                            input.display()
+                        ),
+                    }
+                }
+                if !inputs.is_empty() {
+                    shard_sources.push((source.inputs.clone(), ImportConfig { inputs, ..source }));
+                }
             }
+            shard_sources
         }
-
-        let mut attrs = HashMap::new();
-        for (k, v) in attr_kvs
+        None => sources
             .into_iter()
-            .chain(additional_timeline_attributes.clone().into_iter())
-            .chain(override_timeline_attributes.clone().into_iter())
-        {
-            attrs.insert(k, v);
-        }
+            .map(|source| (source.inputs.clone(), source))
+            .collect(),
+    };
 
-        client.c.open_timeline(tid).await?;
-        client.c.timeline_metadata(attrs).await?;
-        last_timeline_ordering_val.insert(tid, 0);
+    // Skip the traces that were already imported, the bench runs them all
+    let mut state = cfg
+        .plugin
+        .import
+        .state_file
+        .as_deref()
+        .filter(|_| bench.is_none())
+        .map(IngestState::load)
+        .transpose()?;
+    let mut source_digests = Vec::with_capacity(sources.len());
+    let mut source_records = Vec::with_capacity(sources.len());
+    let sources = if let Some(state) = &state {
+        let mut new_sources = Vec::with_capacity(sources.len());
+        for (inputs, source) in sources.into_iter() {
+            let digest = trace_digest(&inputs)?;
+            let record = state.get(&digest).cloned();
+            if state.is_imported(&digest) && !cfg.plugin.import.reimport {
+                info!("Skipping input(s) {inputs:?}, the trace was already imported");
+            } else {
+                source_digests.push(digest);
+                source_records.push(record);
+                new_sources.push((inputs, source));
+            }
+        }
+        new_sources
+    } else {
+        sources
+    };
+    let (source_inputs, sources): (Vec<_>, Vec<_>) = sources.into_iter().unzip();
+    if let Some(bench) = bench.as_mut() {
+        for inputs in source_inputs.iter() {
+            bench.add_trace_bytes(trace_size(inputs)?);
+        }
     }
+    let tag_trace_uuid = cfg.plugin.merge_stream_id.is_some() || sources.len() > 1;
+    let mut source_messages = spawn_decoders(
+        sources,
+        cfg.plugin.clock_class,
+        parallelism,
+        cfg.plugin.log_level,
+        interruptor.clone(),
+    );
+    let mut source_props: HashMap<usize, CtfProperties> = Default::default();
 
-    for maybe_event in trace_iter {
+    let mut drain_deadline = None;
+    let mut abandoned_events: u64 = 0;
+    let started = Instant::now();
+    loop {
+        let waiting_since = Instant::now();
+        let msg = match source_messages.recv().await {
+            Some(msg) => msg,
+            None => break,
+        };
+        if let (Some(bench), SourceMessage::Event(..)) = (bench.as_mut(), &msg) {
+            bench.record_decode(waiting_since.elapsed());
+        }
+        // On interrupt the decoders stop, and the events they already
+        // decoded are still ingested until the drain timeout
         if interruptor.is_set() {
-            break;
+            let deadline = *drain_deadline.get_or_insert_with(|| {
+                info!("Interrupted, draining the decoded events");
+                Instant::now() + cfg.plugin.drain_timeout()
+            });
+            if Instant::now() >= deadline {
+                if let SourceMessage::Event(..) = msg {
+                    abandoned_events += 1;
+                    diagnostics.dropped(DropReason::DrainTimeout);
+                }
+                continue;
+            }
+        }
+
+        let (source, event) = match msg {
+            SourceMessage::Properties(source, trace_props, stream_props) => {
+                // Reuse the run ID and timeline IDs of a previous import of the trace
+                let record = source_records.get(source).cloned().flatten().or_else(|| {
+                    let state = state.as_ref()?;
+                    state.find_by_trace_uuid(&trace_props.uuid?).cloned()
+                });
+                let mut plugin_cfg = cfg.plugin.clone();
+                if let Some(record) = &record {
+                    if plugin_cfg.generated_run_id && record.run_id.is_some() {
+                        plugin_cfg.run_id = record.run_id;
+                        plugin_cfg.generated_run_id = false;
+                    }
+                    plugin_cfg.trace_uuid = plugin_cfg.trace_uuid.or(record.trace_uuid);
+                    if plugin_cfg.stream_timeline_map.is_empty() {
+                        plugin_cfg.stream_timeline_map = record
+                            .streams
+                            .iter()
+                            .map(|s| (s.stream_id, s.timeline_id))
+                            .collect();
+                    }
+                }
+
+                let mut props =
+                    CtfProperties::new(&plugin_cfg, &trace_props, &stream_props, &mut client)
+                        .await?;
+                for s in stream_props.iter() {
+                    let stream_path = s
+                        .name
+                        .as_deref()
+                        .and_then(|n| stream_file_path(&source_inputs[source], n));
+                    let index = match stream_path.as_deref().map(PacketIndex::read) {
+                        Some(Ok(index)) => index,
+                        Some(Err(e)) => {
+                            warn!(
+                                stream_id = s.id,
+                                "Skipping the packet index of the stream, it can't be read. {e}"
+                            );
+                            None
+                        }
+                        None => None,
+                    };
+                    if let (Some(p), Some(index)) = (props.streams.get_mut(&s.id), index) {
+                        p.set_packet_index(&index, &mut client).await?;
+                        if let Some((begin_ns, end_ns)) = p.packet_index_range_ns(&index) {
+                            progress.add_stream(source, s.id, begin_ns, end_ns);
+                        }
+                    }
+                }
+                if cfg.plugin.source_uri_attrs {
+                    for s in stream_props.iter() {
+                        let uri = fs_source_uri(&source_inputs[source], s);
+                        if let (Some(p), Some(uri)) = (props.streams.get_mut(&s.id), uri) {
+                            p.set_source_uri(uri, &mut client).await?;
+                        }
+                    }
+                }
+
+                if let Some(name) = fs_trace_name(&source_inputs[source]) {
+                    props.set_fallback_trace_name(name, &mut client).await?;
+                }
+
+                if props.streams.is_empty() {
+                    warn!("The CTF containing input path(s) don't contain any trace data");
+                }
+
+                if let Some(stream_id) = cfg.plugin.merge_stream_id {
+                    if !props.streams.contains_key(&stream_id) {
+                        return Err(modality_ctf::error::Error::MergeStreamIdNotFound.into());
+                    }
+                }
+
+                timeline_registry
+                    .register_timelines(
+                        cfg,
+                        &props,
+                        &mut client,
+                        &mut last_timeline_ordering_val,
+                        diagnostics.drops_mut(),
+                    )
+                    .await?;
+                if let Some(record) = &record {
+                    for stream in record.streams.iter() {
+                        let tid = TimelineId::from(stream.timeline_id);
+                        if let Some(ordering) = last_timeline_ordering_val.get_mut(&tid) {
+                            *ordering = (*ordering).max(stream.next_ordering);
+                        }
+                        // An interrupted import resumes after its last imported events
+                        if cfg.plugin.import.dedup_snapshots || record.partial {
+                            dedup_marks
+                                .insert((source, stream.stream_id), StreamMark::from(stream));
+                        }
+                        stream_marks
+                            .entry((source, stream.stream_id))
+                            .or_insert_with(|| StreamMark::from(stream));
+                    }
+                }
+                remote_timelines.learn(&props);
+                source_props.insert(source, props);
+                continue;
+            }
+            SourceMessage::Event(source, event) => (source, event),
+            SourceMessage::Error(e) => return Err(e.into()),
+        };
+
+        // Each decoder sends its properties before any of its events
+        let props = match source_props.get(&source) {
+            Some(props) => props,
+            None => continue,
+        };
+
+        if let Some(min_log_level) = cfg.plugin.min_log_level {
+            if let Some(ll) = EventLogLevel::from_event(&event) {
+                if !ll.is_at_least(min_log_level) {
+                    continue;
+                }
+            }
         }
-        let event = maybe_event?;
 
         let event_stream_id = if let Some(merge_stream_id) = cfg.plugin.merge_stream_id {
             merge_stream_id
@@ -251,30 +836,706 @@ async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
         let timeline_id = match props.streams.get(&event_stream_id).map(|s| s.timeline_id()) {
             Some(tid) => tid,
             None => {
-                warn!(
+                debug!(
                     "Dropping event ID {} because it's stream ID was not reported in the metadata",
                     event.class_properties.id
                 );
+                diagnostics.dropped(DropReason::UnknownStream);
                 continue;
             }
         };
 
+        if let Some(percent) = progress.observe(
+            source,
+            event.stream_id,
+            event.clock_snapshot,
+            PROGRESS_STEP_PERCENT,
+        ) {
+            info!("Imported {percent}% of the indexed trace data");
+        }
+
+        // Events of overlapping snapshots that were already imported
+        let packet_seq_num = packet_context_seq_num(&event);
+        if let Some(mark) = dedup_marks.get(&(source, event.stream_id)) {
+            if mark.covers(packet_seq_num, event.clock_snapshot) {
+                dedup_skipped_events += 1;
+                continue;
+            }
+        }
+
         let ordering = match last_timeline_ordering_val.get_mut(&timeline_id) {
             Some(ord) => ord,
             None => {
-                warn!(
+                debug!(
                     "Dropping event ID {} because it's timeline ID was not registered",
                     event.class_properties.id
                 );
+                diagnostics.dropped(DropReason::UnregisteredTimeline);
                 continue;
             }
         };
 
-        let event = CtfEvent::new(&event, &mut client).await?;
-        client.c.open_timeline(timeline_id).await?;
-        client.c.event(*ordering, event.attr_kvs()).await?;
-        *ordering += 1;
-        client.c.close_timeline();
+        let stream_props = props.streams.get(&event.stream_id);
+        let packet_timestamps_ns = stream_props
+            .map(|s| s.packet_timestamps_ns(&event))
+            .unwrap_or_default();
+        if track_messages {
+            let messages = message_tracker.observe(timeline_id, &event, packet_timestamps_ns);
+            if messages
+                .iter()
+                .any(|m| cfg.plugin.message_handling.action(m.message_type) == MessageAction::Event)
+            {
+                timeline_registry
+                    .open_timeline(timeline_id, diagnostics.drops_mut(), &mut client)
+                    .await?;
+            }
+            handle_messages(
+                &cfg.plugin.message_handling,
+                timeline_id,
+                &messages,
+                ordering,
+                diagnostics.drops_mut(),
+                &mut client,
+            )
+            .await?;
+        }
+        // In-band timeline attrs apply to the emitting stream's timeline
+        if timeline_attrs_event.is_some()
+            && event.class_properties.name.as_deref() == timeline_attrs_event
+        {
+            let mut attrs: HashMap<_, _> = in_band_timeline_attr_kvs(&event, &mut client)
+                .await?
+                .into_iter()
+                .collect();
+            attrs.extend(timeline_registry.override_attrs().iter().cloned());
+            timeline_registry
+                .open_timeline(timeline_id, diagnostics.drops_mut(), &mut client)
+                .await?;
+            client.timeline_metadata(attrs).await?;
+            continue;
+        }
+        match cfg.plugin.message_handling.event {
+            MessageAction::Event => (),
+            MessageAction::Log => {
+                info!(
+                    stream_id = event.stream_id,
+                    "Babeltrace event message '{}'",
+                    event.class_properties.name.as_deref().unwrap_or_default()
+                );
+                continue;
+            }
+            MessageAction::Ignore => continue,
+        }
+
+        stream_marks
+            .entry((source, event.stream_id))
+            .or_default()
+            .advance(packet_seq_num, event.clock_snapshot);
+        let sampled = sampler.sample(timeline_id, &event);
+        if sampled == Sampled::Dropped {
+            diagnostics.dropped(DropReason::Sampled);
+            continue;
+        }
+        if let Some(max) = cfg.plugin.max_events_per_timeline {
+            let stats = timeline_stats.entry(timeline_id).or_default();
+            if stats.event_count() >= max {
+                if stats.capped_event_count() == 0 {
+                    warn!("Timeline {timeline_id} reached the max-events-per-timeline of {max}, dropping its further events");
+                    let marker = CtfEvent::timeline_capped(max, &mut client).await?;
+                    timeline_registry
+                        .open_timeline(timeline_id, diagnostics.drops_mut(), &mut client)
+                        .await?;
+                    if !client.send_event(*ordering, marker.attr_kvs()).await? {
+                        diagnostics.dropped(DropReason::IngestError);
+                    }
+                    *ordering += 1;
+                }
+                stats.record_capped();
+                diagnostics.dropped(DropReason::TimelineCapped);
+                continue;
+            }
+        }
+        let cpu_id = packet_context_cpu_id(&event, &cfg.plugin.event_mapping);
+        let new_clock_correction_ns = clock_sync.observe(props.trace_uuid, &event);
+        let timestamp_offset_ns = stream_props.and_then(|s| {
+            s.timestamp_offset_ns(clock_sync.correction_ns(props.trace_uuid, event.stream_id))
+        });
+        let event_mapping = stream_event_mappings.get(
+            &cfg.plugin,
+            event.stream_id,
+            stream_props.map(|s| s.stream_name()),
+        );
+        let mut ctf_event = CtfEvent::new(
+            &event,
+            event_mapping,
+            &symbolizer,
+            remote_timelines.for_trace(props.trace_uuid),
+            &field_mappers,
+            diagnostics.drops_mut(),
+            &mut client,
+        )
+        .await?;
+        ctf_event
+            .packet_timestamps(packet_timestamps_ns, &mut client)
+            .await?;
+        if let Some(offset) = timestamp_offset_ns {
+            ctf_event.offset_timestamp(offset, &mut client).await?;
+        }
+        if let Some(uri) = stream_props.and_then(|s| s.source_uri()) {
+            ctf_event.source_uri(uri, &mut client).await?;
+        }
+        if let Sampled::Kept { rate } = sampled {
+            ctf_event.sample_rate(rate, &mut client).await?;
+        }
+        if cfg.plugin.timestamp_precision_attrs {
+            if let Some(ns) = stream_props.and_then(|s| s.timestamp_precision_ns()) {
+                ctf_event.timestamp_precision(ns, &mut client).await?;
+            }
+        }
+        if let Some(uuid) = props.metadata_trace_uuid.filter(|_| tag_trace_uuid) {
+            ctf_event.trace_uuid(uuid, &mut client).await?;
+        }
+        if !interaction_pairs.is_empty() {
+            let timestamp_ns = ctf_event.timestamp_ns(&mut client).await?;
+            if let Some(remote) = interaction_pairs.pair(&event, timeline_id, timestamp_ns) {
+                ctf_event.remote_event(remote, &mut client).await?;
+            }
+        }
+        if !ctf_event
+            .transform(&mut event_transform, diagnostics.drops_mut(), &mut client)
+            .await?
+        {
+            continue;
+        }
+        ctf_event.apply_limits(&cfg.plugin.attr_limits, diagnostics.drops_mut(), &client);
+        if let Some(report) = mapping_report.as_mut() {
+            report.record(
+                &event,
+                event_mapping,
+                &symbolizer,
+                remote_timelines.for_trace(props.trace_uuid),
+                &ctf_event,
+                &client,
+            )?;
+        }
+        timeline_registry
+            .open_timeline(timeline_id, diagnostics.drops_mut(), &mut client)
+            .await?;
+
+        if let Some(correction) = new_clock_correction_ns {
+            let attrs = clock::correction_attr_kvs(
+                correction,
+                clock::CLOCK_CORRECTION_SOURCE_EVENT,
+                &mut client,
+            )
+            .await?;
+            client.timeline_metadata(attrs).await?;
+        }
+
+        // Per-CPU streams get their CPU as a timeline attr once it's known
+        if cfg.plugin.merge_stream_id.is_none() {
+            if let Some(cpu) = cpu_id {
+                if timelines_with_cpu.insert(timeline_id) {
+                    let key = client.interned_timeline_key(TimelineAttrKey::Cpu).await?;
+                    client
+                        .timeline_metadata([(key, modality_api::BigInt::new_attr_val(cpu.into()))])
+                        .await?;
+                }
+            }
+        }
+
+        let aggregated_class = event
+            .class_properties
+            .name
+            .as_deref()
+            .filter(|c| aggregator.is_aggregated(c));
+        if let Some(class) = aggregated_class {
+            let timestamp_ns = event.clock_snapshot.and_then(|ts| {
+                u64::try_from(i128::from(ts) + timestamp_offset_ns.unwrap_or(0)).ok()
+            });
+            // Aggregated events count as ingested, the bucket events carry them
+            timeline_stats
+                .entry(timeline_id)
+                .or_default()
+                .record(&event);
+            if let Some(bucket) =
+                aggregator.record(timeline_id, class, timestamp_ns, &ctf_event, &client)
+            {
+                let attrs = bucket.attr_kvs(&mut client).await?;
+                if !client.send_event(*ordering, attrs).await? {
+                    diagnostics.dropped(DropReason::IngestError);
+                }
+                *ordering += 1;
+            }
+        } else {
+            // Skipped events aren't counted as ingested
+            if client.send_event(*ordering, ctf_event.attr_kvs()).await? {
+                timeline_stats
+                    .entry(timeline_id)
+                    .or_default()
+                    .record(&event);
+            } else {
+                diagnostics.dropped(DropReason::IngestError);
+            }
+            *ordering += 1;
+        }
+        if let Some(url) = client.take_reconnected() {
+            diagnostics
+                .drops_mut()
+                .add(DropReason::ReconnectLoss, client.take_lost_events());
+            diagnostics
+                .record(
+                    DiagnosticEvent::Reconnected {
+                        url: url.to_string(),
+                    },
+                    &mut client,
+                )
+                .await?;
+        }
+    }
+
+    // The streams only end when the whole trace was read
+    if track_messages && !interruptor.is_set() {
+        for (tid, msg) in message_tracker.finish() {
+            if let Some(ordering) = last_timeline_ordering_val.get_mut(&tid) {
+                if cfg.plugin.message_handling.action(msg.message_type) == MessageAction::Event {
+                    timeline_registry
+                        .open_timeline(tid, diagnostics.drops_mut(), &mut client)
+                        .await?;
+                }
+                handle_messages(
+                    &cfg.plugin.message_handling,
+                    tid,
+                    &[msg],
+                    ordering,
+                    diagnostics.drops_mut(),
+                    &mut client,
+                )
+                .await?;
+            }
+        }
+    }
+    for (tid, bucket) in aggregator.finish() {
+        if let Some(ordering) = last_timeline_ordering_val.get_mut(&tid) {
+            let attrs = bucket.attr_kvs(&mut client).await?;
+            timeline_registry
+                .open_timeline(tid, diagnostics.drops_mut(), &mut client)
+                .await?;
+            if !client.send_event(*ordering, attrs).await? {
+                diagnostics.dropped(DropReason::IngestError);
+            }
+            *ordering += 1;
+        }
+    }
+    client.close_timeline();
+
+    if let Some(mut bench) = bench {
+        bench.finish(started.elapsed());
+        print!("{bench}");
+        return Ok(());
+    }
+
+    if timeline_registry.pending_count() != 0 {
+        info!(
+            "Skipped registering {} timelines without events",
+            timeline_registry.pending_count()
+        );
+    }
+
+    if dedup_skipped_events != 0 {
+        info!("Skipped {dedup_skipped_events} events already imported from a previous snapshot");
+    }
+
+    for (tid, stats) in timeline_stats.iter() {
+        let attrs = stats.attr_kvs(&mut client).await?;
+        client.open_timeline(*tid).await?;
+        client.timeline_metadata(attrs).await?;
+        client.close_timeline();
+    }
+
+    let ingested_events: u64 = timeline_stats.values().map(|s| s.event_count()).sum();
+    diagnostics.record_dropped(&mut client).await?;
+    client
+        .drain(
+            cfg.plugin.drain_timeout(),
+            &mut diagnostics,
+            interruptor.is_set(),
+            abandoned_events,
+            ingested_events,
+        )
+        .await?;
+
+    diagnostics.drops().log_summary();
+
+    if let (Some(report), Some(path)) = (&mapping_report, &cfg.plugin.import.mapping_report) {
+        std::fs::write(path, serde_json::to_string_pretty(report)?)?;
+        info!("Wrote the field mapping report to '{}'", path.display());
+    }
+
+    if let (Some(state), Some(state_file)) = (&mut state, &cfg.plugin.import.state_file) {
+        // The sources of an interrupted import are recorded as partial, except
+        // the ones that weren't started
+        let partial = interruptor.is_set();
+        for (source, (digest, inputs)) in source_digests
+            .into_iter()
+            .zip(source_inputs.into_iter())
+            .enumerate()
+        {
+            let props = source_props.get(&source);
+            if partial && props.is_none() {
+                continue;
+            }
+            let streams = props
+                .map(|p| {
+                    p.streams
+                        .iter()
+                        .map(|(stream_id, s)| {
+                            let mark = stream_marks
+                                .get(&(source, *stream_id))
+                                .copied()
+                                .unwrap_or_default();
+                            StreamRecord {
+                                stream_id: *stream_id,
+                                timeline_id: *s.timeline_id().get_raw(),
+                                next_ordering: last_timeline_ordering_val
+                                    .get(&s.timeline_id())
+                                    .copied()
+                                    .unwrap_or(0),
+                                last_packet_seq_num: mark.packet_seq_num,
+                                last_clock_snapshot: mark.clock_snapshot,
+                            }
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            state.insert(
+                digest,
+                TraceRecord {
+                    inputs,
+                    run_id: props.map(|p| p.trace.run_id()),
+                    trace_uuid: props.map(|p| p.trace_uuid),
+                    partial,
+                    streams,
+                },
+            );
+        }
+        state.store(state_file)?;
+    }
+
+    if cfg.plugin.fail_on_empty && ingested_events == 0 && !interruptor.is_set() {
+        return Err(Error::NoEventsIngested.into());
+    }
+
+    Ok(())
+}
+
+/// Messages produced by the babeltrace decoder threads, tagged with
+/// the index of the source they came from
+enum SourceMessage {
+    Properties(usize, TraceProperties, BTreeSet<StreamProperties>),
+    Event(usize, OwnedEvent),
+    Error(BabeltraceError),
+}
+
+const SOURCE_CHANNEL_CAPACITY: usize = 1024;
+
+/// The import progress is logged each time it gets this much further
+const PROGRESS_STEP_PERCENT: u64 = 10;
+
+/// libbabeltrace2 plugin loading and graph construction isn't thread-safe
+static BABELTRACE_INIT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Decode the sources on up to `parallelism` threads
+fn spawn_decoders(
+    sources: Vec<ImportConfig>,
+    clock_class: ClockClassConfig,
+    parallelism: usize,
+    log_level: LoggingLevel,
+    interruptor: Interruptor,
+) -> mpsc::Receiver<SourceMessage> {
+    let (tx, rx) = mpsc::channel(SOURCE_CHANNEL_CAPACITY);
+    let queue = Arc::new(Mutex::new(
+        sources.into_iter().enumerate().collect::<VecDeque<_>>(),
+    ));
+    for _ in 0..parallelism {
+        let queue = queue.clone();
+        let tx = tx.clone();
+        let interruptor = interruptor.clone();
+        thread::spawn(move || loop {
+            if interruptor.is_set() {
+                break;
+            }
+            let next = queue.lock().unwrap_or_else(|e| e.into_inner()).pop_front();
+            let (source, source_cfg) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            if let Err(e) = decode_source(
+                source,
+                &source_cfg,
+                &clock_class,
+                log_level,
+                &tx,
+                &interruptor,
+            ) {
+                let e = e.with_source(source_cfg.source_description(&clock_class));
+                let _ = tx.blocking_send(SourceMessage::Error(e));
+                break;
+            }
+        });
+    }
+    rx
+}
+
+/// Print the schema summary of the trace, with the attr keys the events
+/// would be ingested with
+fn inspect(
+    cfg: &CtfConfig,
+    rename_timeline_attr: &[AttrKeyRename],
+    rename_event_attr: &[AttrKeyRename],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let import_cfg = &cfg.plugin.import;
+    let clock_class = &cfg.plugin.clock_class;
+    let mut rename_timeline_attrs = rename_timeline_attr.to_vec();
+    rename_timeline_attrs.extend(cfg.plugin.rename_timeline_attrs.clone());
+    let mut rename_event_attrs = rename_event_attr.to_vec();
+    rename_event_attrs.extend(cfg.plugin.rename_event_attrs.clone());
+    // Only used for the attr key names, nothing is sent
+    let client = Client::offline(rename_timeline_attrs, rename_event_attrs);
+    let symbolizer = Symbolizer::from_config(&cfg.plugin.event_mapping)?;
+    let field_mappers = FieldMappers::registered();
+    let remote_timelines = RemoteTimelines::from_config(&cfg.plugin.event_mapping);
+
+    let ctf_params = import_cfg.ctf_fs_params(clock_class)?;
+    let trace_iter = CtfIterator::new(cfg.plugin.log_level.into(), &ctf_params)
+        .map_err(|e| source_error(e, import_cfg, clock_class))?;
+    let trace_uuid = trace_iter.trace_properties().uuid.unwrap_or_default();
+    let mut schema = TraceSchema::new(
+        trace_iter.trace_properties(),
+        trace_iter.stream_properties(),
+        &field_mappers,
+    );
+    for event in trace_iter {
+        schema.record(
+            &event?,
+            &cfg.plugin.event_mapping,
+            &symbolizer,
+            remote_timelines.for_trace(trace_uuid),
+            &client,
+        )?;
+    }
+    print!("{schema}");
+    Ok(())
+}
+
+/// The import configuration of each babeltrace source and how many to decode in parallel
+fn import_sources(
+    cfg: &CtfConfig,
+) -> Result<(Vec<ImportConfig>, usize), Box<dyn std::error::Error>> {
+    // Either all the inputs make up a single logical trace, or with parallel-inputs
+    // each input is an independent trace decoded by its own babeltrace iterator
+    let sources = match cfg.plugin.import.parallel_inputs {
+        Some(parallelism) => {
+            if cfg.plugin.trace_uuid.is_some() && cfg.plugin.import.inputs.len() > 1 {
+                warn!("Using a trace UUID override with parallel-inputs will produce the same timeline IDs for the streams of each input");
+            }
+            let sources = cfg
+                .plugin
+                .import
+                .inputs
+                .iter()
+                .map(|p| ImportConfig {
+                    inputs: vec![p.clone()],
+                    ..cfg.plugin.import.clone()
+                })
+                .collect::<Vec<_>>();
+            (sources, parallelism.max(1))
+        }
+        None => {
+            let groups = group_inputs_by_trace_uuid(&cfg.plugin.import.inputs)?;
+            if groups.iter().filter(|(uuid, _)| uuid.is_some()).count() <= 1 {
+                (vec![cfg.plugin.import.clone()], 1)
+            } else if cfg.plugin.import.group_inputs_by_trace_uuid {
+                let sources = groups
+                    .into_iter()
+                    .map(|(uuid, inputs)| {
+                        match uuid {
+                            Some(uuid) => info!("Importing input(s) {inputs:?} as trace {uuid}"),
+                            None => info!("Importing input(s) {inputs:?} without a trace UUID"),
+                        }
+                        ImportConfig {
+                            inputs,
+                            ..cfg.plugin.import.clone()
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                (sources, 1)
+            } else {
+                let conflicts = groups
+                    .iter()
+                    .map(|(uuid, inputs)| {
+                        let inputs = inputs
+                            .iter()
+                            .map(|p| p.display().to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        match uuid {
+                            Some(uuid) => format!("Trace UUID {uuid}: {inputs}"),
+                            None => format!("No trace UUID: {inputs}"),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                return Err(modality_ctf::error::Error::ConflictingTraceUuids(conflicts).into());
+            }
+        }
+    };
+    Ok(sources)
+}
+
+/// Print the timelines each source would create, without connecting to Modality
+fn list_timelines(
+    plugin_cfg: &PluginConfig,
+    sources: &[ImportConfig],
+) -> Result<(), Box<dyn std::error::Error>> {
+    for source_cfg in sources.iter() {
+        if sources.len() > 1 {
+            println!("Input(s) {:?}", source_cfg.inputs);
+        }
+        let ctf_params = source_cfg.ctf_fs_params(&plugin_cfg.clock_class)?;
+        let trace_iter = CtfIterator::new(plugin_cfg.log_level.into(), &ctf_params)
+            .map_err(|e| source_error(e, source_cfg, &plugin_cfg.clock_class))?;
+        let preview = TimelinesPreview::new(
+            plugin_cfg,
+            trace_iter.trace_properties(),
+            trace_iter.stream_properties(),
+        )?;
+        print!("{preview}");
+    }
+    Ok(())
+}
+
+/// Print the event statistics of the trace
+fn stats(
+    import_cfg: &ImportConfig,
+    clock_class: &ClockClassConfig,
+    log_level: LoggingLevel,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ctf_params = import_cfg.ctf_fs_params(clock_class)?;
+    let trace_iter = CtfIterator::new(log_level.into(), &ctf_params)
+        .map_err(|e| source_error(e, import_cfg, clock_class))?;
+    let mut stats = TraceStats::default();
+    for event in trace_iter {
+        stats.record(&event?);
+    }
+    print!("{stats}");
+    Ok(())
+}
+
+/// Why babeltrace can't open the input as a CTF trace, if it can't
+fn input_problem(
+    input: &Path,
+    import_cfg: &ImportConfig,
+    clock_class: &ClockClassConfig,
+    log_level: LoggingLevel,
+) -> Option<String> {
+    if !input.join("metadata").exists() {
+        return Some("no metadata file".to_owned());
+    }
+    let input_cfg = ImportConfig {
+        inputs: vec![input.to_owned()],
+        ..import_cfg.clone()
+    };
+    let opened = input_cfg
+        .ctf_fs_params(clock_class)
+        .map_err(|e| e.to_string())
+        .and_then(|ctf_params| {
+            let _guard = BABELTRACE_INIT_LOCK
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            CtfIterator::new(log_level.into(), &ctf_params).map_err(|e| e.to_string())
+        });
+    opened.err()
+}
+
+/// Describe the source in a babeltrace error, must be called on the thread
+/// that ran the failing call to capture the libbabeltrace2 error causes
+fn source_error(
+    e: babeltrace2_sys::Error,
+    source_cfg: &ImportConfig,
+    clock_class: &ClockClassConfig,
+) -> BabeltraceError {
+    BabeltraceError::from(e).with_source(source_cfg.source_description(clock_class))
+}
+
+fn decode_source(
+    source: usize,
+    source_cfg: &ImportConfig,
+    clock_class: &ClockClassConfig,
+    log_level: LoggingLevel,
+    tx: &mpsc::Sender<SourceMessage>,
+    interruptor: &Interruptor,
+) -> Result<(), BabeltraceError> {
+    let ctf_params = source_cfg.ctf_fs_params(clock_class)?;
+    let trace_iter = {
+        let _guard = BABELTRACE_INIT_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        CtfIterator::new(log_level.into(), &ctf_params)
+            .map_err(|e| source_error(e, source_cfg, clock_class))?
+    };
+
+    // In auto mode the clock classes are inspected first, and the source
+    // is opened again if the origin needs to be forced
+    let forced_params;
+    let mut forced_unix_epoch_origin =
+        clock_class.force_clock_class_origin_unix_epoch == Some(UnixEpochOrigin::Enabled);
+    let trace_iter = if clock_class.force_clock_class_origin_unix_epoch
+        == Some(UnixEpochOrigin::Auto)
+        && clock::auto_unix_epoch_origin(
+            trace_iter.trace_properties(),
+            trace_iter.stream_properties(),
+        ) {
+        info!(
+            "Forcing a Unix epoch clock class origin for input(s) {:?}",
+            source_cfg.inputs
+        );
+        drop(trace_iter);
+        forced_unix_epoch_origin = true;
+        forced_params = source_cfg.ctf_fs_params(&ClockClassConfig {
+            force_clock_class_origin_unix_epoch: UnixEpochOrigin::Enabled.into(),
+            ..*clock_class
+        })?;
+        let _guard = BABELTRACE_INIT_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        CtfIterator::new(log_level.into(), &forced_params)
+            .map_err(|e| source_error(e, source_cfg, clock_class))?
+    } else {
+        trace_iter
+    };
+    clock::warn_implausible_wall_clock(trace_iter.stream_properties(), forced_unix_epoch_origin);
+
+    let props = SourceMessage::Properties(
+        source,
+        trace_iter.trace_properties().clone(),
+        trace_iter.stream_properties().clone(),
+    );
+    if tx.blocking_send(props).is_err() {
+        // Receiver is gone, we're shutting down
+        return Ok(());
+    }
+
+    for maybe_event in trace_iter {
+        if interruptor.is_set() {
+            break;
+        }
+        if tx
+            .blocking_send(SourceMessage::Event(source, maybe_event?))
+            .is_err()
+        {
+            break;
+        }
     }
 
     Ok(())
@@ -1,22 +1,52 @@
 #![deny(warnings, clippy::all)]
 
-use babeltrace2_sys::{CtfPluginSourceLttnLiveInitParams, CtfStream, RunStatus};
+use babeltrace2_sys::{
+    CtfPluginSourceLttnLiveInitParams, CtfStream, OwnedEvent, RunStatus, StreamProperties,
+    TraceProperties,
+};
 use clap::Parser;
 use modality_api::types::TimelineId;
 use modality_ctf::{
-    config::AttrKeyRename,
+    aggregation::Aggregator,
+    client::connect_ingest,
+    clock::{self, ClockSyncTracker},
+    config::{parse_attr_key_rename, AttrKeyRename, LttngLiveConfig, StreamEventMappings},
+    control::{spawn_control_server, spawn_metrics_server, ControlSocketGuard, ControlState},
+    diagnostics::{DiagnosticEvent, Diagnostics, DropCounts, DropReason},
+    error::BabeltraceError,
+    event::{in_band_timeline_attr_kvs, packet_context_cpu_id},
+    healthcheck::{self, HealthCheckError},
+    interaction::{InteractionPairs, RemoteTimelines},
+    messages::{handle_messages, InferredMessage, PacketBoundaryTracker},
     prelude::*,
-    tracing::try_init_tracing_subscriber,
-    types::{RetryDurationUs, SessionNotFoundAction},
+    preview::TimelinesPreview,
+    profile::{detect::detect_from_trace_properties, ProfileRules},
+    reflector,
+    relayd::{self, SessionPattern, LTTNG_RELAYD_DEFAULT_PORT},
+    retry::RetryBackoff,
+    sampling::{Sampled, Sampler},
+    stats::TimelineStats,
+    symbols::Symbolizer,
+    timeline::TimelineRegistry,
+    tracing::{try_init_tracing_subscriber, BabeltraceLogBridge},
+    transform::EventTransform,
+    types::{
+        EventLogLevel, LoggingLevel, MessageAction, MetadataTimeoutAction, Pauser, Profile,
+        RetryDurationUs, SessionNotFoundAction, UnixEpochOrigin,
+    },
 };
-use modality_ingest_client::IngestClient;
 use socket2::{Domain, Socket, Type};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::ffi::CString;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{net, thread};
 use thiserror::Error;
-use tracing::{debug, warn};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::task::{JoinError, JoinSet, LocalSet};
+use tracing::{debug, info, warn};
 use url::Url;
 
 /// Import CTF trace data from files
@@ -35,6 +65,24 @@ pub struct Opts {
     #[clap(long, name = "duration µs")]
     pub retry_duration_us: Option<RetryDurationUs>,
 
+    /// Back off exponentially (with jitter) on consecutive retries, up to
+    /// max-retry-duration-us µs
+    #[clap(long, name = "max duration µs")]
+    pub max_retry_duration_us: Option<RetryDurationUs>,
+
+    /// Exit after this many consecutive retries
+    #[clap(long, name = "count")]
+    pub max_retries: Option<u64>,
+
+    /// Exit after the graph hasn't made progress for max-idle-duration-us µs
+    #[clap(long, name = "idle duration µs")]
+    pub max_idle_duration_us: Option<RetryDurationUs>,
+
+    /// Drop the events older than the newest one seen on their timeline by more
+    /// than this many milliseconds
+    #[clap(long, name = "horizon ms")]
+    pub stale_event_horizon_ms: Option<u64>,
+
     /// When the message iterator does not find the specified remote tracing
     /// session (SESSION part of the inputs parameter), do one of the following actions.
     /// * continue (default)
@@ -43,6 +91,84 @@ pub struct Opts {
     #[clap(long, verbatim_doc_comment, name = "action")]
     pub session_not_found_action: Option<SessionNotFoundAction>,
 
+    /// With session-not-found-action end, exit with code 66 (EX_NOINPUT) instead
+    /// of success when the session was never found
+    #[clap(long)]
+    pub require_session: bool,
+
+    /// Give up waiting for the CTF metadata of the session after this many seconds
+    #[clap(long, name = "metadata timeout s")]
+    pub metadata_timeout: Option<u64>,
+
+    /// What to do when the metadata timeout expires.
+    /// * fail (default)
+    /// * end
+    #[clap(long, verbatim_doc_comment, name = "metadata timeout action")]
+    pub on_metadata_timeout: Option<MetadataTimeoutAction>,
+
+    /// Start with ingestion paused, send SIGUSR2 to resume it.
+    /// SIGUSR1 pauses ingestion again, keeping the relay daemon connection open
+    #[clap(long)]
+    pub start_paused: bool,
+
+    /// Serve control commands (status, stats, flush, pause, resume, update-filters)
+    /// on this unix socket
+    #[clap(long, name = "socket path")]
+    pub control_socket: Option<PathBuf>,
+
+    /// Compute the ingest lag, the wall-clock time elapsed since the timestamp
+    /// of the newest ingested event, every lag-interval-ms milliseconds
+    #[clap(long, name = "lag interval ms")]
+    pub lag_interval_ms: Option<u64>,
+
+    /// Also emit the ingest lag as a heartbeat event each lag-interval-ms
+    #[clap(long)]
+    pub lag_heartbeat_events: bool,
+
+    /// When no events were sent for this many milliseconds, check the ingest
+    /// connection so middleboxes don't drop it while the session is quiet
+    #[clap(long, name = "keepalive interval ms")]
+    pub keepalive_interval_ms: Option<u64>,
+
+    /// When the ingest endpoint can't be reached at startup, keep retrying
+    /// (with backoff) for up to this many milliseconds instead of exiting
+    #[clap(long, name = "wait for ingest ms")]
+    pub wait_for_ingest_ms: Option<u64>,
+
+    /// Serve Prometheus metrics over HTTP on this address
+    #[clap(long, name = "metrics addr")]
+    pub metrics_listen_addr: Option<SocketAddr>,
+
+    /// When the URL's TGTHOST or SESSION has wildcards, look for new matching
+    /// sessions every session-discovery-interval-ms milliseconds
+    /// (default: 5000)
+    #[clap(long, name = "discovery interval ms")]
+    pub session_discovery_interval_ms: Option<u64>,
+
+    /// Check the configuration loads, the ingest endpoint is reachable and accepts
+    /// the auth token, and the relay daemon answers the sessions query, then exit
+    #[clap(long)]
+    pub healthcheck: bool,
+
+    /// Wait for the trace metadata from the relay daemon, print the timelines that
+    /// would be created, with their names, IDs, and clocks, then exit without
+    /// connecting to Modality
+    #[clap(long, conflicts_with = "healthcheck")]
+    pub list_timelines: bool,
+
+    /// Add offset-ns nanoseconds to the event timestamps, like a clock class offset
+    #[clap(long, name = "offset-ns")]
+    pub clock_class_offset_ns: Option<i64>,
+
+    /// Add offset-s seconds to the event timestamps, like a clock class offset
+    #[clap(long, name = "offset-s")]
+    pub clock_class_offset_s: Option<i64>,
+
+    /// Treat all the clock classes as having a Unix epoch origin.
+    /// Use 'auto' to decide from the trace's clock classes
+    #[clap(long, name = "unix-epoch")]
+    pub force_clock_class_origin_unix_epoch: Option<UnixEpochOrigin>,
+
     /// Rename a timeline attribute key as it is being imported. Specify as 'original_key,new_key'
     #[clap(long, name = "original.tl.attr,new.tl.attr", help_heading = "IMPORT CONFIGURATION", value_parser = parse_attr_key_rename)]
     pub rename_timeline_attr: Vec<AttrKeyRename>,
@@ -64,22 +190,15 @@ pub struct Opts {
     /// * SESSION
     ///   Name of the LTTng tracing session from which to receive data.
     ///
+    /// TGTHOST and SESSION may contain '*' and '?' wildcards, every matching
+    /// session is collected, including the ones that appear later.
+    ///
     /// Example: net://localhost/host/ubuntu-focal/my-kernel-session
+    /// Example: net://localhost/host/*/autotest-*
     #[clap(verbatim_doc_comment, name = "url")]
     pub url: Option<Url>,
 }
 
-fn parse_attr_key_rename(
-    s: &str,
-) -> Result<AttrKeyRename, Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let pos = s
-        .find(',')
-        .ok_or_else(|| format!("invalid original,new: no `,` found in `{s}`"))?;
-    let original = s[..pos].parse()?;
-    let new = s[pos + 1..].parse()?;
-    Ok(AttrKeyRename { original, new })
-}
-
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
@@ -90,10 +209,47 @@ pub enum Error {
 
     #[error("The CTF connection was established but the trace doesn't contain any stream data.")]
     EmptyCtfTrace,
+
+    #[error(transparent)]
+    Babeltrace(#[from] BabeltraceError),
+
+    #[error(
+        "Gave up running the libbabeltrace graph after {0} consecutive retries without progress."
+    )]
+    RetryLimitReached(u64),
+
+    #[error("No events were ingested.")]
+    NoEventsIngested,
+
+    #[error("The LTTng tracing session of '{0}' wasn't found on the relay daemon.")]
+    SessionNotFound(String),
+
+    #[error("No CTF metadata arrived within the metadata timeout of {0:?}.")]
+    MetadataTimeout(Duration),
+
+    #[error("The relay daemon host name of the URL didn't resolve.")]
+    UnresolvedRelaydHost,
+}
+
+impl From<babeltrace2_sys::Error> for Error {
+    fn from(e: babeltrace2_sys::Error) -> Self {
+        Error::Babeltrace(e.into())
+    }
+}
+
+impl Error {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Error::RetryLimitReached(_) | Error::MetadataTimeout(_) => exitcode::TEMPFAIL,
+            Error::NoEventsIngested => exitcode::DATAERR,
+            Error::SessionNotFound(_) => exitcode::NOINPUT,
+            _ => exitcode::SOFTWARE,
+        }
+    }
 }
 
-const LTTNG_RELAYD_DEFAULT_PORT: u16 = 5344;
 const RELAYD_QUICK_PING_CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
+const RELAYD_LIST_SESSIONS_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[tokio::main]
 async fn main() {
@@ -106,15 +262,63 @@ async fn main() {
                 eprintln!("Caused by: {err}");
                 cause = err.source();
             }
-            std::process::exit(exitcode::SOFTWARE);
+            std::process::exit(exit_code(&*e));
         }
     }
 }
 
+fn exit_code(e: &(dyn std::error::Error + 'static)) -> i32 {
+    e.downcast_ref::<Error>()
+        .map(Error::exit_code)
+        .or_else(|| {
+            e.downcast_ref::<HealthCheckError>()
+                .map(HealthCheckError::exit_code)
+        })
+        .unwrap_or(exitcode::SOFTWARE)
+}
+
+/// How bad a failure is, the worst session failure decides the exit code
+/// when collecting several sessions. Unexpected failures are the worst.
+fn exit_code_severity(code: i32) -> u8 {
+    match code {
+        exitcode::TEMPFAIL => 1,
+        exitcode::DATAERR => 2,
+        exitcode::NOINPUT => 3,
+        _ => 4,
+    }
+}
+
 async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
     let opts = Opts::parse();
+    let log_level = opts.rf_opts.log_level_filter();
+
+    if opts.healthcheck {
+        let cfg = CtfConfig::load_merge_with_opts(opts.rf_opts, opts.bt_opts)
+            .map_err(|e| HealthCheckError::Config(e.to_string()))?;
+        try_init_tracing_subscriber(&cfg.plugin, log_level)?;
+        cfg.plugin.warn_unknown_keys();
+        let url = opts
+            .url
+            .or_else(|| cfg.plugin.lttng_live.url.clone())
+            .ok_or_else(|| HealthCheckError::Config(Error::MissingUrl.to_string()))?;
+        println!("config: ok");
+        healthcheck::check_ingest(&cfg).await?;
+        let action = opts
+            .session_not_found_action
+            .unwrap_or(cfg.plugin.lttng_live.session_not_found_action);
+        healthcheck::check_relayd(
+            &url,
+            action.0 != babeltrace2_sys::SessionNotFoundAction::Continue,
+        )?;
+        return Ok(());
+    }
+
+    // The log file settings come from the configuration, so it's loaded first
+    let mut cfg = CtfConfig::load_merge_with_opts(opts.rf_opts, opts.bt_opts)?;
+    try_init_tracing_subscriber(&cfg.plugin, log_level)?;
+    cfg.plugin.warn_unknown_keys();
 
-    try_init_tracing_subscriber()?;
+    let _bt_log_bridge = BabeltraceLogBridge::start(cfg.plugin.log_level)?;
 
     let intr = Interruptor::new();
     let interruptor = intr.clone();
@@ -127,22 +331,89 @@ async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
         }
     })?;
 
-    let mut cfg = CtfConfig::load_merge_with_opts(opts.rf_opts, opts.bt_opts)?;
+    // SIGUSR1 pauses ingestion, SIGUSR2 resumes it
+    let pauser = Pauser::new();
+    if opts.start_paused {
+        pauser.pause();
+    }
+    spawn_pause_signal_handlers(pauser.clone())?;
+
+    if cfg.is_reflector_child() {
+        reflector::exit_with_parent()?;
+        info!("Running as a reflector child plugin");
+    }
     if let Some(retry) = opts.retry_duration_us {
         cfg.plugin.lttng_live.retry_duration_us = retry;
     }
+    if let Some(max_retry) = opts.max_retry_duration_us {
+        cfg.plugin.lttng_live.max_retry_duration_us = max_retry.into();
+    }
+    if let Some(max_retries) = opts.max_retries {
+        cfg.plugin.lttng_live.max_retries = max_retries.into();
+    }
+    if let Some(max_idle) = opts.max_idle_duration_us {
+        cfg.plugin.lttng_live.max_idle_duration_us = max_idle.into();
+    }
+    if let Some(horizon) = opts.stale_event_horizon_ms {
+        cfg.plugin.lttng_live.stale_event_horizon_ms = horizon.into();
+    }
     if let Some(action) = opts.session_not_found_action {
         cfg.plugin.lttng_live.session_not_found_action = action;
     }
+    if opts.require_session {
+        cfg.plugin.lttng_live.require_session = true;
+    }
+    if let Some(timeout) = opts.metadata_timeout {
+        cfg.plugin.lttng_live.metadata_timeout_s = timeout.into();
+    }
+    if let Some(action) = opts.on_metadata_timeout {
+        cfg.plugin.lttng_live.on_metadata_timeout = action;
+    }
     if let Some(url) = opts.url {
         cfg.plugin.lttng_live.url = url.into();
     }
+    if let Some(path) = opts.control_socket {
+        cfg.plugin.lttng_live.control_socket = path.into();
+    }
+    if let Some(interval) = opts.lag_interval_ms {
+        cfg.plugin.lttng_live.lag_interval_ms = interval.into();
+    }
+    if opts.lag_heartbeat_events {
+        cfg.plugin.lttng_live.lag_heartbeat_events = true;
+    }
+    if let Some(interval) = opts.keepalive_interval_ms {
+        cfg.plugin.lttng_live.keepalive_interval_ms = interval.into();
+    }
+    if let Some(ms) = opts.wait_for_ingest_ms {
+        cfg.plugin.wait_for_ingest_ms = ms.into();
+    }
+    if let Some(addr) = opts.metrics_listen_addr {
+        cfg.plugin.lttng_live.metrics_listen_addr = addr.into();
+    }
+    if let Some(interval) = opts.session_discovery_interval_ms {
+        cfg.plugin.lttng_live.session_discovery_interval_ms = interval.into();
+    }
+    if let Some(ns) = opts.clock_class_offset_ns {
+        cfg.plugin.clock_class.clock_class_offset_ns = ns.into();
+    }
+    if let Some(s) = opts.clock_class_offset_s {
+        cfg.plugin.clock_class.clock_class_offset_s = s.into();
+    }
+    if let Some(ue) = opts.force_clock_class_origin_unix_epoch {
+        cfg.plugin.clock_class.force_clock_class_origin_unix_epoch = ue.into();
+    }
 
-    let mut rename_timeline_attrs = opts.rename_timeline_attr.clone();
-    rename_timeline_attrs.extend(cfg.plugin.rename_timeline_attrs.clone());
-
-    let mut rename_event_attrs = opts.rename_event_attr.clone();
-    rename_event_attrs.extend(cfg.plugin.rename_event_attrs.clone());
+    let control = ControlState::new(pauser.clone(), cfg.plugin.min_log_level);
+    let _control_socket_guard = match cfg.plugin.lttng_live.control_socket.as_ref() {
+        Some(path) => {
+            spawn_control_server(path, control.clone())?;
+            Some(ControlSocketGuard(path.clone()))
+        }
+        None => None,
+    };
+    if let Some(addr) = cfg.plugin.lttng_live.metrics_listen_addr {
+        spawn_metrics_server(addr, control.clone()).await?;
+    }
 
     let url = match cfg.plugin.lttng_live.url.as_ref() {
         Some(url) => url.clone(),
@@ -189,7 +460,7 @@ async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
                     break 'conn_loop;
                 } else {
                     // Keep trying
-                    thread::sleep(retry_duration);
+                    tokio::time::sleep(retry_duration).await;
                 }
             }
         } else {
@@ -197,50 +468,254 @@ async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    let url_cstring = CString::new(url.to_string().as_bytes())?;
-    let params = CtfPluginSourceLttnLiveInitParams::new(
-        &url_cstring,
-        Some(cfg.plugin.lttng_live.session_not_found_action.into()),
-    )?;
-    let mut ctf_stream = CtfStream::new(cfg.plugin.log_level.into(), &params)?;
+    let collector = Collector {
+        cfg,
+        list_timelines: opts.list_timelines,
+        rename_timeline_attr: opts.rename_timeline_attr,
+        rename_event_attr: opts.rename_event_attr,
+        control: control.clone(),
+        pauser,
+        interruptor,
+    };
+    let res = match SessionPattern::from_url(&url).filter(SessionPattern::has_wildcards) {
+        Some(pattern) => collect_matching_sessions(collector, url, pattern).await,
+        None => collect_session(collector, url).await,
+    };
+    control.drop_counts().log_summary();
+    res
+}
 
-    debug!("Waiting for CTF metadata");
+/// What the collection of each session shares
+#[derive(Clone, Debug)]
+struct Collector {
+    cfg: CtfConfig,
+    list_timelines: bool,
+    rename_timeline_attr: Vec<AttrKeyRename>,
+    rename_event_attr: Vec<AttrKeyRename>,
+    control: ControlState,
+    pauser: Pauser,
+    interruptor: Interruptor,
+}
 
-    // Loop until we get some metadata from the relayd
-    while !ctf_stream.has_metadata() {
-        if interruptor.is_set() {
-            return Ok(());
-        }
+/// Attach to every session matching the URL's TGTHOST and SESSION wildcards,
+/// re-discovering the relay daemon's sessions every session-discovery-interval-ms
+async fn collect_matching_sessions(
+    collector: Collector,
+    url: Url,
+    pattern: SessionPattern,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let addr = url
+        .socket_addrs(|| Some(LTTNG_RELAYD_DEFAULT_PORT))?
+        .into_iter()
+        .next()
+        .ok_or(Error::UnresolvedRelaydHost)?;
+    let interval = Duration::from_millis(
+        collector
+            .cfg
+            .plugin
+            .lttng_live
+            .session_discovery_interval_ms
+            .unwrap_or(LttngLiveConfig::DEFAULT_SESSION_DISCOVERY_INTERVAL_MS)
+            .max(1),
+    );
+    info!("Collecting the sessions matching '{pattern}'");
 
-        match ctf_stream.update()? {
-            RunStatus::Ok => (),
-            RunStatus::TryAgain => {
-                thread::sleep(retry_duration);
-                continue;
+    // The sessions are collected concurrently on this thread, each with its
+    // own babeltrace pump thread and ingest connection
+    let local = LocalSet::new();
+    local
+        .run_until(async move {
+            // Relay daemon session IDs, a re-created session gets a new one
+            let mut attached: HashSet<u64> = Default::default();
+            let mut sessions = JoinSet::new();
+            let mut worst = None;
+            let mut discovery = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = discovery.tick() => {
+                        if collector.interruptor.is_set() {
+                            break;
+                        }
+                        let listed = tokio::task::spawn_blocking(move || {
+                            relayd::list_sessions(&addr, RELAYD_LIST_SESSIONS_TIMEOUT)
+                        })
+                        .await?;
+                        match listed {
+                            Ok(listed) => {
+                                for s in listed.iter().filter(|s| pattern.matches(s)) {
+                                    if !attached.insert(s.id) {
+                                        continue;
+                                    }
+                                    let session_url = relayd::session_url(&url, s);
+                                    info!("Attaching to session '{session_url}'");
+                                    let collector = collector.clone();
+                                    sessions.spawn_local(async move {
+                                        let res =
+                                            collect_session(collector, session_url.clone()).await;
+                                        (session_url, res)
+                                    });
+                                }
+                            }
+                            Err(e) => warn!("Failed to list the relay daemon's sessions. {e}"),
+                        }
+
+                        // Only the sessions found up front are listed
+                        if collector.list_timelines {
+                            break;
+                        }
+                    }
+                    Some(done) = sessions.join_next() => keep_worst(&mut worst, log_session_end(done)),
+                }
             }
-            RunStatus::End => break,
+            while let Some(done) = sessions.join_next().await {
+                keep_worst(&mut worst, log_session_end(done));
+            }
+            // The sessions are independent, so every one is collected before
+            // the worst failure is reported
+            match worst {
+                Some(e) => Err(e),
+                None => Ok(()),
+            }
+        })
+        .await
+}
+
+type SessionResult = Result<(), Box<dyn std::error::Error>>;
+
+/// Log the end of a session, returning its failure
+fn log_session_end(
+    done: Result<(Url, SessionResult), JoinError>,
+) -> Option<Box<dyn std::error::Error>> {
+    match done {
+        Ok((url, Ok(()))) => {
+            info!("Session '{url}' ended");
+            None
+        }
+        Ok((url, Err(e))) => {
+            warn!("Session '{url}' ended with an error. {e}");
+            Some(e)
+        }
+        Err(e) => {
+            warn!("A session collection task failed. {e}");
+            Some(e.into())
         }
     }
+}
 
+fn keep_worst(
+    worst: &mut Option<Box<dyn std::error::Error>>,
+    failure: Option<Box<dyn std::error::Error>>,
+) {
+    let failure = match failure {
+        Some(e) => e,
+        None => return,
+    };
+    let is_worse = worst
+        .as_ref()
+        .map(|w| exit_code_severity(exit_code(&*failure)) > exit_code_severity(exit_code(&**w)))
+        .unwrap_or(true);
+    if is_worse {
+        *worst = Some(failure);
+    }
+}
+
+async fn collect_session(collector: Collector, url: Url) -> Result<(), Box<dyn std::error::Error>> {
+    let Collector {
+        mut cfg,
+        list_timelines,
+        rename_timeline_attr,
+        rename_event_attr,
+        control,
+        pauser,
+        interruptor,
+    } = collector;
+
+    let url_cstring = CString::new(url.to_string().as_bytes())?;
+    let mut live_messages = spawn_pump(
+        url_cstring,
+        cfg.plugin.lttng_live.clone(),
+        cfg.plugin.log_level,
+        cfg.plugin.message_handling.message_iterator_inactivity != MessageAction::Ignore,
+        pauser.clone(),
+        interruptor.clone(),
+    );
+
+    debug!("Waiting for CTF metadata");
+    let (trace_props, stream_props) = match live_messages.recv().await {
+        Some(LiveMessage::Properties(t, s)) => (t, s),
+        // Not an error unless required, the session may legitimately be gone
+        Some(LiveMessage::Error(Error::SessionNotFound(_)))
+            if !cfg.plugin.lttng_live.require_session =>
+        {
+            warn!("The session of '{url}' wasn't found, nothing was collected");
+            return Ok(());
+        }
+        Some(LiveMessage::Error(Error::MetadataTimeout(timeout)))
+            if cfg.plugin.lttng_live.on_metadata_timeout == MetadataTimeoutAction::End =>
+        {
+            warn!("No CTF metadata arrived from '{url}' within {timeout:?}, nothing was collected");
+            return Ok(());
+        }
+        Some(LiveMessage::Error(e)) => return Err(e.into()),
+        // Interrupted before the metadata arrived
+        Some(LiveMessage::Events(_)) | Some(LiveMessage::Inactivity(_)) | None => return Ok(()),
+    };
     debug!("Found CTF metadata");
 
-    if ctf_stream.stream_properties().is_empty() {
+    if stream_props.is_empty() {
         return Err(Error::EmptyCtfTrace.into());
     }
 
-    let c =
-        IngestClient::connect(&cfg.protocol_parent_url()?, cfg.ingest.allow_insecure_tls).await?;
-    let c_authed = c.authenticate(cfg.resolve_auth()?.into()).await?;
+    if cfg.plugin.profile == Profile::Auto {
+        let detection = detect_from_trace_properties(&trace_props, &Default::default());
+        detection.log();
+        ProfileRules::for_profile(detection.profile).merge_into(&mut cfg.plugin);
+    }
+
+    if list_timelines {
+        print!(
+            "{}",
+            TimelinesPreview::new(&cfg.plugin, &trace_props, &stream_props)?
+        );
+        return Ok(());
+    }
+
+    let mut rename_timeline_attrs = rename_timeline_attr;
+    rename_timeline_attrs.extend(cfg.plugin.rename_timeline_attrs.clone());
+
+    let mut rename_event_attrs = rename_event_attr;
+    rename_event_attrs.extend(cfg.plugin.rename_event_attrs.clone());
+
+    let (c_authed, ingest_url) = connect_ingest(&cfg).await?;
     let mut client = Client::new(c_authed, rename_timeline_attrs, rename_event_attrs);
+    if let Some(budget) = cfg.plugin.event_attr_key_budget() {
+        client.set_event_attr_key_budget(budget, cfg.plugin.strict_attr_key_budget);
+    }
+    client.set_reconnect(&cfg);
+    client.set_ingest_error_policy(
+        cfg.plugin.on_ingest_error,
+        cfg.plugin.ingest_error_retries(),
+    );
+    let mut diagnostics = Diagnostics::new(&cfg.plugin, env!("CARGO_BIN_NAME"), url.as_str());
+    diagnostics
+        .record(
+            DiagnosticEvent::Connected {
+                url: ingest_url.to_string(),
+            },
+            &mut client,
+        )
+        .await?;
 
-    let props = CtfProperties::new(
-        cfg.plugin.run_id,
-        cfg.plugin.trace_uuid,
-        ctf_stream.trace_properties(),
-        ctf_stream.stream_properties(),
-        &mut client,
-    )
-    .await?;
+    let mut props =
+        session_properties(&cfg.plugin, &trace_props, &stream_props, &mut client).await?;
+    if let Some(session) = SessionPattern::from_url(&url) {
+        props
+            .set_fallback_trace_name(session.to_string(), &mut client)
+            .await?;
+    }
+    if cfg.plugin.source_uri_attrs {
+        props.set_source_uri(url.as_str(), &mut client).await?;
+    }
 
     if let Some(stream_id) = cfg.plugin.merge_stream_id {
         if !props.streams.contains_key(&stream_id) {
@@ -249,95 +724,195 @@ async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     let mut last_timeline_ordering_val: HashMap<TimelineId, u128> = Default::default();
+    let mut timeline_stats: HashMap<TimelineId, TimelineStats> = Default::default();
+    let mut timelines_with_cpu: HashSet<TimelineId> = Default::default();
+    let mut clock_sync = ClockSyncTracker::new(&cfg.plugin);
+    let track_messages = cfg.plugin.message_handling.tracks_packet_boundaries();
+    let mut message_tracker = PacketBoundaryTracker::default();
+    let symbolizer = Symbolizer::from_config(&cfg.plugin.event_mapping)?;
+    let field_mappers = FieldMappers::registered();
+    let timeline_attrs_event = cfg.plugin.timeline_attrs_event.as_deref();
+    let mut remote_timelines = RemoteTimelines::from_config(&cfg.plugin.event_mapping);
+    let mut interaction_pairs = InteractionPairs::from_config(&cfg.plugin);
+    remote_timelines.learn(&props);
+    let mut event_transform = EventTransform::from_config(&cfg.plugin.event_mapping)?;
+    let mut sampler = Sampler::from_config(&cfg.plugin.sampling);
+    let mut aggregator = Aggregator::from_config(&cfg.plugin.aggregations);
+    let mut stream_event_mappings = StreamEventMappings::default();
 
-    let mut additional_timeline_attributes = Vec::with_capacity(
-        cfg.ingest
-            .timeline_attributes
-            .additional_timeline_attributes
-            .len(),
-    );
-    for kv in cfg
-        .ingest
-        .timeline_attributes
-        .additional_timeline_attributes
-        .iter()
-    {
-        additional_timeline_attributes.push((
-            client
-                .interned_timeline_key(TimelineAttrKey::Custom(kv.0.to_string()))
-                .await?,
-            kv.1.clone(),
-        ));
-    }
+    let mut timeline_registry =
+        TimelineRegistry::new(env!("CARGO_BIN_NAME"), &cfg, &sampler, &mut client).await?;
 
-    if let Some(stream_id) = cfg.plugin.merge_stream_id {
-        additional_timeline_attributes.push((
-            client
-                .interned_timeline_key(TimelineAttrKey::MergeStreamId)
-                .await?,
-            modality_api::BigInt::new_attr_val(stream_id.into()),
-        ));
-    }
-
-    let mut override_timeline_attributes = Vec::with_capacity(
-        cfg.ingest
-            .timeline_attributes
-            .override_timeline_attributes
-            .len(),
-    );
-    for kv in cfg
-        .ingest
-        .timeline_attributes
-        .override_timeline_attributes
-        .iter()
-    {
-        override_timeline_attributes.push((
-            client
-                .interned_timeline_key(TimelineAttrKey::Custom(kv.0.to_string()))
-                .await?,
-            kv.1.clone(),
-        ));
-    }
-
-    for (stream_id, tid, attr_kvs) in props.timelines() {
-        if let Some(merge_stream_id) = cfg.plugin.merge_stream_id {
-            if stream_id != merge_stream_id {
+    timeline_registry
+        .register_timelines(
+            &cfg,
+            &props,
+            &mut client,
+            &mut last_timeline_ordering_val,
+            diagnostics.drops_mut(),
+        )
+        .await?;
+
+    // Loop until user-signaled-exit or server-side-signaled-done
+    let mut drain_deadline = None;
+    let mut abandoned_events: u64 = 0;
+    let stale_event_horizon_ns = cfg
+        .plugin
+        .lttng_live
+        .stale_event_horizon_ms
+        .map(|ms| i64::try_from(ms.saturating_mul(1_000_000)).unwrap_or(i64::MAX));
+    let mut newest_timestamps: HashMap<TimelineId, i64> = Default::default();
+    let mut lag_ticker = cfg
+        .plugin
+        .lttng_live
+        .lag_interval_ms
+        .map(|ms| tokio::time::interval(Duration::from_millis(ms.max(1))));
+    let keepalive_interval = cfg
+        .plugin
+        .lttng_live
+        .keepalive_interval_ms
+        .map(|ms| Duration::from_millis(ms.max(1)));
+    let mut keepalive_ticker = keepalive_interval.map(tokio::time::interval);
+    let mut last_sent = Instant::now();
+    let mut newest_event: Option<(i128, TimelineId)> = None;
+    loop {
+        let msg = tokio::select! {
+            msg = live_messages.recv() => msg,
+            _ = tick(&mut lag_ticker) => {
+                if let Some((newest_ns, timeline_id)) = newest_event {
+                    report_ingest_lag(
+                        &cfg,
+                        &control,
+                        newest_ns,
+                        timeline_id,
+                        &mut client,
+                        &mut last_timeline_ordering_val,
+                        diagnostics.drops_mut(),
+                    )
+                    .await?;
+                }
                 continue;
             }
-        }
+            _ = tick(&mut keepalive_ticker) => {
+                if keepalive_interval.map(|i| last_sent.elapsed() >= i).unwrap_or(false) {
+                    client.keepalive().await?;
+                    if let Some(url) = client.take_reconnected() {
+                        diagnostics
+                            .drops_mut()
+                            .add(DropReason::ReconnectLoss, client.take_lost_events());
+                        diagnostics
+                            .record(
+                                DiagnosticEvent::Reconnected {
+                                    url: url.to_string(),
+                                },
+                                &mut client,
+                            )
+                            .await?;
+                    }
+                    last_sent = Instant::now();
+                }
+                continue;
+            }
+        };
+        let msg = match msg {
+            Some(msg) => msg,
+            None => break,
+        };
+        let events = match msg {
+            LiveMessage::Events(events) => events,
+            LiveMessage::Error(e) => return Err(e.into()),
 
-        let mut attrs = HashMap::new();
-        for (k, v) in attr_kvs
-            .into_iter()
-            .chain(additional_timeline_attributes.clone().into_iter())
-            .chain(override_timeline_attributes.clone().into_iter())
-        {
-            attrs.insert(k, v);
-        }
+            // Inactivity isn't specific to a stream, its event goes on the
+            // timeline of the newest event
+            LiveMessage::Inactivity(now_ns) => {
+                let msg = InferredMessage::inactivity(now_ns);
+                let newest = newest_event.and_then(|(_, tid)| {
+                    last_timeline_ordering_val
+                        .get_mut(&tid)
+                        .map(|ordering| (tid, ordering))
+                });
+                match newest {
+                    Some((tid, ordering)) => {
+                        handle_messages(
+                            &cfg.plugin.message_handling,
+                            tid,
+                            &[msg],
+                            ordering,
+                            diagnostics.drops_mut(),
+                            &mut client,
+                        )
+                        .await?
+                    }
+                    None if cfg.plugin.message_handling.message_iterator_inactivity
+                        == MessageAction::Log =>
+                    {
+                        info!("{msg}")
+                    }
+                    None => (),
+                }
+                continue;
+            }
 
-        client.c.open_timeline(tid).await?;
-        client.c.timeline_metadata(attrs).await?;
-        last_timeline_ordering_val.insert(tid, 0);
-    }
+            // The trace and stream properties can change mid-session, e.g. when the
+            // traced session is restarted or new streams are added
+            LiveMessage::Properties(trace_props, stream_props) => {
+                info!("CTF trace properties changed, refreshing timeline metadata");
 
-    // Loop until user-signaled-exit or server-side-signaled-done
-    loop {
-        if interruptor.is_set() {
-            break;
-        }
+                // Keep the run ID and any generated trace UUID stable across refreshes
+                let mut refresh_cfg = cfg.plugin.clone();
+                refresh_cfg.run_id = props.trace.run_id().into();
+                refresh_cfg.trace_uuid = cfg
+                    .plugin
+                    .trace_uuid
+                    .or(trace_props.uuid)
+                    .or(Some(props.trace_uuid));
+                props = session_properties(&refresh_cfg, &trace_props, &stream_props, &mut client)
+                    .await?;
+                if let Some(session) = SessionPattern::from_url(&url) {
+                    props
+                        .set_fallback_trace_name(session.to_string(), &mut client)
+                        .await?;
+                }
+                remote_timelines.learn(&props);
+                if cfg.plugin.source_uri_attrs {
+                    props.set_source_uri(url.as_str(), &mut client).await?;
+                }
 
-        match ctf_stream.update()? {
-            RunStatus::Ok => (),
-            RunStatus::TryAgain => {
-                thread::sleep(retry_duration);
+                timeline_registry
+                    .register_timelines(
+                        &cfg,
+                        &props,
+                        &mut client,
+                        &mut last_timeline_ordering_val,
+                        diagnostics.drops_mut(),
+                    )
+                    .await?;
                 continue;
             }
-            RunStatus::End => break,
-        }
+        };
 
-        for event in ctf_stream.events_chunk() {
+        let min_log_level = control.min_log_level();
+        for event in events.into_iter() {
+            // On interrupt the pump stops, and the events it already received
+            // are still ingested until the drain timeout
             if interruptor.is_set() {
-                break;
+                let deadline = *drain_deadline.get_or_insert_with(|| {
+                    info!("Interrupted, draining the received events");
+                    Instant::now() + cfg.plugin.drain_timeout()
+                });
+                if Instant::now() >= deadline {
+                    abandoned_events += 1;
+                    diagnostics.dropped(DropReason::DrainTimeout);
+                    continue;
+                }
+            }
+
+            if let Some(min_log_level) = min_log_level {
+                if let Some(ll) = EventLogLevel::from_event(&event) {
+                    if !ll.is_at_least(min_log_level) {
+                        continue;
+                    }
+                }
             }
 
             let event_stream_id = if let Some(merge_stream_id) = cfg.plugin.merge_stream_id {
@@ -349,33 +924,570 @@ async fn do_main() -> Result<(), Box<dyn std::error::Error>> {
             let timeline_id = match props.streams.get(&event_stream_id).map(|s| s.timeline_id()) {
                 Some(tid) => tid,
                 None => {
-                    warn!(
+                    debug!(
                         "Dropping event ID {} because it's stream ID was not reported in the metadata",
                         event.class_properties.id
                     );
+                    diagnostics.dropped(DropReason::UnknownStream);
                     continue;
                 }
             };
 
+            if let (Some(horizon), Some(ts)) = (stale_event_horizon_ns, event.clock_snapshot) {
+                let newest = newest_timestamps.entry(timeline_id).or_insert(ts);
+                if ts > *newest {
+                    *newest = ts;
+                } else if newest.saturating_sub(ts) > horizon {
+                    diagnostics.dropped(DropReason::Stale);
+                    continue;
+                }
+            }
+
             let ordering = match last_timeline_ordering_val.get_mut(&timeline_id) {
                 Some(ord) => ord,
                 None => {
-                    warn!(
+                    debug!(
                         "Dropping event ID {} because it's timeline ID was not registered",
                         event.class_properties.id
                     );
+                    diagnostics.dropped(DropReason::UnregisteredTimeline);
                     continue;
                 }
             };
 
-            let event = CtfEvent::new(&event, &mut client).await?;
-            client.c.open_timeline(timeline_id).await?;
-            client.c.event(*ordering, event.attr_kvs()).await?;
+            let stream_props = props.streams.get(&event.stream_id);
+            let packet_timestamps_ns = stream_props
+                .map(|s| s.packet_timestamps_ns(&event))
+                .unwrap_or_default();
+            if track_messages {
+                let messages = message_tracker.observe(timeline_id, &event, packet_timestamps_ns);
+                handle_messages(
+                    &cfg.plugin.message_handling,
+                    timeline_id,
+                    &messages,
+                    ordering,
+                    diagnostics.drops_mut(),
+                    &mut client,
+                )
+                .await?;
+            }
+            // In-band timeline attrs apply to the emitting stream's timeline
+            if timeline_attrs_event.is_some()
+                && event.class_properties.name.as_deref() == timeline_attrs_event
+            {
+                let mut attrs: HashMap<_, _> = in_band_timeline_attr_kvs(&event, &mut client)
+                    .await?
+                    .into_iter()
+                    .collect();
+                attrs.extend(timeline_registry.override_attrs().iter().cloned());
+                client.open_timeline(timeline_id).await?;
+                client.timeline_metadata(attrs).await?;
+                continue;
+            }
+            match cfg.plugin.message_handling.event {
+                MessageAction::Event => (),
+                MessageAction::Log => {
+                    info!(
+                        stream_id = event.stream_id,
+                        "Babeltrace event message '{}'",
+                        event.class_properties.name.as_deref().unwrap_or_default()
+                    );
+                    continue;
+                }
+                MessageAction::Ignore => continue,
+            }
+
+            let sampled = sampler.sample(timeline_id, &event);
+            if sampled == Sampled::Dropped {
+                diagnostics.dropped(DropReason::Sampled);
+                continue;
+            }
+            if let Some(max) = cfg.plugin.max_events_per_timeline {
+                let stats = timeline_stats.entry(timeline_id).or_default();
+                if stats.event_count() >= max {
+                    if stats.capped_event_count() == 0 {
+                        warn!("Timeline {timeline_id} reached the max-events-per-timeline of {max}, dropping its further events");
+                        let marker = CtfEvent::timeline_capped(max, &mut client).await?;
+                        client.open_timeline(timeline_id).await?;
+                        if !client.send_event(*ordering, marker.attr_kvs()).await? {
+                            diagnostics.dropped(DropReason::IngestError);
+                        }
+                        *ordering += 1;
+                    }
+                    stats.record_capped();
+                    diagnostics.dropped(DropReason::TimelineCapped);
+                    continue;
+                }
+            }
+            let cpu_id = packet_context_cpu_id(&event, &cfg.plugin.event_mapping);
+            let new_clock_correction_ns = clock_sync.observe(props.trace_uuid, &event);
+            let timestamp_offset_ns = stream_props.and_then(|s| {
+                s.timestamp_offset_ns(clock_sync.correction_ns(props.trace_uuid, event.stream_id))
+            });
+            if let Some(ts) = event.clock_snapshot {
+                let ts = i128::from(ts) + timestamp_offset_ns.unwrap_or(0);
+                if newest_event.map(|(newest, _)| ts > newest).unwrap_or(true) {
+                    newest_event = Some((ts, timeline_id));
+                }
+            }
+            let event_mapping = stream_event_mappings.get(
+                &cfg.plugin,
+                event.stream_id,
+                stream_props.map(|s| s.stream_name()),
+            );
+            let aggregated_class = event
+                .class_properties
+                .name
+                .clone()
+                .filter(|c| aggregator.is_aggregated(c));
+            let clock_snapshot = event.clock_snapshot;
+            let mut ctf_event = CtfEvent::new(
+                &event,
+                event_mapping,
+                &symbolizer,
+                remote_timelines.for_trace(props.trace_uuid),
+                &field_mappers,
+                diagnostics.drops_mut(),
+                &mut client,
+            )
+            .await?;
+            ctf_event
+                .packet_timestamps(packet_timestamps_ns, &mut client)
+                .await?;
+            if let Some(offset) = timestamp_offset_ns {
+                ctf_event.offset_timestamp(offset, &mut client).await?;
+            }
+            if let Some(uri) = stream_props.and_then(|s| s.source_uri()) {
+                ctf_event.source_uri(uri, &mut client).await?;
+            }
+            if let Sampled::Kept { rate } = sampled {
+                ctf_event.sample_rate(rate, &mut client).await?;
+            }
+            if cfg.plugin.timestamp_precision_attrs {
+                if let Some(ns) = stream_props.and_then(|s| s.timestamp_precision_ns()) {
+                    ctf_event.timestamp_precision(ns, &mut client).await?;
+                }
+            }
+            // A session can span several traces, e.g. with per-user buffers
+            if let Some(uuid) = props.metadata_trace_uuid {
+                ctf_event.trace_uuid(uuid, &mut client).await?;
+            }
+            if !interaction_pairs.is_empty() {
+                let timestamp_ns = ctf_event.timestamp_ns(&mut client).await?;
+                if let Some(remote) = interaction_pairs.pair(&event, timeline_id, timestamp_ns) {
+                    ctf_event.remote_event(remote, &mut client).await?;
+                }
+            }
+            if !ctf_event
+                .transform(&mut event_transform, diagnostics.drops_mut(), &mut client)
+                .await?
+            {
+                continue;
+            }
+            ctf_event.apply_limits(&cfg.plugin.attr_limits, diagnostics.drops_mut(), &client);
+            client.open_timeline(timeline_id).await?;
+
+            if let Some(correction) = new_clock_correction_ns {
+                let attrs = clock::correction_attr_kvs(
+                    correction,
+                    clock::CLOCK_CORRECTION_SOURCE_EVENT,
+                    &mut client,
+                )
+                .await?;
+                client.timeline_metadata(attrs).await?;
+            }
+
+            // Per-CPU streams get their CPU as a timeline attr once it's known
+            if cfg.plugin.merge_stream_id.is_none() {
+                if let Some(cpu) = cpu_id {
+                    if timelines_with_cpu.insert(timeline_id) {
+                        let key = client.interned_timeline_key(TimelineAttrKey::Cpu).await?;
+                        client
+                            .timeline_metadata([(
+                                key,
+                                modality_api::BigInt::new_attr_val(cpu.into()),
+                            )])
+                            .await?;
+                    }
+                }
+            }
+
+            if let Some(class) = aggregated_class.as_deref() {
+                let timestamp_ns = clock_snapshot.and_then(|ts| {
+                    u64::try_from(i128::from(ts) + timestamp_offset_ns.unwrap_or(0)).ok()
+                });
+                // Aggregated events count as ingested, the bucket events carry them
+                timeline_stats
+                    .entry(timeline_id)
+                    .or_default()
+                    .record(&event);
+                if let Some(bucket) =
+                    aggregator.record(timeline_id, class, timestamp_ns, &ctf_event, &client)
+                {
+                    let attrs = bucket.attr_kvs(&mut client).await?;
+                    if !client.send_event(*ordering, attrs).await? {
+                        diagnostics.dropped(DropReason::IngestError);
+                    }
+                    *ordering += 1;
+                }
+            } else {
+                // Skipped events aren't counted as ingested
+                if client.send_event(*ordering, ctf_event.attr_kvs()).await? {
+                    timeline_stats
+                        .entry(timeline_id)
+                        .or_default()
+                        .record(&event);
+                } else {
+                    diagnostics.dropped(DropReason::IngestError);
+                }
+                *ordering += 1;
+            }
+            if let Some(url) = client.take_reconnected() {
+                diagnostics
+                    .drops_mut()
+                    .add(DropReason::ReconnectLoss, client.take_lost_events());
+                diagnostics
+                    .record(
+                        DiagnosticEvent::Reconnected {
+                            url: url.to_string(),
+                        },
+                        &mut client,
+                    )
+                    .await?;
+            }
+        }
+
+        control.update_event_counts(
+            timeline_stats
+                .iter()
+                .map(|(tid, s)| (*tid, s.event_count())),
+        );
+        control.update_drop_counts(url.as_str(), diagnostics.drops());
+        last_sent = Instant::now();
+        if control.take_flush_request() {
+            for (tid, stats) in timeline_stats.iter() {
+                let attrs = stats.attr_kvs(&mut client).await?;
+                client.open_timeline(*tid).await?;
+                client.timeline_metadata(attrs).await?;
+            }
+        }
+        diagnostics.record_dropped(&mut client).await?;
+    }
+
+    // The streams only end when the session does
+    if track_messages && !interruptor.is_set() {
+        for (tid, msg) in message_tracker.finish() {
+            if let Some(ordering) = last_timeline_ordering_val.get_mut(&tid) {
+                handle_messages(
+                    &cfg.plugin.message_handling,
+                    tid,
+                    &[msg],
+                    ordering,
+                    diagnostics.drops_mut(),
+                    &mut client,
+                )
+                .await?;
+            }
+        }
+    }
+    for (tid, bucket) in aggregator.finish() {
+        if let Some(ordering) = last_timeline_ordering_val.get_mut(&tid) {
+            let attrs = bucket.attr_kvs(&mut client).await?;
+            client.open_timeline(tid).await?;
+            if !client.send_event(*ordering, attrs).await? {
+                diagnostics.dropped(DropReason::IngestError);
+            }
             *ordering += 1;
-            client.c.close_timeline();
         }
     }
+    client.close_timeline();
+
+    for (tid, stats) in timeline_stats.iter() {
+        let attrs = stats.attr_kvs(&mut client).await?;
+        client.open_timeline(*tid).await?;
+        client.timeline_metadata(attrs).await?;
+        client.close_timeline();
+    }
 
+    let ingested_events: u64 = timeline_stats.values().map(|s| s.event_count()).sum();
+    diagnostics.record_dropped(&mut client).await?;
+    client
+        .drain(
+            cfg.plugin.drain_timeout(),
+            &mut diagnostics,
+            interruptor.is_set(),
+            abandoned_events,
+            ingested_events,
+        )
+        .await?;
+    control.update_drop_counts(url.as_str(), diagnostics.drops());
+
+    if cfg.plugin.fail_on_empty && ingested_events == 0 && !interruptor.is_set() {
+        return Err(Error::NoEventsIngested.into());
+    }
+
+    Ok(())
+}
+
+/// The CTF properties of the session, with the clock class corrections applied.
+///
+/// The lttng-live source has no clock class parameters, so unlike the ctf.fs
+/// source, the clock-class-offset-* settings are applied to the decoded event
+/// timestamps and force-clock-class-origin-unix-epoch to the stream properties.
+async fn session_properties(
+    cfg: &PluginConfig,
+    trace_props: &TraceProperties,
+    stream_props: &BTreeSet<StreamProperties>,
+    client: &mut Client,
+) -> Result<CtfProperties, Error> {
+    let forced_unix_epoch_origin = clock::forced_unix_epoch_origin(
+        cfg.clock_class.force_clock_class_origin_unix_epoch,
+        trace_props,
+        stream_props,
+    );
+    clock::warn_implausible_wall_clock(stream_props, forced_unix_epoch_origin);
+    let mut props = if forced_unix_epoch_origin {
+        let stream_props = clock::with_unix_epoch_origin(stream_props);
+        CtfProperties::new(cfg, trace_props, &stream_props, client).await?
+    } else {
+        CtfProperties::new(cfg, trace_props, stream_props, client).await?
+    };
+    if let Some(offset_ns) = cfg.clock_class.offset_total_ns() {
+        props.set_clock_class_offset_ns(offset_ns, client).await?;
+    }
+    Ok(props)
+}
+
+/// Wait for the next tick of the ticker, forever when there is none
+async fn tick(ticker: &mut Option<tokio::time::Interval>) {
+    match ticker.as_mut() {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Log and publish the ingest lag, the wall-clock time elapsed since
+/// the timestamp of the newest ingested event
+async fn report_ingest_lag(
+    cfg: &CtfConfig,
+    control: &ControlState,
+    newest_event_ns: i128,
+    timeline_id: TimelineId,
+    client: &mut Client,
+    last_timeline_ordering_val: &mut HashMap<TimelineId, u128>,
+    drops: &mut DropCounts,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let now_ns = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i128)
+        .unwrap_or(0);
+    let lag_ns = (now_ns - newest_event_ns).clamp(i64::MIN.into(), i64::MAX.into()) as i64;
+    info!(lag_ms = lag_ns / 1_000_000, "Ingest lag");
+    control.set_ingest_lag_ms(lag_ns / 1_000_000);
+
+    if cfg.plugin.lttng_live.lag_heartbeat_events {
+        if let Some(ordering) = last_timeline_ordering_val.get_mut(&timeline_id) {
+            let event = CtfEvent::ingest_lag(lag_ns, now_ns as u64, client).await?;
+            client.open_timeline(timeline_id).await?;
+            if !client.send_event(*ordering, event.attr_kvs()).await? {
+                drops.count(DropReason::IngestError);
+            }
+            *ordering += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Messages produced by the babeltrace pump thread
+enum LiveMessage {
+    /// The initial trace and stream properties, and any later changes
+    Properties(TraceProperties, BTreeSet<StreamProperties>),
+    Events(Vec<OwnedEvent>),
+    /// The relay daemon had no new data, with the wall-clock time in nanoseconds
+    Inactivity(u64),
+    Error(Error),
+}
+
+/// Capacity of the pump channel, in event chunks
+const LIVE_CHANNEL_CAPACITY: usize = 64;
+
+/// Run the babeltrace graph on its own thread, so its blocking updates and retry
+/// sleeps don't stall the async runtime doing the ingest I/O
+fn spawn_pump(
+    url: CString,
+    live_cfg: LttngLiveConfig,
+    log_level: LoggingLevel,
+    report_inactivity: bool,
+    pauser: Pauser,
+    interruptor: Interruptor,
+) -> mpsc::Receiver<LiveMessage> {
+    let (tx, rx) = mpsc::channel(LIVE_CHANNEL_CAPACITY);
+    thread::spawn(move || {
+        let pumped = pump(
+            &url,
+            &live_cfg,
+            log_level,
+            report_inactivity,
+            &pauser,
+            &interruptor,
+            &tx,
+        );
+        if let Err(e) = pumped {
+            let e = match e {
+                Error::Babeltrace(e) => Error::Babeltrace(e.with_source(format!(
+                    "source.ctf.lttng-live URL '{}' (session-not-found-action {})",
+                    url.to_string_lossy(),
+                    live_cfg.session_not_found_action
+                ))),
+                e => e,
+            };
+            let _ = tx.blocking_send(LiveMessage::Error(e));
+        }
+    });
+    rx
+}
+
+fn pump(
+    url: &CString,
+    live_cfg: &LttngLiveConfig,
+    log_level: LoggingLevel,
+    report_inactivity: bool,
+    pauser: &Pauser,
+    interruptor: &Interruptor,
+    tx: &mpsc::Sender<LiveMessage>,
+) -> Result<(), Error> {
+    let retry_duration = Duration::from_micros(live_cfg.retry_duration_us.into());
+    let mut backoff = RetryBackoff::from_config(live_cfg);
+    let params = CtfPluginSourceLttnLiveInitParams::new(
+        url,
+        Some(live_cfg.session_not_found_action.into()),
+    )?;
+    let mut ctf_stream = CtfStream::new(log_level.into(), &params)?;
+
+    // Loop until we get some metadata from the relayd
+    let metadata_timeout = live_cfg.metadata_timeout_s.map(Duration::from_secs);
+    let waiting_since = Instant::now();
+    while !ctf_stream.has_metadata() {
+        if interruptor.is_set() {
+            return Ok(());
+        }
+        if let Some(timeout) = metadata_timeout {
+            if waiting_since.elapsed() >= timeout {
+                return Err(Error::MetadataTimeout(timeout));
+            }
+        }
+
+        match ctf_stream.update()? {
+            RunStatus::Ok => backoff.reset(),
+            RunStatus::TryAgain => {
+                match backoff.next_delay() {
+                    Some(delay) => thread::sleep(delay),
+                    None => return Err(Error::RetryLimitReached(backoff.retries())),
+                }
+                continue;
+            }
+            RunStatus::End => break,
+        }
+    }
+
+    // With session-not-found-action end, the graph ends right away
+    // when the session doesn't exist
+    if !ctf_stream.has_metadata() {
+        return Err(Error::SessionNotFound(url.to_string_lossy().into_owned()));
+    }
+
+    let mut trace_props_snapshot = ctf_stream.trace_properties().clone();
+    let mut stream_props_snapshot = ctf_stream.stream_properties().clone();
+    let properties =
+        LiveMessage::Properties(trace_props_snapshot.clone(), stream_props_snapshot.clone());
+    if tx.blocking_send(properties).is_err() {
+        return Ok(());
+    }
+
+    let mut was_paused = false;
+    let mut inactive = false;
+    loop {
+        if interruptor.is_set() {
+            break;
+        }
+
+        // While paused the relayd connection stays open, but nothing is
+        // consumed from it or sent to the backend
+        if pauser.is_paused() {
+            if !was_paused {
+                info!("Ingestion paused");
+                was_paused = true;
+            }
+            thread::sleep(retry_duration);
+            continue;
+        } else if was_paused {
+            info!("Ingestion resumed");
+            was_paused = false;
+            backoff.reset();
+        }
+
+        match ctf_stream.update()? {
+            RunStatus::Ok => {
+                backoff.reset();
+                inactive = false;
+            }
+            RunStatus::TryAgain => {
+                // Reported once for each stretch of inactivity
+                if report_inactivity && !inactive {
+                    inactive = true;
+                    let now_ns = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_nanos() as u64)
+                        .unwrap_or(0);
+                    if tx.blocking_send(LiveMessage::Inactivity(now_ns)).is_err() {
+                        break;
+                    }
+                }
+                match backoff.next_delay() {
+                    Some(delay) => thread::sleep(delay),
+                    None => return Err(Error::RetryLimitReached(backoff.retries())),
+                }
+                continue;
+            }
+            RunStatus::End => break,
+        }
+
+        if *ctf_stream.trace_properties() != trace_props_snapshot
+            || *ctf_stream.stream_properties() != stream_props_snapshot
+        {
+            trace_props_snapshot = ctf_stream.trace_properties().clone();
+            stream_props_snapshot = ctf_stream.stream_properties().clone();
+            let properties = LiveMessage::Properties(
+                trace_props_snapshot.clone(),
+                stream_props_snapshot.clone(),
+            );
+            if tx.blocking_send(properties).is_err() {
+                break;
+            }
+        }
+
+        let events = ctf_stream.events_chunk().collect::<Vec<_>>();
+        if !events.is_empty() && tx.blocking_send(LiveMessage::Events(events)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn spawn_pause_signal_handlers(pauser: Pauser) -> Result<(), std::io::Error> {
+    let mut pause_signal = signal(SignalKind::user_defined1())?;
+    let mut resume_signal = signal(SignalKind::user_defined2())?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(()) = pause_signal.recv() => pauser.pause(),
+                Some(()) = resume_signal.recv() => pauser.resume(),
+                else => break,
+            }
+        }
+    });
     Ok(())
 }
 
@@ -1,17 +1,200 @@
 use crate::attrs::{EventAttrKey, TimelineAttrKey};
-use crate::config::AttrKeyRename;
+use crate::config::{AttrKeyRename, CtfConfig};
+use crate::diagnostics::{DiagnosticEvent, Diagnostics};
 use crate::error::Error;
-use modality_ingest_client::dynamic::DynamicIngestClient;
-use modality_ingest_client::{IngestClient, ReadyState};
+use crate::retry::RetryBackoff;
+use crate::types::IngestErrorPolicy;
+use modality_api::{AttrVal, TimelineId};
+use modality_ingest_client::dynamic::{DynamicIngestClient, DynamicIngestError};
+use modality_ingest_client::{IngestClient, ReadyState, UnauthenticatedState};
 use modality_ingest_protocol::InternedAttrKey;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use url::Url;
+
+const INGEST_CONNECT_RETRY_BASE: Duration = Duration::from_millis(500);
+const INGEST_CONNECT_RETRY_MAX: Duration = Duration::from_secs(10);
+const INGEST_EVENT_RETRY_BASE: Duration = Duration::from_millis(100);
+const INGEST_EVENT_RETRY_MAX: Duration = Duration::from_secs(5);
+const INGEST_KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Minimum time between the warnings about skipped events
+const SKIPPED_EVENT_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Connect to the first reachable ingest endpoint, the protocol parent URL
+/// then the `ingest-failover-urls`, and authenticate. Returns the URL connected to.
+///
+/// With `wait-for-ingest-ms` set, failed connection attempts are retried
+/// with backoff until that much time has passed without a connection.
+pub async fn connect_ingest(cfg: &CtfConfig) -> Result<(IngestClient<ReadyState>, Url), Error> {
+    let urls = cfg.ingest_urls()?;
+    let mut backoff = RetryBackoff::new(
+        INGEST_CONNECT_RETRY_BASE,
+        Some(INGEST_CONNECT_RETRY_MAX),
+        None,
+        cfg.plugin.wait_for_ingest_ms.map(Duration::from_millis),
+    );
+    let (c, url) = loop {
+        match connect_first(cfg, &urls).await {
+            Ok(c) => break c,
+            Err(e) if cfg.plugin.wait_for_ingest_ms.is_some() => match backoff.next_delay() {
+                Some(delay) => {
+                    warn!("Failed to connect to the ingest endpoint, retrying. {e}");
+                    tokio::time::sleep(delay).await;
+                }
+                None => return Err(e),
+            },
+            Err(e) => return Err(e),
+        }
+    };
+    Ok((c.authenticate(cfg.resolve_auth().await?.into()).await?, url))
+}
+
+/// Connect to the first of the URLs that can be reached
+async fn connect_first(
+    cfg: &CtfConfig,
+    urls: &[Url],
+) -> Result<(IngestClient<UnauthenticatedState>, Url), Error> {
+    let mut last_err = None;
+    for url in urls.iter() {
+        match IngestClient::connect(url, cfg.ingest.allow_insecure_tls).await {
+            Ok(c) => return Ok((c, url.clone())),
+            Err(e) => {
+                if urls.len() > 1 {
+                    warn!("Failed to connect to the ingest endpoint '{url}'. {e}");
+                }
+                last_err = Some(e);
+            }
+        }
+    }
+    match last_err {
+        Some(e) => Err(e.into()),
+        None => Err(Error::NoIngestUrls),
+    }
+}
+
+/// The ingest connection, or none for the runs that only exercise the mapping,
+/// where the attr keys are interned locally and everything sent is discarded
+enum Ingest {
+    Connected(DynamicIngestClient),
+    Offline { next_key: u32 },
+}
+
+impl Ingest {
+    async fn declare_attr_key(
+        &mut self,
+        name: String,
+    ) -> Result<InternedAttrKey, DynamicIngestError> {
+        match self {
+            Ingest::Connected(c) => c.declare_attr_key(name).await,
+            Ingest::Offline { next_key } => {
+                *next_key += 1;
+                Ok(InternedAttrKey::from(*next_key))
+            }
+        }
+    }
+
+    async fn open_timeline(&mut self, id: TimelineId) -> Result<(), DynamicIngestError> {
+        match self {
+            Ingest::Connected(c) => c.open_timeline(id).await,
+            Ingest::Offline { .. } => Ok(()),
+        }
+    }
+
+    fn close_timeline(&mut self) {
+        if let Ingest::Connected(c) = self {
+            c.close_timeline();
+        }
+    }
+
+    async fn timeline_metadata<I>(&mut self, attrs: I) -> Result<(), DynamicIngestError>
+    where
+        I: IntoIterator<Item = (InternedAttrKey, AttrVal)>,
+    {
+        match self {
+            Ingest::Connected(c) => c.timeline_metadata(attrs).await,
+            Ingest::Offline { .. } => Ok(()),
+        }
+    }
+
+    async fn event<I>(&mut self, ordering: u128, attrs: I) -> Result<(), DynamicIngestError>
+    where
+        I: IntoIterator<Item = (InternedAttrKey, AttrVal)>,
+    {
+        match self {
+            Ingest::Connected(c) => c.event(ordering, attrs).await,
+            Ingest::Offline { .. } => Ok(()),
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), DynamicIngestError> {
+        match self {
+            Ingest::Connected(c) => c.flush().await,
+            Ingest::Offline { .. } => Ok(()),
+        }
+    }
+}
 
 pub struct Client {
-    pub c: DynamicIngestClient,
+    c: Ingest,
     timeline_keys: BTreeMap<String, InternedAttrKey>,
     event_keys: BTreeMap<String, InternedAttrKey>,
+    event_key_names: HashMap<InternedAttrKey, String>,
     rename_timeline_attrs: HashMap<String, String>,
     rename_event_attrs: HashMap<String, String>,
+
+    /// Distinct event attr keys to declare before warning, or failing when strict
+    event_attr_key_budget: Option<(usize, bool)>,
+
+    /// What to do when an event fails to be sent, and how many times to retry it
+    ingest_error_policy: (IngestErrorPolicy, u32),
+
+    /// The currently open timeline
+    open_timeline: Option<TimelineId>,
+
+    /// The configuration to reconnect with, set with [`Client::set_reconnect`]
+    /// for a connected client
+    connect_cfg: Option<CtfConfig>,
+
+    /// Failed sends reconnect, see [`Client::set_reconnect`]
+    reconnect_on_failure: bool,
+
+    /// The attr keys in the order they were declared, re-declared on reconnect
+    declared_keys: Vec<(InternedAttrKey, String)>,
+
+    /// The timeline metadata sent so far, re-sent on reconnect
+    timeline_metadata: HashMap<TimelineId, HashMap<InternedAttrKey, AttrVal>>,
+
+    /// The URL reconnected to, see [`Client::take_reconnected`]
+    reconnected: Option<Url>,
+
+    /// Events sent since the backend last acknowledged everything with a flush,
+    /// and the ones of them that were pending when the connection failed,
+    /// see [`Client::take_lost_events`]
+    unacked_events: u64,
+    lost_events: u64,
+
+    /// Events skipped by the `skip` ingest error policy, and when that was last warned about
+    skipped_events: u64,
+    last_skip_warning: Option<Instant>,
+
+    /// The event attr keys whose conflicts were warned about, see `attr-conflict-policy`
+    warned_attr_conflicts: BTreeSet<String>,
+}
+
+/// Whether a failure to send is the connection's (an I/O failure anywhere in the
+/// error chain) rather than the event's, e.g. an unencodable attr value
+fn is_connection_error(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut cause = Some(e);
+    while let Some(e) = cause {
+        if e.is::<io::Error>() {
+            return true;
+        }
+        cause = e.source();
+    }
+    false
 }
 
 fn normalize_timeline_key(s: String) -> String {
@@ -35,11 +218,37 @@ impl Client {
         c: IngestClient<ReadyState>,
         rename_timeline_attrs: Vec<AttrKeyRename>,
         rename_event_attrs: Vec<AttrKeyRename>,
+    ) -> Self {
+        Self::with_ingest(
+            Ingest::Connected(c.into()),
+            rename_timeline_attrs,
+            rename_event_attrs,
+        )
+    }
+
+    /// A client without an ingest connection: the attr keys are interned locally
+    /// and the events and timeline metadata are discarded, e.g. to benchmark the mapping
+    pub fn offline(
+        rename_timeline_attrs: Vec<AttrKeyRename>,
+        rename_event_attrs: Vec<AttrKeyRename>,
+    ) -> Self {
+        Self::with_ingest(
+            Ingest::Offline { next_key: 0 },
+            rename_timeline_attrs,
+            rename_event_attrs,
+        )
+    }
+
+    fn with_ingest(
+        c: Ingest,
+        rename_timeline_attrs: Vec<AttrKeyRename>,
+        rename_event_attrs: Vec<AttrKeyRename>,
     ) -> Self {
         Self {
-            c: c.into(),
+            c,
             timeline_keys: Default::default(),
             event_keys: Default::default(),
+            event_key_names: Default::default(),
             rename_timeline_attrs: rename_timeline_attrs
                 .into_iter()
                 .map(|r| {
@@ -53,7 +262,302 @@ impl Client {
                 .into_iter()
                 .map(|r| (normalize_event_key(r.original), normalize_event_key(r.new)))
                 .collect(),
+            event_attr_key_budget: None,
+            ingest_error_policy: (IngestErrorPolicy::Fail, 0),
+            open_timeline: None,
+            connect_cfg: None,
+            reconnect_on_failure: false,
+            declared_keys: Vec::new(),
+            timeline_metadata: Default::default(),
+            reconnected: None,
+            unacked_events: 0,
+            lost_events: 0,
+            skipped_events: 0,
+            last_skip_warning: None,
+            warned_attr_conflicts: Default::default(),
+        }
+    }
+
+    /// Reconnect when sending fails, to the first reachable ingest URL starting
+    /// with the protocol parent URL, re-authenticating with the token re-read from
+    /// its source. A recovered primary is only failed back to on the next failure,
+    /// a working connection to a failover URL is kept.
+    /// Failures only reconnect with `ingest-failover-urls` or a refreshable auth token,
+    /// a timed out keepalive always does. A no-op for an offline client.
+    pub fn set_reconnect(&mut self, cfg: &CtfConfig) {
+        if matches!(self.c, Ingest::Offline { .. }) {
+            return;
+        }
+        self.connect_cfg = Some(cfg.clone());
+        self.reconnect_on_failure = !cfg.plugin.ingest_failover_urls.is_empty()
+            || cfg.plugin.auth_token_file.is_some()
+            || cfg.plugin.auth_token_command.is_some();
+    }
+
+    /// The URL reconnected to since the last call, if any
+    pub fn take_reconnected(&mut self) -> Option<Url> {
+        self.reconnected.take()
+    }
+
+    /// The events that were sent but not acknowledged when the connection failed,
+    /// since the last call. They may or may not have reached the backend, the
+    /// failed connection can't be flushed to find out.
+    pub fn take_lost_events(&mut self) -> u64 {
+        std::mem::take(&mut self.lost_events)
+    }
+
+    /// Reconnect, re-declare the attr keys with the same interned keys, and re-send
+    /// the timeline metadata, so the callers' interned keys stay valid
+    async fn reconnect(&mut self) -> Result<(), Error> {
+        let cfg = match self.connect_cfg.clone() {
+            Some(cfg) => cfg,
+            None => return Ok(()),
+        };
+        self.lost_events += std::mem::take(&mut self.unacked_events);
+        let (c, url) = connect_first(&cfg, &cfg.ingest_urls()?).await?;
+        self.c = Ingest::Connected(
+            c.authenticate(cfg.resolve_auth().await?.into())
+                .await?
+                .into(),
+        );
+        for (key, name) in self.declared_keys.iter() {
+            let k = self.c.declare_attr_key(name.clone()).await?;
+            if k != *key {
+                return Err(Error::ReconnectAttrKeyMismatch(name.clone()));
+            }
+        }
+        for (tid, attrs) in self.timeline_metadata.iter() {
+            self.c.open_timeline(*tid).await?;
+            self.c
+                .timeline_metadata(attrs.iter().map(|(k, v)| (*k, v.clone())))
+                .await?;
+        }
+        match self.open_timeline {
+            Some(tid) => self.c.open_timeline(tid).await?,
+            None => self.c.close_timeline(),
+        }
+        warn!("Reconnected to the ingest endpoint '{url}'");
+        self.reconnected = Some(url);
+        Ok(())
+    }
+
+    /// Warn once more than `budget` distinct event attr keys were declared,
+    /// or fail with [`Error::EventAttrKeyBudgetExceeded`] when `strict`
+    pub fn set_event_attr_key_budget(&mut self, budget: usize, strict: bool) {
+        self.event_attr_key_budget = Some((budget, strict));
+    }
+
+    /// How [`Client::send_event`] handles the events that fail to be sent
+    pub fn set_ingest_error_policy(&mut self, policy: IngestErrorPolicy, retries: u32) {
+        self.ingest_error_policy = (policy, retries);
+    }
+
+    /// Send an event on the open timeline, applying the ingest error policy.
+    /// Returns false when the event failed to be sent and was skipped.
+    ///
+    /// The policy only applies to the failures of the event itself. A failed
+    /// connection is reconnected when reconnecting is enabled, and is an error
+    /// otherwise, since every further event would fail the same way.
+    pub async fn send_event(
+        &mut self,
+        ordering: u128,
+        attrs: Vec<(InternedAttrKey, AttrVal)>,
+    ) -> Result<bool, Error> {
+        let (policy, retries) = self.ingest_error_policy;
+        if policy == IngestErrorPolicy::Fail && !self.reconnect_on_failure {
+            self.c.event(ordering, attrs).await?;
+            self.unacked_events += 1;
+            return Ok(true);
+        }
+        let mut backoff = RetryBackoff::new(
+            INGEST_EVENT_RETRY_BASE,
+            Some(INGEST_EVENT_RETRY_MAX),
+            Some(retries.into()),
+            None,
+        );
+        let mut reconnected = false;
+        loop {
+            let e = match self.c.event(ordering, attrs.iter().cloned()).await {
+                Ok(()) => {
+                    self.unacked_events += 1;
+                    return Ok(true);
+                }
+                Err(e) => e,
+            };
+            if is_connection_error(&e) {
+                if !self.reconnect_on_failure || reconnected {
+                    return Err(e.into());
+                }
+                warn!("Failed to ingest an event, reconnecting. {e}");
+                self.reconnect().await?;
+                reconnected = true;
+                continue;
+            }
+            let delay = match policy {
+                IngestErrorPolicy::Fail => return Err(e.into()),
+                IngestErrorPolicy::Retry => backoff.next_delay(),
+                IngestErrorPolicy::Skip => None,
+            };
+            match delay {
+                Some(delay) => {
+                    debug!("Failed to ingest an event, retrying. {e}");
+                    tokio::time::sleep(delay).await;
+                }
+                None => {
+                    self.warn_skipped_event(&e);
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Warn about the skipped events, at most every SKIPPED_EVENT_WARN_INTERVAL
+    fn warn_skipped_event(&mut self, e: &dyn std::fmt::Display) {
+        self.skipped_events += 1;
+        let due = self
+            .last_skip_warning
+            .map(|t| t.elapsed() >= SKIPPED_EVENT_WARN_INTERVAL)
+            .unwrap_or(true);
+        if due {
+            warn!(
+                "Failed to ingest an event, skipping it ({} skipped so far). {e}",
+                self.skipped_events
+            );
+            self.last_skip_warning = Some(Instant::now());
+        } else {
+            debug!("Failed to ingest an event, skipping it. {e}");
+        }
+    }
+
+    /// Whether the conflict of a payload field with the event attr key is the
+    /// first one, the ones after that aren't warned about
+    pub(crate) fn first_attr_conflict(&mut self, key: &str) -> bool {
+        self.warned_attr_conflicts.insert(key.to_owned())
+    }
+
+    /// Open the timeline, unless it's already the open one, so runs of
+    /// events on the same timeline don't switch timelines for every event
+    pub async fn open_timeline(&mut self, id: TimelineId) -> Result<(), Error> {
+        if self.open_timeline != Some(id) {
+            if let Err(e) = self.c.open_timeline(id).await {
+                if !self.reconnect_on_failure {
+                    return Err(e.into());
+                }
+                warn!("Failed to open timeline {id}, reconnecting. {e}");
+                self.reconnect().await?;
+                self.c.open_timeline(id).await?;
+            }
+            self.open_timeline = Some(id);
+        }
+        Ok(())
+    }
+
+    /// Send timeline metadata for the open timeline, kept to be re-sent on reconnect
+    pub async fn timeline_metadata<I>(&mut self, attrs: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (InternedAttrKey, AttrVal)>,
+    {
+        let attrs: Vec<_> = attrs.into_iter().collect();
+        if self.connect_cfg.is_none() {
+            self.c.timeline_metadata(attrs).await?;
+            return Ok(());
         }
+        if let Some(tid) = self.open_timeline {
+            self.timeline_metadata
+                .entry(tid)
+                .or_default()
+                .extend(attrs.iter().cloned());
+        }
+        if let Err(e) = self.c.timeline_metadata(attrs.iter().cloned()).await {
+            if !self.reconnect_on_failure {
+                return Err(e.into());
+            }
+            // The metadata was recorded, and is re-sent by the reconnect
+            warn!("Failed to send timeline metadata, reconnecting. {e}");
+            self.reconnect().await?;
+        }
+        Ok(())
+    }
+
+    pub fn close_timeline(&mut self) {
+        self.c.close_timeline();
+        self.open_timeline = None;
+    }
+
+    /// Check the connection of an idle client with a flush round trip, the
+    /// traffic keeps middleboxes from dropping it. A failed check reconnects,
+    /// or is an error when reconnecting isn't enabled.
+    ///
+    /// A timed out check leaves the connection in the middle of the flush request,
+    /// so it's always dropped and recreated.
+    pub async fn keepalive(&mut self) -> Result<(), Error> {
+        match tokio::time::timeout(INGEST_KEEPALIVE_TIMEOUT, self.c.flush()).await {
+            Ok(Ok(())) => {
+                self.unacked_events = 0;
+                Ok(())
+            }
+            Ok(Err(e)) if self.reconnect_on_failure => {
+                warn!("The ingest connection keepalive failed, reconnecting. {e}");
+                self.reconnect().await
+            }
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) if self.connect_cfg.is_some() => {
+                warn!(
+                    "The ingest connection keepalive timed out after {:?}, reconnecting",
+                    INGEST_KEEPALIVE_TIMEOUT
+                );
+                self.reconnect().await
+            }
+            Err(_) => Err(Error::KeepaliveTimeout(INGEST_KEEPALIVE_TIMEOUT)),
+        }
+    }
+
+    /// Wait for the backend to acknowledge everything sent so far,
+    /// returns false if it didn't within the timeout
+    pub async fn flush_with_timeout(&mut self, timeout: Duration) -> Result<bool, Error> {
+        match tokio::time::timeout(timeout, self.c.flush()).await {
+            Ok(res) => {
+                res?;
+                self.unacked_events = 0;
+                Ok(true)
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Wait for the backend to acknowledge the ingested events and report
+    /// the events abandoned on interrupt
+    pub async fn drain(
+        &mut self,
+        drain_timeout: Duration,
+        diagnostics: &mut Diagnostics,
+        interrupted: bool,
+        abandoned_events: u64,
+        ingested_events: u64,
+    ) -> Result<(), Error> {
+        let acknowledged = self.flush_with_timeout(drain_timeout).await?;
+        if !acknowledged {
+            warn!("The ingested events weren't acknowledged within the drain timeout");
+        }
+        if diagnostics.is_enabled() {
+            diagnostics
+                .record(
+                    DiagnosticEvent::BatchFlushed {
+                        event_count: ingested_events,
+                        acknowledged,
+                    },
+                    self,
+                )
+                .await?;
+            self.close_timeline();
+            self.flush_with_timeout(drain_timeout).await?;
+        }
+        if abandoned_events != 0 {
+            warn!("Abandoned {abandoned_events} events that weren't ingested within the drain timeout");
+        } else if interrupted {
+            info!("Drained all the events");
+        }
+        Ok(())
     }
 
     pub async fn interned_timeline_key(
@@ -70,11 +574,25 @@ impl Client {
         } else {
             let k = self.c.declare_attr_key(key.to_string()).await?;
             self.timeline_keys.insert(key.to_string(), k);
+            self.declared_keys.push((k, key.to_string()));
             k
         };
         Ok(int_key)
     }
 
+    /// The attr key name of an interned event attr key
+    pub fn event_key_name(&self, key: &InternedAttrKey) -> Option<&str> {
+        self.event_key_names.get(key).map(|k| k.as_str())
+    }
+
+    /// The event attr key name after the configured renames
+    pub fn renamed_event_key(&self, key: &str) -> String {
+        self.rename_event_attrs
+            .get(key)
+            .cloned()
+            .unwrap_or_else(|| key.to_owned())
+    }
+
     pub async fn interned_event_key(
         &mut self,
         key: EventAttrKey,
@@ -87,10 +605,83 @@ impl Client {
         let int_key = if let Some(k) = self.event_keys.get(&key.to_string()) {
             *k
         } else {
+            if let Some((budget, strict)) = self.event_attr_key_budget {
+                if self.event_keys.len() == budget {
+                    if strict {
+                        return Err(Error::EventAttrKeyBudgetExceeded(budget, key.to_string()));
+                    }
+                    warn!(
+                        "Declared more than {budget} distinct event attr keys, the latest being '{key}'. \
+                        Keys derived from values (e.g. sequence numbers or IDs in field names) \
+                        pollute the backend, consider renaming, removing or transforming those fields"
+                    );
+                }
+            }
             let k = self.c.declare_attr_key(key.to_string()).await?;
             self.event_keys.insert(key.to_string(), k);
+            self.event_key_names.insert(k, key.to_string());
+            self.declared_keys.push((k, key.to_string()));
             k
         };
         Ok(int_key)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn offline_interning() {
+        let mut client = Client::offline(
+            Vec::new(),
+            vec![AttrKeyRename {
+                original: "event.a".to_owned(),
+                new: "event.b".to_owned(),
+            }],
+        );
+        let a = client
+            .interned_event_key(EventAttrKey::Field("a".to_owned()))
+            .await
+            .unwrap();
+        let c = client
+            .interned_event_key(EventAttrKey::Field("c".to_owned()))
+            .await
+            .unwrap();
+        let name = client
+            .interned_timeline_key(TimelineAttrKey::Name)
+            .await
+            .unwrap();
+        assert_ne!(a, c);
+        assert_ne!(a, name);
+        assert_eq!(client.event_key_name(&a), Some("event.b"));
+        assert_eq!(
+            client
+                .interned_event_key(EventAttrKey::Field("a".to_owned()))
+                .await
+                .unwrap(),
+            a
+        );
+
+        // Nothing is sent, and there's nothing to reconnect
+        client
+            .open_timeline(TimelineId::from(uuid::Uuid::from_u128(1)))
+            .await
+            .unwrap();
+        assert!(client
+            .send_event(0, vec![(a, AttrVal::from(1_i64))])
+            .await
+            .unwrap());
+        assert_eq!(client.unacked_events, 1);
+        assert!(client
+            .flush_with_timeout(Duration::from_millis(1))
+            .await
+            .unwrap());
+        assert_eq!(client.unacked_events, 0);
+        assert_eq!(client.take_lost_events(), 0);
+        client.set_reconnect(&CtfConfig::default());
+        assert!(client.connect_cfg.is_none());
+        assert!(!client.reconnect_on_failure);
+    }
+}
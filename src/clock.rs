@@ -0,0 +1,269 @@
+use crate::attrs::TimelineAttrKey;
+use crate::client::Client;
+use crate::config::PluginConfig;
+use crate::error::Error;
+use crate::event::root_scalar_field;
+use crate::properties::{TracerIdentity, TracerKind};
+use crate::types::UnixEpochOrigin;
+use babeltrace2_sys::{OwnedEvent, OwnedField, ScalarField, StreamProperties, TraceProperties};
+use modality_api::AttrVal;
+use modality_ingest_protocol::InternedAttrKey;
+use std::collections::{BTreeSet, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// `timeline.internal.ctf.stream.clock.correction_source` value for corrections from
+/// the trace environment
+pub const CLOCK_CORRECTION_SOURCE_ENV: &str = "env";
+
+/// `timeline.internal.ctf.stream.clock.correction_source` value for corrections from
+/// clock sync events
+pub const CLOCK_CORRECTION_SOURCE_EVENT: &str = "event";
+
+const DEFAULT_CLOCK_CORRECTION_FIELD: &str = "offset_ns";
+
+/// Earliest plausible wall-clock time of trace data, 2000-01-01T00:00:00Z
+const PLAUSIBLE_WALL_CLOCK_MIN_S: i64 = 946_684_800;
+
+/// How far past the present a wall-clock time is still plausible (clock skew, time zones)
+const PLAUSIBLE_WALL_CLOCK_FUTURE_S: i64 = 86_400;
+
+/// Decide whether the origin of the trace's clock classes should be forced to the Unix epoch.
+///
+/// Clock classes that already have a Unix epoch origin don't need it.
+/// LTTng sets the clock class offset to the wall-clock time at trace start, so its
+/// traces are forced. Otherwise the origin is forced only when every clock class offset
+/// lands near the present when interpreted as Unix time; small offsets indicate a
+/// relative (e.g. boot-time) clock.
+pub fn auto_unix_epoch_origin(t: &TraceProperties, streams: &BTreeSet<StreamProperties>) -> bool {
+    let clocks = streams
+        .iter()
+        .filter_map(|s| s.clock.as_ref())
+        .collect::<Vec<_>>();
+    if clocks.is_empty() {
+        debug!("Trace has no clock classes, leaving the clock class origin as is");
+        return false;
+    }
+    if clocks.iter().all(|c| c.unix_epoch_origin) {
+        debug!("All clock classes have a Unix epoch origin");
+        return false;
+    }
+
+    let tracer_kind = TracerIdentity::from_trace_properties(t).map(|t| t.kind());
+    if matches!(
+        tracer_kind,
+        Some(TracerKind::LttngModules) | Some(TracerKind::LttngUst)
+    ) {
+        debug!("LTTng clock class offsets are wall-clock based, forcing a Unix epoch origin");
+        return true;
+    }
+
+    let force = clocks.iter().all(|c| {
+        let offset_s = c
+            .offset_seconds
+            .saturating_add((c.offset_cycles / c.frequency.max(1)) as i64);
+        is_plausible_wall_clock(offset_s)
+    });
+    debug!(
+        force,
+        "Picked the clock class origin handling from the clock class offsets"
+    );
+    force
+}
+
+/// Resolve `force-clock-class-origin-unix-epoch` from the decoded stream properties,
+/// for sources that don't take the component parameter (lttng-live)
+pub fn forced_unix_epoch_origin(
+    origin: Option<UnixEpochOrigin>,
+    t: &TraceProperties,
+    streams: &BTreeSet<StreamProperties>,
+) -> bool {
+    match origin {
+        Some(UnixEpochOrigin::Enabled) => true,
+        Some(UnixEpochOrigin::Auto) => auto_unix_epoch_origin(t, streams),
+        Some(UnixEpochOrigin::Disabled) | None => false,
+    }
+}
+
+/// The stream properties with the origin of their clock classes forced to the Unix epoch.
+///
+/// The event clock snapshots are already nanoseconds from the clock origin, so only
+/// their interpretation (and the rebasing onto a wall-clock origin) changes.
+pub fn with_unix_epoch_origin(streams: &BTreeSet<StreamProperties>) -> BTreeSet<StreamProperties> {
+    streams
+        .iter()
+        .cloned()
+        .map(|mut s| {
+            if let Some(c) = s.clock.as_mut() {
+                c.unix_epoch_origin = true;
+            }
+            s
+        })
+        .collect()
+}
+
+/// Warn about the streams whose Unix epoch based timestamps would land implausibly
+/// far from the present
+pub fn warn_implausible_wall_clock(
+    streams: &BTreeSet<StreamProperties>,
+    forced_unix_epoch_origin: bool,
+) {
+    for s in streams.iter() {
+        let c = match &s.clock {
+            Some(c) if c.unix_epoch_origin || forced_unix_epoch_origin => c,
+            _ => continue,
+        };
+        let offset_s = c
+            .offset_seconds
+            .saturating_add((c.offset_cycles / c.frequency.max(1)) as i64);
+        if !is_plausible_wall_clock(offset_s) {
+            warn!(
+                stream_id = s.id,
+                offset_seconds = offset_s,
+                "The clock class of stream {} has a Unix epoch origin but an offset of {offset_s} s, \
+                 its timestamps are implausibly far from the present",
+                s.id
+            );
+        }
+    }
+}
+
+fn is_plausible_wall_clock(unix_s: i64) -> bool {
+    let now_s = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(i64::MAX);
+    (PLAUSIBLE_WALL_CLOCK_MIN_S..=now_s.saturating_add(PLAUSIBLE_WALL_CLOCK_FUTURE_S))
+        .contains(&unix_s)
+}
+
+/// Tracks the clock corrections carried by clock sync events, per stream of each trace
+/// since a correction applies to the clock of the stream it was observed on, whatever
+/// timeline the stream's events end up on
+#[derive(Clone, Debug, Default)]
+pub struct ClockSyncTracker {
+    event_name: Option<String>,
+    field: String,
+    corrections: HashMap<(Uuid, u64), i64>,
+}
+
+impl ClockSyncTracker {
+    pub fn new(cfg: &PluginConfig) -> Self {
+        Self {
+            event_name: cfg.clock_correction_event.clone(),
+            field: cfg
+                .clock_correction_field
+                .clone()
+                .unwrap_or_else(|| DEFAULT_CLOCK_CORRECTION_FIELD.to_owned()),
+            corrections: Default::default(),
+        }
+    }
+
+    /// Returns the new correction if the event is a clock sync event that changed it
+    pub fn observe(&mut self, trace_uuid: Uuid, event: &OwnedEvent) -> Option<i64> {
+        let event_name = self.event_name.as_deref()?;
+        if event.class_properties.name.as_deref() != Some(event_name) {
+            return None;
+        }
+        self.observe_payload(
+            trace_uuid,
+            event.stream_id,
+            event.properties.payload.as_ref(),
+        )
+    }
+
+    fn observe_payload(
+        &mut self,
+        trace_uuid: Uuid,
+        stream_id: u64,
+        payload: Option<&OwnedField>,
+    ) -> Option<i64> {
+        let correction = match payload.and_then(|p| root_scalar_field(p, &self.field)) {
+            Some(ScalarField::SignedInteger(ns)) => *ns,
+            Some(ScalarField::UnsignedInteger(ns)) => i64::try_from(*ns).ok()?,
+            _ => {
+                warn!(
+                    "Clock sync event '{}' doesn't have an integer '{}' payload field",
+                    self.event_name.as_deref().unwrap_or_default(),
+                    self.field
+                );
+                return None;
+            }
+        };
+        if self.corrections.insert((trace_uuid, stream_id), correction) == Some(correction) {
+            None
+        } else {
+            Some(correction)
+        }
+    }
+
+    /// The latest correction observed on the stream
+    pub fn correction_ns(&self, trace_uuid: Uuid, stream_id: u64) -> Option<i64> {
+        self.corrections.get(&(trace_uuid, stream_id)).copied()
+    }
+}
+
+/// The timeline attrs recording an applied clock correction
+pub async fn correction_attr_kvs(
+    correction_ns: i64,
+    source: &str,
+    client: &mut Client,
+) -> Result<Vec<(InternedAttrKey, AttrVal)>, Error> {
+    Ok(vec![
+        (
+            client
+                .interned_timeline_key(TimelineAttrKey::StreamClockCorrection)
+                .await?,
+            correction_ns.into(),
+        ),
+        (
+            client
+                .interned_timeline_key(TimelineAttrKey::StreamClockCorrectionSource)
+                .await?,
+            source.to_owned().into(),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn clock_sync(offset_ns: ScalarField) -> OwnedField {
+        OwnedField::Structure(
+            None,
+            vec![OwnedField::Scalar("offset_ns".to_owned().into(), offset_ns)],
+        )
+    }
+
+    #[test]
+    fn clock_sync_corrections_per_stream() {
+        let mut tracker = ClockSyncTracker::new(&PluginConfig {
+            clock_correction_event: "clock_sync".to_owned().into(),
+            ..Default::default()
+        });
+        let (a, b) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let sync = |ns| Some(clock_sync(ScalarField::SignedInteger(ns)));
+
+        assert_eq!(tracker.observe_payload(a, 0, sync(-5).as_ref()), Some(-5));
+        // Unchanged
+        assert_eq!(tracker.observe_payload(a, 0, sync(-5).as_ref()), None);
+        assert_eq!(tracker.observe_payload(a, 1, sync(7).as_ref()), Some(7));
+        assert_eq!(tracker.observe_payload(b, 0, sync(9).as_ref()), Some(9));
+        assert_eq!(tracker.correction_ns(a, 0), Some(-5));
+        assert_eq!(tracker.correction_ns(a, 1), Some(7));
+        assert_eq!(tracker.correction_ns(b, 0), Some(9));
+        assert_eq!(tracker.correction_ns(b, 1), None);
+
+        let unsigned = clock_sync(ScalarField::UnsignedInteger(3));
+        assert_eq!(tracker.observe_payload(b, 0, Some(&unsigned)), Some(3));
+        let too_large = clock_sync(ScalarField::UnsignedInteger(u64::MAX));
+        assert_eq!(tracker.observe_payload(b, 0, Some(&too_large)), None);
+        let not_an_integer = clock_sync(ScalarField::String("3".to_owned()));
+        assert_eq!(tracker.observe_payload(b, 0, Some(&not_an_integer)), None);
+        assert_eq!(tracker.observe_payload(b, 0, None), None);
+        assert_eq!(tracker.correction_ns(b, 0), Some(3));
+    }
+}
@@ -1,14 +1,26 @@
 use crate::auth::{AuthTokenBytes, AuthTokenError};
+use crate::error::Error;
 use crate::opts::{BabeltraceOpts, ReflectorOpts};
-use crate::types::{LoggingLevel, RetryDurationUs, SessionNotFoundAction};
+use crate::profile::ProfileRules;
+use crate::schema::{self, ConfigError, KeyPath, UnknownKey};
+use crate::types::{
+    AttrConflictPolicy, EventLogLevel, FieldType, IngestErrorPolicy, LogBackend, LoggingLevel,
+    MergeStreamNamespace, MessageAction, MetadataTimeoutAction, Profile, RetryDurationUs,
+    SessionNotFoundAction, Shard, StreamNamePattern, TimestampFormat, UnixEpochOrigin,
+    UnknownKeyPolicy,
+};
 use babeltrace2_sys::CtfPluginSourceFsInitParams;
 use modality_reflector_config::{Config, TomlValue, TopLevelIngest, CONFIG_ENV_VAR};
-use serde::Deserialize;
-use std::convert::TryFrom;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
 use std::ffi::{CString, NulError};
+use std::fs;
+use std::net::SocketAddr;
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
 use url::Url;
 use uuid::Uuid;
 
@@ -22,33 +34,448 @@ pub struct CtfConfig {
 #[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
 #[serde(rename_all = "kebab-case", default)]
 pub struct PluginConfig {
+    /// Resolved once when the configuration is loaded, a new run ID is
+    /// generated unless one is configured
     pub run_id: Option<Uuid>,
 
+    /// Whether the run ID was generated rather than configured, a generated
+    /// run ID yields to a shard's derived one or one recorded in the state file
+    #[serde(skip)]
+    pub generated_run_id: bool,
+
     /// Optionally provide a trace UUID to override any present (or not) UUID contained
     /// in the CTF metadata.
     ///
     /// This is useful for constructing deterministic trace UUIDis which form the timeline IDs.
     pub trace_uuid: Option<Uuid>,
 
+    /// Name of the trace environment entry the trace UUID is derived from, used when
+    /// trace-uuid isn't set. A UUID string is used as is, any other value is hashed
+    /// into a UUIDv5 (e.g. a device serial number).
+    pub trace_uuid_from_env: Option<String>,
+
     /// Logging level for libbabeltrace
     pub log_level: LoggingLevel,
 
+    /// Only ingest events whose event class log level is at least as severe as this level.
+    /// Events without a log level are always ingested.
+    pub min_log_level: Option<EventLogLevel>,
+
+    /// Semantic mapping profile whose rules are merged under this configuration
+    pub profile: Profile,
+
     /// Rename a timeline attribute key as it is being imported
     pub rename_timeline_attrs: Vec<AttrKeyRename>,
 
     /// Rename an event attribute key as it is being imported
     pub rename_event_attrs: Vec<AttrKeyRename>,
 
+    /// A CSV (`original,new` lines) or TOML (`'original' = 'new'` entries) file
+    /// of timeline attribute key renames, applied after rename-timeline-attrs
+    pub rename_timeline_attrs_file: Option<PathBuf>,
+
+    /// A CSV or TOML file of event attribute key renames, applied after rename-event-attrs
+    pub rename_event_attrs_file: Option<PathBuf>,
+
     /// Merge all streams into the stream with the given ID, producing a single timeline.
     pub merge_stream_id: Option<u64>,
 
-    #[serde(flatten)]
+    /// Namespace the payload attrs of the merged streams by their originating stream,
+    /// one of `none` (the default), `stream-id` or `stream-name`
+    pub merge_stream_namespace: MergeStreamNamespace,
+
+    /// Emit a `modality.ingest.begin` event at the start of each timeline
+    /// carrying the run ID and plugin metadata
+    pub emit_ingest_begin_event: bool,
+
+    /// Ingest the plugin's own operational events (connections, flushes, dropped
+    /// events) on a dedicated `<plugin>.diagnostics` timeline
+    pub diagnostics_timeline: bool,
+
+    /// Exit with a distinct non-zero code when no events were ingested
+    pub fail_on_empty: bool,
+
+    /// Warn once more than this many distinct event attr keys were declared,
+    /// defaults to 1000
+    pub event_attr_key_budget: Option<usize>,
+
+    /// Fail instead of warning when the event-attr-key-budget is exceeded
+    pub strict_attr_key_budget: bool,
+
+    /// Tag the timelines and events with the URI of the trace directory
+    /// or lttng-live session they come from
+    pub source_uri_attrs: bool,
+
+    /// Parse the per-CPU stream names (`<channel>_<cpu>`) into a `timeline.cpu`
+    /// timeline attr, set by the LTTng profiles
+    pub per_cpu_streams: bool,
+
+    /// Name the per-CPU stream timelines under this prefix, set by the LTTng profiles
+    #[serde(skip)]
+    pub per_cpu_timeline_prefix: Option<String>,
+
+    /// Tag the events with the precision of their stream clock, in nanoseconds
+    pub timestamp_precision_attrs: bool,
+
+    /// What to do with each type of babeltrace message
+    pub message_handling: MessageHandlingConfig,
+
+    /// Stop ingesting the events of a timeline once it has this many
+    pub max_events_per_timeline: Option<u64>,
+
+    /// What to do when an event fails to be ingested
+    pub on_ingest_error: IngestErrorPolicy,
+
+    /// How many times to retry sending an event with the `retry` policy, defaults to 3
+    pub ingest_error_retries: Option<u32>,
+
+    /// Import-time event sampling
+    pub sampling: SamplingConfig,
+
+    /// Event classes rolled up into time buckets, keyed by event class name
+    pub aggregations: BTreeMap<String, AggregationConfig>,
+
+    /// Limits the event attrs are fit into before being sent
+    pub attr_limits: AttrLimitsConfig,
+
+    /// Use the given timeline ID for a stream ID instead of deriving one from
+    /// the trace UUID and stream ID
+    #[serde(deserialize_with = "deserialize_stream_id_map")]
+    pub stream_timeline_map: BTreeMap<u64, Uuid>,
+
+    /// Use the given `timeline.name` for a stream ID instead of the
+    /// name derived from the stream file
+    #[serde(deserialize_with = "deserialize_stream_id_map")]
+    pub stream_timeline_names: BTreeMap<u64, String>,
+
+    /// Name the timelines of the streams whose babeltrace stream name matches
+    /// a pattern, checked in order after stream-timeline-names
+    pub timeline_name_patterns: Vec<TimelineNamePattern>,
+
+    /// Infer interactions between the events of two event classes whose
+    /// payload fields carry the same value
+    pub pairing_rules: Vec<PairingRule>,
+
+    /// Trace environment entries that never become timeline attrs
+    /// (e.g. usernames or host paths)
+    pub exclude_trace_env: BTreeSet<String>,
+
+    /// UTC time, in nanoseconds since the Unix epoch, of the origin of relative
+    /// (non Unix epoch origin) clocks. Event timestamps on those clocks are rebased onto it.
+    pub wall_clock_origin_ns: Option<u64>,
+
+    /// Name of the trace environment entry holding the wall-clock origin in
+    /// nanoseconds since the Unix epoch, used when wall-clock-origin-ns isn't set
+    pub wall_clock_origin_env: Option<String>,
+
+    /// Nanoseconds added to every event timestamp, so clocks anchored before the
+    /// Unix epoch (negative clock snapshots) still produce timestamps. Recorded in the
+    /// `timeline.internal.ctf.stream.clock.timestamp_shift_ns` attr.
+    pub timestamp_shift_ns: Option<u64>,
+
+    /// Name of the trace environment entry holding a clock correction in nanoseconds
+    /// (e.g. a PTP offset recorded by the target), added to the event timestamps
+    pub clock_correction_env: Option<String>,
+
+    /// Name of the event class carrying clock sync information. The correction in its
+    /// clock-correction-field payload field applies to the subsequent events of the stream.
+    pub clock_correction_event: Option<String>,
+
+    /// Payload field of the clock sync event holding the correction in nanoseconds,
+    /// defaults to `offset_ns`
+    pub clock_correction_field: Option<String>,
+
+    /// Name of the event class whose payload fields are applied as timeline attrs
+    /// of the emitting stream instead of being ingested as an event,
+    /// disabled unless set
+    pub timeline_attrs_event: Option<String>,
+
+    /// On interrupt, keep ingesting the already decoded events and wait for the
+    /// backend to acknowledge them for up to this long, defaults to 5000
+    pub drain_timeout_ms: Option<u64>,
+
+    /// When the ingest endpoint can't be reached at startup, keep retrying
+    /// (with backoff) for up to this long instead of exiting
+    pub wait_for_ingest_ms: Option<u64>,
+
+    /// Ingest endpoints to fail over to, in order, when the protocol-parent-url
+    /// can't be reached or its connection fails
+    pub ingest_failover_urls: Vec<Url>,
+
+    /// File to read the auth token hex string from when no auth token is given,
+    /// re-read on reconnect so short-lived tokens can be refreshed
+    pub auth_token_file: Option<PathBuf>,
+
+    /// Command whose stdout is the auth token hex string, run with `sh -c` when
+    /// no auth token is given, takes precedence over auth-token-file
+    pub auth_token_command: Option<String>,
+
+    /// Write the plugin logs to this file instead of stderr, rotating it once it
+    /// reaches log-file-max-size bytes
+    pub log_file: Option<PathBuf>,
+
+    /// Size in bytes at which the log file is rotated, defaults to 10485760 (10 MiB)
+    pub log_file_max_size: Option<u64>,
+
+    /// Number of rotated log files (`<log-file>.1` being the most recent) kept
+    /// alongside the current one, defaults to 5
+    pub log_file_max_files: Option<usize>,
+
+    /// Where the plugin logs go, one of `stderr` (the default), `syslog` or `journald`
+    pub log_backend: LogBackend,
+
+    /// What to do with the unknown keys of this table, one of `warn` (the default),
+    /// `fail`, or `ignore`
+    pub on_unknown_key: UnknownKeyPolicy,
+
+    /// The unknown keys found when the configuration was loaded,
+    /// see [`PluginConfig::warn_unknown_keys`]
+    #[serde(skip)]
+    pub unknown_keys: Vec<UnknownKey>,
+
+    /// The sections sharing this table, deserialized from it on their own,
+    /// see [`PluginConfig::from_table`]
+    #[serde(skip)]
+    pub event_mapping: EventMappingConfig,
+
+    #[serde(skip)]
+    pub clock_class: ClockClassConfig,
+
+    #[serde(skip)]
     pub import: ImportConfig,
 
-    #[serde(flatten)]
+    #[serde(skip)]
     pub lttng_live: LttngLiveConfig,
 }
 
+impl PluginConfig {
+    pub const DEFAULT_DRAIN_TIMEOUT_MS: u64 = 5000;
+    pub const DEFAULT_LOG_FILE_MAX_SIZE: u64 = 10 * 1024 * 1024;
+    pub const DEFAULT_LOG_FILE_MAX_FILES: usize = 5;
+    pub const DEFAULT_EVENT_ATTR_KEY_BUDGET: usize = 1000;
+    pub const DEFAULT_INGEST_ERROR_RETRIES: u32 = 3;
+
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_millis(
+            self.drain_timeout_ms
+                .unwrap_or(Self::DEFAULT_DRAIN_TIMEOUT_MS),
+        )
+    }
+
+    /// The event attr key budget, only checked when configured or
+    /// with `strict-attr-key-budget`
+    pub fn event_attr_key_budget(&self) -> Option<usize> {
+        self.event_attr_key_budget.or(self
+            .strict_attr_key_budget
+            .then_some(Self::DEFAULT_EVENT_ATTR_KEY_BUDGET))
+    }
+
+    pub fn ingest_error_retries(&self) -> u32 {
+        self.ingest_error_retries
+            .unwrap_or(Self::DEFAULT_INGEST_ERROR_RETRIES)
+    }
+
+    /// Deserialize the configuration table and the sections sharing it, along with
+    /// the paths of the keys none of them took
+    pub(crate) fn from_table(table: TomlValue) -> Result<(Self, Vec<KeyPath>), ConfigError> {
+        let (mut cfg, mut ignored): (Self, _) = schema::deserialize_table(table.clone())?;
+        let (event_mapping, event_mapping_ignored) = schema::deserialize_table(table.clone())?;
+        let (clock_class, clock_class_ignored) = schema::deserialize_table(table.clone())?;
+        let (import, import_ignored) = schema::deserialize_table(table.clone())?;
+        let (lttng_live, lttng_live_ignored) = schema::deserialize_table(table)?;
+        cfg.event_mapping = event_mapping;
+        cfg.clock_class = clock_class;
+        cfg.import = import;
+        cfg.lttng_live = lttng_live;
+
+        // Each ignores the top-level keys of the others, the nested keys
+        // they ignore are in their own tables
+        ignored.retain(|path| match path.last_key() {
+            Some(key) if path.len() == 1 => !Self::table_field_names(path).contains(&key),
+            _ => true,
+        });
+        ignored.extend(
+            event_mapping_ignored
+                .into_iter()
+                .chain(clock_class_ignored)
+                .chain(import_ignored)
+                .chain(lttng_live_ignored)
+                .filter(|path| path.len() > 1),
+        );
+        Ok((cfg, ignored))
+    }
+
+    /// The unknown keys of the configuration table, given the keys
+    /// [`PluginConfig::from_table`] reported as ignored
+    pub(crate) fn find_unknown_keys(ignored: Vec<KeyPath>) -> Vec<UnknownKey> {
+        ignored
+            .into_iter()
+            .map(|path| {
+                let names = Self::table_field_names(&path);
+                UnknownKey::new(path, names)
+            })
+            .collect()
+    }
+
+    /// The field names of the table the key is in
+    fn table_field_names(path: &KeyPath) -> Vec<&'static str> {
+        if path.len() == 1 {
+            return schema::field_names::<Self>()
+                .iter()
+                .chain(schema::field_names::<EventMappingConfig>())
+                .chain(schema::field_names::<ClockClassConfig>())
+                .chain(schema::field_names::<ImportConfig>())
+                .chain(schema::field_names::<LttngLiveConfig>())
+                .copied()
+                .collect();
+        }
+        match path.first_key() {
+            Some("message-handling") => schema::field_names::<MessageHandlingConfig>(),
+            Some("sampling") => schema::field_names::<SamplingConfig>(),
+            Some("aggregations") => schema::field_names::<AggregationConfig>(),
+            Some("attr-limits") => schema::field_names::<AttrLimitsConfig>(),
+            Some("rename-timeline-attrs" | "rename-event-attrs") => {
+                schema::field_names::<AttrKeyRename>()
+            }
+            Some("timeline-name-patterns") => schema::field_names::<TimelineNamePattern>(),
+            Some("pairing-rules") => schema::field_names::<PairingRule>(),
+            Some("elf-binaries") => schema::field_names::<ElfBinaryConfig>(),
+            _ => &[],
+        }
+        .to_vec()
+    }
+
+    /// Warn about the unknown keys, once the logging is set up
+    pub fn warn_unknown_keys(&self) {
+        for key in self.unknown_keys.iter() {
+            warn!("Ignoring the unknown [metadata] key {key}");
+        }
+    }
+
+    /// Resolve the relative paths of the file and directory settings against
+    /// the directory of the configuration file that set them
+    fn resolve_paths(&mut self, dir: &Path) {
+        let resolve = |p: &mut PathBuf| {
+            if p.is_relative() {
+                *p = dir.join(&*p);
+            }
+        };
+        [
+            &mut self.rename_timeline_attrs_file,
+            &mut self.rename_event_attrs_file,
+            &mut self.auth_token_file,
+            &mut self.log_file,
+            &mut self.event_mapping.kallsyms,
+            &mut self.event_mapping.wasm_transform,
+            &mut self.event_mapping.script_transform,
+            &mut self.import.state_file,
+            &mut self.import.mapping_report,
+            &mut self.import.watch_dir,
+            &mut self.lttng_live.control_socket,
+        ]
+        .into_iter()
+        .flatten()
+        .for_each(resolve);
+        self.event_mapping
+            .elf_binaries
+            .iter_mut()
+            .for_each(|b| resolve(&mut b.path));
+        self.import.inputs.iter_mut().for_each(resolve);
+    }
+
+    /// Append the renames of the rename-timeline-attrs-file and
+    /// rename-event-attrs-file mapping files, done at config load
+    pub fn load_rename_files(&mut self) -> Result<(), Error> {
+        if let Some(p) = &self.rename_timeline_attrs_file {
+            self.rename_timeline_attrs.extend(load_renames_file(p)?);
+        }
+        if let Some(p) = &self.rename_event_attrs_file {
+            self.rename_event_attrs.extend(load_renames_file(p)?);
+        }
+        Ok(())
+    }
+}
+
+/// Read a rename mapping file, the format is picked from the `.csv` or `.toml` extension
+fn load_renames_file(path: &Path) -> Result<Vec<AttrKeyRename>, Error> {
+    let content = fs::read_to_string(path).map_err(|e| Error::RenamesFileIo(path.to_owned(), e))?;
+    let renames = match path.extension().and_then(|e| e.to_str()) {
+        Some("csv") => parse_csv_renames(&content),
+        Some("toml") => toml::from_str::<BTreeMap<String, String>>(&content)
+            .map(|renames| {
+                renames
+                    .into_iter()
+                    .map(|(original, new)| AttrKeyRename { original, new })
+                    .collect()
+            })
+            .map_err(|e| e.to_string()),
+        _ => Err("The file extension must be .csv or .toml".to_owned()),
+    };
+    renames.map_err(|e| Error::InvalidRenamesFile(path.to_owned(), e))
+}
+
+/// One `original,new` rename per line, blank lines, `#` comments and
+/// an `original,new` header line are skipped
+fn parse_csv_renames(content: &str) -> Result<Vec<AttrKeyRename>, String> {
+    let mut renames = Vec::new();
+    for (line_num, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line
+            .split(',')
+            .map(|f| f.trim().trim_matches('"'))
+            .collect();
+        match fields.as_slice() {
+            ["original", "new"] if renames.is_empty() => (),
+            [original, new] if !original.is_empty() && !new.is_empty() => {
+                renames.push(AttrKeyRename {
+                    original: original.to_string(),
+                    new: new.to_string(),
+                })
+            }
+            _ => {
+                return Err(format!(
+                    "Line {} must be an 'original,new' attr key pair",
+                    line_num + 1
+                ))
+            }
+        }
+    }
+    Ok(renames)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TimelineNamePattern {
+    /// Regular expression matched against the derived stream name, i.e. the
+    /// `timeline.stream_name` attr (the stream file name for CTF traces on disk)
+    pub stream_name: StreamNamePattern,
+
+    /// The `timeline.name` to use, may refer to the pattern's capture
+    /// groups (e.g. `'cpu$1'`)
+    pub timeline_name: String,
+}
+
+/// An interaction between two events whose payload fields carry the same value,
+/// e.g. a message ID, see [`crate::interaction::InteractionPairs`]
+#[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct PairingRule {
+    /// The event class name of the interaction's source events
+    pub source_event: String,
+
+    /// The root payload field of the source events holding the pairing value
+    pub source_field: String,
+
+    /// The event class name of the interaction's destination events
+    pub destination_event: String,
+
+    /// The root payload field of the destination events holding the pairing value
+    pub destination_field: String,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
 #[serde(rename_all = "kebab-case", default)]
 pub struct AttrKeyRename {
@@ -59,12 +486,263 @@ pub struct AttrKeyRename {
     pub new: String,
 }
 
+/// Parse an `original,new` rename, the format of the `--rename-*-attr` options
+pub fn parse_attr_key_rename(
+    s: &str,
+) -> Result<AttrKeyRename, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let pos = s
+        .find(',')
+        .ok_or_else(|| format!("invalid original,new: no `,` found in `{s}`"))?;
+    let original = s[..pos].parse()?;
+    let new = s[pos + 1..].parse()?;
+    Ok(AttrKeyRename { original, new })
+}
+
+/// The action for each babeltrace message type. Only events are ingested by default.
+///
+/// The packet, discarded events and stream messages are inferred from the
+/// events' packet context (`packet_seq_num`, `timestamp_begin`, `events_discarded`).
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct MessageHandlingConfig {
+    pub event: MessageAction,
+    pub packet_beginning: MessageAction,
+    pub packet_end: MessageAction,
+    pub discarded_events: MessageAction,
+    pub stream_beginning: MessageAction,
+    pub stream_end: MessageAction,
+
+    /// lttng-live only, the relay daemon had no new data
+    pub message_iterator_inactivity: MessageAction,
+}
+
+impl Default for MessageHandlingConfig {
+    fn default() -> Self {
+        Self {
+            event: MessageAction::Event,
+            packet_beginning: MessageAction::Ignore,
+            packet_end: MessageAction::Ignore,
+            discarded_events: MessageAction::Ignore,
+            stream_beginning: MessageAction::Ignore,
+            stream_end: MessageAction::Ignore,
+            message_iterator_inactivity: MessageAction::Ignore,
+        }
+    }
+}
+
+/// Import-time event sampling, per event class and timeline, see [`crate::sampling`]
 #[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
 #[serde(rename_all = "kebab-case", default)]
-pub struct ImportConfig {
-    /// See <https://babeltrace.org/docs/v2.0/man7/babeltrace2-source.ctf.fs.7/#doc-param-trace-name>
-    pub trace_name: Option<String>,
+pub struct SamplingConfig {
+    /// Keep 1 in N events of each event class
+    pub sample_rate: Option<u64>,
+
+    /// Per event class 1-in-N sample rates, overriding sample-rate
+    pub class_sample_rates: BTreeMap<String, u64>,
+
+    /// Event classes sampled with a probability decaying past decaying-threshold
+    /// events per decaying-window-ms of event time
+    pub decaying_classes: BTreeSet<String>,
+    pub decaying_threshold: Option<u64>,
+    pub decaying_window_ms: Option<u64>,
+}
+
+/// Limits of the event attr values and counts, see [`crate::limits`]
+#[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct AttrLimitsConfig {
+    /// Truncate string attr values longer than this many bytes
+    pub max_string_len: Option<usize>,
+
+    /// Drop the attrs of an event beyond this many
+    pub max_attrs_per_event: Option<usize>,
+}
+
+/// Time-bucketed aggregation of an event class, see [`crate::aggregation`]
+#[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct AggregationConfig {
+    /// The bucket duration, in milliseconds of event time
+    pub bucket_ms: Option<u64>,
+
+    /// The numeric fields to sum, min, and max, keyed by the event attr key
+    /// (e.g. `'event.runtime'`)
+    pub fields: BTreeSet<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct EventMappingConfig {
+    /// Place the payload-derived event attrs under `event.<prefix>.<field>`
+    /// instead of `event.<field>`
+    pub event_attr_prefix: Option<String>,
+
+    /// Force payload fields to a target attr value type, keyed by the
+    /// event attr key (e.g. `'event.status' = 'integer'`)
+    #[serde(deserialize_with = "deserialize_event_attr_key_map")]
+    pub field_types: BTreeMap<String, FieldType>,
+
+    /// Integer payload fields to also render as a hex string `<key>.hex` attr,
+    /// keyed by the event attr key (e.g. `'event.ip'`)
+    #[serde(deserialize_with = "deserialize_event_attr_keys")]
+    pub hex_fields: BTreeSet<String>,
+
+    /// Render the hex-fields as a hex string in place of the integer value
+    pub hex_fields_replace: bool,
+
+    /// Round the floating point payload values to this many significant digits
+    pub float_significant_digits: Option<u32>,
+
+    /// Floating point payload fields converted to integers, multiplied by the
+    /// scale factor and rounded, keyed by the event attr key (e.g. `'event.temp' = 1000`)
+    #[serde(deserialize_with = "deserialize_event_attr_key_map")]
+    pub float_scale_fields: BTreeMap<String, u64>,
+
+    /// String payload fields parsed into timestamps, keyed by the event attr key,
+    /// with the format of the string (e.g. `'event.logged_at' = 'rfc3339'`)
+    #[serde(deserialize_with = "deserialize_event_attr_key_map")]
+    pub timestamp_formats: BTreeMap<String, TimestampFormat>,
+
+    /// Sensitive payload fields whose values are replaced by an HMAC-SHA256
+    /// keyed by hash-salt, keyed by the event attr key (e.g. `'event.user'`).
+    /// Equal values produce equal hashes so the attrs can still be joined on.
+    #[serde(deserialize_with = "deserialize_event_attr_keys")]
+    pub hash_fields: BTreeSet<String>,
+
+    /// The secret key of the hash-fields hashes, required when hash-fields is set
+    pub hash_salt: Option<String>,
+
+    /// Address payload fields to resolve into a `<key>.symbol` attr,
+    /// keyed by the event attr key (e.g. `'event.ip'`)
+    #[serde(deserialize_with = "deserialize_event_attr_keys")]
+    pub symbolize_fields: BTreeSet<String>,
+
+    /// A `/proc/kallsyms` dump or `System.map` file used to resolve the
+    /// symbolize-fields kernel addresses
+    pub kallsyms: Option<PathBuf>,
+
+    /// Userspace or bare-metal ELF binaries used to resolve the symbolize-fields
+    /// addresses into function names and, with debug info, `file:line` locations
+    pub elf_binaries: Vec<ElfBinaryConfig>,
+
+    /// A WASM module whose `transform` function is applied to the attrs of each event
+    pub wasm_transform: Option<PathBuf>,
+
+    /// A Rhai script run on the attrs of each event
+    pub script_transform: Option<PathBuf>,
+
+    /// The operation budget of each script-transform run
+    pub script_max_operations: Option<u64>,
+
+    /// How to resolve a payload field and a context field mapping to the same attr key
+    pub attr_conflict_policy: AttrConflictPolicy,
+
+    /// Timeline IDs of the remote timeline names found in `remote_timeline_name`
+    /// payload fields, looked up before the names of the imported timelines
+    pub remote_timeline_names: BTreeMap<String, Uuid>,
+
+    /// Additional well-known event names, keyed by their C-identifier-like event
+    /// class name (e.g. `'acme_link_up' = 'acme.link.up'`). The events are renamed and
+    /// get the reserved event field mappings, like the built-in `modality_*` events.
+    pub reserved_event_names: BTreeMap<String, String>,
+
+    /// Tag the events with the `cpu_id` packet context field of the per-CPU streams
+    /// as `event.cpu`, and the timelines of unmerged streams as `timeline.cpu`,
+    /// set by the LTTng profiles
+    pub cpu_attrs: bool,
+}
+
+impl EventMappingConfig {
+    /// The mapping configuration of a merged stream's payload attrs, placed under
+    /// `event.<event-attr-prefix>.<namespace>`. The field keyed settings match
+    /// both the un-namespaced and the namespaced attr keys.
+    pub fn with_payload_namespace(&self, namespace: &str) -> Self {
+        let prefix = match self.event_attr_prefix.as_deref() {
+            Some(prefix) => format!("{prefix}.{namespace}"),
+            None => namespace.to_owned(),
+        };
+        let namespaced_key = |k: &String| match self.event_attr_prefix.as_deref() {
+            Some(old) => k
+                .strip_prefix(old)
+                .and_then(|f| f.strip_prefix('.'))
+                .map(|f| format!("{prefix}.{f}")),
+            None => Some(format!("{prefix}.{k}")),
+        };
+        fn namespaced_map<V: Clone>(
+            m: &BTreeMap<String, V>,
+            key: impl Fn(&String) -> Option<String>,
+        ) -> BTreeMap<String, V> {
+            // The explicitly namespaced keys take precedence
+            m.iter()
+                .filter_map(|(k, v)| key(k).map(|k| (k, v.clone())))
+                .chain(m.iter().map(|(k, v)| (k.clone(), v.clone())))
+                .collect()
+        }
+        fn namespaced_set(
+            s: &BTreeSet<String>,
+            key: impl Fn(&String) -> Option<String>,
+        ) -> BTreeSet<String> {
+            s.iter().filter_map(key).chain(s.iter().cloned()).collect()
+        }
+
+        let mut cfg = self.clone();
+        cfg.field_types = namespaced_map(&self.field_types, namespaced_key);
+        cfg.hex_fields = namespaced_set(&self.hex_fields, namespaced_key);
+        cfg.float_scale_fields = namespaced_map(&self.float_scale_fields, namespaced_key);
+        cfg.timestamp_formats = namespaced_map(&self.timestamp_formats, namespaced_key);
+        cfg.hash_fields = namespaced_set(&self.hash_fields, namespaced_key);
+        cfg.symbolize_fields = namespaced_set(&self.symbolize_fields, namespaced_key);
+        cfg.event_attr_prefix = Some(prefix);
+        cfg
+    }
+}
 
+/// The event mapping configuration of each event's stream, the merged streams
+/// get a namespaced configuration with `merge-stream-namespace`
+#[derive(Debug, Default)]
+pub struct StreamEventMappings {
+    namespaced: HashMap<String, EventMappingConfig>,
+}
+
+impl StreamEventMappings {
+    /// `stream_name` is `None` for a stream without properties, which isn't namespaced
+    pub fn get<'a>(
+        &'a mut self,
+        cfg: &'a PluginConfig,
+        stream_id: u64,
+        stream_name: Option<&str>,
+    ) -> &'a EventMappingConfig {
+        let namespace = cfg
+            .merge_stream_id
+            .and(stream_name)
+            .and_then(|name| cfg.merge_stream_namespace.namespace(stream_id, name));
+        match namespace {
+            Some(ns) => self
+                .namespaced
+                .entry(ns)
+                .or_insert_with_key(|ns| cfg.event_mapping.with_payload_namespace(ns)),
+            None => &cfg.event_mapping,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ElfBinaryConfig {
+    pub path: PathBuf,
+
+    /// Address the binary is loaded at, subtracted from the field addresses to get
+    /// the ELF virtual addresses. Zero for binaries that aren't relocated.
+    pub load_address: u64,
+}
+
+/// The clock class corrections shared by the importer and the LTTng collector.
+///
+/// The importer passes them to the ctf.fs source, the lttng-live source has no
+/// clock class parameters so the collector applies them to the decoded events.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ClockClassConfig {
     /// See <https://babeltrace.org/docs/v2.0/man7/babeltrace2-source.ctf.fs.7/#doc-param-clock-class-offset-ns>
     pub clock_class_offset_ns: Option<i64>,
 
@@ -72,10 +750,75 @@ pub struct ImportConfig {
     pub clock_class_offset_s: Option<i64>,
 
     /// See <https://babeltrace.org/docs/v2.0/man7/babeltrace2-source.ctf.fs.7/#doc-param-force-clock-class-origin-unix-epoch>
-    pub force_clock_class_origin_unix_epoch: Option<bool>,
+    ///
+    /// Also accepts `auto` to pick it from the trace's clock classes.
+    pub force_clock_class_origin_unix_epoch: Option<UnixEpochOrigin>,
+}
+
+impl ClockClassConfig {
+    /// The combined clock-class-offset-s and clock-class-offset-ns, in nanoseconds,
+    /// for sources that apply it to the decoded event timestamps
+    pub fn offset_total_ns(&self) -> Option<i64> {
+        if self.clock_class_offset_s.is_none() && self.clock_class_offset_ns.is_none() {
+            return None;
+        }
+        Some(
+            self.clock_class_offset_s
+                .unwrap_or(0)
+                .saturating_mul(1_000_000_000)
+                .saturating_add(self.clock_class_offset_ns.unwrap_or(0)),
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ImportConfig {
+    /// See <https://babeltrace.org/docs/v2.0/man7/babeltrace2-source.ctf.fs.7/#doc-param-trace-name>
+    pub trace_name: Option<String>,
 
     /// See <https://babeltrace.org/docs/v2.0/man7/babeltrace2-source.ctf.fs.7/#doc-param-inputs>
     pub inputs: Vec<PathBuf>,
+
+    /// Treat each input as an independent trace, importing up to this many inputs in parallel
+    pub parallel_inputs: Option<usize>,
+
+    /// Import the inputs with different trace UUIDs as separate traces,
+    /// instead of failing
+    pub group_inputs_by_trace_uuid: bool,
+
+    /// Check babeltrace can open every input as a CTF trace before importing
+    /// anything, instead of warning about the ones that don't
+    pub strict_inputs: bool,
+
+    /// Record the content digest of each imported trace in this file and skip
+    /// traces that were already imported
+    pub state_file: Option<PathBuf>,
+
+    /// Import the traces recorded in the state file again, reusing the
+    /// run ID and timeline IDs from the previous import
+    pub reimport: bool,
+
+    /// Skip the events of a trace that were already imported from a previous
+    /// snapshot of the same session, as recorded in the state file
+    pub dedup_snapshots: bool,
+
+    /// Only register the timelines of the streams that have events, instead of
+    /// one for every stream in the metadata
+    pub skip_empty_timelines: bool,
+
+    /// Write the field to attr key mapping of each event class to this JSON file
+    pub mapping_report: Option<PathBuf>,
+
+    /// Only import this shard of the streams, see [`Shard`]
+    pub shard: Option<Shard>,
+
+    /// Keep importing the traces copied into this directory, instead of the inputs,
+    /// skipping the ones recorded in the state file
+    pub watch_dir: Option<PathBuf>,
+
+    /// How often the watched directory is scanned for new traces
+    pub watch_interval_ms: Option<u64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
@@ -85,13 +828,63 @@ pub struct LttngLiveConfig {
     /// the graph later, retry in retry-duration-us µs
     pub retry_duration_us: RetryDurationUs,
 
+    /// When set, consecutive retries back off exponentially (with jitter)
+    /// starting from retry-duration-us up to max-retry-duration-us µs
+    pub max_retry_duration_us: Option<RetryDurationUs>,
+
+    /// Give up after this many consecutive retries
+    pub max_retries: Option<u64>,
+
+    /// Give up after the graph hasn't made progress for max-idle-duration-us µs
+    pub max_idle_duration_us: Option<RetryDurationUs>,
+
+    /// Drop the events whose timestamp is older than the newest one seen on
+    /// their timeline by more than this many milliseconds
+    pub stale_event_horizon_ms: Option<u64>,
+
     /// See
     /// <https://babeltrace.org/docs/v2.0/man7/babeltrace2-source.ctf.lttng-live.7/#doc-param-session-not-found-action>
     pub session_not_found_action: SessionNotFoundAction,
 
+    /// With session-not-found-action end, exit with an error instead of success
+    /// when the session was never found
+    pub require_session: bool,
+
+    /// Give up waiting for the CTF metadata of the session after this many seconds
+    pub metadata_timeout_s: Option<u64>,
+
+    /// What to do when the metadata timeout expires, fail (the default) or end
+    pub on_metadata_timeout: MetadataTimeoutAction,
+
     /// See
     /// <https://babeltrace.org/docs/v2.0/man7/babeltrace2-source.ctf.lttng-live.7/#doc-param-inputs>
     pub url: Option<Url>,
+
+    /// Serve the control commands (status, stats, flush, pause, resume,
+    /// update-filters) on this unix socket
+    pub control_socket: Option<PathBuf>,
+
+    /// Compute the ingest lag, the wall-clock time elapsed since the timestamp
+    /// of the newest ingested event, every lag-interval-ms milliseconds
+    pub lag_interval_ms: Option<u64>,
+
+    /// Also emit the ingest lag as a heartbeat event each lag-interval-ms
+    pub lag_heartbeat_events: bool,
+
+    /// When no events were sent for this long, check the ingest connection with
+    /// a flush round trip so an idle connection isn't dropped by middleboxes
+    pub keepalive_interval_ms: Option<u64>,
+
+    /// Serve Prometheus metrics over HTTP on this address
+    pub metrics_listen_addr: Option<SocketAddr>,
+
+    /// When the url's TGTHOST or SESSION has wildcards, look for new
+    /// matching sessions this often, defaults to 5000
+    pub session_discovery_interval_ms: Option<u64>,
+}
+
+impl LttngLiveConfig {
+    pub const DEFAULT_SESSION_DISCOVERY_INTERVAL_MS: u64 = 5000;
 }
 
 impl CtfConfig {
@@ -99,34 +892,156 @@ impl CtfConfig {
         rf_opts: ReflectorOpts,
         bt_opts: BabeltraceOpts,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let cfg = if let Some(cfg_path) = &rf_opts.config_file {
-            modality_reflector_config::try_from_file(cfg_path)?
-        } else if let Ok(env_path) = env::var(CONFIG_ENV_VAR) {
-            modality_reflector_config::try_from_file(Path::new(&env_path))?
-        } else {
-            Config::default()
+        let cfg_path = rf_opts
+            .config_file
+            .clone()
+            .or_else(|| env::var(CONFIG_ENV_VAR).ok().map(PathBuf::from));
+        let cfg = match &cfg_path {
+            Some(cfg_path) => modality_reflector_config::try_from_file(cfg_path)?,
+            None => Config::default(),
         };
-
         let mut ingest = cfg.ingest.clone().unwrap_or_default();
         if let Some(url) = &rf_opts.protocol_parent_url {
             ingest.protocol_parent_url = Some(url.clone());
+        } else if let Some(port) = ingest.protocol_child_port {
+            // Running as a reflector child plugin, the parent URL is the reflector's own
+            // upstream, and the reflector accepts the child connections on this port
+            ingest.protocol_parent_url =
+                Some(Url::parse(&format!("modality-ingest://127.0.0.1:{port}"))?);
         }
         if rf_opts.allow_insecure_tls {
             ingest.allow_insecure_tls = true;
         }
 
-        let plugin_cfg: PluginConfig =
-            TomlValue::Table(cfg.metadata.into_iter().collect()).try_into()?;
-        let plugin = PluginConfig {
-            run_id: rf_opts.run_id.or(plugin_cfg.run_id),
+        let table = TomlValue::Table(cfg.metadata.into_iter().collect());
+        let (mut plugin_cfg, ignored) = PluginConfig::from_table(table)?;
+        // The files named in the config file are relative to its directory
+        if let Some(dir) = cfg_path.as_deref().and_then(Path::parent) {
+            plugin_cfg.resolve_paths(dir);
+        }
+        let on_unknown_key = bt_opts.on_unknown_key.unwrap_or(plugin_cfg.on_unknown_key);
+        let unknown_keys = match on_unknown_key {
+            UnknownKeyPolicy::Ignore => Vec::new(),
+            _ => PluginConfig::find_unknown_keys(ignored),
+        };
+        if on_unknown_key == UnknownKeyPolicy::Fail && !unknown_keys.is_empty() {
+            let keys: Vec<String> = unknown_keys.iter().map(|k| k.to_string()).collect();
+            return Err(ConfigError::UnknownKeys(keys.join(", ")).into());
+        }
+        let mut event_mapping = plugin_cfg.event_mapping;
+        if !event_mapping.hash_fields.is_empty() && event_mapping.hash_salt.is_none() {
+            return Err(ConfigError::MissingKey("hash-fields", "hash-salt").into());
+        }
+        if let Some(prefix) = bt_opts.event_attr_prefix {
+            event_mapping.event_attr_prefix = prefix.into();
+        }
+        if let Some(prefix) = event_mapping.event_attr_prefix.as_deref() {
+            validate_event_attr_prefix(prefix)
+                .map_err(|msg| ConfigError::InvalidKey(KeyPath::from("event-attr-prefix"), msg))?;
+        }
+        if let Some(p) = bt_opts.kallsyms {
+            event_mapping.kallsyms = p.into();
+        }
+        // Resolved once so the diagnostics timeline and the trace timelines of a run agree
+        let configured_run_id = rf_opts.run_id.or(plugin_cfg.run_id);
+        let mut plugin = PluginConfig {
+            profile: bt_opts.profile.unwrap_or(plugin_cfg.profile),
+            run_id: configured_run_id.or_else(|| Some(Uuid::new_v4())),
+            generated_run_id: configured_run_id.is_none(),
             trace_uuid: bt_opts.trace_uuid.or(plugin_cfg.trace_uuid),
+            trace_uuid_from_env: bt_opts
+                .trace_uuid_from_env
+                .or(plugin_cfg.trace_uuid_from_env),
             log_level: bt_opts.log_level.unwrap_or(plugin_cfg.log_level),
+            min_log_level: bt_opts.min_log_level.or(plugin_cfg.min_log_level),
+            clock_class: plugin_cfg.clock_class,
             import: plugin_cfg.import,
             lttng_live: plugin_cfg.lttng_live,
             rename_timeline_attrs: plugin_cfg.rename_timeline_attrs,
             rename_event_attrs: plugin_cfg.rename_event_attrs,
+            rename_timeline_attrs_file: bt_opts
+                .rename_timeline_attrs_file
+                .or(plugin_cfg.rename_timeline_attrs_file),
+            rename_event_attrs_file: bt_opts
+                .rename_event_attrs_file
+                .or(plugin_cfg.rename_event_attrs_file),
             merge_stream_id: bt_opts.merge_stream_id.or(plugin_cfg.merge_stream_id),
+            merge_stream_namespace: bt_opts
+                .merge_stream_namespace
+                .unwrap_or(plugin_cfg.merge_stream_namespace),
+            emit_ingest_begin_event: bt_opts.emit_ingest_begin_event
+                || plugin_cfg.emit_ingest_begin_event,
+            diagnostics_timeline: bt_opts.diagnostics_timeline || plugin_cfg.diagnostics_timeline,
+            fail_on_empty: bt_opts.fail_on_empty || plugin_cfg.fail_on_empty,
+            event_attr_key_budget: bt_opts
+                .event_attr_key_budget
+                .or(plugin_cfg.event_attr_key_budget),
+            strict_attr_key_budget: bt_opts.strict_attr_key_budget
+                || plugin_cfg.strict_attr_key_budget,
+            source_uri_attrs: bt_opts.source_uri_attrs || plugin_cfg.source_uri_attrs,
+            per_cpu_streams: bt_opts.per_cpu_streams || plugin_cfg.per_cpu_streams,
+            per_cpu_timeline_prefix: None,
+            timestamp_precision_attrs: bt_opts.timestamp_precision_attrs
+                || plugin_cfg.timestamp_precision_attrs,
+            message_handling: plugin_cfg.message_handling,
+            max_events_per_timeline: bt_opts
+                .max_events_per_timeline
+                .or(plugin_cfg.max_events_per_timeline),
+            on_ingest_error: bt_opts
+                .on_ingest_error
+                .unwrap_or(plugin_cfg.on_ingest_error),
+            ingest_error_retries: bt_opts
+                .ingest_error_retries
+                .or(plugin_cfg.ingest_error_retries),
+            sampling: SamplingConfig {
+                sample_rate: bt_opts.sample_rate.or(plugin_cfg.sampling.sample_rate),
+                ..plugin_cfg.sampling
+            },
+            aggregations: plugin_cfg.aggregations,
+            attr_limits: plugin_cfg.attr_limits,
+            stream_timeline_map: plugin_cfg.stream_timeline_map,
+            stream_timeline_names: plugin_cfg.stream_timeline_names,
+            timeline_name_patterns: plugin_cfg.timeline_name_patterns,
+            pairing_rules: plugin_cfg.pairing_rules,
+            exclude_trace_env: plugin_cfg.exclude_trace_env,
+            wall_clock_origin_ns: bt_opts
+                .wall_clock_origin_ns
+                .or(plugin_cfg.wall_clock_origin_ns),
+            wall_clock_origin_env: bt_opts
+                .wall_clock_origin_env
+                .or(plugin_cfg.wall_clock_origin_env),
+            timestamp_shift_ns: bt_opts.timestamp_shift_ns.or(plugin_cfg.timestamp_shift_ns),
+            clock_correction_env: bt_opts
+                .clock_correction_env
+                .or(plugin_cfg.clock_correction_env),
+            clock_correction_event: bt_opts
+                .clock_correction_event
+                .or(plugin_cfg.clock_correction_event),
+            clock_correction_field: bt_opts
+                .clock_correction_field
+                .or(plugin_cfg.clock_correction_field),
+            timeline_attrs_event: bt_opts
+                .timeline_attrs_event
+                .or(plugin_cfg.timeline_attrs_event),
+            drain_timeout_ms: bt_opts.drain_timeout_ms.or(plugin_cfg.drain_timeout_ms),
+            wait_for_ingest_ms: plugin_cfg.wait_for_ingest_ms,
+            ingest_failover_urls: if bt_opts.ingest_failover_urls.is_empty() {
+                plugin_cfg.ingest_failover_urls
+            } else {
+                bt_opts.ingest_failover_urls
+            },
+            auth_token_file: bt_opts.auth_token_file.or(plugin_cfg.auth_token_file),
+            auth_token_command: bt_opts.auth_token_command.or(plugin_cfg.auth_token_command),
+            log_file: bt_opts.log_file.or(plugin_cfg.log_file),
+            log_file_max_size: bt_opts.log_file_max_size.or(plugin_cfg.log_file_max_size),
+            log_file_max_files: bt_opts.log_file_max_files.or(plugin_cfg.log_file_max_files),
+            log_backend: bt_opts.log_backend.unwrap_or(plugin_cfg.log_backend),
+            on_unknown_key,
+            unknown_keys,
+            event_mapping,
         };
+        ProfileRules::for_profile(plugin.profile).merge_into(&mut plugin);
+        plugin.load_rename_files()?;
 
         Ok(Self {
             auth_token: rf_opts.auth_token,
@@ -144,25 +1059,122 @@ impl CtfConfig {
         }
     }
 
-    pub fn resolve_auth(&self) -> Result<AuthTokenBytes, AuthTokenError> {
-        AuthTokenBytes::resolve(self.auth_token.as_deref())
+    /// The protocol parent URL followed by the ingest-failover-urls
+    pub fn ingest_urls(&self) -> Result<Vec<Url>, url::ParseError> {
+        let mut urls = vec![self.protocol_parent_url()?];
+        for url in self.plugin.ingest_failover_urls.iter() {
+            if !urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+        Ok(urls)
+    }
+
+    /// Whether the plugin runs as a modality-reflector child plugin
+    pub fn is_reflector_child(&self) -> bool {
+        self.ingest.protocol_child_port.is_some()
     }
+
+    pub async fn resolve_auth(&self) -> Result<AuthTokenBytes, AuthTokenError> {
+        AuthTokenBytes::resolve(
+            self.auth_token.as_deref(),
+            self.plugin.auth_token_command.as_deref(),
+            self.plugin.auth_token_file.as_deref(),
+        )
+        .await
+    }
+}
+
+/// TOML table keys are always strings, parse them as stream IDs
+fn deserialize_stream_id_map<'de, D, V>(deserializer: D) -> Result<BTreeMap<u64, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    BTreeMap::<String, V>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(k, v)| {
+            k.trim()
+                .parse::<u64>()
+                .map(|id| (id, v))
+                .map_err(|e| D::Error::custom(format!("Invalid stream ID '{k}'. {e}")))
+        })
+        .collect()
+}
+
+/// Event attr keys are stored without the implied 'event.' component
+fn deserialize_event_attr_key_map<'de, D, V>(
+    deserializer: D,
+) -> Result<BTreeMap<String, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    Ok(BTreeMap::<String, V>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(k, v)| {
+            let k = k.trim();
+            (k.strip_prefix("event.").unwrap_or(k).to_owned(), v)
+        })
+        .collect())
+}
+
+fn deserialize_event_attr_keys<'de, D>(deserializer: D) -> Result<BTreeSet<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|k| {
+            let k = k.trim();
+            k.strip_prefix("event.").unwrap_or(k).to_owned()
+        })
+        .collect())
 }
 
-impl TryFrom<&ImportConfig> for CtfPluginSourceFsInitParams {
-    type Error = babeltrace2_sys::Error;
+impl ImportConfig {
+    pub const DEFAULT_WATCH_INTERVAL_MS: u64 = 5000;
 
-    fn try_from(config: &ImportConfig) -> Result<Self, Self::Error> {
-        let trace_name: Option<CString> = config
+    pub fn watch_interval(&self) -> Duration {
+        Duration::from_millis(
+            self.watch_interval_ms
+                .unwrap_or(Self::DEFAULT_WATCH_INTERVAL_MS),
+        )
+    }
+
+    /// The babeltrace source and its parameters, to put failures in context
+    pub fn source_description(&self, clock_class: &ClockClassConfig) -> String {
+        let mut desc = format!("source.ctf.fs inputs {:?}", self.inputs);
+        if let Some(name) = &self.trace_name {
+            desc.push_str(&format!(", trace-name '{name}'"));
+        }
+        if let Some(s) = clock_class.clock_class_offset_s {
+            desc.push_str(&format!(", clock-class-offset-s {s}"));
+        }
+        if let Some(ns) = clock_class.clock_class_offset_ns {
+            desc.push_str(&format!(", clock-class-offset-ns {ns}"));
+        }
+        if let Some(origin) = clock_class.force_clock_class_origin_unix_epoch {
+            desc.push_str(&format!(", force-clock-class-origin-unix-epoch {origin}"));
+        }
+        desc
+    }
+
+    /// The ctf.fs source parameters of the inputs, with the clock class corrections
+    pub fn ctf_fs_params(
+        &self,
+        clock_class: &ClockClassConfig,
+    ) -> Result<CtfPluginSourceFsInitParams, babeltrace2_sys::Error> {
+        let trace_name: Option<CString> = self
             .trace_name
             .as_ref()
             .map(|n| CString::new(n.as_bytes()))
             .transpose()?;
 
-        let input_cstrings: Vec<CString> = config
+        let input_cstrings: Vec<CString> = self
             .inputs
             .iter()
-            .map(|p| CString::new(p.as_os_str().as_bytes()))
+            .map(|p| path_cstring(p))
             .collect::<Result<Vec<CString>, NulError>>()?;
         let inputs = input_cstrings
             .iter()
@@ -171,14 +1183,34 @@ impl TryFrom<&ImportConfig> for CtfPluginSourceFsInitParams {
 
         CtfPluginSourceFsInitParams::new(
             trace_name.as_deref(),
-            config.clock_class_offset_ns,
-            config.clock_class_offset_s,
-            config.force_clock_class_origin_unix_epoch,
+            clock_class.clock_class_offset_ns,
+            clock_class.clock_class_offset_s,
+            clock_class
+                .force_clock_class_origin_unix_epoch
+                .and_then(UnixEpochOrigin::force_param),
             &inputs,
         )
     }
 }
 
+/// The prefix is placed between `event.` and the payload field names, so each
+/// of its `.` separated components must be a non-empty name
+fn validate_event_attr_prefix(prefix: &str) -> Result<(), String> {
+    if prefix
+        .split('.')
+        .any(|c| c.is_empty() || c.contains(char::is_whitespace))
+    {
+        Err(format!("The prefix '{prefix}' must be one or more non-empty, '.' separated names without whitespace, e.g. 'ctf' or 'ctf.kernel'."))
+    } else {
+        Ok(())
+    }
+}
+
+/// Paths are passed to libbabeltrace as their raw bytes, they don't have to be UTF-8
+fn path_cstring(p: &Path) -> Result<CString, NulError> {
+    CString::new(p.as_os_str().as_bytes())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -197,12 +1229,122 @@ additional-timeline-attributes = [
 [metadata]
 run-id = 'a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d1'
 trace-uuid = 'a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d2'
+trace-uuid-from-env = 'device_serial'
 log-level = 'info'
+min-log-level = 'warning'
+profile = 'none'
+rename-event-attrs-file = 'path/renames.csv'
 trace-name = 'my-trace'
 clock-class-offset-ns = -1
 clock-class-offset-s = 2
 force-clock-class-origin-unix-epoch = true
+event-attr-prefix = 'ctf'
+hex-fields = ['event.ctf.ip']
+float-significant-digits = 4
+hash-fields = ['event.ctf.user']
+hash-salt = 'pepper'
+symbolize-fields = ['event.ctf.ip']
+kallsyms = 'path/kallsyms'
+wasm-transform = 'path/transform.wasm'
+script-transform = 'path/transform.rhai'
+script-max-operations = 5000
+attr-conflict-policy = 'suffix-context'
+cpu-attrs = true
+emit-ingest-begin-event = true
+diagnostics-timeline = true
+fail-on-empty = true
+merge-stream-namespace = 'stream-name'
+event-attr-key-budget = 500
+strict-attr-key-budget = true
+source-uri-attrs = true
+per-cpu-streams = true
+timestamp-precision-attrs = true
+max-events-per-timeline = 1000000
+on-ingest-error = 'retry'
+ingest-error-retries = 5
 inputs = ['path/traces-a', 'path/traces-b']
+parallel-inputs = 2
+group-inputs-by-trace-uuid = true
+strict-inputs = true
+state-file = 'path/state.toml'
+reimport = true
+dedup-snapshots = true
+skip-empty-timelines = true
+mapping-report = 'path/mapping.json'
+shard = '1/4'
+watch-dir = 'path/incoming'
+watch-interval-ms = 1000
+wall-clock-origin-env = 'capture_time_ns'
+timestamp-shift-ns = 3155760000000000000
+clock-correction-env = 'ptp_offset_ns'
+clock-correction-event = 'clock_sync'
+timeline-attrs-event = 'fw_timeline_attrs'
+clock-correction-field = 'offset'
+exclude-trace-env = ['hostname', 'trace_creation_datetime']
+log-file = 'path/modality-ctf-import.log'
+log-file-max-size = 1048576
+log-backend = 'stderr'
+
+[[metadata.elf-binaries]]
+path = 'path/app.elf'
+
+[[metadata.elf-binaries]]
+path = 'path/libfoo.so'
+load-address = 0x7f0000000000
+
+[metadata.float-scale-fields]
+'event.ctf.temp' = 1000
+
+[metadata.timestamp-formats]
+'event.ctf.logged_at' = 'rfc3339'
+'ctf.rtc' = '%d/%m/%Y %T'
+
+[metadata.field-types]
+'event.ctf.status' = 'integer'
+'ctf.ts' = 'timestamp'
+
+[[metadata.timeline-name-patterns]]
+stream-name = 'channel0_(\d+)$'
+timeline-name = 'cpu$1'
+
+[[metadata.pairing-rules]]
+source-event = 'msg_send'
+source-field = 'msg_id'
+destination-event = 'msg_recv'
+destination-field = 'id'
+
+[metadata.stream-timeline-map]
+0 = 'a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d3'
+2 = 'a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d4'
+
+[metadata.stream-timeline-names]
+1 = 'rig-a-kernel'
+
+[metadata.remote-timeline-names]
+gateway = 'a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d5'
+
+[metadata.reserved-event-names]
+acme_link_up = 'acme.link.up'
+
+[metadata.message-handling]
+packet-beginning = 'log'
+discarded-events = 'event'
+
+[metadata.sampling]
+sample-rate = 10
+decaying-classes = ['sched_switch']
+decaying-threshold = 100
+
+[metadata.sampling.class-sample-rates]
+irq_handler_entry = 100
+
+[metadata.aggregations.sched_stat_runtime]
+bucket-ms = 100
+fields = ['event.runtime', 'event.vruntime']
+
+[metadata.attr-limits]
+max-string-len = 1024
+max-attrs-per-event = 64
 "#;
 
     const LTTNG_LIVE_CONFIG: &str = r#"[ingest]
@@ -214,11 +1356,31 @@ additional-timeline-attributes = [
 
 [metadata]
 run-id = 'a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d1'
+clock-class-offset-ns = 5
 trace-uuid = 'a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d2'
 log-level = 'debug'
 retry-duration-us = 100
+max-retry-duration-us = 2000
+max-retries = 10
+wall-clock-origin-ns = 1700000000000000000
 session-not-found-action = 'end'
+require-session = true
+metadata-timeout-s = 30
+on-metadata-timeout = 'end'
 url = 'net://localhost/host/ubuntu-focal/my-kernel-session'
+drain-timeout-ms = 1000
+wait-for-ingest-ms = 30000
+ingest-failover-urls = ['modality-ingest://10.0.0.2:14182', 'modality-ingest://10.0.0.3:14182']
+auth-token-file = '/run/secrets/modality-auth-token'
+auth-token-command = 'vault kv get -field=token secret/modality'
+on-unknown-key = 'fail'
+control-socket = '/run/modality-lttng-live.sock'
+stale-event-horizon-ms = 60000
+lag-interval-ms = 10000
+lag-heartbeat-events = true
+keepalive-interval-ms = 30000
+metrics-listen-addr = '127.0.0.1:9464'
+session-discovery-interval-ms = 2000
 "#;
 
     #[test]
@@ -230,6 +1392,9 @@ url = 'net://localhost/host/ubuntu-focal/my-kernel-session'
             f.write_all(IMPORT_CONFIG.as_bytes()).unwrap();
             f.flush().unwrap();
         }
+        // Relative to the config file's directory
+        fs::create_dir(dir.path().join("path")).unwrap();
+        fs::write(dir.path().join("path/renames.csv"), "event.a,event.b\n").unwrap();
 
         let cfg = CtfConfig::load_merge_with_opts(
             ReflectorOpts {
@@ -269,22 +1434,188 @@ url = 'net://localhost/host/ubuntu-focal/my-kernel-session'
                     run_id: Uuid::from_str("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d1")
                         .unwrap()
                         .into(),
+                    generated_run_id: false,
                     trace_uuid: Uuid::from_str("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d2")
                         .unwrap()
                         .into(),
+                    trace_uuid_from_env: "device_serial".to_owned().into(),
                     log_level: babeltrace2_sys::LoggingLevel::Info.into(),
+                    min_log_level: EventLogLevel::Warning.into(),
+                    profile: Profile::None,
                     rename_timeline_attrs: Default::default(),
-                    rename_event_attrs: Default::default(),
+                    rename_event_attrs: vec![AttrKeyRename {
+                        original: "event.a".to_owned(),
+                        new: "event.b".to_owned(),
+                    }],
+                    rename_timeline_attrs_file: None,
+                    rename_event_attrs_file: dir.path().join("path/renames.csv").into(),
                     merge_stream_id: None,
-                    import: ImportConfig {
-                        trace_name: "my-trace".to_owned().into(),
+                    merge_stream_namespace: MergeStreamNamespace::StreamName,
+                    emit_ingest_begin_event: true,
+                    diagnostics_timeline: true,
+                    fail_on_empty: true,
+                    event_attr_key_budget: Some(500),
+                    strict_attr_key_budget: true,
+                    source_uri_attrs: true,
+                    per_cpu_streams: true,
+                    per_cpu_timeline_prefix: None,
+                    timestamp_precision_attrs: true,
+                    message_handling: MessageHandlingConfig {
+                        packet_beginning: MessageAction::Log,
+                        discarded_events: MessageAction::Event,
+                        ..Default::default()
+                    },
+                    max_events_per_timeline: Some(1_000_000),
+                    on_ingest_error: IngestErrorPolicy::Retry,
+                    ingest_error_retries: Some(5),
+                    sampling: SamplingConfig {
+                        sample_rate: Some(10),
+                        class_sample_rates: vec![("irq_handler_entry".to_owned(), 100)]
+                            .into_iter()
+                            .collect(),
+                        decaying_classes: vec!["sched_switch".to_owned()].into_iter().collect(),
+                        decaying_threshold: Some(100),
+                        decaying_window_ms: None,
+                    },
+                    aggregations: vec![(
+                        "sched_stat_runtime".to_owned(),
+                        AggregationConfig {
+                            bucket_ms: Some(100),
+                            fields: vec!["event.runtime".to_owned(), "event.vruntime".to_owned()]
+                                .into_iter()
+                                .collect(),
+                        }
+                    )]
+                    .into_iter()
+                    .collect(),
+                    attr_limits: AttrLimitsConfig {
+                        max_string_len: Some(1024),
+                        max_attrs_per_event: Some(64),
+                    },
+                    stream_timeline_map: vec![
+                        (
+                            0,
+                            Uuid::from_str("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d3").unwrap()
+                        ),
+                        (
+                            2,
+                            Uuid::from_str("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d4").unwrap()
+                        ),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    stream_timeline_names: vec![(1, "rig-a-kernel".to_owned())]
+                        .into_iter()
+                        .collect(),
+                    timeline_name_patterns: vec![TimelineNamePattern {
+                        stream_name: "channel0_(\\d+)$".parse().unwrap(),
+                        timeline_name: "cpu$1".to_owned(),
+                    }],
+                    pairing_rules: vec![PairingRule {
+                        source_event: "msg_send".to_owned(),
+                        source_field: "msg_id".to_owned(),
+                        destination_event: "msg_recv".to_owned(),
+                        destination_field: "id".to_owned(),
+                    }],
+                    exclude_trace_env: ["hostname", "trace_creation_datetime"]
+                        .into_iter()
+                        .map(str::to_owned)
+                        .collect(),
+                    wall_clock_origin_ns: None,
+                    wall_clock_origin_env: "capture_time_ns".to_owned().into(),
+                    timestamp_shift_ns: Some(3155760000000000000),
+                    clock_correction_env: "ptp_offset_ns".to_owned().into(),
+                    clock_correction_event: "clock_sync".to_owned().into(),
+                    clock_correction_field: "offset".to_owned().into(),
+                    timeline_attrs_event: "fw_timeline_attrs".to_owned().into(),
+                    drain_timeout_ms: None,
+                    wait_for_ingest_ms: None,
+                    ingest_failover_urls: Vec::new(),
+                    auth_token_file: None,
+                    auth_token_command: None,
+                    log_file: Some(dir.path().join("path/modality-ctf-import.log")),
+                    log_file_max_size: Some(1048576),
+                    log_file_max_files: None,
+                    log_backend: LogBackend::Stderr,
+                    on_unknown_key: Default::default(),
+                    unknown_keys: Vec::new(),
+                    event_mapping: EventMappingConfig {
+                        event_attr_prefix: "ctf".to_owned().into(),
+                        field_types: vec![
+                            ("ctf.status".to_owned(), FieldType::Integer),
+                            ("ctf.ts".to_owned(), FieldType::Timestamp),
+                        ]
+                        .into_iter()
+                        .collect(),
+                        hex_fields: vec!["ctf.ip".to_owned()].into_iter().collect(),
+                        hex_fields_replace: false,
+                        float_significant_digits: Some(4),
+                        float_scale_fields: vec![("ctf.temp".to_owned(), 1000)]
+                            .into_iter()
+                            .collect(),
+                        timestamp_formats: vec![
+                            ("ctf.logged_at".to_owned(), TimestampFormat::Rfc3339),
+                            (
+                                "ctf.rtc".to_owned(),
+                                TimestampFormat::Custom("%d/%m/%Y %T".to_owned()),
+                            ),
+                        ]
+                        .into_iter()
+                        .collect(),
+                        hash_fields: vec!["ctf.user".to_owned()].into_iter().collect(),
+                        hash_salt: "pepper".to_owned().into(),
+                        symbolize_fields: vec!["ctf.ip".to_owned()].into_iter().collect(),
+                        kallsyms: dir.path().join("path/kallsyms").into(),
+                        elf_binaries: vec![
+                            ElfBinaryConfig {
+                                path: dir.path().join("path/app.elf"),
+                                load_address: 0,
+                            },
+                            ElfBinaryConfig {
+                                path: dir.path().join("path/libfoo.so"),
+                                load_address: 0x7f0000000000,
+                            },
+                        ],
+                        wasm_transform: dir.path().join("path/transform.wasm").into(),
+                        script_transform: dir.path().join("path/transform.rhai").into(),
+                        script_max_operations: 5000.into(),
+                        attr_conflict_policy: AttrConflictPolicy::SuffixContext,
+                        remote_timeline_names: vec![(
+                            "gateway".to_owned(),
+                            Uuid::from_str("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d5").unwrap(),
+                        )]
+                        .into_iter()
+                        .collect(),
+                        reserved_event_names: vec![(
+                            "acme_link_up".to_owned(),
+                            "acme.link.up".to_owned(),
+                        )]
+                        .into_iter()
+                        .collect(),
+                        cpu_attrs: true,
+                    },
+                    clock_class: ClockClassConfig {
                         clock_class_offset_ns: Some(-1_i64),
                         clock_class_offset_s: 2_i64.into(),
-                        force_clock_class_origin_unix_epoch: true.into(),
+                        force_clock_class_origin_unix_epoch: UnixEpochOrigin::Enabled.into(),
+                    },
+                    import: ImportConfig {
+                        trace_name: "my-trace".to_owned().into(),
                         inputs: vec![
-                            PathBuf::from("path/traces-a"),
-                            PathBuf::from("path/traces-b")
+                            dir.path().join("path/traces-a"),
+                            dir.path().join("path/traces-b")
                         ],
+                        parallel_inputs: Some(2),
+                        group_inputs_by_trace_uuid: true,
+                        strict_inputs: true,
+                        state_file: dir.path().join("path/state.toml").into(),
+                        reimport: true,
+                        dedup_snapshots: true,
+                        skip_empty_timelines: true,
+                        mapping_report: dir.path().join("path/mapping.json").into(),
+                        shard: Shard { index: 1, count: 4 }.into(),
+                        watch_dir: dir.path().join("path/incoming").into(),
+                        watch_interval_ms: 1000.into(),
                     },
                     lttng_live: Default::default(),
                 }
@@ -340,24 +1671,460 @@ url = 'net://localhost/host/ubuntu-focal/my-kernel-session'
                     run_id: Uuid::from_str("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d1")
                         .unwrap()
                         .into(),
+                    generated_run_id: false,
                     trace_uuid: Uuid::from_str("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d2")
                         .unwrap()
                         .into(),
+                    trace_uuid_from_env: None,
                     log_level: babeltrace2_sys::LoggingLevel::Debug.into(),
+                    min_log_level: None,
+                    profile: Profile::None,
+                    clock_class: ClockClassConfig {
+                        clock_class_offset_ns: Some(5),
+                        ..Default::default()
+                    },
                     import: Default::default(),
                     rename_timeline_attrs: Default::default(),
                     rename_event_attrs: Default::default(),
+                    rename_timeline_attrs_file: None,
+                    rename_event_attrs_file: None,
                     merge_stream_id: None,
+                    merge_stream_namespace: Default::default(),
+                    emit_ingest_begin_event: false,
+                    diagnostics_timeline: false,
+                    fail_on_empty: false,
+                    event_attr_key_budget: None,
+                    strict_attr_key_budget: false,
+                    source_uri_attrs: false,
+                    per_cpu_streams: false,
+                    per_cpu_timeline_prefix: None,
+                    timestamp_precision_attrs: false,
+                    message_handling: Default::default(),
+                    max_events_per_timeline: None,
+                    on_ingest_error: Default::default(),
+                    ingest_error_retries: None,
+                    sampling: Default::default(),
+                    aggregations: Default::default(),
+                    attr_limits: Default::default(),
+                    stream_timeline_map: Default::default(),
+                    stream_timeline_names: Default::default(),
+                    timeline_name_patterns: Default::default(),
+                    pairing_rules: Default::default(),
+                    exclude_trace_env: Default::default(),
+                    wall_clock_origin_ns: Some(1_700_000_000_000_000_000),
+                    wall_clock_origin_env: None,
+                    timestamp_shift_ns: None,
+                    clock_correction_env: None,
+                    clock_correction_event: None,
+                    clock_correction_field: None,
+                    timeline_attrs_event: None,
+                    drain_timeout_ms: Some(1000),
+                    wait_for_ingest_ms: Some(30000),
+                    ingest_failover_urls: vec![
+                        Url::parse("modality-ingest://10.0.0.2:14182").unwrap(),
+                        Url::parse("modality-ingest://10.0.0.3:14182").unwrap(),
+                    ],
+                    auth_token_file: PathBuf::from("/run/secrets/modality-auth-token").into(),
+                    auth_token_command: "vault kv get -field=token secret/modality"
+                        .to_owned()
+                        .into(),
+                    log_file: None,
+                    log_file_max_size: None,
+                    log_file_max_files: None,
+                    log_backend: Default::default(),
+                    on_unknown_key: UnknownKeyPolicy::Fail,
+                    unknown_keys: Vec::new(),
+                    event_mapping: Default::default(),
                     lttng_live: LttngLiveConfig {
                         retry_duration_us: 100.into(),
+                        max_retry_duration_us: Some(2000.into()),
+                        max_retries: Some(10),
+                        max_idle_duration_us: None,
+                        stale_event_horizon_ms: Some(60000),
                         session_not_found_action: babeltrace2_sys::SessionNotFoundAction::End
                             .into(),
+                        require_session: true,
+                        metadata_timeout_s: Some(30),
+                        on_metadata_timeout: MetadataTimeoutAction::End,
                         url: Url::parse("net://localhost/host/ubuntu-focal/my-kernel-session")
                             .unwrap()
                             .into(),
+                        control_socket: PathBuf::from("/run/modality-lttng-live.sock").into(),
+                        lag_interval_ms: Some(10000),
+                        lag_heartbeat_events: true,
+                        keepalive_interval_ms: Some(30000),
+                        metrics_listen_addr: "127.0.0.1:9464".parse::<SocketAddr>().unwrap().into(),
+                        session_discovery_interval_ms: Some(2000),
                     }
                 }
             }
         );
     }
+
+    #[test]
+    fn non_utf8_input_cstring() {
+        use std::ffi::OsStr;
+
+        let input = Path::new(OsStr::from_bytes(b"traces/caf\xe9"));
+        assert_eq!(
+            path_cstring(input).unwrap().as_bytes(),
+            b"traces/caf\xe9".as_slice()
+        );
+        assert!(path_cstring(Path::new(OsStr::from_bytes(b"traces\0"))).is_err());
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg_dir = dir.path().join("cfg");
+        fs::create_dir(&cfg_dir).unwrap();
+        let path = cfg_dir.join("my_config.toml");
+        fs::write(
+            &path,
+            "[metadata]
+kallsyms = 'symbols/kallsyms'
+state-file = '/var/lib/modality/state.toml'
+log-file = 'logs/import.log'
+elf-binaries = [{ path = 'bin/app.elf' }]
+",
+        )
+        .unwrap();
+        let load = |bt_opts: BabeltraceOpts| {
+            CtfConfig::load_merge_with_opts(
+                ReflectorOpts {
+                    config_file: Some(path.to_path_buf()),
+                    ..Default::default()
+                },
+                bt_opts,
+            )
+            .unwrap()
+            .plugin
+        };
+
+        let cfg = load(Default::default());
+        assert_eq!(
+            cfg.event_mapping.kallsyms,
+            Some(cfg_dir.join("symbols/kallsyms"))
+        );
+        assert_eq!(
+            cfg.import.state_file,
+            Some(PathBuf::from("/var/lib/modality/state.toml"))
+        );
+        assert_eq!(cfg.log_file, Some(cfg_dir.join("logs/import.log")));
+        assert_eq!(
+            cfg.event_mapping.elf_binaries[0].path,
+            cfg_dir.join("bin/app.elf")
+        );
+
+        // Command line paths are relative to the working directory
+        let cfg = load(BabeltraceOpts {
+            kallsyms: PathBuf::from("kallsyms").into(),
+            ..Default::default()
+        });
+        assert_eq!(cfg.event_mapping.kallsyms, Some(PathBuf::from("kallsyms")));
+    }
+
+    #[test]
+    fn renames_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv_path = dir.path().join("renames.csv");
+        std::fs::write(
+            &csv_path,
+            "original,new\n# Old schema\nevent.a, event.b\n\n\"event.c\",event.d\n",
+        )
+        .unwrap();
+        let toml_path = dir.path().join("renames.toml");
+        std::fs::write(&toml_path, "'timeline.a' = 'timeline.b'\n").unwrap();
+
+        let mut cfg = PluginConfig {
+            rename_event_attrs: vec![AttrKeyRename {
+                original: "event.x".to_owned(),
+                new: "event.y".to_owned(),
+            }],
+            rename_event_attrs_file: csv_path.into(),
+            rename_timeline_attrs_file: toml_path.into(),
+            ..Default::default()
+        };
+        cfg.load_rename_files().unwrap();
+        let rename = |original: &str, new: &str| AttrKeyRename {
+            original: original.to_owned(),
+            new: new.to_owned(),
+        };
+        assert_eq!(
+            cfg.rename_event_attrs,
+            vec![
+                rename("event.x", "event.y"),
+                rename("event.a", "event.b"),
+                rename("event.c", "event.d"),
+            ]
+        );
+        assert_eq!(
+            cfg.rename_timeline_attrs,
+            vec![rename("timeline.a", "timeline.b")]
+        );
+
+        assert!(parse_csv_renames("event.a,event.b,event.c").is_err());
+        assert!(parse_csv_renames("event.a,").is_err());
+    }
+
+    #[test]
+    fn event_attr_key_budget_is_opt_in() {
+        assert_eq!(PluginConfig::default().event_attr_key_budget(), None);
+        let cfg = PluginConfig {
+            strict_attr_key_budget: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            cfg.event_attr_key_budget(),
+            Some(PluginConfig::DEFAULT_EVENT_ATTR_KEY_BUDGET)
+        );
+        let cfg = PluginConfig {
+            event_attr_key_budget: 5.into(),
+            ..Default::default()
+        };
+        assert_eq!(cfg.event_attr_key_budget(), Some(5));
+    }
+
+    #[test]
+    fn attr_key_rename_arg() {
+        assert_eq!(
+            parse_attr_key_rename("event.a,event.b").unwrap(),
+            AttrKeyRename {
+                original: "event.a".to_owned(),
+                new: "event.b".to_owned(),
+            }
+        );
+        assert!(parse_attr_key_rename("event.a").is_err());
+    }
+
+    #[test]
+    fn merge_stream_payload_namespace() {
+        let ns = MergeStreamNamespace::StreamName.namespace(3, "channel0.3 a");
+        assert_eq!(ns.as_deref(), Some("channel0_3_a"));
+        assert_eq!(
+            MergeStreamNamespace::StreamId.namespace(3, "channel0_3"),
+            Some("stream3".to_owned())
+        );
+        assert_eq!(MergeStreamNamespace::None.namespace(3, "channel0_3"), None);
+
+        let cfg = EventMappingConfig::default();
+        assert_eq!(
+            cfg.with_payload_namespace("stream3").event_attr_prefix,
+            Some("stream3".to_owned())
+        );
+        let cfg = EventMappingConfig {
+            event_attr_prefix: Some("ctf".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            cfg.with_payload_namespace("stream3").event_attr_prefix,
+            Some("ctf.stream3".to_owned())
+        );
+    }
+
+    #[test]
+    fn merge_stream_namespace_keeps_field_settings() {
+        let cfg = EventMappingConfig {
+            event_attr_prefix: Some("ctf".to_owned()),
+            field_types: vec![
+                ("ctf.status".to_owned(), FieldType::Integer),
+                ("ctf.stream3.code".to_owned(), FieldType::String),
+            ]
+            .into_iter()
+            .collect(),
+            hex_fields: vec!["ctf.ip".to_owned()].into_iter().collect(),
+            ..Default::default()
+        };
+        let ns_cfg = cfg.with_payload_namespace("stream3");
+        assert_eq!(
+            ns_cfg.field_types.get("ctf.stream3.status"),
+            Some(&FieldType::Integer)
+        );
+        assert_eq!(
+            ns_cfg.field_types.get("ctf.stream3.code"),
+            Some(&FieldType::String)
+        );
+        assert!(ns_cfg.hex_fields.contains("ctf.stream3.ip"));
+
+        let plugin = PluginConfig {
+            merge_stream_id: Some(0),
+            merge_stream_namespace: MergeStreamNamespace::StreamId,
+            event_mapping: cfg,
+            ..Default::default()
+        };
+        let mut mappings = StreamEventMappings::default();
+        assert_eq!(
+            mappings
+                .get(&plugin, 3, Some("channel0_3"))
+                .event_attr_prefix
+                .as_deref(),
+            Some("ctf.stream3")
+        );
+        assert_eq!(
+            mappings.get(&plugin, 3, None).event_attr_prefix.as_deref(),
+            Some("ctf")
+        );
+    }
+
+    #[test]
+    fn clock_class_offset_total() {
+        assert_eq!(ClockClassConfig::default().offset_total_ns(), None);
+        let cfg = ClockClassConfig {
+            clock_class_offset_s: Some(-2),
+            clock_class_offset_ns: Some(500),
+            ..Default::default()
+        };
+        assert_eq!(cfg.offset_total_ns(), Some(-1_999_999_500));
+        let cfg = ClockClassConfig {
+            clock_class_offset_ns: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(cfg.offset_total_ns(), Some(7));
+    }
+
+    #[test]
+    fn hash_fields_require_salt() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("my_config.toml");
+        let load = |metadata: &str| {
+            fs::write(&path, format!("[metadata]\n{metadata}")).unwrap();
+            CtfConfig::load_merge_with_opts(
+                ReflectorOpts {
+                    config_file: Some(path.to_path_buf()),
+                    ..Default::default()
+                },
+                Default::default(),
+            )
+        };
+
+        let err = load("hash-fields = ['event.user']\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The [metadata] key 'hash-fields' requires 'hash-salt' to also be set."
+        );
+        assert!(load("hash-fields = ['event.user']\nhash-salt = 'pepper'\n").is_ok());
+        assert!(load("hash-salt = 'pepper'\n").is_ok());
+    }
+
+    #[test]
+    fn event_attr_prefix_is_validated_at_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("my_config.toml");
+        let load = |metadata: &str, prefix_opt: Option<&str>| {
+            fs::write(&path, format!("[metadata]\n{metadata}")).unwrap();
+            CtfConfig::load_merge_with_opts(
+                ReflectorOpts {
+                    config_file: Some(path.to_path_buf()),
+                    ..Default::default()
+                },
+                BabeltraceOpts {
+                    event_attr_prefix: prefix_opt.map(str::to_owned),
+                    ..Default::default()
+                },
+            )
+        };
+
+        for prefix in ["", ".ctf", "ctf.", "ctf..kernel", "my ctf"] {
+            let err = load(&format!("event-attr-prefix = '{prefix}'\n"), None).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("The [metadata] key 'event-attr-prefix' is invalid. The prefix '{prefix}' must be one or more non-empty, '.' separated names without whitespace, e.g. 'ctf' or 'ctf.kernel'.")
+            );
+        }
+        assert!(load("", Some("ctf.")).is_err());
+
+        let cfg = load("event-attr-prefix = 'ctf.kernel'\n", None).unwrap();
+        assert_eq!(
+            cfg.plugin.event_mapping.event_attr_prefix.as_deref(),
+            Some("ctf.kernel")
+        );
+        assert!(load("event-attr-prefix = '.ctf'\n", Some("ctf")).is_ok());
+    }
+
+    #[test]
+    fn timeline_name_patterns_are_compiled_at_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("my_config.toml");
+        let load = |metadata: &str| {
+            fs::write(&path, format!("[metadata]\n{metadata}")).unwrap();
+            CtfConfig::load_merge_with_opts(
+                ReflectorOpts {
+                    config_file: Some(path.to_path_buf()),
+                    ..Default::default()
+                },
+                Default::default(),
+            )
+        };
+
+        let err = load(
+            "timeline-name-patterns = [{ stream-name = 'channel0_(\\d+', timeline-name = 'cpu$1' }]\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.starts_with("The [metadata] key 'timeline-name-patterns"),
+            "{err}"
+        );
+        assert!(
+            err.contains("Invalid stream name pattern 'channel0_(\\d+'"),
+            "{err}"
+        );
+
+        let cfg = load(
+            "timeline-name-patterns = [{ stream-name = 'channel0_(\\d+)$', timeline-name = 'cpu$1' }]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.plugin.timeline_name_patterns[0]
+                .stream_name
+                .regex()
+                .replace("channel0_3", "cpu$1"),
+            "cpu3"
+        );
+    }
+
+    #[test]
+    fn run_id_is_resolved_at_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("my_config.toml");
+        fs::write(&path, "[metadata]\n").unwrap();
+        let load = |run_id: Option<Uuid>| {
+            CtfConfig::load_merge_with_opts(
+                ReflectorOpts {
+                    config_file: Some(path.to_path_buf()),
+                    run_id,
+                    ..Default::default()
+                },
+                Default::default(),
+            )
+            .unwrap()
+        };
+
+        let cfg = load(None);
+        assert!(cfg.plugin.run_id.is_some());
+        assert!(cfg.plugin.generated_run_id);
+
+        let run_id = Uuid::from_u128(1);
+        let cfg = load(Some(run_id));
+        assert_eq!(cfg.plugin.run_id, Some(run_id));
+        assert!(!cfg.plugin.generated_run_id);
+    }
+
+    #[test]
+    fn field_names_are_settings() {
+        // The sections' keys aren't unknown to the table they share
+        let table: TomlValue =
+            toml::from_str("log-level = 'info'\nkallsyms = 'k'\ninputs = []").unwrap();
+        let (_, ignored) = PluginConfig::from_table(table).unwrap();
+        assert_eq!(ignored, Vec::new());
+
+        // The test configurations have nearly every setting
+        for config in [IMPORT_CONFIG, LTTNG_LIVE_CONFIG] {
+            let config: TomlValue = toml::from_str(config).unwrap();
+            let names = PluginConfig::table_field_names(&KeyPath::from("log-level"));
+            for key in config["metadata"].as_table().unwrap().keys() {
+                assert!(names.contains(&key.as_str()), "{key}");
+            }
+        }
+    }
 }
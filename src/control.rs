@@ -0,0 +1,407 @@
+//! A line-based control endpoint on a local unix socket, used to operate
+//! a long-lived live collector without restarting it.
+//!
+//! Each request is a single line, each response is zero or more lines
+//! followed by an `ok` or `error: <reason>` line. Commands:
+//! * `status` — Pause state, ingested event and timeline counts, ingest lag, and filters
+//! * `stats` — The ingested event count of each timeline
+//! * `metrics` — The Prometheus metrics, also served by [`spawn_metrics_server`]
+//! * `flush` — Write the current timeline statistics attrs
+//! * `pause`, `resume` — Pause or resume ingestion
+//! * `update-filters min-log-level=<level|none>` — Change the event log level filter
+
+use crate::diagnostics::DropCounts;
+use crate::types::{EventLogLevel, Pauser};
+use modality_api::TimelineId;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// The pause after a failed accept before accepting again, the accept errors
+/// are transient (e.g. running out of file descriptors)
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// State shared between the control endpoint and the ingest loop
+#[derive(Clone, Debug)]
+pub struct ControlState {
+    pauser: Pauser,
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    timeline_event_counts: BTreeMap<Uuid, u64>,
+    min_log_level: Option<EventLogLevel>,
+    flush_requested: bool,
+    ingest_lag_ms: Option<i64>,
+    /// The drop counts of each ingest source, e.g. each collected session
+    drop_counts: BTreeMap<String, DropCounts>,
+}
+
+impl ControlState {
+    pub fn new(pauser: Pauser, min_log_level: Option<EventLogLevel>) -> Self {
+        Self {
+            pauser,
+            shared: Arc::new(Mutex::new(Shared {
+                min_log_level,
+                ..Default::default()
+            })),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn update_event_counts<I: IntoIterator<Item = (TimelineId, u64)>>(&self, counts: I) {
+        self.lock()
+            .timeline_event_counts
+            .extend(counts.into_iter().map(|(tid, n)| (*tid.get_raw(), n)));
+    }
+
+    pub fn set_ingest_lag_ms(&self, lag_ms: i64) {
+        self.lock().ingest_lag_ms = Some(lag_ms);
+    }
+
+    pub fn update_drop_counts(&self, source: &str, counts: &DropCounts) {
+        self.lock()
+            .drop_counts
+            .insert(source.to_owned(), counts.clone());
+    }
+
+    /// The drop counts of all the ingest sources
+    pub fn drop_counts(&self) -> DropCounts {
+        let mut total = DropCounts::default();
+        for counts in self.lock().drop_counts.values() {
+            total.merge(counts);
+        }
+        total
+    }
+
+    pub fn min_log_level(&self) -> Option<EventLogLevel> {
+        self.lock().min_log_level
+    }
+
+    /// Returns true once for each `flush` request
+    pub fn take_flush_request(&self) -> bool {
+        std::mem::take(&mut self.lock().flush_requested)
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn prometheus_metrics(&self) -> String {
+        let drop_counts = self.drop_counts();
+        let shared = self.lock();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            out.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+            ));
+        };
+        metric(
+            "modality_ctf_ingested_events_total",
+            "counter",
+            "Events ingested",
+            shared
+                .timeline_event_counts
+                .values()
+                .sum::<u64>()
+                .to_string(),
+        );
+        metric(
+            "modality_ctf_timelines",
+            "gauge",
+            "Timelines with ingested events",
+            shared.timeline_event_counts.len().to_string(),
+        );
+        metric(
+            "modality_ctf_paused",
+            "gauge",
+            "Whether ingestion is paused",
+            u8::from(self.pauser.is_paused()).to_string(),
+        );
+        if let Some(lag_ms) = shared.ingest_lag_ms {
+            metric(
+                "modality_ctf_ingest_lag_seconds",
+                "gauge",
+                "Wall-clock time elapsed since the timestamp of the newest ingested event",
+                (lag_ms as f64 / 1000.0).to_string(),
+            );
+        }
+        if !drop_counts.is_empty() {
+            let name = "modality_ctf_dropped_total";
+            out.push_str(&format!(
+                "# HELP {name} Events, or attrs of events, dropped instead of being ingested\n# TYPE {name} counter\n"
+            ));
+            for (reason, count) in drop_counts.iter() {
+                out.push_str(&format!("{name}{{reason=\"{reason}\"}} {count}\n"));
+            }
+        }
+        out
+    }
+
+    /// Run a command, returning the response lines
+    pub fn handle(&self, line: &str) -> Result<Vec<String>, String> {
+        let mut parts = line.split_whitespace();
+        let cmd = match parts.next() {
+            Some(cmd) => cmd,
+            None => return Ok(Vec::new()),
+        };
+        match cmd {
+            "status" => {
+                let shared = self.lock();
+                Ok(vec![format!(
+                    "state={} events={} timelines={} lag-ms={} min-log-level={}",
+                    if self.pauser.is_paused() {
+                        "paused"
+                    } else {
+                        "running"
+                    },
+                    shared.timeline_event_counts.values().sum::<u64>(),
+                    shared.timeline_event_counts.len(),
+                    shared
+                        .ingest_lag_ms
+                        .map(|l| l.to_string())
+                        .unwrap_or_else(|| "unknown".to_owned()),
+                    shared
+                        .min_log_level
+                        .map(|l| l.to_string())
+                        .unwrap_or_else(|| "none".to_owned()),
+                )])
+            }
+            "stats" => Ok(self
+                .lock()
+                .timeline_event_counts
+                .iter()
+                .map(|(tid, count)| format!("{tid} events={count}"))
+                .collect()),
+            "metrics" => Ok(self
+                .prometheus_metrics()
+                .lines()
+                .map(str::to_owned)
+                .collect()),
+            "flush" => {
+                self.lock().flush_requested = true;
+                Ok(Vec::new())
+            }
+            "pause" => {
+                self.pauser.pause();
+                Ok(Vec::new())
+            }
+            "resume" => {
+                self.pauser.resume();
+                Ok(Vec::new())
+            }
+            "update-filters" => {
+                for filter in parts {
+                    match filter.split_once('=') {
+                        Some(("min-log-level", "none")) => self.lock().min_log_level = None,
+                        Some(("min-log-level", level)) => {
+                            self.lock().min_log_level = Some(level.parse()?)
+                        }
+                        _ => return Err(format!("Unsupported filter '{filter}'")),
+                    }
+                }
+                Ok(Vec::new())
+            }
+            _ => Err(format!("Unknown command '{cmd}'")),
+        }
+    }
+}
+
+/// Serve the Prometheus metrics to any HTTP request on a background task
+pub async fn spawn_metrics_server(addr: SocketAddr, state: ControlState) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    debug!(%addr, "Serving metrics");
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((mut stream, _)) => {
+                    let body = state.prometheus_metrics();
+                    tokio::spawn(async move {
+                        // Only GET /metrics style requests are expected, the request isn't parsed
+                        let mut request = [0; 1024];
+                        let _ = stream.read(&mut request).await;
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                            body.len()
+                        );
+                        if let Err(e) = stream.write_all(response.as_bytes()).await {
+                            debug!("Metrics connection error. {e}");
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!(%addr, "Failed to accept a metrics connection. {e}");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Listen on the unix socket, replacing a stale socket file, and
+/// serve the control commands on a background task
+pub fn spawn_control_server(path: &Path, state: ControlState) -> io::Result<()> {
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    let path = path.to_owned();
+    debug!(path = %path.display(), "Listening for control commands");
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve_connection(stream, state).await {
+                            debug!("Control connection error. {e}");
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!(path = %path.display(), "Failed to accept a control connection. {e}");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Remove the socket file left by a previous run. Anything else at the path is
+/// refused, so a mistyped control-socket setting can't delete a file.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "The control socket path '{}' exists and isn't a socket, refusing to replace it",
+                path.display()
+            ),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+async fn serve_connection(stream: UnixStream, state: ControlState) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let mut response = String::new();
+        match state.handle(&line) {
+            Ok(out) => {
+                for l in out {
+                    response.push_str(&l);
+                    response.push('\n');
+                }
+                response.push_str("ok\n");
+            }
+            Err(e) => response.push_str(&format!("error: {e}\n")),
+        }
+        writer.write_all(response.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Remove the socket file on exit
+pub struct ControlSocketGuard(pub PathBuf);
+
+impl Drop for ControlSocketGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diagnostics::DropReason;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn control_commands() {
+        let pauser = Pauser::new();
+        let state = ControlState::new(pauser.clone(), None);
+        state.update_event_counts([(TimelineId::from(Uuid::from_u128(1)), 3)]);
+
+        assert_eq!(
+            state.handle("status").unwrap(),
+            vec!["state=running events=3 timelines=1 lag-ms=unknown min-log-level=none".to_owned()]
+        );
+        state.set_ingest_lag_ms(1500);
+        assert_eq!(
+            state.handle("metrics").unwrap()[6..],
+            [
+                "# HELP modality_ctf_paused Whether ingestion is paused".to_owned(),
+                "# TYPE modality_ctf_paused gauge".to_owned(),
+                "modality_ctf_paused 0".to_owned(),
+                "# HELP modality_ctf_ingest_lag_seconds Wall-clock time elapsed since the timestamp of the newest ingested event".to_owned(),
+                "# TYPE modality_ctf_ingest_lag_seconds gauge".to_owned(),
+                "modality_ctf_ingest_lag_seconds 1.5".to_owned(),
+            ]
+        );
+        let mut a = DropCounts::default();
+        a.count(DropReason::Stale);
+        a.count(DropReason::UnknownStream);
+        let mut b = DropCounts::default();
+        b.count(DropReason::Stale);
+        state.update_drop_counts("net://localhost/host/a/s0", &a);
+        state.update_drop_counts("net://localhost/host/a/s1", &b);
+        assert_eq!(
+            state.handle("metrics").unwrap()[12..],
+            [
+                "# HELP modality_ctf_dropped_total Events, or attrs of events, dropped instead of being ingested".to_owned(),
+                "# TYPE modality_ctf_dropped_total counter".to_owned(),
+                "modality_ctf_dropped_total{reason=\"unknown-stream\"} 1".to_owned(),
+                "modality_ctf_dropped_total{reason=\"stale\"} 2".to_owned(),
+            ]
+        );
+        assert_eq!(
+            state.handle("stats").unwrap(),
+            vec!["00000000-0000-0000-0000-000000000001 events=3".to_owned()]
+        );
+
+        state.handle("pause").unwrap();
+        assert!(pauser.is_paused());
+        state.handle("resume").unwrap();
+        assert!(!pauser.is_paused());
+
+        assert!(!state.take_flush_request());
+        state.handle("flush").unwrap();
+        assert!(state.take_flush_request());
+        assert!(!state.take_flush_request());
+
+        state
+            .handle("update-filters min-log-level=warning")
+            .unwrap();
+        assert_eq!(state.min_log_level(), Some(EventLogLevel::Warning));
+        assert!(state.handle("update-filters foo=bar").is_err());
+        assert!(state.handle("bogus").is_err());
+    }
+
+    #[test]
+    fn only_stale_sockets_are_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("modality.toml");
+        std::fs::write(&file, "[ingest]").unwrap();
+        let e = remove_stale_socket(&file).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "[ingest]");
+
+        let socket = dir.path().join("control.sock");
+        remove_stale_socket(&socket).unwrap();
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        remove_stale_socket(&socket).unwrap();
+        assert!(!socket.exists());
+    }
+}
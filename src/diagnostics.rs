@@ -0,0 +1,474 @@
+//! The plugin's own operational events, ingested on a dedicated diagnostics
+//! timeline when `diagnostics-timeline` is enabled, so the health of the ingest
+//! pipeline can be analyzed alongside the trace data.
+//!
+//! The diagnostics timeline ID is derived from the run ID and the ingest source
+//! (the lttng-live session URL, each session having its own ingest connection),
+//! and its events are timestamped with the wall-clock time they happened at.
+
+use crate::attrs::{EventAttrKey, TimelineAttrKey, TIMELINE_INGEST_SOURCE_VAL};
+use crate::client::Client;
+use crate::config::PluginConfig;
+use crate::error::Error;
+use derive_more::Display;
+use modality_api::{AttrVal, BigInt, Nanoseconds, TimelineId};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Why events, or some of their attrs, were dropped instead of being ingested
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+pub enum DropReason {
+    /// The event's stream ID wasn't reported in the metadata
+    #[display(fmt = "unknown-stream")]
+    UnknownStream,
+    /// The event's timeline wasn't registered
+    #[display(fmt = "unregistered-timeline")]
+    UnregisteredTimeline,
+    /// The event was older than the stale-event-horizon-ms
+    #[display(fmt = "stale")]
+    Stale,
+    /// The event was decoded but not ingested within the drain timeout
+    #[display(fmt = "drain-timeout")]
+    DrainTimeout,
+    /// The event was left out by the sampling
+    #[display(fmt = "sampled")]
+    Sampled,
+    /// The event's timeline reached the max-events-per-timeline
+    #[display(fmt = "timeline-capped")]
+    TimelineCapped,
+    /// The event failed to be ingested and was skipped by the on-ingest-error policy
+    #[display(fmt = "ingest-error")]
+    IngestError,
+    /// The event's clock snapshot was negative, its timestamp is omitted unless
+    /// offset to a non-negative value
+    #[display(fmt = "negative-clock-snapshot")]
+    NegativeClockSnapshot,
+    /// An interaction field couldn't be mapped to its `interaction.` attr
+    #[display(fmt = "invalid-interaction-field")]
+    InvalidInteractionField,
+    /// A mutator or mutation field of a reserved event couldn't be mapped to its attr
+    #[display(fmt = "invalid-reserved-field")]
+    InvalidReservedField,
+    /// A field couldn't be parsed as a timestamp, coerced to its type, or scaled,
+    /// its value was kept as is
+    #[display(fmt = "field-conversion")]
+    FieldConversion,
+    /// An attr was truncated, coerced, or dropped to fit the ingest limits
+    #[display(fmt = "attr-limit")]
+    AttrLimit,
+    /// The event was sent but not acknowledged when the ingest connection failed
+    /// and was reconnected, it may have been lost
+    #[display(fmt = "reconnect-loss")]
+    ReconnectLoss,
+    /// The event transform failed on the event, see `wasm-transform` and `script-transform`
+    #[display(fmt = "transform-error")]
+    TransformError,
+}
+
+impl DropReason {
+    /// What was dropped, for the end-of-run summary
+    fn summary(&self) -> &'static str {
+        match self {
+            DropReason::UnknownStream => "events whose stream ID wasn't reported in the metadata",
+            DropReason::UnregisteredTimeline => "events whose timeline wasn't registered",
+            DropReason::Stale => "events older than the stale-event-horizon-ms",
+            DropReason::DrainTimeout => "events that weren't ingested within the drain timeout",
+            DropReason::Sampled => "events left out by the sampling",
+            DropReason::TimelineCapped => {
+                "events of the timelines that reached the max-events-per-timeline"
+            }
+            DropReason::IngestError => "events that failed to be ingested",
+            DropReason::NegativeClockSnapshot => {
+                "timestamps of events with a negative clock snapshot, consider setting wall-clock-origin-ns or timestamp-shift-ns"
+            }
+            DropReason::InvalidInteractionField => {
+                "interaction fields that couldn't be mapped to their interaction attr"
+            }
+            DropReason::InvalidReservedField => {
+                "mutator and mutation fields of reserved events that couldn't be mapped to their attr"
+            }
+            DropReason::FieldConversion => {
+                "timestamp format, field type, or float scale conversions that failed"
+            }
+            DropReason::AttrLimit => {
+                "attrs truncated, coerced, or dropped to fit the ingest limits (attr-limits)"
+            }
+            DropReason::ReconnectLoss => {
+                "events sent but not acknowledged before the ingest connection failed, possibly lost"
+            }
+            DropReason::TransformError => "events the event transforms failed on",
+        }
+    }
+}
+
+/// The drops of a run by reason, counted where the events are dropped
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DropCounts(BTreeMap<DropReason, u64>);
+
+impl DropCounts {
+    pub fn count(&mut self, reason: DropReason) {
+        self.add(reason, 1);
+    }
+
+    /// Count a drop, returning true if it's the first one of the reason in the run,
+    /// e.g. to only warn about it once
+    pub fn count_first(&mut self, reason: DropReason) -> bool {
+        let first = self.get(reason) == 0;
+        self.count(reason);
+        first
+    }
+
+    pub fn add(&mut self, reason: DropReason, count: u64) {
+        if count != 0 {
+            *self.0.entry(reason).or_default() += count;
+        }
+    }
+
+    pub fn get(&self, reason: DropReason) -> u64 {
+        self.0.get(&reason).copied().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (DropReason, u64)> + '_ {
+        self.0.iter().map(|(reason, count)| (*reason, *count))
+    }
+
+    /// Add the counts of another, e.g. of a concurrently collected session
+    pub fn merge(&mut self, other: &DropCounts) {
+        for (reason, count) in other.iter() {
+            *self.0.entry(reason).or_default() += count;
+        }
+    }
+
+    /// Log the drops, one line per reason
+    pub fn log_summary(&self) {
+        for (reason, count) in self.iter() {
+            match reason {
+                DropReason::Sampled => info!("Dropped {count} {}", reason.summary()),
+                _ => warn!("Dropped {count} {} ({reason})", reason.summary()),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiagnosticEvent {
+    /// Connected to the ingest endpoint
+    Connected {
+        url: String,
+    },
+    /// Re-established the connection to the ingest endpoint
+    Reconnected {
+        url: String,
+    },
+    /// The backend was asked to acknowledge the events ingested since the last flush
+    BatchFlushed {
+        event_count: u64,
+        acknowledged: bool,
+    },
+    EventsDropped {
+        count: u64,
+        reason: DropReason,
+    },
+    /// Nothing happened for longer than the watchdog period
+    WatchdogFired {
+        idle_ms: u64,
+    },
+}
+
+impl DiagnosticEvent {
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            DiagnosticEvent::Connected { .. } => "modality.ingest.connected",
+            DiagnosticEvent::Reconnected { .. } => "modality.ingest.reconnected",
+            DiagnosticEvent::BatchFlushed { .. } => "modality.ingest.batch_flushed",
+            DiagnosticEvent::EventsDropped { .. } => "modality.ingest.events_dropped",
+            DiagnosticEvent::WatchdogFired { .. } => "modality.ingest.watchdog_fired",
+        }
+    }
+}
+
+/// Records the diagnostic events, a no-op unless `diagnostics-timeline` is enabled
+#[derive(Clone, Debug)]
+pub struct Diagnostics {
+    timeline: Option<DiagnosticsTimeline>,
+
+    /// The drops of the run so far
+    drops: DropCounts,
+
+    /// The drops as of the last [`Diagnostics::record_dropped`]
+    recorded: DropCounts,
+}
+
+#[derive(Clone, Debug)]
+struct DiagnosticsTimeline {
+    timeline_id: TimelineId,
+    run_id: Uuid,
+    name: String,
+    ordering: u128,
+    registered: bool,
+}
+
+impl Diagnostics {
+    /// `source` tells apart the concurrent ingest connections of a run, e.g. the
+    /// collector's session URLs
+    pub fn new(cfg: &PluginConfig, plugin_name: &str, source: &str) -> Self {
+        let timeline = cfg.diagnostics_timeline.then(|| {
+            let run_id = cfg.run_id.unwrap_or_else(Uuid::new_v4);
+            DiagnosticsTimeline {
+                timeline_id: diagnostics_timeline_id(&run_id, source),
+                run_id,
+                name: format!("{plugin_name}.diagnostics"),
+                ordering: 0,
+                registered: false,
+            }
+        });
+        Self {
+            timeline,
+            drops: Default::default(),
+            recorded: Default::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.timeline.is_some()
+    }
+
+    /// Count a dropped event, the counts are recorded as `events_dropped`
+    /// events by [`Diagnostics::record_dropped`]
+    pub fn dropped(&mut self, reason: DropReason) {
+        self.drops.count(reason);
+    }
+
+    pub fn drops(&self) -> &DropCounts {
+        &self.drops
+    }
+
+    /// The counts to pass down to where events are dropped
+    pub fn drops_mut(&mut self) -> &mut DropCounts {
+        &mut self.drops
+    }
+
+    /// Record an `events_dropped` event for each reason events were dropped
+    /// for since the last call
+    pub async fn record_dropped(&mut self, client: &mut Client) -> Result<(), Error> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let new_drops: Vec<(DropReason, u64)> = self
+            .drops
+            .iter()
+            .map(|(reason, count)| (reason, count - self.recorded.get(reason)))
+            .filter(|(_, count)| *count != 0)
+            .collect();
+        self.recorded = self.drops.clone();
+        for (reason, count) in new_drops.into_iter() {
+            self.record(DiagnosticEvent::EventsDropped { count, reason }, client)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn record(
+        &mut self,
+        event: DiagnosticEvent,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        let timeline = match self.timeline.as_mut() {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+
+        client.open_timeline(timeline.timeline_id).await?;
+        if !timeline.registered {
+            let attrs = [
+                (
+                    client.interned_timeline_key(TimelineAttrKey::Name).await?,
+                    timeline.name.clone().into(),
+                ),
+                (
+                    client.interned_timeline_key(TimelineAttrKey::RunId).await?,
+                    timeline.run_id.to_string().into(),
+                ),
+                (
+                    client
+                        .interned_timeline_key(TimelineAttrKey::IngestSource)
+                        .await?,
+                    TIMELINE_INGEST_SOURCE_VAL.into(),
+                ),
+            ];
+            client.timeline_metadata(attrs).await?;
+            timeline.registered = true;
+        }
+
+        let now_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        let mut attrs: HashMap<_, AttrVal> = HashMap::new();
+        attrs.insert(
+            client.interned_event_key(EventAttrKey::Name).await?,
+            event.event_name().into(),
+        );
+        attrs.insert(
+            client.interned_event_key(EventAttrKey::Timestamp).await?,
+            Nanoseconds::from(now_ns).into(),
+        );
+        match event {
+            DiagnosticEvent::Connected { url } | DiagnosticEvent::Reconnected { url } => {
+                attrs.insert(
+                    client.interned_event_key(EventAttrKey::IngestUrl).await?,
+                    url.into(),
+                );
+            }
+            DiagnosticEvent::BatchFlushed {
+                event_count,
+                acknowledged,
+            } => {
+                attrs.insert(
+                    client
+                        .interned_event_key(EventAttrKey::IngestEventCount)
+                        .await?,
+                    BigInt::new_attr_val(event_count.into()),
+                );
+                attrs.insert(
+                    client
+                        .interned_event_key(EventAttrKey::IngestAcknowledged)
+                        .await?,
+                    acknowledged.into(),
+                );
+            }
+            DiagnosticEvent::EventsDropped { count, reason } => {
+                attrs.insert(
+                    client
+                        .interned_event_key(EventAttrKey::IngestEventCount)
+                        .await?,
+                    BigInt::new_attr_val(count.into()),
+                );
+                attrs.insert(
+                    client
+                        .interned_event_key(EventAttrKey::IngestDropReason)
+                        .await?,
+                    reason.to_string().into(),
+                );
+            }
+            DiagnosticEvent::WatchdogFired { idle_ms } => {
+                attrs.insert(
+                    client
+                        .interned_event_key(EventAttrKey::IngestIdleMs)
+                        .await?,
+                    BigInt::new_attr_val(idle_ms.into()),
+                );
+            }
+        }
+        if !client
+            .send_event(timeline.ordering, attrs.into_iter().collect())
+            .await?
+        {
+            self.drops.count(DropReason::IngestError);
+        }
+        timeline.ordering += 1;
+        Ok(())
+    }
+}
+
+/// The diagnostics timeline of a run's ingest source
+pub fn diagnostics_timeline_id(run_id: &Uuid, source: &str) -> TimelineId {
+    Uuid::new_v5(
+        run_id,
+        format!("modality.ingest.diagnostics:{source}").as_bytes(),
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn diagnostics_timeline_per_run() {
+        let run_id = Uuid::from_u128(1);
+        assert_eq!(
+            diagnostics_timeline_id(&run_id, ""),
+            diagnostics_timeline_id(&run_id, "")
+        );
+        assert_ne!(
+            diagnostics_timeline_id(&run_id, ""),
+            diagnostics_timeline_id(&Uuid::from_u128(2), "")
+        );
+        assert_ne!(
+            diagnostics_timeline_id(&run_id, "net://localhost/host/a/s0"),
+            diagnostics_timeline_id(&run_id, "net://localhost/host/a/s1")
+        );
+
+        let mut diagnostics = Diagnostics::new(&PluginConfig::default(), "modality-ctf-import", "");
+        assert!(!diagnostics.is_enabled());
+        diagnostics.dropped(DropReason::Stale);
+        assert_eq!(diagnostics.drops().get(DropReason::Stale), 1);
+
+        let cfg = PluginConfig {
+            diagnostics_timeline: true,
+            run_id: Some(run_id),
+            ..Default::default()
+        };
+        let mut diagnostics = Diagnostics::new(&cfg, "modality-ctf-import", "");
+        diagnostics.dropped(DropReason::Stale);
+        diagnostics.dropped(DropReason::Stale);
+        diagnostics.drops_mut().count(DropReason::UnknownStream);
+        assert_eq!(
+            diagnostics.drops().iter().collect::<Vec<_>>(),
+            vec![(DropReason::UnknownStream, 1), (DropReason::Stale, 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn records_new_drops_once() {
+        let cfg = PluginConfig {
+            diagnostics_timeline: true,
+            ..Default::default()
+        };
+        let mut client = Client::offline(Vec::new(), Vec::new());
+        let mut diagnostics = Diagnostics::new(&cfg, "modality-ctf-import", "");
+        let recorded = |d: &Diagnostics| d.timeline.as_ref().unwrap().ordering;
+
+        diagnostics.dropped(DropReason::Stale);
+        diagnostics.dropped(DropReason::Stale);
+        diagnostics.record_dropped(&mut client).await.unwrap();
+        assert_eq!(recorded(&diagnostics), 1);
+        diagnostics.record_dropped(&mut client).await.unwrap();
+        assert_eq!(recorded(&diagnostics), 1);
+
+        diagnostics.dropped(DropReason::Stale);
+        diagnostics.dropped(DropReason::Sampled);
+        diagnostics.record_dropped(&mut client).await.unwrap();
+        assert_eq!(recorded(&diagnostics), 3);
+        assert_eq!(diagnostics.drops().get(DropReason::Stale), 3);
+    }
+
+    #[test]
+    fn drop_counts() {
+        let mut a = DropCounts::default();
+        assert!(a.is_empty());
+        a.count(DropReason::DrainTimeout);
+        a.count(DropReason::DrainTimeout);
+        a.count(DropReason::Sampled);
+        let mut b = DropCounts::default();
+        b.count(DropReason::DrainTimeout);
+        b.merge(&a);
+        assert_eq!(b.get(DropReason::DrainTimeout), 3);
+        assert_eq!(b.get(DropReason::Sampled), 1);
+        assert_eq!(b.get(DropReason::Stale), 0);
+
+        assert!(b.count_first(DropReason::Stale));
+        assert!(!b.count_first(DropReason::Stale));
+        assert!(!b.count_first(DropReason::Sampled));
+        assert_eq!(b.get(DropReason::Stale), 2);
+        assert!(DropCounts::default().count_first(DropReason::Stale));
+    }
+}
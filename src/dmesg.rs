@@ -0,0 +1,230 @@
+//! Kernel ring buffer log records, as printed by `dmesg` or `dmesg --raw`.
+//!
+//! This follows the input format of babeltrace's `src.text.dmesg` component:
+//! an optional `<N>` syslog priority prefix, an optional `[seconds.micros]`
+//! timestamp relative to boot, and the message.
+
+use crate::attrs::{EventAttrKey, TimelineAttrKey};
+use crate::client::Client;
+use crate::error::Error;
+use crate::types::EventLogLevel;
+use modality_api::{AttrVal, BigInt, Nanoseconds};
+use modality_ingest_protocol::InternedAttrKey;
+
+pub const DMESG_EVENT_NAME: &str = "dmesg";
+pub const DMESG_TIMELINE_NAME: &str = "dmesg";
+pub const DMESG_INGEST_SOURCE_VAL: &str = "ctf-plugins.dmesg";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DmesgRecord {
+    pub facility: Option<u8>,
+    pub level: Option<u8>,
+
+    /// Nanoseconds since boot
+    pub timestamp_ns: Option<u64>,
+
+    pub message: String,
+}
+
+impl DmesgRecord {
+    /// Parse a line, blank lines aren't records
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end();
+        if rest.trim().is_empty() {
+            return None;
+        }
+
+        let mut facility = None;
+        let mut level = None;
+        if let Some((prio, r)) = rest
+            .strip_prefix('<')
+            .and_then(|r| r.split_once('>'))
+            .and_then(|(prio, r)| Some((prio.parse::<u16>().ok()?, r)))
+        {
+            facility = u8::try_from(prio >> 3).ok();
+            level = Some((prio & 0x7) as u8);
+            rest = r;
+        }
+
+        let mut timestamp_ns = None;
+        if let Some((ts, r)) = rest
+            .strip_prefix('[')
+            .and_then(|r| r.split_once(']'))
+            .and_then(|(ts, r)| Some((parse_timestamp_ns(ts.trim())?, r)))
+        {
+            timestamp_ns = Some(ts);
+            rest = r.strip_prefix(' ').unwrap_or(r);
+        }
+
+        Some(Self {
+            facility,
+            level,
+            timestamp_ns,
+            message: rest.to_owned(),
+        })
+    }
+
+    /// The kernel log levels share their meaning with the first syslog-style
+    /// CTF event log levels, except the kernel's debug level
+    pub fn log_level(&self) -> Option<EventLogLevel> {
+        use EventLogLevel::*;
+        Some(match self.level? {
+            0 => Emergency,
+            1 => Alert,
+            2 => Critical,
+            3 => Error,
+            4 => Warning,
+            5 => Notice,
+            6 => Info,
+            _ => Debug,
+        })
+    }
+
+    pub async fn attr_kvs(
+        &self,
+        wall_clock_origin_ns: Option<u64>,
+        client: &mut Client,
+    ) -> Result<Vec<(InternedAttrKey, AttrVal)>, Error> {
+        let mut attrs = vec![
+            (
+                client.interned_event_key(EventAttrKey::Name).await?,
+                DMESG_EVENT_NAME.into(),
+            ),
+            (
+                client
+                    .interned_event_key(EventAttrKey::Field("message".to_owned()))
+                    .await?,
+                self.message.clone().into(),
+            ),
+        ];
+        if let Some(ts) = self.timestamp_ns {
+            attrs.push((
+                client.interned_event_key(EventAttrKey::Timestamp).await?,
+                Nanoseconds::from(ts.saturating_add(wall_clock_origin_ns.unwrap_or(0))).into(),
+            ));
+        }
+        if let Some(ll) = self.log_level() {
+            attrs.push((
+                client.interned_event_key(EventAttrKey::LogLevel).await?,
+                ll.to_string().into(),
+            ));
+            attrs.push((
+                client
+                    .interned_event_key(EventAttrKey::LogLevelValue)
+                    .await?,
+                BigInt::new_attr_val(ll as i128),
+            ));
+        }
+        if let Some(facility) = self.facility {
+            attrs.push((
+                client
+                    .interned_event_key(EventAttrKey::Field("facility".to_owned()))
+                    .await?,
+                BigInt::new_attr_val(facility.into()),
+            ));
+        }
+        Ok(attrs)
+    }
+}
+
+/// Parse a `seconds.fraction` timestamp
+fn parse_timestamp_ns(ts: &str) -> Option<u64> {
+    let (secs, frac) = ts.split_once('.').unwrap_or((ts, ""));
+    if frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secs = secs.parse::<u64>().ok()?;
+    let frac_ns = if frac.is_empty() {
+        0
+    } else {
+        frac.parse::<u64>().ok()? * 10_u64.pow(9 - frac.len() as u32)
+    };
+    secs.checked_mul(1_000_000_000)?.checked_add(frac_ns)
+}
+
+/// Attrs of the timeline the records are imported to
+pub async fn timeline_attr_kvs(
+    name: &str,
+    run_id: &uuid::Uuid,
+    wall_clock_origin_ns: Option<u64>,
+    client: &mut Client,
+) -> Result<Vec<(InternedAttrKey, AttrVal)>, Error> {
+    let mut attrs = vec![
+        (
+            client.interned_timeline_key(TimelineAttrKey::Name).await?,
+            name.to_owned().into(),
+        ),
+        (
+            client.interned_timeline_key(TimelineAttrKey::RunId).await?,
+            run_id.to_string().into(),
+        ),
+        (
+            client
+                .interned_timeline_key(TimelineAttrKey::IngestSource)
+                .await?,
+            DMESG_INGEST_SOURCE_VAL.into(),
+        ),
+        (
+            client
+                .interned_timeline_key(TimelineAttrKey::ClockStyle)
+                .await?,
+            String::from(if wall_clock_origin_ns.is_some() {
+                "utc"
+            } else {
+                "relative"
+            })
+            .into(),
+        ),
+    ];
+    if let Some(origin) = wall_clock_origin_ns {
+        attrs.push((
+            client
+                .interned_timeline_key(TimelineAttrKey::StreamClockWallClockOrigin)
+                .await?,
+            Nanoseconds::from(origin).into(),
+        ));
+    }
+    Ok(attrs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_records() {
+        assert_eq!(
+            DmesgRecord::parse("<6>[    0.000000] Linux version 6.1.0"),
+            Some(DmesgRecord {
+                facility: Some(0),
+                level: Some(6),
+                timestamp_ns: Some(0),
+                message: "Linux version 6.1.0".to_owned(),
+            })
+        );
+        assert_eq!(
+            DmesgRecord::parse("[12345.678901] usb 1-1: new high-speed USB device"),
+            Some(DmesgRecord {
+                facility: None,
+                level: None,
+                timestamp_ns: Some(12_345_678_901_000),
+                message: "usb 1-1: new high-speed USB device".to_owned(),
+            })
+        );
+        assert_eq!(
+            DmesgRecord::parse("<12>systemd[1]: Started foo"),
+            Some(DmesgRecord {
+                facility: Some(1),
+                level: Some(4),
+                timestamp_ns: None,
+                message: "systemd[1]: Started foo".to_owned(),
+            })
+        );
+        assert_eq!(DmesgRecord::parse("   "), None);
+        assert_eq!(
+            DmesgRecord::parse("<7>[    1.5] x").and_then(|r| r.log_level()),
+            Some(EventLogLevel::Debug)
+        );
+    }
+}
@@ -1,9 +1,12 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
-    Babeltrace(#[from] babeltrace2_sys::Error),
+    Babeltrace(#[from] BabeltraceError),
 
     #[error("Encountered an ingest client initialization error. {0}")]
     IngestClientInitialization(#[from] modality_ingest_client::IngestClientInitializationError),
@@ -17,11 +20,183 @@ pub enum Error {
     #[error(transparent)]
     Auth(#[from] crate::auth::AuthTokenError),
 
+    #[error("The ingest protocol parent URL is invalid. {0}")]
+    ProtocolParentUrl(#[from] url::ParseError),
+
+    #[error("There are no ingest URLs to connect to")]
+    NoIngestUrls,
+
+    #[error("Reconnected to an ingest endpoint that interned the attr key '{0}' differently")]
+    ReconnectAttrKeyMismatch(String),
+
+    #[error("The ingest endpoint didn't answer the keepalive within {0:?}")]
+    KeepaliveTimeout(std::time::Duration),
+
+    #[error(transparent)]
+    State(#[from] crate::state::StateError),
+
+    #[error(transparent)]
+    Symbol(#[from] crate::symbols::SymbolError),
+
+    #[error(transparent)]
+    Transform(#[from] crate::transform::TransformError),
+
+    #[error("Failed to read the rename mapping file '{}'. {}", .0.display(), .1)]
+    RenamesFileIo(PathBuf, #[source] std::io::Error),
+
+    #[error("The rename mapping file '{}' is invalid. {}", .0.display(), .1)]
+    InvalidRenamesFile(PathBuf, String),
+
+    #[error("Declared more than {0} distinct event attr keys, the latest being '{1}'. Raise event-attr-key-budget or fix the payload schema.")]
+    EventAttrKeyBudgetExceeded(usize, String),
+
+    #[error("The log-file setting can't be used with the {0} log-backend")]
+    LogFileWithLogBackend(crate::types::LogBackend),
+
     #[error(
         "Event attribute key prefix cannot start or end with the reserved delimeter '.' character"
     )]
     InvalidAttrKeyPrefix,
 
+    #[error("The inputs contain traces with different UUIDs, which can't be imported as a single trace. Import them separately or set group-inputs-by-trace-uuid. {0}")]
+    ConflictingTraceUuids(String),
+
+    #[error("Some of the inputs aren't valid CTF traces. {0}")]
+    InvalidInputs(String),
+
+    #[error("The shards of an import must derive the same timeline IDs, but the trace has no UUID. Set trace-uuid or trace-uuid-from-env.")]
+    ShardWithoutTraceUuid,
+
     #[error("The available stream properties doesn't contain a stream ID matching the provided merge-stream-id")]
     MergeStreamIdNotFound,
 }
+
+impl From<babeltrace2_sys::Error> for Error {
+    fn from(e: babeltrace2_sys::Error) -> Self {
+        Error::Babeltrace(e.into())
+    }
+}
+
+/// A libbabeltrace2 failure, with the error causes libbabeltrace2 recorded
+/// and a description of the source (URL or inputs and parameters) that failed
+#[derive(Debug)]
+pub struct BabeltraceError {
+    error: babeltrace2_sys::Error,
+    source: Option<String>,
+    causes: Vec<String>,
+}
+
+impl BabeltraceError {
+    /// Take the error causes libbabeltrace2 recorded for the current thread,
+    /// so this must be called on the thread that ran the failing call
+    pub fn new(error: babeltrace2_sys::Error) -> Self {
+        Self {
+            error,
+            source: None,
+            causes: take_current_thread_causes(),
+        }
+    }
+
+    /// Describe the source that failed, unless already described
+    pub fn with_source<S: Into<String>>(mut self, source: S) -> Self {
+        if self.source.is_none() {
+            self.source = Some(source.into());
+        }
+        self
+    }
+}
+
+impl From<babeltrace2_sys::Error> for BabeltraceError {
+    fn from(e: babeltrace2_sys::Error) -> Self {
+        BabeltraceError::new(e)
+    }
+}
+
+impl fmt::Display for BabeltraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(
+                f,
+                "The babeltrace graph failed for {source}. {}",
+                self.error
+            )?,
+            None => write!(f, "The babeltrace graph failed. {}", self.error)?,
+        }
+        // Most recent cause first
+        for cause in self.causes.iter() {
+            write!(f, "\n  caused by: {cause}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BabeltraceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Take the libbabeltrace2 error of the current thread, if any, as its causes
+fn take_current_thread_causes() -> Vec<String> {
+    use babeltrace2_sys::ffi::*;
+
+    let to_string = |s: *const std::os::raw::c_char| {
+        if s.is_null() {
+            String::new()
+        } else {
+            unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned()
+        }
+    };
+    unsafe {
+        let error = bt_current_thread_take_error();
+        if error.is_null() {
+            return Vec::new();
+        }
+        let count = bt_error_get_cause_count(error);
+        let mut causes = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let cause = bt_error_borrow_cause_by_index(error, i);
+            causes.push(format!(
+                "{}: {} ({}:{})",
+                to_string(bt_error_cause_get_module_name(cause)),
+                to_string(bt_error_cause_get_message(cause)),
+                to_string(bt_error_cause_get_file_name(cause)),
+                bt_error_cause_get_line_number(cause),
+            ));
+        }
+        bt_error_release(error);
+        causes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::ffi::CString;
+
+    #[test]
+    fn babeltrace_error_context() {
+        let nul_error = || CString::new("a\0b").unwrap_err();
+        let e = BabeltraceError {
+            error: nul_error().into(),
+            source: None,
+            causes: vec![
+                "SRC.CTF.LTTNG-LIVE: Error connecting to the relay daemon (viewer-connection.c:42)"
+                    .to_owned(),
+                "GRAPH: Component's \"seek\" method failed (graph.c:7)".to_owned(),
+            ],
+        }
+        .with_source("source.ctf.lttng-live URL 'net://localhost/host/h/s'")
+        .with_source("ignored");
+        let bt_error = babeltrace2_sys::Error::from(nul_error()).to_string();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "The babeltrace graph failed for source.ctf.lttng-live URL 'net://localhost/host/h/s'. {bt_error}\n  \
+                 caused by: SRC.CTF.LTTNG-LIVE: Error connecting to the relay daemon (viewer-connection.c:42)\n  \
+                 caused by: GRAPH: Component's \"seek\" method failed (graph.c:7)"
+            )
+        );
+    }
+}
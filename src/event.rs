@@ -1,11 +1,23 @@
-use crate::attrs::EventAttrKey;
+use crate::attrs::{EventAttrKey, TimelineAttrKey, TIMELINE_INGEST_SOURCE_VAL};
 use crate::client::Client;
+use crate::config::{AttrLimitsConfig, EventMappingConfig};
+use crate::diagnostics::{DropCounts, DropReason};
 use crate::error::Error;
+use crate::interaction::{RemoteEvent, RemoteTimelineLookup};
+use crate::limits::apply_limits;
+use crate::mapper::{FieldMapperContext, FieldMappers};
+use crate::messages::InferredMessage;
+use crate::symbols::Symbolizer;
+use crate::timestamp::parse_timestamp;
+use crate::transform::EventTransform;
+use crate::types::{AttrConflictPolicy, EventLogLevel, FieldType, TimestampFormat};
 use babeltrace2_sys::{OwnedEvent, OwnedField, ScalarField};
-use modality_api::{AttrKey, AttrVal, BigInt, LogicalTime, Nanoseconds};
+use hmac::{Hmac, Mac};
+use modality_api::{AttrKey, AttrVal, BigInt, Nanoseconds};
 use modality_ingest_protocol::InternedAttrKey;
-use std::collections::{BTreeSet, HashMap};
-use tracing::warn;
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tracing::{debug, warn};
 use uuid::Uuid;
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -14,52 +26,60 @@ pub struct CtfEvent {
 }
 
 impl CtfEvent {
-    pub async fn new(event: &OwnedEvent, client: &mut Client) -> Result<Self, Error> {
+    pub async fn new(
+        event: &OwnedEvent,
+        cfg: &EventMappingConfig,
+        symbolizer: &Symbolizer,
+        remote_timelines: RemoteTimelineLookup<'_>,
+        mappers: &FieldMappers,
+        drops: &mut DropCounts,
+        client: &mut Client,
+    ) -> Result<Self, Error> {
         let mut attrs = HashMap::new();
 
         let mut is_reserved_event = false;
         if let Some(n) = event.class_properties.name.as_deref() {
-            // Convert the well-known modality event names from their C-identifier-like names
-            let (event_name, reserved_event) = match n {
-                "modality_mutator_announced" => ("modality.mutator.announced", true),
-                "modality_mutator_retired" => ("modality.mutator.retired", true),
-                "modality_mutation_command_communicated" => {
-                    ("modality.mutation.command_communicated", true)
-                }
-                "modality_mutation_clear_communicated" => {
-                    ("modality.mutation.clear_communicated", true)
-                }
-                "modality_mutation_triggered" => ("modality.mutation.triggered", true),
-                "modality_mutation_injected" => ("modality.mutation.injected", true),
-                _ => (n, false),
-            };
-            is_reserved_event = reserved_event;
+            // Convert the well-known event names from their C-identifier-like names
+            let reserved_event_name = reserved_event_name(cfg, n);
+            is_reserved_event = reserved_event_name.is_some();
+            let event_name = reserved_event_name.unwrap_or(n);
             attrs.insert(
                 client.interned_event_key(EventAttrKey::Name).await?,
                 event_name.to_owned().into(),
             );
         }
 
-        let timestamp_ns: Option<u64> = event.clock_snapshot.and_then(|c: i64| {
-                if c < 0 {
-                    warn!("Dropping Event ID {} clock snapshot because it's negative, consider adjusting the origin epoch offset input parameter",
-                          event.class_properties.id);
-                    None
-                } else {
-                    Some(c as u64)
+        match event.clock_snapshot {
+            Some(c) if c >= 0 => {
+                attrs.insert(
+                    client.interned_event_key(EventAttrKey::Timestamp).await?,
+                    Nanoseconds::from(c as u64).into(),
+                );
+                attrs.insert(
+                    client
+                        .interned_event_key(EventAttrKey::ClockSnapshot)
+                        .await?,
+                    Nanoseconds::from(c as u64).into(),
+                );
+            }
+            Some(c) => {
+                // Clocks anchored before the Unix epoch, the signed clock snapshot is kept
+                // under its own key and the timestamp is only set once offset into the
+                // representable range
+                if drops.count_first(DropReason::NegativeClockSnapshot) {
+                    warn!(
+                        "Event ID {} has a negative clock snapshot, its timestamp is omitted unless offset to a non-negative value, consider setting wall-clock-origin-ns or timestamp-shift-ns",
+                        event.class_properties.id
+                    );
                 }
-            });
-        if let Some(ts) = timestamp_ns {
-            attrs.insert(
-                client.interned_event_key(EventAttrKey::Timestamp).await?,
-                Nanoseconds::from(ts).into(),
-            );
-            attrs.insert(
-                client
-                    .interned_event_key(EventAttrKey::ClockSnapshot)
-                    .await?,
-                Nanoseconds::from(ts).into(),
-            );
+                attrs.insert(
+                    client
+                        .interned_event_key(EventAttrKey::SignedClockSnapshot)
+                        .await?,
+                    c.into(),
+                );
+            }
+            None => (),
         }
 
         attrs.insert(
@@ -70,10 +90,23 @@ impl CtfEvent {
             client.interned_event_key(EventAttrKey::Id).await?,
             BigInt::new_attr_val(event.class_properties.id.into()),
         );
-        if let Some(ll) = event.class_properties.log_level {
+        if let Some(ll) = EventLogLevel::from_event(event) {
             attrs.insert(
                 client.interned_event_key(EventAttrKey::LogLevel).await?,
-                format!("{ll:?}").to_lowercase().into(),
+                ll.to_string().into(),
+            );
+            attrs.insert(
+                client
+                    .interned_event_key(EventAttrKey::LogLevelValue)
+                    .await?,
+                (ll as i64).into(),
+            );
+        }
+
+        if let Some(cpu) = packet_context_cpu_id(event, cfg) {
+            attrs.insert(
+                client.interned_event_key(EventAttrKey::Cpu).await?,
+                BigInt::new_attr_val(cpu.into()),
             );
         }
 
@@ -82,7 +115,7 @@ impl CtfEvent {
             .properties
             .common_context
             .as_ref()
-            .map(|f| field_to_attr(f, EMPTY_PREFIX, false, false))
+            .map(|f| field_to_attr(f, EMPTY_PREFIX, false, false, mappers))
             .transpose()?
             .unwrap_or_default();
         for (k, v) in common_context.into_iter() {
@@ -98,7 +131,7 @@ impl CtfEvent {
             .properties
             .specific_context
             .as_ref()
-            .map(|f| field_to_attr(f, EMPTY_PREFIX, false, false))
+            .map(|f| field_to_attr(f, EMPTY_PREFIX, false, false, mappers))
             .transpose()?
             .unwrap_or_default();
         for (k, v) in specific_context.into_iter() {
@@ -114,7 +147,7 @@ impl CtfEvent {
             .properties
             .packet_context
             .as_ref()
-            .map(|f| field_to_attr(f, EMPTY_PREFIX, false, false))
+            .map(|f| field_to_attr(f, EMPTY_PREFIX, false, false, mappers))
             .transpose()?
             .unwrap_or_default();
         for (k, v) in packet_context.into_iter() {
@@ -131,45 +164,647 @@ impl CtfEvent {
             .payload
             .as_ref()
             .map(|f| {
-                field_to_attr(
-                    f,
-                    EMPTY_PREFIX,
-                    true, // auto_map_interaction_fields,
+                payload_attr_keys_gen(
+                    cfg,
                     is_reserved_event,
+                    symbolizer,
+                    remote_timelines,
+                    mappers,
                 )
+                .map(|gen| gen.generate(f, drops))
             })
             .transpose()?
             .unwrap_or_default();
         for (k, v) in event_fields.into_iter() {
+            let key = client
+                .interned_event_key(EventAttrKey::Field(k.into()))
+                .await?;
+            if attrs.contains_key(&key) {
+                insert_conflicting_attr(
+                    &mut attrs,
+                    cfg.attr_conflict_policy,
+                    event.class_properties.name.as_deref(),
+                    key,
+                    v,
+                    client,
+                )
+                .await?;
+            } else {
+                attrs.insert(key, v);
+            }
+        }
+
+        Ok(Self { attrs })
+    }
+
+    /// The marker event emitted at the start of each timeline when
+    /// `emit-ingest-begin-event` is enabled
+    pub async fn ingest_begin(
+        run_id: Uuid,
+        plugin_name: &str,
+        client: &mut Client,
+    ) -> Result<Self, Error> {
+        let mut attrs = HashMap::new();
+        attrs.insert(
+            client.interned_event_key(EventAttrKey::Name).await?,
+            INGEST_BEGIN_EVENT_NAME.into(),
+        );
+        attrs.insert(
+            client.interned_event_key(EventAttrKey::RunId).await?,
+            run_id.to_string().into(),
+        );
+        attrs.insert(
+            client
+                .interned_event_key(EventAttrKey::IngestSource)
+                .await?,
+            TIMELINE_INGEST_SOURCE_VAL.into(),
+        );
+        attrs.insert(
+            client
+                .interned_event_key(EventAttrKey::IngestPlugin)
+                .await?,
+            plugin_name.to_owned().into(),
+        );
+        attrs.insert(
+            client
+                .interned_event_key(EventAttrKey::IngestVersion)
+                .await?,
+            env!("CARGO_PKG_VERSION").into(),
+        );
+        Ok(Self { attrs })
+    }
+
+    /// The heartbeat event emitted each `lag-interval-ms` when
+    /// `lag-heartbeat-events` is enabled
+    pub async fn ingest_lag(lag_ns: i64, now_ns: u64, client: &mut Client) -> Result<Self, Error> {
+        let mut attrs = HashMap::new();
+        attrs.insert(
+            client.interned_event_key(EventAttrKey::Name).await?,
+            INGEST_LAG_EVENT_NAME.into(),
+        );
+        attrs.insert(
+            client.interned_event_key(EventAttrKey::Timestamp).await?,
+            Nanoseconds::from(now_ns).into(),
+        );
+        attrs.insert(
+            client.interned_event_key(EventAttrKey::IngestLag).await?,
+            lag_ns.into(),
+        );
+        Ok(Self { attrs })
+    }
+
+    /// The last event of a timeline that reached the max-events-per-timeline
+    pub async fn timeline_capped(max_events: u64, client: &mut Client) -> Result<Self, Error> {
+        let mut attrs = HashMap::new();
+        attrs.insert(
+            client.interned_event_key(EventAttrKey::Name).await?,
+            TIMELINE_CAPPED_EVENT_NAME.into(),
+        );
+        attrs.insert(
+            client
+                .interned_event_key(EventAttrKey::MaxEventsPerTimeline)
+                .await?,
+            BigInt::new_attr_val(max_events.into()),
+        );
+        Ok(Self { attrs })
+    }
+
+    /// An event standing in for a babeltrace message other than an event
+    pub async fn message(msg: &InferredMessage, client: &mut Client) -> Result<Self, Error> {
+        let mut attrs = HashMap::new();
+        attrs.insert(
+            client.interned_event_key(EventAttrKey::Name).await?,
+            msg.message_type.event_name().into(),
+        );
+        if let Some(ns) = msg.timestamp_ns {
+            attrs.insert(
+                client.interned_event_key(EventAttrKey::Timestamp).await?,
+                Nanoseconds::from(ns).into(),
+            );
+        }
+        if let Some(stream_id) = msg.stream_id {
+            attrs.insert(
+                client.interned_event_key(EventAttrKey::StreamId).await?,
+                BigInt::new_attr_val(stream_id.into()),
+            );
+        }
+        if let Some(count) = msg.discarded_events {
             attrs.insert(
                 client
-                    .interned_event_key(EventAttrKey::Field(k.into()))
+                    .interned_event_key(EventAttrKey::DiscardedEvents)
                     .await?,
-                v,
+                BigInt::new_attr_val(count.into()),
             );
         }
-
         Ok(Self { attrs })
     }
 
+    /// Add the packet begin and end timestamps, from
+    /// [`CtfStreamProperties::packet_timestamps_ns`](crate::properties::CtfStreamProperties::packet_timestamps_ns)
+    pub async fn packet_timestamps(
+        &mut self,
+        (begin_ns, end_ns): (Option<u64>, Option<u64>),
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        if let Some(ns) = begin_ns {
+            self.attrs.insert(
+                client
+                    .interned_event_key(EventAttrKey::PacketBeginNs)
+                    .await?,
+                Nanoseconds::from(ns).into(),
+            );
+        }
+        if let Some(ns) = end_ns {
+            self.attrs.insert(
+                client.interned_event_key(EventAttrKey::PacketEndNs).await?,
+                Nanoseconds::from(ns).into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Record the UUID of the trace the event comes from, for when events of
+    /// several streams or traces share a timeline
+    pub async fn trace_uuid(&mut self, uuid: Uuid, client: &mut Client) -> Result<(), Error> {
+        self.attrs.insert(
+            client.interned_event_key(EventAttrKey::TraceUuid).await?,
+            uuid.to_string().into(),
+        );
+        Ok(())
+    }
+
+    /// Record the precision of the event's stream clock, the quantization error
+    /// bars of its timestamp, see
+    /// [`CtfStreamProperties::timestamp_precision_ns`](crate::properties::CtfStreamProperties::timestamp_precision_ns)
+    pub async fn timestamp_precision(
+        &mut self,
+        precision_ns: u64,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        self.attrs.insert(
+            client
+                .interned_event_key(EventAttrKey::TimestampPrecision)
+                .await?,
+            Nanoseconds::from(precision_ns).into(),
+        );
+        Ok(())
+    }
+
+    /// Record the number of events a sampled event stands for, see [`crate::sampling`]
+    pub async fn sample_rate(&mut self, rate: f64, client: &mut Client) -> Result<(), Error> {
+        self.attrs.insert(
+            client.interned_event_key(EventAttrKey::SampleRate).await?,
+            rate.into(),
+        );
+        Ok(())
+    }
+
+    /// The event timestamp, once offset by [`CtfEvent::offset_timestamp`]
+    pub async fn timestamp_ns(&self, client: &mut Client) -> Result<Option<u64>, Error> {
+        let key = client.interned_event_key(EventAttrKey::Timestamp).await?;
+        Ok(match self.attrs.get(&key) {
+            Some(AttrVal::Timestamp(ts)) => Some(ts.get_raw()),
+            _ => None,
+        })
+    }
+
+    /// Record the remote event of an interaction inferred by the `pairing-rules`,
+    /// see [`InteractionPairs`](crate::interaction::InteractionPairs)
+    pub async fn remote_event(
+        &mut self,
+        remote: RemoteEvent,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        self.attrs.insert(
+            client
+                .interned_event_key(EventAttrKey::RemoteTimelineId)
+                .await?,
+            AttrVal::TimelineId(Box::new(remote.timeline_id)),
+        );
+        if let Some(ts) = remote.timestamp_ns {
+            self.attrs.insert(
+                client
+                    .interned_event_key(EventAttrKey::RemoteTimestamp)
+                    .await?,
+                Nanoseconds::from(ts).into(),
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn source_uri(&mut self, uri: &str, client: &mut Client) -> Result<(), Error> {
+        self.attrs.insert(
+            client.interned_event_key(EventAttrKey::SourceUri).await?,
+            uri.to_owned().into(),
+        );
+        Ok(())
+    }
+
+    /// Offset the event timestamp, e.g. rebasing it onto the wall-clock origin of its
+    /// relative clock, applying a clock correction or the timestamp shift.
+    /// The clock snapshot attr keeps the original value, the packet
+    /// timestamps are offset along with the event timestamp.
+    /// A negative clock snapshot gets its timestamp here when the offset brings it
+    /// to a non-negative value, timestamps ending up out of range are removed.
+    pub async fn offset_timestamp(
+        &mut self,
+        offset_ns: i128,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        let timestamp_key = client.interned_event_key(EventAttrKey::Timestamp).await?;
+        let snapshot_key = client
+            .interned_event_key(EventAttrKey::SignedClockSnapshot)
+            .await?;
+        let timestamp_ns = match (
+            self.attrs.get(&timestamp_key),
+            self.attrs.get(&snapshot_key),
+        ) {
+            (Some(AttrVal::Timestamp(ts)), _) => Some(i128::from(ts.get_raw())),
+            (None, Some(AttrVal::Integer(c))) => Some(i128::from(*c)),
+            _ => None,
+        };
+        if let Some(ns) = timestamp_ns {
+            match offset_timestamp_ns(ns, offset_ns) {
+                Some(ts) => self
+                    .attrs
+                    .insert(timestamp_key, Nanoseconds::from(ts).into()),
+                None => self.attrs.remove(&timestamp_key),
+            };
+        }
+        for key in [EventAttrKey::PacketBeginNs, EventAttrKey::PacketEndNs] {
+            let key = client.interned_event_key(key).await?;
+            if let Some(AttrVal::Timestamp(ts)) = self.attrs.get(&key) {
+                match offset_timestamp_ns(i128::from(ts.get_raw()), offset_ns) {
+                    Some(ts) => self.attrs.insert(key, Nanoseconds::from(ts).into()),
+                    None => self.attrs.remove(&key),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the attrs with the output of the configured transforms, `false` when
+    /// a transform failed and the event is to be dropped
+    pub async fn transform(
+        &mut self,
+        transform: &mut EventTransform,
+        drops: &mut DropCounts,
+        client: &mut Client,
+    ) -> Result<bool, Error> {
+        if transform.is_empty() {
+            return Ok(true);
+        }
+        let name_key = client.interned_event_key(EventAttrKey::Name).await?;
+        let name = match self.attrs.get(&name_key) {
+            Some(AttrVal::String(n)) => Some(n.to_string()),
+            _ => None,
+        };
+        let attrs = self
+            .attrs
+            .drain()
+            .filter_map(|(k, v)| client.event_key_name(&k).map(|n| (n.to_owned(), v)))
+            .collect();
+        let attrs = match transform.apply_or_drop(name.as_deref(), attrs, drops) {
+            Some(attrs) => attrs,
+            None => return Ok(false),
+        };
+        for (k, v) in attrs.into_iter() {
+            let k = k.strip_prefix("event.").unwrap_or(&k).to_owned();
+            self.attrs
+                .insert(client.interned_event_key(EventAttrKey::Field(k)).await?, v);
+        }
+        Ok(true)
+    }
+
+    /// Fit the attrs into the configured ingest limits, see [`crate::limits`]
+    pub fn apply_limits(
+        &mut self,
+        cfg: &AttrLimitsConfig,
+        drops: &mut DropCounts,
+        client: &Client,
+    ) {
+        apply_limits(cfg, &mut self.attrs, drops, |k| {
+            client.event_key_name(k).map(str::to_owned)
+        });
+    }
+
     pub fn attr_kvs(&self) -> Vec<(InternedAttrKey, AttrVal)> {
         self.attrs.clone().into_iter().collect()
     }
 }
 
+/// The well-known modality event names, keyed by their C-identifier-like names
+const BUILTIN_RESERVED_EVENT_NAMES: &[(&str, &str)] = &[
+    ("modality_mutator_announced", "modality.mutator.announced"),
+    ("modality_mutator_retired", "modality.mutator.retired"),
+    (
+        "modality_mutation_command_communicated",
+        "modality.mutation.command_communicated",
+    ),
+    (
+        "modality_mutation_clear_communicated",
+        "modality.mutation.clear_communicated",
+    ),
+    ("modality_mutation_triggered", "modality.mutation.triggered"),
+    ("modality_mutation_injected", "modality.mutation.injected"),
+];
+
+/// The dotted name of a reserved event, the configured `reserved-event-names`
+/// take precedence over the built-in ones
+fn reserved_event_name<'a>(cfg: &'a EventMappingConfig, name: &str) -> Option<&'a str> {
+    cfg.reserved_event_names
+        .get(name)
+        .map(String::as_str)
+        .or_else(|| {
+            BUILTIN_RESERVED_EVENT_NAMES
+                .iter()
+                .find_map(|(n, dotted)| (*n == name).then_some(*dotted))
+        })
+}
+
+/// Timeline attr keys (under `timeline.`) managed by the plugin or Modality,
+/// in-band timeline attrs can't set these or anything nested below them
+const RESERVED_TIMELINE_ATTR_KEYS: &[&str] = &[
+    "id",
+    "name",
+    "description",
+    "run_id",
+    "time_domain",
+    "clock_style",
+    "ingest_source",
+    "tracer",
+    "cpu",
+    "internal",
+];
+
+fn is_reserved_timeline_attr_key(key: &str) -> bool {
+    RESERVED_TIMELINE_ATTR_KEYS.iter().any(|r| {
+        key.strip_prefix(r)
+            .map(|rest| rest.is_empty() || rest.starts_with('.'))
+            .unwrap_or(false)
+    })
+}
+
+/// The payload fields of an in-band timeline attrs event, minus the ones
+/// that would set a reserved timeline attr
+fn in_band_timeline_attrs(payload: &OwnedField) -> Result<Vec<(String, AttrVal)>, Error> {
+    let mut attrs = Vec::new();
+    let mappers = FieldMappers::default();
+    for (k, v) in field_to_attr(payload, "", false, false, &mappers)?.into_iter() {
+        let k: String = k.into();
+        if is_reserved_timeline_attr_key(&k) {
+            warn!(
+                attr_key = %format!("timeline.{k}"),
+                "Ignoring in-band timeline attr, the key is reserved"
+            );
+            continue;
+        }
+        attrs.push((k, v));
+    }
+    attrs.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(attrs)
+}
+
+/// The timeline attrs carried by an in-band timeline attrs event, each
+/// payload field `<key>` becomes the `timeline.<key>` attr, reserved keys
+/// are ignored
+pub async fn in_band_timeline_attr_kvs(
+    event: &OwnedEvent,
+    client: &mut Client,
+) -> Result<Vec<(InternedAttrKey, AttrVal)>, Error> {
+    let fields = event
+        .properties
+        .payload
+        .as_ref()
+        .map(in_band_timeline_attrs)
+        .transpose()?
+        .unwrap_or_default();
+    let mut attr_kvs = Vec::with_capacity(fields.len());
+    for (k, v) in fields.into_iter() {
+        attr_kvs.push((
+            client
+                .interned_timeline_key(TimelineAttrKey::Custom(k))
+                .await?,
+            v,
+        ));
+    }
+    Ok(attr_kvs)
+}
+
+pub const INGEST_BEGIN_EVENT_NAME: &str = "modality.ingest.begin";
+pub const INGEST_LAG_EVENT_NAME: &str = "modality.ingest.lag";
+pub const TIMELINE_CAPPED_EVENT_NAME: &str = "modality.ingest.timeline_capped";
+
+/// The offset timestamp, `None` when it's before the Unix epoch or
+/// beyond what the ingest API can represent
+fn offset_timestamp_ns(ns: i128, offset_ns: i128) -> Option<u64> {
+    u64::try_from(ns + offset_ns).ok()
+}
+
+/// Insert a payload value whose key is already taken by a context (or built-in)
+/// value, according to the conflict policy
+async fn insert_conflicting_attr(
+    attrs: &mut HashMap<InternedAttrKey, AttrVal>,
+    policy: AttrConflictPolicy,
+    event_name: Option<&str>,
+    key: InternedAttrKey,
+    payload_val: AttrVal,
+    client: &mut Client,
+) -> Result<(), Error> {
+    let name = client.event_key_name(&key).unwrap_or_default().to_owned();
+    if client.first_attr_conflict(&name) {
+        warn!(
+            "A payload field of event '{}' maps to the attr key '{name}' already taken by a context or built-in attr, applying the {policy} policy",
+            event_name.unwrap_or_default()
+        );
+    }
+    let suffixed = |suffix: &str| {
+        let name = name.strip_prefix("event.").unwrap_or(&name);
+        EventAttrKey::Field(format!("{name}.{suffix}"))
+    };
+    match policy {
+        AttrConflictPolicy::PayloadWins => {
+            attrs.insert(key, payload_val);
+        }
+        AttrConflictPolicy::ContextWins => (),
+        AttrConflictPolicy::SuffixContext => {
+            let ctx_key = client.interned_event_key(suffixed("context")).await?;
+            if let Some(ctx_val) = attrs.insert(key, payload_val) {
+                attrs.insert(ctx_key, ctx_val);
+            }
+        }
+        AttrConflictPolicy::SuffixPayload => {
+            let payload_key = client.interned_event_key(suffixed("payload")).await?;
+            attrs.insert(payload_key, payload_val);
+        }
+    }
+    Ok(())
+}
+
+/// The CPU the event was recorded on, from the `cpu_id` packet context
+/// field of per-CPU streams (e.g. LTTng kernel traces), with `cpu-attrs`
+pub fn packet_context_cpu_id(event: &OwnedEvent, cfg: &EventMappingConfig) -> Option<u64> {
+    cpu_id(event.properties.packet_context.as_ref()?, cfg)
+}
+
+/// The `cpu_id` packet context field, only taken as the CPU with `cpu-attrs`,
+/// since other tracers' fields of that name needn't be one
+fn cpu_id(packet_context: &OwnedField, cfg: &EventMappingConfig) -> Option<u64> {
+    if !cfg.cpu_attrs {
+        return None;
+    }
+    match root_scalar_field(packet_context, "cpu_id")? {
+        ScalarField::UnsignedInteger(cpu) => Some(*cpu),
+        _ => None,
+    }
+}
+
+/// The `packet_seq_num` packet context field, present in LTTng traces
+pub fn packet_context_seq_num(event: &OwnedEvent) -> Option<u64> {
+    let f = event.properties.packet_context.as_ref()?;
+    match root_scalar_field(f, "packet_seq_num")? {
+        ScalarField::UnsignedInteger(seq) => Some(*seq),
+        _ => None,
+    }
+}
+
+/// The `events_discarded` packet context field, the running count of the
+/// events the tracer discarded on the stream
+pub fn packet_context_events_discarded(event: &OwnedEvent) -> Option<u64> {
+    let f = event.properties.packet_context.as_ref()?;
+    match root_scalar_field(f, "events_discarded")? {
+        ScalarField::UnsignedInteger(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Find a scalar field by name among the root-level fields of a structure
+pub(crate) fn root_scalar_field<'a>(f: &'a OwnedField, name: &str) -> Option<&'a ScalarField> {
+    match f {
+        OwnedField::Structure(_, fields) => fields.iter().find_map(|f| match f {
+            OwnedField::Scalar(Some(n), s) if n == name => Some(s),
+            _ => None,
+        }),
+        OwnedField::Scalar(_, _) => None,
+    }
+}
+
+/// The attr key each scalar field of the event's scopes maps to, keyed by the
+/// field path rooted at its scope (e.g. `payload.next.tid`), as generated by
+/// [`CtfEvent::new`] before any renames or event transforms
+pub(crate) fn field_attr_keys(
+    event: &OwnedEvent,
+    cfg: &EventMappingConfig,
+    symbolizer: &Symbolizer,
+    remote_timelines: RemoteTimelineLookup<'_>,
+    mappers: &FieldMappers,
+) -> Result<Vec<(String, EventAttrKey)>, Error> {
+    let is_reserved_event = event
+        .class_properties
+        .name
+        .as_deref()
+        .map(|n| reserved_event_name(cfg, n).is_some())
+        .unwrap_or(false);
+    let props = &event.properties;
+    let mut keys = Vec::new();
+    if let Some(f) = &props.payload {
+        let gen = payload_attr_keys_gen(
+            cfg,
+            is_reserved_event,
+            symbolizer,
+            remote_timelines,
+            mappers,
+        )?;
+        keys.extend(scope_field_keys(gen, f, "payload", EventAttrKey::Field));
+    }
+    if let Some(f) = &props.common_context {
+        let gen = FieldToAttrKeysGen::new("", false, false)?.with_mappers(mappers);
+        keys.extend(scope_field_keys(
+            gen,
+            f,
+            "common_context",
+            EventAttrKey::CommonContext,
+        ));
+    }
+    if let Some(f) = &props.specific_context {
+        let gen = FieldToAttrKeysGen::new("", false, false)?.with_mappers(mappers);
+        keys.extend(scope_field_keys(
+            gen,
+            f,
+            "specific_context",
+            EventAttrKey::SpecificContext,
+        ));
+    }
+    if let Some(f) = &props.packet_context {
+        let gen = FieldToAttrKeysGen::new("", false, false)?.with_mappers(mappers);
+        keys.extend(scope_field_keys(
+            gen,
+            f,
+            "packet_context",
+            EventAttrKey::PacketContext,
+        ));
+    }
+    Ok(keys)
+}
+
+fn scope_field_keys<F: Fn(String) -> EventAttrKey>(
+    gen: FieldToAttrKeysGen<'_>,
+    f: &OwnedField,
+    scope: &str,
+    attr_key: F,
+) -> Vec<(String, EventAttrKey)> {
+    gen.field_keys(f)
+        .into_iter()
+        .map(|(path, k)| (format!("{scope}.{path}"), attr_key(k.into())))
+        .collect()
+}
+
+/// The generator of the payload attrs, with the configured field mappings
+fn payload_attr_keys_gen<'a>(
+    cfg: &'a EventMappingConfig,
+    is_reserved_event: bool,
+    symbolizer: &'a Symbolizer,
+    remote_timelines: RemoteTimelineLookup<'a>,
+    mappers: &'a FieldMappers,
+) -> Result<FieldToAttrKeysGen<'a>, Error> {
+    // The event attr key's 'event.' component is implied
+    let payload_prefix = cfg
+        .event_attr_prefix
+        .as_deref()
+        .map(|p| p.strip_prefix("event.").unwrap_or(p))
+        .unwrap_or_default();
+    Ok(FieldToAttrKeysGen::new(
+        payload_prefix,
+        true, // auto_map_interaction_fields,
+        is_reserved_event,
+    )?
+    .with_field_types(&cfg.field_types)
+    .with_hex_fields(&cfg.hex_fields, cfg.hex_fields_replace)
+    .with_float_rounding(cfg.float_significant_digits, &cfg.float_scale_fields)
+    .with_timestamp_formats(&cfg.timestamp_formats)
+    .with_hash_fields(&cfg.hash_fields, cfg.hash_salt.as_deref())
+    .with_symbolizer(&cfg.symbolize_fields, symbolizer)
+    .with_remote_timelines(remote_timelines)
+    .with_mappers(mappers))
+}
+
 /// Yields a map of <'<prefix>.<possibly.nested.key>', AttrVal>
 fn field_to_attr(
     f: &OwnedField,
     prefix: &str,
     auto_map_interaction_fields: bool,
     is_reserved_event: bool,
+    mappers: &FieldMappers,
 ) -> Result<HashMap<AttrKey, AttrVal>, Error> {
-    let gen = FieldToAttrKeysGen::new(prefix, auto_map_interaction_fields, is_reserved_event)?;
-    Ok(gen.generate(f))
+    let gen = FieldToAttrKeysGen::new(prefix, auto_map_interaction_fields, is_reserved_event)?
+        .with_mappers(mappers);
+    // Without any field conversions configured, only the field mappers
+    // can drop fields
+    Ok(gen.generate(f, &mut DropCounts::default()))
 }
 
 #[derive(Debug)]
-struct FieldToAttrKeysGen {
+struct FieldToAttrKeysGen<'a> {
     /// A stack of indices for each nested structure.
     /// We use this to name fields that did not come with a name
     /// since it's allowed in the spec, although unlikely in the wild.
@@ -197,10 +832,44 @@ struct FieldToAttrKeysGen {
     /// We'll consider more attr key/val transformations if so.
     is_reserved_event: bool,
 
+    /// Target attr value types for specific fields, keyed by attr key
+    field_types: Option<&'a BTreeMap<String, FieldType>>,
+
+    /// Integer fields rendered as hex strings, either as an extra
+    /// `<key>.hex` attr or in place of the integer value
+    hex_fields: Option<&'a BTreeSet<String>>,
+    hex_fields_replace: bool,
+
+    /// Float fields are rounded to this many significant digits, or converted
+    /// to scaled integers
+    float_significant_digits: Option<u32>,
+    float_scale_fields: Option<&'a BTreeMap<String, u64>>,
+
+    /// String fields parsed into timestamps, keyed by attr key
+    timestamp_formats: Option<&'a BTreeMap<String, TimestampFormat>>,
+
+    /// Fields whose values are replaced by a salted hash, and the salt
+    hash_fields: Option<(&'a BTreeSet<String>, &'a str)>,
+
+    /// Address fields resolved into an extra `<key>.symbol` attr
+    symbolize_fields: Option<(&'a BTreeSet<String>, &'a Symbolizer)>,
+
+    /// Resolves the `remote_timeline_name` and `remote_stream_id` interaction fields
+    remote_timelines: Option<RemoteTimelineLookup<'a>>,
+
+    /// The run's field mappers, no fields are mapped without them
+    mappers: Option<&'a FieldMappers>,
+
+    /// The fields that failed to be mapped or converted
+    drops: DropCounts,
+
+    /// The field path and attr key of each scalar field, see [`FieldToAttrKeysGen::field_keys`]
+    field_keys: Option<Vec<(String, AttrKey)>>,
+
     attrs: HashMap<AttrKey, AttrVal>,
 }
 
-impl FieldToAttrKeysGen {
+impl<'a> FieldToAttrKeysGen<'a> {
     /// Invariant: key_prefix must not end in a '.', this util will handle that based
     /// on compound or singular scalar types
     fn new(
@@ -217,29 +886,129 @@ impl FieldToAttrKeysGen {
                 root_struct_observed: false,
                 auto_map_interaction_fields,
                 is_reserved_event,
+                field_types: None,
+                hex_fields: None,
+                hex_fields_replace: false,
+                float_significant_digits: None,
+                float_scale_fields: None,
+                timestamp_formats: None,
+                hash_fields: None,
+                symbolize_fields: None,
+                remote_timelines: None,
+                mappers: None,
+                drops: Default::default(),
+                field_keys: None,
                 attrs: Default::default(),
             })
         }
     }
 
+    /// Coerce the values of the given fields to a target type instead of
+    /// the one implied by their field class
+    fn with_field_types(mut self, field_types: &'a BTreeMap<String, FieldType>) -> Self {
+        if !field_types.is_empty() {
+            self.field_types = Some(field_types);
+        }
+        self
+    }
+
+    fn with_mappers(mut self, mappers: &'a FieldMappers) -> Self {
+        self.mappers = Some(mappers);
+        self
+    }
+
+    fn with_symbolizer(mut self, fields: &'a BTreeSet<String>, symbolizer: &'a Symbolizer) -> Self {
+        if !fields.is_empty() && !symbolizer.is_empty() {
+            self.symbolize_fields = Some((fields, symbolizer));
+        }
+        self
+    }
+
+    fn with_remote_timelines(mut self, remote_timelines: RemoteTimelineLookup<'a>) -> Self {
+        self.remote_timelines = Some(remote_timelines);
+        self
+    }
+
+    fn with_hex_fields(mut self, hex_fields: &'a BTreeSet<String>, replace: bool) -> Self {
+        if !hex_fields.is_empty() {
+            self.hex_fields = Some(hex_fields);
+            self.hex_fields_replace = replace;
+        }
+        self
+    }
+
+    fn with_float_rounding(
+        mut self,
+        significant_digits: Option<u32>,
+        scale_fields: &'a BTreeMap<String, u64>,
+    ) -> Self {
+        self.float_significant_digits = significant_digits;
+        if !scale_fields.is_empty() {
+            self.float_scale_fields = Some(scale_fields);
+        }
+        self
+    }
+
+    fn with_timestamp_formats(
+        mut self,
+        timestamp_formats: &'a BTreeMap<String, TimestampFormat>,
+    ) -> Self {
+        if !timestamp_formats.is_empty() {
+            self.timestamp_formats = Some(timestamp_formats);
+        }
+        self
+    }
+
+    fn with_hash_fields(
+        mut self,
+        hash_fields: &'a BTreeSet<String>,
+        salt: Option<&'a str>,
+    ) -> Self {
+        // The config requires a salt alongside any hash-fields
+        if let (false, Some(salt)) = (hash_fields.is_empty(), salt) {
+            self.hash_fields = Some((hash_fields, salt));
+        }
+        self
+    }
+
     /// Destructure the contents of `root_field`
-    /// into its representative set of attr keys and values
-    fn generate(mut self, root_field: &OwnedField) -> HashMap<AttrKey, AttrVal> {
+    /// into its representative set of attr keys and values,
+    /// counting the fields that failed to be mapped or converted in `drops`
+    fn generate(
+        mut self,
+        root_field: &OwnedField,
+        drops: &mut DropCounts,
+    ) -> HashMap<AttrKey, AttrVal> {
         self.generate_inner(root_field);
+        drops.merge(&self.drops);
         self.attrs
     }
 
+    /// The path of each scalar field of `root_field`, relative to the key
+    /// prefix, and the attr key it's mapped to
+    fn field_keys(mut self, root_field: &OwnedField) -> Vec<(String, AttrKey)> {
+        self.field_keys = Some(Vec::new());
+        self.generate_inner(root_field);
+        self.field_keys.unwrap_or_default()
+    }
+
     fn generate_inner(&mut self, root_field: &OwnedField) {
         match root_field {
-            OwnedField::Scalar(name, scalar) => match self.handle_scalar_field(name, scalar) {
-                ScalarFieldAttrKeyVal::Single(kv) => {
-                    self.attrs.insert(kv.0, kv.1);
-                }
-                ScalarFieldAttrKeyVal::Double(kv, extra_kv) => {
-                    self.attrs.insert(kv.0, kv.1);
-                    self.attrs.insert(extra_kv.0, extra_kv.1);
+            OwnedField::Scalar(name, scalar) => {
+                let k = self.attr_key_for_field_name(name);
+                let path = self.field_keys.is_some().then(|| self.field_path(&k));
+                let kv = match self.handle_scalar_field(k, scalar) {
+                    ScalarFieldAttrKeyVal::Single(kv) => kv,
+                    ScalarFieldAttrKeyVal::Double(kv, extra_kv) => {
+                        self.attrs.insert(extra_kv.0, extra_kv.1);
+                        kv
+                    }
+                };
+                if let (Some(field_keys), Some(path)) = (self.field_keys.as_mut(), path) {
+                    field_keys.push((path, kv.0.clone()));
                 }
-            },
+                self.attrs.insert(kv.0, kv.1);
+            }
             OwnedField::Structure(name, fields) => {
                 self.begin_nested_struture(name);
 
@@ -253,130 +1022,113 @@ impl FieldToAttrKeysGen {
         }
     }
 
-    fn handle_scalar_field(
-        &mut self,
-        field_name: &Option<String>,
-        s: &ScalarField,
-    ) -> ScalarFieldAttrKeyVal {
-        let k = self.attr_key_for_field_name(field_name);
+    fn handle_scalar_field(&mut self, k: String, s: &ScalarField) -> ScalarFieldAttrKeyVal {
+        // Hashed fields don't get any of the derived attrs, they'd leak the value
+        if let Some((fields, salt)) = self.hash_fields {
+            if fields.contains(&k) {
+                let hash = salted_hash(salt, s);
+                return ScalarFieldAttrKeyVal::Single((AttrKey::new(k), hash.into()));
+            }
+        }
+        let is_hex_field = self.hex_fields.map(|hf| hf.contains(&k)).unwrap_or(false);
+        if is_hex_field && !self.hex_fields_replace {
+            if let Some(hex) = scalar_field_to_hex(s) {
+                self.attrs
+                    .insert(AttrKey::new(format!("{k}.hex")), hex.into());
+            }
+        }
+        if let Some((fields, symbolizer)) = self.symbolize_fields {
+            if fields.contains(&k) {
+                if let Some(sym) = scalar_field_to_addr(s).and_then(|a| symbolizer.symbolize(a)) {
+                    self.attrs
+                        .insert(AttrKey::new(format!("{k}.symbol")), sym.to_string().into());
+                    if let Some(loc) = sym.location {
+                        self.attrs
+                            .insert(AttrKey::new(format!("{k}.location")), loc.into());
+                    }
+                }
+            }
+        }
         // Enums get an extra `.label` attr
         match s {
             ScalarField::UnsignedEnumeration(_, labels)
-            | ScalarField::SignedEnumeration(_, labels) => enum_label_attr(&k, labels)
-                .map(|extra_kv| {
-                    ScalarFieldAttrKeyVal::Double(
-                        (AttrKey::new(k.clone()), scalar_field_to_val(s)),
-                        extra_kv,
-                    )
-                })
-                .unwrap_or_else(|| {
-                    ScalarFieldAttrKeyVal::Single((AttrKey::new(k.clone()), scalar_field_to_val(s)))
-                }),
+            | ScalarField::SignedEnumeration(_, labels) => {
+                let kv = (AttrKey::new(k.clone()), self.scalar_field_val(&k, s));
+                match enum_label_attr(&k, labels) {
+                    Some(extra_kv) => ScalarFieldAttrKeyVal::Double(kv, extra_kv),
+                    None => ScalarFieldAttrKeyVal::Single(kv),
+                }
+            }
             _ => {
+                let ctx = FieldMapperContext {
+                    auto_map_interaction_fields: self.auto_map_interaction_fields,
+                    is_reserved_event: self.is_reserved_event,
+                };
                 if self.auto_map_interaction_fields {
-                    if ReservedAttrKey::TimelineId.matches_key(&k) {
-                        if let ScalarField::String(tid) = s {
-                            match tid.parse::<Uuid>() {
-                                Ok(tid) => {
-                                    return
-                                        ScalarFieldAttrKeyVal::Single((AttrKey::new(
-                                                    ReservedAttrKey::TimelineId.to_modality_key()
-                                                    .to_string()), AttrVal::TimelineId(Box::new(tid.into()))))
-                                }
-                                Err(e) => warn!("Failed to auto map interaction field as timeline ID UUID type. {e}"),
-                            }
-                        } else {
-                            warn!("Mapping interaction remote timeline ID requires a string type");
-                        }
-                    } else if ReservedAttrKey::LogicalTime.matches_key(&k) {
-                        if let ScalarField::String(t) = s {
-                            match t.parse::<LogicalTime>() {
-                                Ok(t) => {
-                                    return
-                                        ScalarFieldAttrKeyVal::Single((AttrKey::new(
-                                                    ReservedAttrKey::LogicalTime.to_modality_key()
-                                                    .to_string()), AttrVal::LogicalTime(t)))
-                                }
-                                Err(e) => warn!("Failed to auto map interaction field as timeline ID UUID type. {e:?}"),
-                            }
-                        } else {
-                            warn!("Mapping interaction remote logical time requires a string type");
-                        }
-                    } else if ReservedAttrKey::Timestamp.matches_key(&k) {
-                        if let ScalarField::UnsignedInteger(t) = s {
-                            return ScalarFieldAttrKeyVal::Single((
-                                AttrKey::new(
-                                    ReservedAttrKey::Timestamp.to_modality_key().to_string(),
-                                ),
-                                AttrVal::Timestamp((*t).into()),
-                            ));
-                        } else {
-                            warn!("Mapping interaction remote timestamp requires a u64 type");
-                        }
-                    } else if ReservedAttrKey::Nonce.matches_key(&k) {
-                        return ScalarFieldAttrKeyVal::Single((
-                            AttrKey::new(ReservedAttrKey::Nonce.to_modality_key().to_string()),
-                            scalar_field_to_val(s),
-                        ));
+                    if let Some(kv) = self.remote_timelines.and_then(|rt| rt.map_field(&k, s)) {
+                        return ScalarFieldAttrKeyVal::Single(kv);
                     }
                 }
-
-                if self.is_reserved_event {
-                    if ReservedAttrKey::MutatorId.matches_key(&k) {
-                        if let ScalarField::String(id) = s {
-                            match id.parse::<Uuid>() {
-                                Ok(id) => {
-                                    return
-                                        ScalarFieldAttrKeyVal::Single((AttrKey::new(
-                                                    ReservedAttrKey::MutatorId.to_modality_key()
-                                                    .to_string()), uuid_to_integer_attr_val(&id) ))
-                                }
-                                Err(e) => warn!("Failed to auto map reserved field as mutator ID UUID type. {e}"),
-                            }
-                        } else {
-                            warn!("Mapping reserved mutator ID requires a string type");
-                        }
-                    } else if ReservedAttrKey::MutationId.matches_key(&k) {
-                        if let ScalarField::String(id) = s {
-                            match id.parse::<Uuid>() {
-                                Ok(id) => {
-                                    return
-                                        ScalarFieldAttrKeyVal::Single((AttrKey::new(
-                                                    ReservedAttrKey::MutationId.to_modality_key()
-                                                    .to_string()), uuid_to_integer_attr_val(&id) ))
-                                }
-                                Err(e) => warn!("Failed to auto map reserved field as mutation ID UUID type. {e}"),
-                            }
-                        } else {
-                            warn!("Mapping reserved mutation ID requires a string type");
-                        }
-                    } else if ReservedAttrKey::MutationSuccess.matches_key(&k) {
-                        let maybe_success = match s {
-                            ScalarField::Bool(val) => Some(*val),
-                            ScalarField::UnsignedInteger(val) => Some(*val != 0),
-                            ScalarField::SignedInteger(val) => Some(*val != 0),
-                            _ => None,
-                        };
-                        if let Some(success) = maybe_success {
-                            return ScalarFieldAttrKeyVal::Single((
-                                AttrKey::new(
-                                    ReservedAttrKey::MutationSuccess
-                                        .to_modality_key()
-                                        .to_string(),
-                                ),
-                                success.into(),
-                            ));
-                        } else {
-                            warn!("Mapping reserved mutation success requires a boolean or integer type");
-                        }
-                    }
+                if let Some(kv) = self
+                    .mappers
+                    .and_then(|m| m.map_field(&ctx, &k, s, &mut self.drops))
+                {
+                    return ScalarFieldAttrKeyVal::Single(kv);
                 }
 
-                ScalarFieldAttrKeyVal::Single((AttrKey::new(k), scalar_field_to_val(s)))
+                let v = self.scalar_field_val(&k, s);
+                ScalarFieldAttrKeyVal::Single((AttrKey::new(k), v))
             }
         }
     }
 
+    /// The attr value of a scalar field, coerced to the configured type if any
+    fn scalar_field_val(&mut self, k: &str, s: &ScalarField) -> AttrVal {
+        if self.hex_fields_replace && self.hex_fields.map(|hf| hf.contains(k)).unwrap_or(false) {
+            if let Some(hex) = scalar_field_to_hex(s) {
+                return hex.into();
+            }
+        }
+        if let (Some(format), ScalarField::String(ts)) =
+            (self.timestamp_formats.and_then(|tf| tf.get(k)), s)
+        {
+            match parse_timestamp(ts, format) {
+                Some(ns) => return Nanoseconds::from(ns).into(),
+                None => {
+                    debug!("Failed to parse field '{k}' value '{ts}' as a '{format}' timestamp");
+                    self.drops.count(DropReason::FieldConversion);
+                }
+            }
+        }
+        if let Some(ty) = self.field_types.and_then(|ft| ft.get(k)) {
+            match coerce_scalar_field(s, *ty) {
+                Some(v) => return v,
+                None => {
+                    debug!("Failed to coerce field '{k}' to type '{ty}'");
+                    self.drops.count(DropReason::FieldConversion);
+                }
+            }
+        }
+        let float = match s {
+            ScalarField::SinglePrecisionReal(v) => f64::from(v.0),
+            ScalarField::DoublePrecisionReal(v) => v.0,
+            _ => return scalar_field_to_val(s),
+        };
+        if let Some(scale) = self.float_scale_fields.and_then(|sf| sf.get(k)) {
+            match scale_float(float, *scale) {
+                Some(v) => return v.into(),
+                None => {
+                    debug!("Failed to scale field '{k}' value {float} to an integer");
+                    self.drops.count(DropReason::FieldConversion);
+                }
+            }
+        }
+        match self.float_significant_digits {
+            Some(digits) => round_significant_digits(float, digits).into(),
+            None => float.into(),
+        }
+    }
+
     /// Get the fully qualified attr key for the given field name.
     ///
     /// The key is returned as a string so the caller may do additional things
@@ -393,6 +1145,19 @@ impl FieldToAttrKeysGen {
             .join(".")
     }
 
+    /// The field path of the attr key `k`, without the key prefix
+    fn field_path(&self, k: &str) -> String {
+        // Safety: this impl ensures self.attr_key_stack.len() >= 1
+        let prefix = &self.attr_key_stack[0];
+        if prefix.is_empty() {
+            return k.to_owned();
+        }
+        k.strip_prefix(prefix.as_str())
+            .and_then(|p| p.strip_prefix('.'))
+            .unwrap_or(k)
+            .to_owned()
+    }
+
     /// If the field name is none, generate the next anonymous field name
     /// at the current nesting depth, otherwise return the provided name.
     fn resolve_field_name(&mut self, field_name: &Option<String>) -> String {
@@ -463,7 +1228,7 @@ fn enum_label_attr(key_prefix: &str, labels: &BTreeSet<String>) -> Option<(AttrK
     }
 }
 
-fn scalar_field_to_val(s: &ScalarField) -> AttrVal {
+pub(crate) fn scalar_field_to_val(s: &ScalarField) -> AttrVal {
     match s {
         ScalarField::Bool(v) => (*v).into(),
         ScalarField::UnsignedInteger(v) => BigInt::new_attr_val(i128::from(*v)),
@@ -476,51 +1241,119 @@ fn scalar_field_to_val(s: &ScalarField) -> AttrVal {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-enum ReservedAttrKey {
-    TimelineId,
-    LogicalTime,
-    Timestamp,
-    Nonce,
-    MutatorId,
-    MutationId,
-    MutationSuccess,
+/// Round to the given number of significant digits, e.g. 3 digits of
+/// `0.0123456` is `0.0123` and of `123456.0` is `123000.0`
+fn round_significant_digits(v: f64, digits: u32) -> f64 {
+    if v == 0.0 || !v.is_finite() || digits == 0 {
+        return v;
+    }
+    let magnitude = v.abs().log10().floor() as i32;
+    let exp = i32::try_from(digits).unwrap_or(i32::MAX) - 1 - magnitude;
+    // Dividing by an exact power of ten avoids the representation error of its inverse
+    let rounded = if exp >= 0 {
+        let factor = 10_f64.powi(exp);
+        (v * factor).round() / factor
+    } else {
+        let factor = 10_f64.powi(-exp);
+        (v / factor).round() * factor
+    };
+    if rounded.is_finite() {
+        rounded
+    } else {
+        v
+    }
 }
 
-impl ReservedAttrKey {
-    fn matches_key(self, k: &str) -> bool {
-        !k.contains(self.to_modality_key()) && k.contains(self.to_ctf_key())
-    }
+/// The value multiplied by the scale factor, rounded to an integer
+fn scale_float(v: f64, scale: u64) -> Option<i64> {
+    let scaled = (v * scale as f64).round();
+    (scaled.is_finite() && scaled >= i64::MIN as f64 && scaled < i64::MAX as f64)
+        .then_some(scaled as i64)
+}
 
-    fn to_ctf_key(self) -> &'static str {
-        use ReservedAttrKey::*;
-        match self {
-            TimelineId => "remote_timeline_id",
-            LogicalTime => "remote_logical_time",
-            Timestamp => "remote_timestamp",
-            Nonce => "remote_nonce",
-            MutatorId => "mutator_id",
-            MutationId => "mutation_id",
-            MutationSuccess => "mutation_success",
-        }
+fn scalar_field_to_addr(s: &ScalarField) -> Option<u64> {
+    match s {
+        ScalarField::UnsignedInteger(v) | ScalarField::UnsignedEnumeration(v, _) => Some(*v),
+        ScalarField::SignedInteger(v) | ScalarField::SignedEnumeration(v, _) => Some(*v as u64),
+        _ => None,
     }
+}
 
-    fn to_modality_key(self) -> &'static str {
-        use ReservedAttrKey::*;
-        match self {
-            TimelineId => "interaction.remote_timeline_id",
-            LogicalTime => "interaction.remote_logical_time",
-            Timestamp => "interaction.remote_timestamp",
-            Nonce => "interaction.remote_nonce",
-            MutatorId => "mutator.id",
-            MutationId => "mutation.id",
-            MutationSuccess => "mutation.success",
+fn scalar_field_to_hex(s: &ScalarField) -> Option<String> {
+    match s {
+        ScalarField::UnsignedInteger(v) | ScalarField::UnsignedEnumeration(v, _) => {
+            Some(format!("{v:#x}"))
+        }
+        ScalarField::SignedInteger(v) | ScalarField::SignedEnumeration(v, _) => {
+            Some(format!("{v:#x}"))
         }
+        _ => None,
     }
 }
 
-fn uuid_to_integer_attr_val(u: &Uuid) -> AttrVal {
-    i128::from_le_bytes(*u.as_bytes()).into()
+/// Hex HMAC-SHA256 of the value's string representation, keyed by the salt
+fn salted_hash(salt: &str, s: &ScalarField) -> String {
+    use ScalarField::*;
+    let val = match s {
+        Bool(v) => v.to_string(),
+        UnsignedInteger(v) | UnsignedEnumeration(v, _) => v.to_string(),
+        SignedInteger(v) | SignedEnumeration(v, _) => v.to_string(),
+        SinglePrecisionReal(v) => v.0.to_string(),
+        DoublePrecisionReal(v) => v.0.to_string(),
+        String(v) => v.clone(),
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(salt.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(val.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn coerce_scalar_field(s: &ScalarField, ty: FieldType) -> Option<AttrVal> {
+    use ScalarField::*;
+    Some(match ty {
+        FieldType::String => match s {
+            Bool(v) => v.to_string().into(),
+            UnsignedInteger(v) | UnsignedEnumeration(v, _) => v.to_string().into(),
+            SignedInteger(v) | SignedEnumeration(v, _) => v.to_string().into(),
+            SinglePrecisionReal(v) => v.0.to_string().into(),
+            DoublePrecisionReal(v) => v.0.to_string().into(),
+            String(v) => v.clone().into(),
+        },
+        FieldType::Integer => match s {
+            Bool(v) => i64::from(*v).into(),
+            UnsignedInteger(v) | UnsignedEnumeration(v, _) => BigInt::new_attr_val((*v).into()),
+            SignedInteger(v) | SignedEnumeration(v, _) => (*v).into(),
+            SinglePrecisionReal(v) => (v.0 as i64).into(),
+            DoublePrecisionReal(v) => (v.0 as i64).into(),
+            String(v) => BigInt::new_attr_val(v.trim().parse::<i128>().ok()?),
+        },
+        FieldType::Float => match s {
+            Bool(v) => f64::from(u8::from(*v)).into(),
+            UnsignedInteger(v) | UnsignedEnumeration(v, _) => (*v as f64).into(),
+            SignedInteger(v) | SignedEnumeration(v, _) => (*v as f64).into(),
+            SinglePrecisionReal(v) => f64::from(v.0).into(),
+            DoublePrecisionReal(v) => v.0.into(),
+            String(v) => v.trim().parse::<f64>().ok()?.into(),
+        },
+        FieldType::Bool => match s {
+            Bool(v) => (*v).into(),
+            UnsignedInteger(v) | UnsignedEnumeration(v, _) => (*v != 0).into(),
+            SignedInteger(v) | SignedEnumeration(v, _) => (*v != 0).into(),
+            SinglePrecisionReal(v) => (v.0 != 0.0).into(),
+            DoublePrecisionReal(v) => (v.0 != 0.0).into(),
+            String(v) => match v.trim().to_lowercase().as_str() {
+                "true" | "1" => true.into(),
+                "false" | "0" => false.into(),
+                _ => return None,
+            },
+        },
+        FieldType::Timestamp => match s {
+            UnsignedInteger(v) => Nanoseconds::from(*v).into(),
+            SignedInteger(v) => Nanoseconds::from(u64::try_from(*v).ok()?).into(),
+            String(v) => Nanoseconds::from(v.trim().parse::<u64>().ok()?).into(),
+            _ => return None,
+        },
+    })
 }
 
 #[cfg(test)]
@@ -595,8 +1428,16 @@ mod tests {
     #[test]
     fn attr_key_gen_mixed_nested_structs() {
         let root = messy_event_structure();
-        let gen = FieldToAttrKeysGen::new("some.prefix", true, true).unwrap();
-        let mut attrs = gen.generate(&root).into_iter().collect::<Vec<(_, _)>>();
+        let mappers = FieldMappers::default();
+        let gen = FieldToAttrKeysGen::new("some.prefix", true, true)
+            .unwrap()
+            .with_mappers(&mappers);
+        let mut drops = DropCounts::default();
+        let mut attrs = gen
+            .generate(&root, &mut drops)
+            .into_iter()
+            .collect::<Vec<(_, _)>>();
+        assert!(drops.is_empty());
         attrs.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
         assert_eq!(
             attrs,
@@ -672,9 +1513,414 @@ mod tests {
         );
     }
 
+    #[test]
+    fn attr_key_gen_field_type_coercion() {
+        use OwnedField::Scalar;
+        use ScalarField::*;
+        let root = OwnedField::Structure(
+            None,
+            vec![
+                Scalar("status".to_string().into(), String("42".to_string())),
+                Scalar("ts".to_string().into(), UnsignedInteger(10)),
+                Scalar("flag".to_string().into(), UnsignedInteger(1)),
+                Scalar("id".to_string().into(), UnsignedInteger(7)),
+                Scalar("bad".to_string().into(), String("nope".to_string())),
+            ],
+        );
+        let field_types = vec![
+            ("status".to_owned(), FieldType::Integer),
+            ("ts".to_owned(), FieldType::Timestamp),
+            ("flag".to_owned(), FieldType::Bool),
+            ("id".to_owned(), FieldType::String),
+            ("bad".to_owned(), FieldType::Float),
+        ]
+        .into_iter()
+        .collect();
+        let gen = FieldToAttrKeysGen::new("", false, false)
+            .unwrap()
+            .with_field_types(&field_types);
+        let mut drops = DropCounts::default();
+        let mut attrs = gen
+            .generate(&root, &mut drops)
+            .into_iter()
+            .collect::<Vec<(_, _)>>();
+        // 'bad' isn't a float
+        assert_eq!(drops.get(DropReason::FieldConversion), 1);
+        attrs.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+        assert_eq!(
+            attrs,
+            vec![
+                (AttrKey::new("bad".to_owned()), "nope".to_string().into()),
+                (AttrKey::new("flag".to_owned()), true.into()),
+                (AttrKey::new("id".to_owned()), "7".to_string().into()),
+                (AttrKey::new("status".to_owned()), BigInt::new_attr_val(42)),
+                (AttrKey::new("ts".to_owned()), Nanoseconds::from(10).into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn attr_key_gen_field_keys() {
+        use OwnedField::Scalar;
+        use ScalarField::*;
+        let root = OwnedField::Structure(
+            None,
+            vec![
+                Scalar("prev_comm".to_string().into(), Bool(true)),
+                OwnedField::Structure(
+                    "next".to_string().into(),
+                    vec![Scalar("tid".to_string().into(), SignedInteger(-1))],
+                ),
+                Scalar(None, UnsignedInteger(1)),
+                Scalar("remote_timestamp".to_string().into(), UnsignedInteger(8)),
+            ],
+        );
+        let mappers = FieldMappers::default();
+        let gen = FieldToAttrKeysGen::new("ctf", true, false)
+            .unwrap()
+            .with_mappers(&mappers);
+        let keys = scope_field_keys(gen, &root, "payload", EventAttrKey::Field)
+            .into_iter()
+            .map(|(path, key)| (path, key.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                (
+                    "payload.prev_comm".to_owned(),
+                    "event.ctf.prev_comm".to_owned()
+                ),
+                (
+                    "payload.next.tid".to_owned(),
+                    "event.ctf.next.tid".to_owned()
+                ),
+                (
+                    "payload.anonymous_0".to_owned(),
+                    "event.ctf.anonymous_0".to_owned()
+                ),
+                // The key of the interaction field mapper
+                (
+                    "payload.remote_timestamp".to_owned(),
+                    "event.interaction.remote_timestamp".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn attr_key_gen_hex_fields() {
+        use OwnedField::Scalar;
+        use ScalarField::*;
+        let root = OwnedField::Structure(
+            None,
+            vec![
+                Scalar("ip".to_string().into(), UnsignedInteger(0xffffffff81a20000)),
+                Scalar("name".to_string().into(), String("foo".to_string())),
+            ],
+        );
+        let hex_fields = vec!["ip".to_owned(), "name".to_owned()]
+            .into_iter()
+            .collect();
+
+        let gen = FieldToAttrKeysGen::new("", false, false)
+            .unwrap()
+            .with_hex_fields(&hex_fields, false);
+        let mut attrs = gen
+            .generate(&root, &mut DropCounts::default())
+            .into_iter()
+            .collect::<Vec<(_, _)>>();
+        attrs.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+        assert_eq!(
+            attrs,
+            vec![
+                (
+                    AttrKey::new("ip".to_owned()),
+                    BigInt::new_attr_val(0xffffffff81a20000)
+                ),
+                (
+                    AttrKey::new("ip.hex".to_owned()),
+                    "0xffffffff81a20000".to_string().into()
+                ),
+                (AttrKey::new("name".to_owned()), "foo".to_string().into()),
+            ]
+        );
+
+        let gen = FieldToAttrKeysGen::new("", false, false)
+            .unwrap()
+            .with_hex_fields(&hex_fields, true);
+        let mut attrs = gen
+            .generate(&root, &mut DropCounts::default())
+            .into_iter()
+            .collect::<Vec<(_, _)>>();
+        attrs.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+        assert_eq!(
+            attrs,
+            vec![
+                (
+                    AttrKey::new("ip".to_owned()),
+                    "0xffffffff81a20000".to_string().into()
+                ),
+                (AttrKey::new("name".to_owned()), "foo".to_string().into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn attr_key_gen_hash_fields() {
+        use OwnedField::Scalar;
+        use ScalarField::*;
+        let root = |user: &str| {
+            OwnedField::Structure(
+                None,
+                vec![
+                    Scalar("user".to_string().into(), String(user.to_string())),
+                    Scalar("uid".to_string().into(), UnsignedInteger(1000)),
+                ],
+            )
+        };
+        let hash_fields = vec!["user".to_owned(), "uid".to_owned()]
+            .into_iter()
+            .collect();
+        let gen = |salt| {
+            FieldToAttrKeysGen::new("", false, false)
+                .unwrap()
+                .with_hash_fields(&hash_fields, salt)
+        };
+
+        let a = gen(Some("pepper")).generate(&root("alice"), &mut DropCounts::default());
+        let user = a.get(&AttrKey::new("user".to_owned())).unwrap();
+        let uid = a.get(&AttrKey::new("uid".to_owned())).unwrap();
+        assert_eq!(
+            user,
+            &AttrVal::from(salted_hash("pepper", &String("alice".to_owned())))
+        );
+        assert_ne!(user, &AttrVal::from("alice".to_owned()));
+        assert!(matches!(uid, AttrVal::String(_)));
+
+        // Same value and salt, same hash
+        let b = gen(Some("pepper")).generate(&root("alice"), &mut DropCounts::default());
+        assert_eq!(a, b);
+
+        // Different value or salt, different hash
+        let c = gen(Some("pepper")).generate(&root("bob"), &mut DropCounts::default());
+        assert_ne!(c.get(&AttrKey::new("user".to_owned())), Some(user));
+        let d = gen(Some("salt")).generate(&root("alice"), &mut DropCounts::default());
+        assert_ne!(d.get(&AttrKey::new("user".to_owned())), Some(user));
+
+        // Nothing is hashed without a salt
+        let e = gen(None).generate(&root("alice"), &mut DropCounts::default());
+        assert_eq!(
+            e.get(&AttrKey::new("user".to_owned())),
+            Some(&AttrVal::from("alice".to_owned()))
+        );
+
+        // Known HMAC-SHA256 answer
+        assert_eq!(
+            salted_hash(
+                "key",
+                &String("The quick brown fox jumps over the lazy dog".to_owned())
+            ),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn in_band_timeline_attrs_skip_reserved_keys() {
+        use OwnedField::Scalar;
+        use ScalarField::*;
+        let payload = OwnedField::Structure(
+            None,
+            vec![
+                Scalar(
+                    "firmware_version".to_string().into(),
+                    String("1.2".to_string()),
+                ),
+                Scalar("name".to_string().into(), String("spoofed".to_string())),
+                Scalar("run_id".to_string().into(), UnsignedInteger(1)),
+                Scalar("id".to_string().into(), UnsignedInteger(2)),
+                Scalar("names".to_string().into(), UnsignedInteger(3)),
+                OwnedField::Structure(
+                    "internal".to_string().into(),
+                    vec![Scalar("ctf".to_string().into(), Bool(true))],
+                ),
+                OwnedField::Structure(
+                    "tracer".to_string().into(),
+                    vec![Scalar("name".to_string().into(), String("x".to_string()))],
+                ),
+            ],
+        );
+        assert_eq!(
+            in_band_timeline_attrs(&payload).unwrap(),
+            vec![
+                ("firmware_version".to_owned(), "1.2".to_string().into()),
+                ("names".to_owned(), BigInt::new_attr_val(3)),
+            ]
+        );
+        assert!(is_reserved_timeline_attr_key("internal.ctf.trace.uuid"));
+        assert!(!is_reserved_timeline_attr_key("cpus"));
+    }
+
     #[test]
     fn attr_key_gen_smoke() {
         assert!(FieldToAttrKeysGen::new(".asdf", false, false).is_err());
         assert!(FieldToAttrKeysGen::new("asdf.", false, false).is_err());
     }
+
+    #[test]
+    fn offset_timestamps_range() {
+        assert_eq!(offset_timestamp_ns(10, 5), Some(15));
+        // Pre-epoch clock snapshot shifted into range
+        assert_eq!(offset_timestamp_ns(-1_000, 1_500), Some(500));
+        assert_eq!(offset_timestamp_ns(-1_000, 0), None);
+        assert_eq!(offset_timestamp_ns(u64::MAX.into(), 1), None);
+        assert_eq!(
+            offset_timestamp_ns(i64::MIN.into(), u64::MAX.into()),
+            Some(u64::MAX / 2)
+        );
+    }
+
+    #[test]
+    fn reserved_event_names() {
+        let cfg = EventMappingConfig {
+            reserved_event_names: vec![
+                ("acme_link_up".to_owned(), "acme.link.up".to_owned()),
+                (
+                    "modality_mutator_retired".to_owned(),
+                    "acme.mutator.retired".to_owned(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            reserved_event_name(&cfg, "modality_mutation_injected"),
+            Some("modality.mutation.injected")
+        );
+        assert_eq!(
+            reserved_event_name(&cfg, "acme_link_up"),
+            Some("acme.link.up")
+        );
+        assert_eq!(
+            reserved_event_name(&cfg, "modality_mutator_retired"),
+            Some("acme.mutator.retired")
+        );
+        assert_eq!(reserved_event_name(&cfg, "sched_switch"), None);
+    }
+
+    #[test]
+    fn float_rounding() {
+        assert_eq!(round_significant_digits(0.0123456, 3), 0.0123);
+        assert_eq!(round_significant_digits(-21.456789, 4), -21.46);
+        assert_eq!(round_significant_digits(123456.0, 3), 123000.0);
+        assert_eq!(round_significant_digits(0.0, 3), 0.0);
+        assert!(round_significant_digits(f64::NAN, 3).is_nan());
+        assert_eq!(round_significant_digits(1e-300, 17), 1e-300);
+
+        assert_eq!(scale_float(21.4567, 1000), Some(21457));
+        assert_eq!(scale_float(-0.25, 10), Some(-3));
+        assert_eq!(scale_float(f64::INFINITY, 1000), None);
+        assert_eq!(scale_float(1e300, 1000), None);
+    }
+
+    #[test]
+    fn cpu_id_of_lttng_traces_only() {
+        use crate::profile::{detect::detect, ProfileRules};
+        use crate::properties::TracerIdentity;
+        let packet_context = OwnedField::Structure(
+            None,
+            vec![
+                OwnedField::Scalar(
+                    "timestamp_begin".to_string().into(),
+                    ScalarField::UnsignedInteger(10),
+                ),
+                OwnedField::Scalar("cpu_id".to_string().into(), ScalarField::UnsignedInteger(3)),
+            ],
+        );
+        let event_mapping = |tracer_name: &str| {
+            let tracer = TracerIdentity {
+                name: tracer_name.to_owned(),
+                major: None,
+                minor: None,
+                patch: None,
+            };
+            let profile = detect(Some(&tracer), None, &Default::default()).profile;
+            let mut cfg = crate::config::PluginConfig::default();
+            ProfileRules::for_profile(profile).merge_into(&mut cfg);
+            cfg.event_mapping
+        };
+
+        assert_eq!(cpu_id(&packet_context, &event_mapping("barectf")), None);
+        assert_eq!(cpu_id(&packet_context, &event_mapping("acme")), None);
+        assert_eq!(
+            cpu_id(&packet_context, &event_mapping("lttng-modules")),
+            Some(3)
+        );
+        assert_eq!(
+            cpu_id(&packet_context, &event_mapping("lttng-ust")),
+            Some(3)
+        );
+
+        // Opted into for other tracers
+        let cfg = EventMappingConfig {
+            cpu_attrs: true,
+            ..Default::default()
+        };
+        assert_eq!(cpu_id(&packet_context, &cfg), Some(3));
+    }
+
+    /// The attrs of a payload field `cpu` conflicting with the context's `event.cpu`
+    /// under the policy, by attr key name
+    async fn conflicting_attrs(
+        policy: AttrConflictPolicy,
+        client: &mut Client,
+    ) -> BTreeMap<String, AttrVal> {
+        let key = client.interned_event_key(EventAttrKey::Cpu).await.unwrap();
+        let mut attrs = HashMap::new();
+        attrs.insert(key, AttrVal::from(1_i64));
+        insert_conflicting_attr(
+            &mut attrs,
+            policy,
+            Some("sched_switch"),
+            key,
+            AttrVal::from(2_i64),
+            client,
+        )
+        .await
+        .unwrap();
+        attrs
+            .into_iter()
+            .map(|(k, v)| (client.event_key_name(&k).unwrap().to_owned(), v))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn attr_conflict_policies() {
+        let mut client = Client::offline(Vec::new(), Vec::new());
+        let attrs = |kvs: &[(&str, i64)]| {
+            kvs.iter()
+                .map(|(k, v)| (k.to_string(), AttrVal::from(*v)))
+                .collect::<BTreeMap<_, _>>()
+        };
+        assert_eq!(
+            conflicting_attrs(AttrConflictPolicy::PayloadWins, &mut client).await,
+            attrs(&[("event.cpu", 2)])
+        );
+        assert_eq!(
+            conflicting_attrs(AttrConflictPolicy::ContextWins, &mut client).await,
+            attrs(&[("event.cpu", 1)])
+        );
+        assert_eq!(
+            conflicting_attrs(AttrConflictPolicy::SuffixContext, &mut client).await,
+            attrs(&[("event.cpu", 2), ("event.cpu.context", 1)])
+        );
+        assert_eq!(
+            conflicting_attrs(AttrConflictPolicy::SuffixPayload, &mut client).await,
+            attrs(&[("event.cpu", 1), ("event.cpu.payload", 2)])
+        );
+
+        // Warned about once per run, each run's client starts over
+        assert!(!client.first_attr_conflict("event.cpu"));
+        assert!(Client::offline(Vec::new(), Vec::new()).first_attr_conflict("event.cpu"));
+    }
 }
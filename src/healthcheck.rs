@@ -0,0 +1,92 @@
+//! Readiness checks for the `--healthcheck` mode of the binaries, each
+//! failure has its own exit code
+
+use crate::config::CtfConfig;
+use crate::relayd::{self, SessionPattern, LTTNG_RELAYD_DEFAULT_PORT};
+use modality_ingest_client::IngestClient;
+use std::time::Duration;
+use thiserror::Error;
+use url::Url;
+
+const RELAYD_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum HealthCheckError {
+    #[error("The configuration is invalid. {0}")]
+    Config(String),
+
+    #[error("The ingest endpoint is unreachable. {0}")]
+    IngestUnreachable(String),
+
+    #[error("The ingest endpoint rejected the authentication. {0}")]
+    IngestAuth(String),
+
+    #[error("The LTTng relay daemon at '{0}' is unreachable. {1}")]
+    RelaydUnreachable(Url, String),
+
+    #[error("The LTTng relay daemon at '{0}' doesn't serve the session '{1}'")]
+    SessionNotFound(Url, String),
+}
+
+impl HealthCheckError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            HealthCheckError::Config(_) => exitcode::CONFIG,
+            HealthCheckError::IngestUnreachable(_) => exitcode::UNAVAILABLE,
+            HealthCheckError::IngestAuth(_) => exitcode::NOPERM,
+            HealthCheckError::RelaydUnreachable(_, _) => exitcode::NOHOST,
+            HealthCheckError::SessionNotFound(_, _) => exitcode::TEMPFAIL,
+        }
+    }
+}
+
+/// Check the ingest endpoint is reachable and accepts the auth token
+pub async fn check_ingest(cfg: &CtfConfig) -> Result<(), HealthCheckError> {
+    let url = cfg
+        .protocol_parent_url()
+        .map_err(|e| HealthCheckError::Config(e.to_string()))?;
+    let auth = cfg
+        .resolve_auth()
+        .await
+        .map_err(|e| HealthCheckError::Config(e.to_string()))?;
+    let c = IngestClient::connect(&url, cfg.ingest.allow_insecure_tls)
+        .await
+        .map_err(|e| HealthCheckError::IngestUnreachable(e.to_string()))?;
+    c.authenticate(auth.into())
+        .await
+        .map_err(|e| HealthCheckError::IngestAuth(e.to_string()))?;
+    println!("ingest: ok ({url})");
+    Ok(())
+}
+
+/// Check the relay daemon answers the sessions query, and that it
+/// serves the URL's session when `require_session` is set
+pub fn check_relayd(url: &Url, require_session: bool) -> Result<(), HealthCheckError> {
+    let unreachable = |e: String| HealthCheckError::RelaydUnreachable(url.clone(), e);
+    let addr = url
+        .socket_addrs(|| Some(LTTNG_RELAYD_DEFAULT_PORT))
+        .map_err(|e| unreachable(e.to_string()))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| unreachable("The host name didn't resolve".to_owned()))?;
+    let sessions =
+        relayd::list_sessions(&addr, RELAYD_TIMEOUT).map_err(|e| unreachable(e.to_string()))?;
+    println!("relayd: ok ({url}, {} sessions)", sessions.len());
+
+    // net://RDHOST/host/TGTHOST/SESSION
+    if let Some(pattern) = SessionPattern::from_url(url) {
+        let matching = sessions.iter().filter(|s| pattern.matches(s)).count();
+        if matching == 0 {
+            if require_session {
+                return Err(HealthCheckError::SessionNotFound(
+                    url.clone(),
+                    pattern.to_string(),
+                ));
+            }
+            println!("relayd: session '{pattern}' not found yet");
+        } else if pattern.has_wildcards() {
+            println!("relayd: {matching} sessions match '{pattern}'");
+        }
+    }
+    Ok(())
+}
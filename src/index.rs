@@ -0,0 +1,206 @@
+//! Reading the LTTng packet index files of fs traces, the
+//! `index/<stream file>.idx` files next to each stream file, and
+//! the import progress through the time range they cover
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const PACKET_INDEX_MAGIC: u32 = 0xC1F1_DCC1;
+const PACKET_INDEX_HEADER_LEN: usize = 16;
+
+/// The fields of a version 1.0 index entry, the ones every version has
+const PACKET_INDEX_MIN_ENTRY_LEN: usize = 56;
+
+/// The packets of a stream, as listed in its packet index file
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct PacketIndex {
+    pub packet_count: u64,
+
+    /// Sum of the packet sizes, in bytes
+    pub total_size: u64,
+
+    /// Earliest packet `timestamp_begin`, in stream clock cycles
+    pub timestamp_begin: Option<u64>,
+
+    /// Latest packet `timestamp_end`, in stream clock cycles
+    pub timestamp_end: Option<u64>,
+}
+
+impl PacketIndex {
+    /// Read the packet index of the stream file at `stream_path`, if it has one,
+    /// an index file that can't be parsed is an `InvalidData` error
+    pub fn read(stream_path: &Path) -> io::Result<Option<Self>> {
+        let (dir, file_name) = match (stream_path.parent(), stream_path.file_name()) {
+            (Some(d), Some(f)) => (d, f),
+            _ => return Ok(None),
+        };
+        let mut index_file_name = file_name.to_owned();
+        index_file_name.push(".idx");
+        let path = dir.join("index").join(index_file_name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        match Self::parse(&fs::read(&path)?) {
+            Some(index) => Ok(Some(index)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' isn't a packet index file", path.display()),
+            )),
+        }
+    }
+
+    /// Parse the contents of a packet index file, the fields are big-endian
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let u32_at = |i: usize| -> Option<u32> {
+            Some(u32::from_be_bytes(data.get(i..i + 4)?.try_into().ok()?))
+        };
+        if u32_at(0)? != PACKET_INDEX_MAGIC {
+            return None;
+        }
+        let entry_len = u32_at(12)? as usize;
+        if entry_len < PACKET_INDEX_MIN_ENTRY_LEN {
+            return None;
+        }
+
+        let mut index = PacketIndex::default();
+        for entry in data[PACKET_INDEX_HEADER_LEN..].chunks_exact(entry_len) {
+            // Entries are at least PACKET_INDEX_MIN_ENTRY_LEN long
+            let u64_at = |i: usize| u64::from_be_bytes(entry[i..i + 8].try_into().unwrap());
+            let (packet_size_bits, begin, end) = (u64_at(8), u64_at(24), u64_at(32));
+            index.packet_count += 1;
+            index.total_size += packet_size_bits / 8;
+            index.timestamp_begin = Some(index.timestamp_begin.map_or(begin, |b| b.min(begin)));
+            index.timestamp_end = Some(index.timestamp_end.map_or(end, |e| e.max(end)));
+        }
+        Some(index)
+    }
+}
+
+/// How far the import got through the time ranges of the indexed streams,
+/// each stream weighted by the duration its packets cover
+#[derive(Clone, Debug, Default)]
+pub struct ImportProgress {
+    /// The begin, end and latest event timestamp of each (source, stream ID),
+    /// in the domain of the event clock snapshots
+    streams: HashMap<(usize, u64), (u64, u64, u64)>,
+    reported_percent: u64,
+}
+
+impl ImportProgress {
+    pub fn add_stream(&mut self, source: usize, stream_id: u64, begin_ns: u64, end_ns: u64) {
+        if end_ns > begin_ns {
+            self.streams
+                .insert((source, stream_id), (begin_ns, end_ns, begin_ns));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// The imported fraction of the indexed time ranges
+    pub fn fraction(&self) -> Option<f64> {
+        let (done, total) =
+            self.streams
+                .values()
+                .fold((0_u64, 0_u64), |(done, total), (begin, end, latest)| {
+                    (
+                        done.saturating_add(latest - begin),
+                        total.saturating_add(end - begin),
+                    )
+                });
+        (total != 0).then(|| done as f64 / total as f64)
+    }
+
+    /// Advance the stream to the event's clock snapshot, returns the imported
+    /// percentage each time it reaches the next multiple of `step_percent`
+    pub fn observe(
+        &mut self,
+        source: usize,
+        stream_id: u64,
+        clock_snapshot: Option<i64>,
+        step_percent: u64,
+    ) -> Option<u64> {
+        let ns = u64::try_from(clock_snapshot?).ok()?;
+        let (begin, end, latest) = self.streams.get_mut(&(source, stream_id))?;
+        let ns = ns.clamp(*begin, *end);
+        if ns <= *latest {
+            return None;
+        }
+        *latest = ns;
+        let percent = (self.fraction()? * 100.0) as u64;
+        let step = step_percent.max(1);
+        if percent / step > self.reported_percent / step {
+            self.reported_percent = percent;
+            Some(percent)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_packet_index() {
+        let entry_len: u32 = 72;
+        let mut data = Vec::new();
+        data.extend_from_slice(&PACKET_INDEX_MAGIC.to_be_bytes());
+        data.extend_from_slice(&1_u32.to_be_bytes()); // Major
+        data.extend_from_slice(&1_u32.to_be_bytes()); // Minor
+        data.extend_from_slice(&entry_len.to_be_bytes());
+        for (offset, begin, end) in [(0_u64, 100_u64, 200_u64), (4096, 150, 300)] {
+            for field in [offset, 4096 * 8, 1000 * 8, begin, end, 0, 0, 0, 0] {
+                data.extend_from_slice(&field.to_be_bytes());
+            }
+        }
+        assert_eq!(
+            PacketIndex::parse(&data),
+            Some(PacketIndex {
+                packet_count: 2,
+                total_size: 8192,
+                timestamp_begin: Some(100),
+                timestamp_end: Some(300),
+            })
+        );
+        assert_eq!(PacketIndex::parse(&data[4..]), None);
+    }
+
+    #[test]
+    fn unreadable_packet_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let stream_path = dir.path().join("channel0_0");
+        assert!(PacketIndex::read(&stream_path).unwrap().is_none());
+        fs::create_dir(dir.path().join("index")).unwrap();
+        fs::write(dir.path().join("index").join("channel0_0.idx"), b"garbage").unwrap();
+        assert_eq!(
+            PacketIndex::read(&stream_path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn import_progress() {
+        let mut progress = ImportProgress::default();
+        assert_eq!(progress.fraction(), None);
+        progress.add_stream(0, 0, 100, 200);
+        progress.add_stream(0, 1, 1000, 1300);
+        // An empty range can't make progress
+        progress.add_stream(0, 2, 50, 50);
+        assert!(!progress.is_empty());
+
+        assert_eq!(progress.observe(0, 0, Some(150), 25), None);
+        assert_eq!(progress.fraction(), Some(50.0 / 400.0));
+        assert_eq!(progress.observe(0, 1, Some(1100), 25), Some(37));
+        // Out of order and unknown streams don't move it
+        assert_eq!(progress.observe(0, 1, Some(1050), 25), None);
+        assert_eq!(progress.observe(1, 0, Some(150), 25), None);
+        assert_eq!(progress.observe(0, 0, Some(500), 25), Some(50));
+        assert_eq!(progress.observe(0, 1, Some(1300), 25), Some(100));
+    }
+}
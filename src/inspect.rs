@@ -0,0 +1,295 @@
+//! Trace schema summary for the importer's `--inspect` mode: the trace
+//! properties, the streams with their clocks, and the event classes with
+//! the attr keys and types of their fields.
+//!
+//! The event class schemas come from the first event of each class, so
+//! classes without any events in the trace aren't listed. The attr keys are
+//! the ones the event mapping generates, with the `event-attr-prefix` and the
+//! renames applied.
+
+use crate::client::Client;
+use crate::config::EventMappingConfig;
+use crate::error::Error;
+use crate::event::field_attr_keys;
+use crate::interaction::RemoteTimelineLookup;
+use crate::mapper::FieldMappers;
+use crate::symbols::Symbolizer;
+use crate::types::EventLogLevel;
+use babeltrace2_sys::{
+    EnvValue, OwnedEvent, OwnedField, ScalarField, StreamProperties, TraceProperties,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+#[derive(Clone, Debug)]
+pub struct TraceSchema {
+    trace: TraceProperties,
+    streams: BTreeSet<StreamProperties>,
+    event_classes: BTreeMap<(u64, u64), EventClassSchema>,
+
+    /// The field mappers the events would be ingested with
+    mappers: FieldMappers,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EventClassSchema {
+    pub name: Option<String>,
+    pub log_level: Option<String>,
+    pub event_count: u64,
+
+    /// Attr key and type of each scalar field
+    pub fields: Vec<(String, &'static str)>,
+}
+
+impl TraceSchema {
+    pub fn new(
+        trace: &TraceProperties,
+        streams: &BTreeSet<StreamProperties>,
+        mappers: &FieldMappers,
+    ) -> Self {
+        Self {
+            trace: trace.clone(),
+            streams: streams.clone(),
+            event_classes: Default::default(),
+            mappers: mappers.clone(),
+        }
+    }
+
+    /// Count the event, and record the schema of its class when it's the first
+    /// one, with the attr keys of its fields as mapped by the event mapping
+    /// and renamed by the client
+    pub fn record(
+        &mut self,
+        event: &OwnedEvent,
+        cfg: &EventMappingConfig,
+        symbolizer: &Symbolizer,
+        remote_timelines: RemoteTimelineLookup<'_>,
+        client: &Client,
+    ) -> Result<(), Error> {
+        let key = (event.stream_id, event.class_properties.id);
+        if let Some(class) = self.event_classes.get_mut(&key) {
+            class.event_count += 1;
+            return Ok(());
+        }
+
+        let attr_keys: HashMap<String, String> =
+            field_attr_keys(event, cfg, symbolizer, remote_timelines, &self.mappers)?
+                .into_iter()
+                .map(|(path, key)| (path, client.renamed_event_key(&key.to_string())))
+                .collect();
+        let mut field_types = Vec::new();
+        let props = &event.properties;
+        let scopes = [
+            (&props.common_context, "common_context"),
+            (&props.specific_context, "specific_context"),
+            (&props.packet_context, "packet_context"),
+            (&props.payload, "payload"),
+        ];
+        for (field, scope) in scopes.iter() {
+            if let Some(f) = field {
+                field_schema(f, scope, &mut field_types);
+            }
+        }
+        let fields = schema_fields(field_types, &attr_keys);
+        self.event_classes.insert(
+            key,
+            EventClassSchema {
+                name: event.class_properties.name.clone(),
+                log_level: EventLogLevel::from_event(event).map(|ll| ll.to_string()),
+                event_count: 1,
+                fields,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// The attr key and type of each scalar field, from the field paths and types
+/// and the attr key of each field path
+fn schema_fields(
+    field_types: Vec<(String, &'static str)>,
+    attr_keys: &HashMap<String, String>,
+) -> Vec<(String, &'static str)> {
+    field_types
+        .into_iter()
+        .map(|(path, ty)| match attr_keys.get(&path) {
+            Some(key) => (key.clone(), ty),
+            None => (path, ty),
+        })
+        .collect()
+}
+
+/// Collect the path and type of the scalar fields, the paths are rooted at
+/// `prefix` and follow the field path naming of the event mapping
+pub fn field_schema(f: &OwnedField, prefix: &str, out: &mut Vec<(String, &'static str)>) {
+    field_schema_inner(f, prefix, 0, out)
+}
+
+fn field_schema_inner(
+    f: &OwnedField,
+    prefix: &str,
+    index: usize,
+    out: &mut Vec<(String, &'static str)>,
+) {
+    let key = |name: &Option<String>| {
+        let name = name.clone().unwrap_or_else(|| format!("anonymous_{index}"));
+        if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}.{name}")
+        }
+    };
+    match f {
+        OwnedField::Scalar(name, s) => out.push((key(name), scalar_type_name(s))),
+        OwnedField::Structure(name, fields) => {
+            // The root structure doesn't add a key component
+            let prefix = if name.is_some() {
+                key(name)
+            } else {
+                prefix.to_owned()
+            };
+            let mut anonymous = 0;
+            for f in fields.iter() {
+                field_schema_inner(f, &prefix, anonymous, out);
+                if matches!(
+                    f,
+                    OwnedField::Scalar(None, _) | OwnedField::Structure(None, _)
+                ) {
+                    anonymous += 1;
+                }
+            }
+        }
+    }
+}
+
+fn scalar_type_name(s: &ScalarField) -> &'static str {
+    match s {
+        ScalarField::Bool(_) => "bool",
+        ScalarField::UnsignedInteger(_) => "u64",
+        ScalarField::SignedInteger(_) => "i64",
+        ScalarField::SinglePrecisionReal(_) => "f32",
+        ScalarField::DoublePrecisionReal(_) => "f64",
+        ScalarField::String(_) => "string",
+        ScalarField::UnsignedEnumeration(_, _) => "enum(u64)",
+        ScalarField::SignedEnumeration(_, _) => "enum(i64)",
+    }
+}
+
+impl fmt::Display for TraceSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = &self.trace;
+        writeln!(f, "Trace")?;
+        writeln!(f, "  name: {}", t.name.as_deref().unwrap_or("<none>"))?;
+        match t.uuid {
+            Some(uuid) => writeln!(f, "  uuid: {uuid}")?,
+            None => writeln!(f, "  uuid: <none>")?,
+        }
+        if let Some(env) = &t.env {
+            writeln!(f, "  env:")?;
+            for (k, v) in env.entries() {
+                match v {
+                    EnvValue::Integer(i) => writeln!(f, "    {k} = {i}")?,
+                    EnvValue::String(s) => writeln!(f, "    {k} = {s:?}")?,
+                }
+            }
+        }
+
+        writeln!(f, "Streams")?;
+        for s in self.streams.iter() {
+            writeln!(
+                f,
+                "  stream {}: {}",
+                s.id,
+                s.name.as_deref().unwrap_or("<unnamed>")
+            )?;
+            if let Some(c) = &s.clock {
+                write!(
+                    f,
+                    "    clock: frequency={} offset-seconds={} offset-cycles={} precision={} unix-epoch-origin={}",
+                    c.frequency, c.offset_seconds, c.offset_cycles, c.precision, c.unix_epoch_origin
+                )?;
+                if let Some(name) = &c.name {
+                    write!(f, " name={name}")?;
+                }
+                if let Some(uuid) = &c.uuid {
+                    write!(f, " uuid={uuid}")?;
+                }
+                writeln!(f)?;
+            }
+        }
+
+        writeln!(f, "Event classes")?;
+        for ((stream_id, id), class) in self.event_classes.iter() {
+            write!(
+                f,
+                "  stream {stream_id} id {id}: {}",
+                class.name.as_deref().unwrap_or("<unnamed>")
+            )?;
+            if let Some(ll) = &class.log_level {
+                write!(f, " [{ll}]")?;
+            }
+            writeln!(f, " ({} events)", class.event_count)?;
+            for (key, ty) in class.fields.iter() {
+                writeln!(f, "    {key}: {ty}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn payload_field_schema() {
+        use OwnedField::{Scalar, Structure};
+        use ScalarField::*;
+        let payload = Structure(
+            None,
+            vec![
+                Scalar("prev_comm".to_string().into(), String("foo".to_string())),
+                Scalar(None, UnsignedInteger(1)),
+                Structure(
+                    "next".to_string().into(),
+                    vec![Scalar("tid".to_string().into(), SignedInteger(-1))],
+                ),
+            ],
+        );
+        let mut fields = Vec::new();
+        field_schema(&payload, "payload", &mut fields);
+        assert_eq!(
+            fields,
+            vec![
+                ("payload.prev_comm".to_owned(), "string"),
+                ("payload.anonymous_0".to_owned(), "u64"),
+                ("payload.next.tid".to_owned(), "i64"),
+            ]
+        );
+    }
+
+    #[test]
+    fn schema_fields_use_attr_keys() {
+        let field_types = vec![
+            ("payload.prev_comm".to_owned(), "string"),
+            ("payload.next.tid".to_owned(), "i64"),
+            ("common_context.cpu_id".to_owned(), "u64"),
+        ];
+        let attr_keys = [
+            ("payload.prev_comm", "event.sched.prev_comm"),
+            ("payload.next.tid", "event.next_tid"),
+        ]
+        .iter()
+        .map(|(p, k)| (p.to_string(), k.to_string()))
+        .collect();
+        assert_eq!(
+            schema_fields(field_types, &attr_keys),
+            vec![
+                ("event.sched.prev_comm".to_owned(), "string"),
+                ("event.next_tid".to_owned(), "i64"),
+                ("common_context.cpu_id".to_owned(), "u64"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! Resolving the remote timeline of interactions whose producer can't embed
+//! the remote timeline ID itself.
+//!
+//! * `remote_timeline_name` payload fields are looked up in the configured
+//!   `remote-timeline-names` table first, then in the names of the timelines the
+//!   import itself creates: their `timeline.name` and their derived stream name.
+//!   The names of the event's own trace come first, generic stream names like
+//!   `channel0_0` repeat across traces, then a name only one other trace has.
+//! * `remote_stream_id` payload fields are intra-trace interactions, the stream ID
+//!   maps to the timeline of that stream of the event's trace, derived from the
+//!   trace UUID like [`CtfStreamProperties`](crate::properties::CtfStreamProperties)
+//!   does unless `stream-timeline-map` pins it.
+//!
+//! The `pairing-rules` infer the interactions of producers that don't write any
+//! interaction fields, from a value both events carry, see [`InteractionPairs`].
+
+use crate::config::{EventMappingConfig, PairingRule, PluginConfig};
+use crate::event::root_scalar_field;
+use crate::properties::{derived_stream_timeline_id, CtfProperties};
+use babeltrace2_sys::{OwnedEvent, OwnedField, ScalarField};
+use modality_api::{AttrKey, AttrVal, TimelineId};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use tracing::warn;
+use uuid::Uuid;
+
+const REMOTE_TIMELINE_NAME_FIELD: &str = "remote_timeline_name";
+const REMOTE_STREAM_ID_FIELD: &str = "remote_stream_id";
+const REMOTE_TIMELINE_ID_KEY: &str = "interaction.remote_timeline_id";
+
+#[derive(Debug, Default)]
+pub struct RemoteTimelines {
+    configured: HashMap<String, TimelineId>,
+
+    /// The timeline of each timeline and stream name, per trace UUID
+    learned: HashMap<Uuid, HashMap<String, TimelineId>>,
+
+    /// The timeline of each stream ID, per trace UUID
+    streams: HashMap<Uuid, HashMap<u64, TimelineId>>,
+
+    /// The names that didn't resolve, warned about once
+    warned_unresolved_names: Mutex<BTreeSet<String>>,
+}
+
+impl RemoteTimelines {
+    pub fn from_config(cfg: &EventMappingConfig) -> Self {
+        Self {
+            configured: cfg
+                .remote_timeline_names
+                .iter()
+                .map(|(name, tid)| (name.clone(), TimelineId::from(*tid)))
+                .collect(),
+            learned: Default::default(),
+            streams: Default::default(),
+            warned_unresolved_names: Default::default(),
+        }
+    }
+
+    /// Learn the names and stream IDs of the trace's timelines
+    pub fn learn(&mut self, props: &CtfProperties) {
+        let streams = self.streams.entry(props.trace_uuid).or_default();
+        for (stream_id, s) in props.streams.iter() {
+            streams.insert(*stream_id, s.timeline_id());
+        }
+        for s in props.streams.values() {
+            self.learn_timeline(props.trace_uuid, s.stream_name(), s.timeline_id());
+            self.learn_timeline(props.trace_uuid, s.timeline_name(), s.timeline_id());
+        }
+    }
+
+    fn learn_timeline(&mut self, trace_uuid: Uuid, name: &str, timeline_id: TimelineId) {
+        self.learned
+            .entry(trace_uuid)
+            .or_default()
+            .insert(name.to_owned(), timeline_id);
+    }
+
+    /// The timeline of a name for the events of the given trace: configured,
+    /// learned from the trace, or learned from exactly one other trace
+    pub fn timeline_id(&self, trace_uuid: Uuid, name: &str) -> Option<TimelineId> {
+        if let Some(tid) = self.configured.get(name) {
+            return Some(*tid);
+        }
+        if let Some(tid) = self.learned.get(&trace_uuid).and_then(|n| n.get(name)) {
+            return Some(*tid);
+        }
+        let mut others = self
+            .learned
+            .iter()
+            .filter(|(uuid, _)| **uuid != trace_uuid)
+            .filter_map(|(_, names)| names.get(name));
+        match (others.next(), others.next()) {
+            (Some(tid), None) => Some(*tid),
+            _ => None,
+        }
+    }
+
+    /// The lookup for the events of the trace with the given UUID
+    pub fn for_trace(&self, trace_uuid: Uuid) -> RemoteTimelineLookup<'_> {
+        RemoteTimelineLookup {
+            remote_timelines: self,
+            trace_uuid,
+        }
+    }
+}
+
+/// The remote timeline lookup of the events of one trace
+#[derive(Copy, Clone, Debug)]
+pub struct RemoteTimelineLookup<'a> {
+    remote_timelines: &'a RemoteTimelines,
+    trace_uuid: Uuid,
+}
+
+impl<'a> RemoteTimelineLookup<'a> {
+    /// The timeline of a stream of the trace, streams missing from the trace
+    /// metadata get the derived timeline ID
+    pub fn stream_timeline_id(&self, stream_id: u64) -> TimelineId {
+        self.remote_timelines
+            .streams
+            .get(&self.trace_uuid)
+            .and_then(|streams| streams.get(&stream_id))
+            .copied()
+            .unwrap_or_else(|| derived_stream_timeline_id(&self.trace_uuid, stream_id))
+    }
+
+    /// Map a `remote_timeline_name` or `remote_stream_id` field to the
+    /// `interaction.remote_timeline_id` attr, returns `None` for other fields
+    /// and names that don't resolve
+    pub(crate) fn map_field(&self, k: &str, s: &ScalarField) -> Option<(AttrKey, AttrVal)> {
+        // The field name, after any event-attr-prefix or enclosing structures
+        let field_name = k.rsplit('.').next().unwrap_or(k);
+        let timeline_id = if field_name == REMOTE_TIMELINE_NAME_FIELD {
+            self.name_timeline_id(s)?
+        } else if field_name == REMOTE_STREAM_ID_FIELD {
+            let stream_id = match s {
+                ScalarField::UnsignedInteger(id) => *id,
+                ScalarField::SignedInteger(id) if *id >= 0 => *id as u64,
+                _ => {
+                    warn!(
+                        "Mapping interaction remote stream ID requires a non-negative integer type"
+                    );
+                    return None;
+                }
+            };
+            self.stream_timeline_id(stream_id)
+        } else {
+            return None;
+        };
+        Some((
+            AttrKey::new(REMOTE_TIMELINE_ID_KEY.to_owned()),
+            AttrVal::TimelineId(Box::new(timeline_id)),
+        ))
+    }
+
+    fn name_timeline_id(&self, s: &ScalarField) -> Option<TimelineId> {
+        let name = match s {
+            ScalarField::String(name) => name,
+            _ => {
+                warn!("Mapping interaction remote timeline name requires a string type");
+                return None;
+            }
+        };
+        let timeline_id = self.remote_timelines.timeline_id(self.trace_uuid, name);
+        if timeline_id.is_none()
+            && self
+                .remote_timelines
+                .warned_unresolved_names
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(name.clone())
+        {
+            warn!("The remote timeline name '{name}' doesn't match any known timeline, consider adding it to remote-timeline-names");
+        }
+        timeline_id
+    }
+}
+
+/// The interactions of the `pairing-rules`: a destination event gets the latest
+/// source event of its rule with the same pairing value as its remote event,
+/// each source event pairs with at most one destination event
+#[derive(Debug, Default)]
+pub struct InteractionPairs {
+    rules: Vec<PairingRule>,
+
+    /// The unpaired source events, by rule index and pairing value
+    sources: HashMap<(usize, String), RemoteEvent>,
+}
+
+/// The remote event of an inferred interaction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RemoteEvent {
+    pub timeline_id: TimelineId,
+    pub timestamp_ns: Option<u64>,
+}
+
+impl InteractionPairs {
+    pub fn from_config(cfg: &PluginConfig) -> Self {
+        Self {
+            rules: cfg.pairing_rules.clone(),
+            sources: Default::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Record the event when it's a source event of a rule, and return its remote
+    /// event when it's the destination of a source event on another timeline
+    pub fn pair(
+        &mut self,
+        event: &OwnedEvent,
+        timeline_id: TimelineId,
+        timestamp_ns: Option<u64>,
+    ) -> Option<RemoteEvent> {
+        let name = event.class_properties.name.as_deref()?;
+        let payload = event.properties.payload.as_ref()?;
+        self.pair_payload(name, payload, timeline_id, timestamp_ns)
+    }
+
+    fn pair_payload(
+        &mut self,
+        name: &str,
+        payload: &OwnedField,
+        timeline_id: TimelineId,
+        timestamp_ns: Option<u64>,
+    ) -> Option<RemoteEvent> {
+        let mut remote = None;
+        for (idx, rule) in self.rules.iter().enumerate() {
+            if remote.is_none() && rule.destination_event == name {
+                if let Some(value) =
+                    root_scalar_field(payload, &rule.destination_field).and_then(pairing_value)
+                {
+                    remote = self
+                        .sources
+                        .remove(&(idx, value))
+                        .filter(|r| r.timeline_id != timeline_id);
+                }
+            }
+            if rule.source_event == name {
+                if let Some(value) =
+                    root_scalar_field(payload, &rule.source_field).and_then(pairing_value)
+                {
+                    self.sources.insert(
+                        (idx, value),
+                        RemoteEvent {
+                            timeline_id,
+                            timestamp_ns,
+                        },
+                    );
+                }
+            }
+        }
+        remote
+    }
+}
+
+/// The pairing value of the integer, enumeration and string fields
+fn pairing_value(s: &ScalarField) -> Option<String> {
+    match s {
+        ScalarField::UnsignedInteger(v) | ScalarField::UnsignedEnumeration(v, _) => {
+            Some(v.to_string())
+        }
+        ScalarField::SignedInteger(v) | ScalarField::SignedEnumeration(v, _) => Some(v.to_string()),
+        ScalarField::String(v) => Some(v.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn remote_timeline_name_lookup() {
+        let gateway = Uuid::from_u128(1);
+        let cfg = EventMappingConfig {
+            remote_timeline_names: vec![("gateway".to_owned(), gateway)].into_iter().collect(),
+            ..Default::default()
+        };
+        let mut remote_timelines = RemoteTimelines::from_config(&cfg);
+        let trace_uuid = Uuid::from_u128(3);
+        let learned = TimelineId::from(Uuid::from_u128(2));
+        remote_timelines.learn_timeline(trace_uuid, "cpu-0", learned);
+        remote_timelines.learn_timeline(trace_uuid, "gateway", learned);
+
+        let lookup = remote_timelines.for_trace(trace_uuid);
+        let field = |name: &str| ScalarField::String(name.to_owned());
+        let key = AttrKey::new(REMOTE_TIMELINE_ID_KEY.to_owned());
+        assert_eq!(
+            lookup.map_field("remote_timeline_name", &field("gateway")),
+            Some((key.clone(), AttrVal::TimelineId(Box::new(gateway.into()))))
+        );
+        assert_eq!(
+            lookup.map_field("ctf.remote_timeline_name", &field("cpu-0")),
+            Some((key, AttrVal::TimelineId(Box::new(learned))))
+        );
+        assert_eq!(
+            lookup.map_field("remote_timeline_name", &field("unknown")),
+            None
+        );
+        assert_eq!(lookup.map_field("timeline_name", &field("cpu-0")), None);
+        assert_eq!(
+            lookup.map_field("remote_timeline_name_hint", &field("cpu-0")),
+            None
+        );
+    }
+
+    #[test]
+    fn remote_timeline_names_per_trace() {
+        let (trace_a, trace_b, trace_c) =
+            (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let (a, b) = (
+            TimelineId::from(Uuid::from_u128(4)),
+            TimelineId::from(Uuid::from_u128(5)),
+        );
+        let mut remote_timelines = RemoteTimelines::default();
+        remote_timelines.learn_timeline(trace_a, "channel0_0", a);
+        remote_timelines.learn_timeline(trace_b, "channel0_0", b);
+        remote_timelines.learn_timeline(trace_b, "gateway", b);
+
+        // The event's own trace first
+        assert_eq!(remote_timelines.timeline_id(trace_a, "channel0_0"), Some(a));
+        assert_eq!(remote_timelines.timeline_id(trace_b, "channel0_0"), Some(b));
+        // A name of exactly one other trace
+        assert_eq!(remote_timelines.timeline_id(trace_a, "gateway"), Some(b));
+        // Ambiguous across the other traces
+        assert_eq!(remote_timelines.timeline_id(trace_c, "channel0_0"), None);
+    }
+
+    #[test]
+    fn remote_stream_id_lookup() {
+        let trace_uuid = Uuid::from_u128(3);
+        let pinned = TimelineId::from(Uuid::from_u128(4));
+        let mut remote_timelines = RemoteTimelines::default();
+        remote_timelines
+            .streams
+            .insert(trace_uuid, vec![(1, pinned)].into_iter().collect());
+
+        let lookup = remote_timelines.for_trace(trace_uuid);
+        let key = AttrKey::new(REMOTE_TIMELINE_ID_KEY.to_owned());
+        assert_eq!(
+            lookup.map_field("remote_stream_id", &ScalarField::UnsignedInteger(1)),
+            Some((key.clone(), AttrVal::TimelineId(Box::new(pinned))))
+        );
+        assert_eq!(
+            lookup.map_field("remote_stream_id", &ScalarField::SignedInteger(2)),
+            Some((
+                key,
+                AttrVal::TimelineId(Box::new(derived_stream_timeline_id(&trace_uuid, 2)))
+            ))
+        );
+        assert_eq!(
+            lookup.map_field("remote_stream_id", &ScalarField::SignedInteger(-1)),
+            None
+        );
+    }
+
+    #[test]
+    fn pairing_rules() {
+        use OwnedField::{Scalar, Structure};
+        let cfg = PluginConfig {
+            pairing_rules: vec![PairingRule {
+                source_event: "sched_wakeup".to_owned(),
+                source_field: "tid".to_owned(),
+                destination_event: "sched_switch".to_owned(),
+                destination_field: "next_tid".to_owned(),
+            }],
+            ..Default::default()
+        };
+        let mut pairs = InteractionPairs::from_config(&cfg);
+        let (cpu0, cpu1) = (
+            TimelineId::from(Uuid::from_u128(1)),
+            TimelineId::from(Uuid::from_u128(2)),
+        );
+        let payload = |name: &str, tid: i64| {
+            Structure(
+                None,
+                vec![Scalar(
+                    Some(name.to_owned()),
+                    ScalarField::SignedInteger(tid),
+                )],
+            )
+        };
+
+        assert_eq!(
+            pairs.pair_payload("sched_wakeup", &payload("tid", 7), cpu0, Some(10)),
+            None
+        );
+        // Another thread, and the wakee on the same timeline
+        assert_eq!(
+            pairs.pair_payload("sched_switch", &payload("next_tid", 8), cpu1, Some(11)),
+            None
+        );
+        assert_eq!(
+            pairs.pair_payload("sched_wakeup", &payload("tid", 8), cpu1, Some(12)),
+            None
+        );
+        assert_eq!(
+            pairs.pair_payload("sched_switch", &payload("next_tid", 8), cpu1, Some(13)),
+            None
+        );
+
+        assert_eq!(
+            pairs.pair_payload("sched_switch", &payload("next_tid", 7), cpu1, Some(14)),
+            Some(RemoteEvent {
+                timeline_id: cpu0,
+                timestamp_ns: Some(10),
+            })
+        );
+        // Paired only once
+        assert_eq!(
+            pairs.pair_payload("sched_switch", &payload("next_tid", 7), cpu1, Some(15)),
+            None
+        );
+    }
+}
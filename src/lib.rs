@@ -10,10 +10,13 @@
 //! # Attrs Mappings
 //!
 //! Trace Attrs
-//! * timeline.internal.ctf.trace.name
+//! * timeline.internal.ctf.trace.name (for an anonymous trace, without a name or a `hostname` environment entry,
+//!   the name of the input directory (importer) or the `<hostname>/<session>` of the lttng-live URL (LTTng collector))
 //! * timeline.internal.ctf.trace.uuid
 //! * timeline.internal.ctf.trace.stream_count
 //! * timeline.internal.ctf.trace.env.`<fields>`
+//! * timeline.tracer.name (from the `tracer_name` env field)
+//! * timeline.tracer.version (from the `tracer_{major,minor,patch}` env fields)
 //!
 //! Stream Attrs
 //! * timeline.internal.ctf.stream.id
@@ -28,20 +31,61 @@
 //! * timeline.internal.ctf.stream.clock.description
 //! * timeline.internal.ctf.stream.clock.uuid
 //!   - timeline.time_domain
+//! * timeline.internal.ctf.stream.clock.wall_clock_origin (when relative clock timestamps are rebased)
+//! * timeline.internal.ctf.stream.clock.timestamp_shift_ns (when `timestamp-shift-ns` is set)
+//! * timeline.internal.ctf.stream.clock.class_offset_ns (when the LTTng collector applies a clock class offset)
+//! * timeline.internal.ctf.stream.clock.correction_ns (when a clock correction is applied)
+//! * timeline.internal.ctf.stream.clock.correction_source
+//! * timeline.internal.ctf.source_uri (with `source-uri-attrs`)
+//! * timeline.internal.ctf.packet_index.packet_count
+//! * timeline.internal.ctf.packet_index.total_size (bytes)
+//! * timeline.internal.ctf.packet_index.begin_ns
+//! * timeline.internal.ctf.packet_index.end_ns
+//!   (from the packet index files of fs traces, when present)
 //! * timeline.ingest_source
 //! * timeline.clock_style
+//! * timeline.cpu (from the `<channel>_<cpu>` stream name with `per-cpu-streams`, or the `cpu_id` packet context field with `cpu-attrs`, when streams aren't merged)
+//!
+//! Stream Statistics Attrs (written once ingest completes)
+//! * timeline.internal.ctf.event_count
+//! * timeline.internal.ctf.min_timestamp
+//! * timeline.internal.ctf.max_timestamp
+//! * timeline.internal.ctf.event_class.`<event_name>`.count
+//! * timeline.internal.ctf.capped_event_count (the events left out by `max-events-per-timeline`)
+//! * timeline.internal.ctf.sample_rate, timeline.internal.ctf.event_class.`<event_name>`.sample_rate,
+//!   timeline.internal.ctf.event_class.`<event_name>`.decaying_threshold, timeline.internal.ctf.sample_decaying_window_ms
+//!   (the configured `sampling`)
 //!
 //! Event Attrs
 //! * event.internal.ctf.stream_id
 //! * event.internal.ctf.id
 //! * event.name
 //! * event.internal.ctf.log_level
+//! * event.internal.ctf.log_level_value (0 = emergency, ..., 14 = debug)
 //! * event.internal.ctf.clock_snapshot
+//!   - event.internal.ctf.signed_clock_snapshot in its place when negative, the event then only has
+//!     a timestamp when the wall-clock origin, clock correction or timestamp shift make it non-negative
 //!   - event.timestamp
+//!   - event.timestamp.precision_ns (with `timestamp-precision-attrs`, when the stream clock precision is non-zero)
+//! * event.cpu (from the `cpu_id` packet context field, with `cpu-attrs`)
 //! * event.internal.ctf.common_context.<possibly.nested.fields>
 //! * event.internal.ctf.specific_context.<possibly.nested.fields>
 //! * event.internal.ctf.packet_context.<possibly.nested.fields>
+//! * event.internal.ctf.packet.begin_ns, event.internal.ctf.packet.end_ns
+//!   (the `timestamp_begin` and `timestamp_end` packet context fields, converted with the stream clock)
+//! * event.internal.ctf.source_uri (with `source-uri-attrs`)
+//! * event.internal.ctf.sample_rate (the number of events a kept event stands for, for the sampled event classes)
+//! * event.internal.ctf.aggregate.count, event.internal.ctf.aggregate.bucket_ns, event.`<field>`.sum, event.`<field>`.min,
+//!   event.`<field>`.max (on the bucket events of the aggregated event classes)
+//! * event.internal.ctf.trace.uuid (the CTF metadata trace UUID, when streams are merged,
+//!   several traces are imported, or collected by the LTTng collector)
+//! * event.internal.ctf.discarded_events (on `modality.ctf.discarded_events` events,
+//!   see the `message-handling` configuration)
 //! * event.<possibly.nested.fields>
+//!   - event.`<event-attr-prefix>`.<possibly.nested.fields> when configured
+//! * event.`<field>`.hex for the configured `hex-fields`
+//! * event.`<field>`.symbol for the configured `symbolize-fields`
+//!   - event.`<field>`.location (`file:line`) when resolved from an ELF binary with debug info
 //!
 //! # Mapping Conventions
 //!
@@ -63,14 +107,42 @@
 //! * event.my_enum = 5
 #![deny(warnings, clippy::all)]
 
+pub mod aggregation;
 pub mod attrs;
 pub mod auth;
 pub mod client;
+pub mod clock;
 pub mod config;
+pub mod control;
+pub mod diagnostics;
+pub mod dmesg;
 pub mod error;
 pub mod event;
+pub mod healthcheck;
+pub mod index;
+pub mod inspect;
+pub mod interaction;
+pub mod limits;
+pub mod mapper;
+pub mod messages;
+pub mod metadata;
 pub mod opts;
 pub mod prelude;
+pub mod preview;
+pub mod profile;
 pub mod properties;
+pub mod reflector;
+pub mod relayd;
+pub mod report;
+pub mod retry;
+pub mod sampling;
+pub mod schema;
+pub mod shard;
+pub mod state;
+pub mod stats;
+pub mod symbols;
+pub mod timeline;
+pub mod timestamp;
 pub mod tracing;
+pub mod transform;
 pub mod types;
@@ -0,0 +1,150 @@
+//! Fits the event attrs into the ingest limits before they're sent, so an
+//! oversized or unrepresentable value is adjusted locally instead of the
+//! backend rejecting the event mid-run.
+//!
+//! The ingest protocol (`modality-ingest-protocol`) sends each event as one CBOR
+//! message, and doesn't publish per-value limits of its own. The defaults are the
+//! plugin's conservative bounds on a single event, configurable with `attr-limits`
+//! for backends that accept more.
+//!
+//! * String values longer than `max-string-len` bytes are truncated on a UTF-8
+//!   character boundary
+//! * Non-finite float values (NaN and infinities) are dropped, so an attr key keeps
+//!   the float type on every event
+//! * Attrs beyond `max-attrs-per-event` are dropped, keeping `event.name` and
+//!   `event.timestamp` and then the attrs in key order
+//!
+//! Each adjusted or dropped attr is counted with the `attr-limit` drop reason.
+
+use crate::config::AttrLimitsConfig;
+use crate::diagnostics::{DropCounts, DropReason};
+use modality_api::AttrVal;
+use std::collections::HashMap;
+use std::hash::Hash;
+use tracing::debug;
+
+pub const DEFAULT_MAX_STRING_LEN: usize = 64 * 1024;
+pub const DEFAULT_MAX_ATTRS_PER_EVENT: usize = 1024;
+
+/// Attrs kept first when an event has too many
+const KEPT_ATTR_KEYS: [&str; 2] = ["event.name", "event.timestamp"];
+
+impl AttrLimitsConfig {
+    pub fn max_string_len(&self) -> usize {
+        self.max_string_len.unwrap_or(DEFAULT_MAX_STRING_LEN)
+    }
+
+    pub fn max_attrs_per_event(&self) -> usize {
+        self.max_attrs_per_event
+            .unwrap_or(DEFAULT_MAX_ATTRS_PER_EVENT)
+    }
+}
+
+/// Fit the attrs into the limits, `key_name` resolves the attr key names.
+/// Returns the number of attrs that were adjusted or dropped, each also
+/// counted in `drops`.
+pub fn apply_limits<K, F>(
+    cfg: &AttrLimitsConfig,
+    attrs: &mut HashMap<K, AttrVal>,
+    drops: &mut DropCounts,
+    key_name: F,
+) -> usize
+where
+    K: Copy + Eq + Hash,
+    F: Fn(&K) -> Option<String>,
+{
+    let mut adjusted = 0;
+    let max_string_len = cfg.max_string_len();
+    let mut non_finite = Vec::new();
+    for (k, v) in attrs.iter_mut() {
+        match v {
+            AttrVal::String(s) if s.len() > max_string_len => {
+                let mut end = max_string_len;
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                debug!(
+                    "Truncated the value of attr '{}' to {max_string_len} bytes",
+                    key_name(k).unwrap_or_default()
+                );
+                *v = AttrVal::from(s[..end].to_owned());
+                adjusted += 1;
+            }
+            AttrVal::Float(f) if !f.0.is_finite() => non_finite.push(*k),
+            _ => (),
+        }
+    }
+    for k in non_finite.into_iter() {
+        debug!(
+            "Dropped attr '{}', its float value isn't finite",
+            key_name(&k).unwrap_or_default()
+        );
+        attrs.remove(&k);
+        adjusted += 1;
+    }
+
+    let max_attrs = cfg.max_attrs_per_event();
+    if attrs.len() > max_attrs {
+        let mut keys: Vec<(bool, String, K)> = attrs
+            .keys()
+            .map(|k| {
+                let name = key_name(k).unwrap_or_default();
+                (!KEPT_ATTR_KEYS.contains(&name.as_str()), name, *k)
+            })
+            .collect();
+        keys.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        for (_, name, k) in keys.into_iter().skip(max_attrs) {
+            debug!("Dropped attr '{name}', the event has more than {max_attrs} attrs");
+            attrs.remove(&k);
+            adjusted += 1;
+        }
+    }
+
+    for _ in 0..adjusted {
+        drops.count(DropReason::AttrLimit);
+    }
+    adjusted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn fit_attrs_into_limits() {
+        let cfg = AttrLimitsConfig {
+            max_string_len: Some(4),
+            max_attrs_per_event: Some(3),
+        };
+        let names = [
+            "event.z",
+            "event.name",
+            "event.a",
+            "event.timestamp",
+            "event.f",
+        ];
+        let mut attrs: HashMap<usize, AttrVal> = HashMap::new();
+        attrs.insert(0, "ab".to_owned().into());
+        attrs.insert(1, "héllo".to_owned().into());
+        attrs.insert(2, "abcdef".to_owned().into());
+        attrs.insert(3, 1_i64.into());
+        attrs.insert(4, f64::NAN.into());
+
+        let mut drops = DropCounts::default();
+        let adjusted = apply_limits(&cfg, &mut attrs, &mut drops, |k| Some(names[*k].to_owned()));
+        // 2 values truncated, the NaN dropped, and then 1 attr over the limit dropped
+        assert_eq!(adjusted, 4);
+        assert_eq!(drops.get(DropReason::AttrLimit), 4);
+        let mut kept: Vec<_> = attrs.into_iter().collect();
+        kept.sort_by_key(|(k, _)| *k);
+        assert_eq!(
+            kept,
+            vec![
+                (1, AttrVal::from("hél".to_owned())),
+                (2, AttrVal::from("abcd".to_owned())),
+                (3, AttrVal::from(1_i64)),
+            ]
+        );
+    }
+}
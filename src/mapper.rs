@@ -0,0 +1,377 @@
+//! Field mappers turn payload scalar fields following some naming convention
+//! into attrs with a specific key and value type, e.g. the modality
+//! interaction and mutation attrs.
+//!
+//! The mappers are applied in sequence to each payload scalar field, the first one
+//! returning a mapping wins and fields no mapper handles get the default mapping.
+//! Crates embedding this one can add their own conventions with [`register_field_mapper`],
+//! the registry is resolved once per run into [`FieldMappers`].
+
+use crate::diagnostics::{DropCounts, DropReason};
+use crate::event::scalar_field_to_val;
+use babeltrace2_sys::ScalarField;
+use modality_api::{AttrKey, AttrVal, LogicalTime};
+use std::sync::{Arc, RwLock};
+use tracing::debug;
+use uuid::Uuid;
+
+/// What's known about the event whose fields are being mapped
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FieldMapperContext {
+    /// Root-level interaction fields should be mapped to the `interaction.` attrs
+    pub auto_map_interaction_fields: bool,
+
+    /// The event is one of the modality reserved events
+    pub is_reserved_event: bool,
+}
+
+pub trait FieldMapper: Send + Sync + std::fmt::Debug {
+    /// Map the scalar field with the fully qualified attr key `key`,
+    /// returns `None` to leave the field to the next mapper. Fields the mapper
+    /// handles but fails to map are counted in `drops`.
+    fn map_field(
+        &self,
+        ctx: &FieldMapperContext,
+        key: &str,
+        field: &ScalarField,
+        drops: &mut DropCounts,
+    ) -> Option<(AttrKey, AttrVal)>;
+}
+
+static REGISTERED_FIELD_MAPPERS: RwLock<Vec<Arc<dyn FieldMapper>>> = RwLock::new(Vec::new());
+
+/// Register a field mapper, applied after the previously registered ones
+/// and before the built-in ones
+pub fn register_field_mapper<M: FieldMapper + 'static>(mapper: M) {
+    REGISTERED_FIELD_MAPPERS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Arc::new(mapper));
+}
+
+/// The field mappers of a run, applied in sequence
+#[derive(Clone, Debug)]
+pub struct FieldMappers(Vec<Arc<dyn FieldMapper>>);
+
+impl FieldMappers {
+    /// The given field mappers followed by the built-in ones
+    pub fn new(mappers: Vec<Arc<dyn FieldMapper>>) -> Self {
+        let mut mappers = mappers;
+        mappers.push(Arc::new(InteractionFieldMapper));
+        mappers.push(Arc::new(ReservedEventFieldMapper));
+        Self(mappers)
+    }
+
+    /// The registered field mappers followed by the built-in ones
+    pub fn registered() -> Self {
+        Self::new(
+            REGISTERED_FIELD_MAPPERS
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        )
+    }
+
+    /// Map the scalar field with the first mapper returning a mapping
+    pub fn map_field(
+        &self,
+        ctx: &FieldMapperContext,
+        key: &str,
+        field: &ScalarField,
+        drops: &mut DropCounts,
+    ) -> Option<(AttrKey, AttrVal)> {
+        self.0
+            .iter()
+            .find_map(|m| m.map_field(ctx, key, field, drops))
+    }
+}
+
+impl Default for FieldMappers {
+    /// Only the built-in field mappers
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+/// Maps the `remote_timeline_id`, `remote_logical_time`, `remote_timestamp`
+/// and `remote_nonce` fields to the `interaction.` attrs
+#[derive(Copy, Clone, Debug, Default)]
+pub struct InteractionFieldMapper;
+
+impl FieldMapper for InteractionFieldMapper {
+    fn map_field(
+        &self,
+        ctx: &FieldMapperContext,
+        k: &str,
+        s: &ScalarField,
+        drops: &mut DropCounts,
+    ) -> Option<(AttrKey, AttrVal)> {
+        if !ctx.auto_map_interaction_fields {
+            return None;
+        }
+
+        if ReservedAttrKey::TimelineId.matches_key(k) {
+            if let ScalarField::String(tid) = s {
+                match tid.parse::<Uuid>() {
+                    Ok(tid) => {
+                        return Some((
+                            ReservedAttrKey::TimelineId.attr_key(),
+                            AttrVal::TimelineId(Box::new(tid.into())),
+                        ))
+                    }
+                    Err(e) => {
+                        debug!(
+                            "Failed to auto map interaction field as timeline ID UUID type. {e}"
+                        );
+                        drops.count(DropReason::InvalidInteractionField);
+                    }
+                }
+            } else {
+                debug!("Mapping interaction remote timeline ID requires a string type");
+                drops.count(DropReason::InvalidInteractionField);
+            }
+        } else if ReservedAttrKey::LogicalTime.matches_key(k) {
+            if let ScalarField::String(t) = s {
+                match t.parse::<LogicalTime>() {
+                    Ok(t) => {
+                        return Some((
+                            ReservedAttrKey::LogicalTime.attr_key(),
+                            AttrVal::LogicalTime(t),
+                        ))
+                    }
+                    Err(e) => {
+                        debug!(
+                            "Failed to auto map interaction field as timeline ID UUID type. {e:?}"
+                        );
+                        drops.count(DropReason::InvalidInteractionField);
+                    }
+                }
+            } else {
+                debug!("Mapping interaction remote logical time requires a string type");
+                drops.count(DropReason::InvalidInteractionField);
+            }
+        } else if ReservedAttrKey::Timestamp.matches_key(k) {
+            if let ScalarField::UnsignedInteger(t) = s {
+                return Some((
+                    ReservedAttrKey::Timestamp.attr_key(),
+                    AttrVal::Timestamp((*t).into()),
+                ));
+            } else {
+                debug!("Mapping interaction remote timestamp requires a u64 type");
+                drops.count(DropReason::InvalidInteractionField);
+            }
+        } else if ReservedAttrKey::Nonce.matches_key(k) {
+            return Some((ReservedAttrKey::Nonce.attr_key(), scalar_field_to_val(s)));
+        }
+        None
+    }
+}
+
+/// Maps the `mutator_id`, `mutation_id` and `mutation_success` fields
+/// of the modality reserved events to the `mutator.` and `mutation.` attrs
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ReservedEventFieldMapper;
+
+impl FieldMapper for ReservedEventFieldMapper {
+    fn map_field(
+        &self,
+        ctx: &FieldMapperContext,
+        k: &str,
+        s: &ScalarField,
+        drops: &mut DropCounts,
+    ) -> Option<(AttrKey, AttrVal)> {
+        if !ctx.is_reserved_event {
+            return None;
+        }
+
+        if ReservedAttrKey::MutatorId.matches_key(k) {
+            if let ScalarField::String(id) = s {
+                match id.parse::<Uuid>() {
+                    Ok(id) => {
+                        return Some((
+                            ReservedAttrKey::MutatorId.attr_key(),
+                            uuid_to_integer_attr_val(&id),
+                        ))
+                    }
+                    Err(e) => {
+                        debug!("Failed to auto map reserved field as mutator ID UUID type. {e}");
+                        drops.count(DropReason::InvalidReservedField);
+                    }
+                }
+            } else {
+                debug!("Mapping reserved mutator ID requires a string type");
+                drops.count(DropReason::InvalidReservedField);
+            }
+        } else if ReservedAttrKey::MutationId.matches_key(k) {
+            if let ScalarField::String(id) = s {
+                match id.parse::<Uuid>() {
+                    Ok(id) => {
+                        return Some((
+                            ReservedAttrKey::MutationId.attr_key(),
+                            uuid_to_integer_attr_val(&id),
+                        ))
+                    }
+                    Err(e) => {
+                        debug!("Failed to auto map reserved field as mutation ID UUID type. {e}");
+                        drops.count(DropReason::InvalidReservedField);
+                    }
+                }
+            } else {
+                debug!("Mapping reserved mutation ID requires a string type");
+                drops.count(DropReason::InvalidReservedField);
+            }
+        } else if ReservedAttrKey::MutationSuccess.matches_key(k) {
+            let maybe_success = match s {
+                ScalarField::Bool(val) => Some(*val),
+                ScalarField::UnsignedInteger(val) => Some(*val != 0),
+                ScalarField::SignedInteger(val) => Some(*val != 0),
+                _ => None,
+            };
+            if let Some(success) = maybe_success {
+                return Some((ReservedAttrKey::MutationSuccess.attr_key(), success.into()));
+            } else {
+                debug!("Mapping reserved mutation success requires a boolean or integer type");
+                drops.count(DropReason::InvalidReservedField);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+enum ReservedAttrKey {
+    TimelineId,
+    LogicalTime,
+    Timestamp,
+    Nonce,
+    MutatorId,
+    MutationId,
+    MutationSuccess,
+}
+
+impl ReservedAttrKey {
+    fn matches_key(self, k: &str) -> bool {
+        !k.contains(self.to_modality_key()) && k.contains(self.to_ctf_key())
+    }
+
+    fn attr_key(self) -> AttrKey {
+        AttrKey::new(self.to_modality_key().to_string())
+    }
+
+    fn to_ctf_key(self) -> &'static str {
+        use ReservedAttrKey::*;
+        match self {
+            TimelineId => "remote_timeline_id",
+            LogicalTime => "remote_logical_time",
+            Timestamp => "remote_timestamp",
+            Nonce => "remote_nonce",
+            MutatorId => "mutator_id",
+            MutationId => "mutation_id",
+            MutationSuccess => "mutation_success",
+        }
+    }
+
+    fn to_modality_key(self) -> &'static str {
+        use ReservedAttrKey::*;
+        match self {
+            TimelineId => "interaction.remote_timeline_id",
+            LogicalTime => "interaction.remote_logical_time",
+            Timestamp => "interaction.remote_timestamp",
+            Nonce => "interaction.remote_nonce",
+            MutatorId => "mutator.id",
+            MutationId => "mutation.id",
+            MutationSuccess => "mutation.success",
+        }
+    }
+}
+
+fn uuid_to_integer_attr_val(u: &Uuid) -> AttrVal {
+    i128::from_le_bytes(*u.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[derive(Debug)]
+    struct TicketMapper;
+
+    impl FieldMapper for TicketMapper {
+        fn map_field(
+            &self,
+            _ctx: &FieldMapperContext,
+            key: &str,
+            field: &ScalarField,
+            _drops: &mut DropCounts,
+        ) -> Option<(AttrKey, AttrVal)> {
+            match field {
+                ScalarField::UnsignedInteger(n) if key == "test_mapper_ticket" => Some((
+                    AttrKey::new("ticket".to_owned()),
+                    format!("TICKET-{n}").into(),
+                )),
+                _ => None,
+            }
+        }
+    }
+
+    fn ctx() -> FieldMapperContext {
+        FieldMapperContext {
+            auto_map_interaction_fields: true,
+            is_reserved_event: false,
+        }
+    }
+
+    #[test]
+    fn mappers_apply_before_builtin() {
+        let mappers = FieldMappers::new(vec![Arc::new(TicketMapper)]);
+        let mut drops = DropCounts::default();
+        let mut apply =
+            |key: &str, field: &ScalarField| mappers.map_field(&ctx(), key, field, &mut drops);
+
+        assert_eq!(
+            apply("test_mapper_ticket", &ScalarField::UnsignedInteger(12)),
+            Some((
+                AttrKey::new("ticket".to_owned()),
+                "TICKET-12".to_owned().into()
+            ))
+        );
+        assert_eq!(
+            apply("remote_timestamp", &ScalarField::UnsignedInteger(12)),
+            Some((
+                AttrKey::new("interaction.remote_timestamp".to_owned()),
+                AttrVal::Timestamp(12.into())
+            ))
+        );
+        assert_eq!(apply("mutator_id", &ScalarField::UnsignedInteger(12)), None);
+        assert_eq!(
+            apply("remote_timeline_id", &ScalarField::UnsignedInteger(12)),
+            None
+        );
+        assert_eq!(drops.get(DropReason::InvalidInteractionField), 1);
+        assert_eq!(drops.get(DropReason::InvalidReservedField), 0);
+    }
+
+    #[test]
+    fn registered_mappers_resolve_once() {
+        let mut drops = DropCounts::default();
+        let field = ScalarField::UnsignedInteger(7);
+        let before = FieldMappers::registered();
+        register_field_mapper(TicketMapper);
+        assert_eq!(
+            before.map_field(&ctx(), "test_mapper_ticket", &field, &mut drops),
+            None
+        );
+        assert_eq!(
+            FieldMappers::registered().map_field(&ctx(), "test_mapper_ticket", &field, &mut drops),
+            Some((
+                AttrKey::new("ticket".to_owned()),
+                "TICKET-7".to_owned().into()
+            ))
+        );
+        assert_eq!(
+            FieldMappers::default().map_field(&ctx(), "test_mapper_ticket", &field, &mut drops),
+            None
+        );
+    }
+}
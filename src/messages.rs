@@ -0,0 +1,329 @@
+//! The babeltrace messages other than events, and their configured handling.
+//!
+//! babeltrace2-sys only yields the event messages of the graph, so rather than
+//! the graph's own messages, the packet beginning/end, discarded events and
+//! stream beginning/end messages are inferred per stream from the packet
+//! boundaries seen in the events' packet context, see [`PacketBoundaryTracker`]:
+//! a new `packet_seq_num` or `timestamp_begin` starts a packet, and an increase
+//! of the `events_discarded` counter between packets reports discarded events.
+
+use crate::client::Client;
+use crate::config::MessageHandlingConfig;
+use crate::diagnostics::{DropCounts, DropReason};
+use crate::error::Error;
+use crate::event::{packet_context_events_discarded, packet_context_seq_num, CtfEvent};
+use crate::types::MessageAction;
+use babeltrace2_sys::OwnedEvent;
+use derive_more::Display;
+use modality_api::TimelineId;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use tracing::info;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+pub enum MessageType {
+    #[display(fmt = "event")]
+    Event,
+    #[display(fmt = "packet-beginning")]
+    PacketBeginning,
+    #[display(fmt = "packet-end")]
+    PacketEnd,
+    #[display(fmt = "discarded-events")]
+    DiscardedEvents,
+    #[display(fmt = "stream-beginning")]
+    StreamBeginning,
+    #[display(fmt = "stream-end")]
+    StreamEnd,
+    #[display(fmt = "message-iterator-inactivity")]
+    MessageIteratorInactivity,
+}
+
+impl MessageType {
+    /// The `event.name` of the events synthesized for this message type,
+    /// e.g. `modality.ctf.packet_beginning`
+    pub fn event_name(&self) -> String {
+        format!("modality.ctf.{}", self.to_string().replace('-', "_"))
+    }
+}
+
+impl MessageHandlingConfig {
+    pub fn action(&self, message_type: MessageType) -> MessageAction {
+        match message_type {
+            MessageType::Event => self.event,
+            MessageType::PacketBeginning => self.packet_beginning,
+            MessageType::PacketEnd => self.packet_end,
+            MessageType::DiscardedEvents => self.discarded_events,
+            MessageType::StreamBeginning => self.stream_beginning,
+            MessageType::StreamEnd => self.stream_end,
+            MessageType::MessageIteratorInactivity => self.message_iterator_inactivity,
+        }
+    }
+
+    /// Whether any of the message types inferred from the packet boundaries
+    /// are logged or ingested
+    pub fn tracks_packet_boundaries(&self) -> bool {
+        [
+            self.packet_beginning,
+            self.packet_end,
+            self.discarded_events,
+            self.stream_beginning,
+            self.stream_end,
+        ]
+        .into_iter()
+        .any(|a| a != MessageAction::Ignore)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InferredMessage {
+    pub message_type: MessageType,
+
+    /// `None` for message iterator inactivity
+    pub stream_id: Option<u64>,
+
+    /// The packet boundary timestamp, or the time the inactivity was observed
+    pub timestamp_ns: Option<u64>,
+
+    /// The number of events discarded, for discarded events messages
+    pub discarded_events: Option<u64>,
+}
+
+impl InferredMessage {
+    pub fn inactivity(now_ns: u64) -> Self {
+        Self {
+            message_type: MessageType::MessageIteratorInactivity,
+            stream_id: None,
+            timestamp_ns: Some(now_ns),
+            discarded_events: None,
+        }
+    }
+}
+
+impl fmt::Display for InferredMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Babeltrace {} message", self.message_type)?;
+        if let Some(stream_id) = self.stream_id {
+            write!(f, " on stream {stream_id}")?;
+        }
+        if let Some(ns) = self.timestamp_ns {
+            write!(f, " at {ns} ns")?;
+        }
+        if let Some(count) = self.discarded_events {
+            write!(f, ", {count} events discarded")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct StreamState {
+    /// The `packet_seq_num` and begin timestamp of the current packet
+    packet: Option<(Option<u64>, Option<u64>)>,
+    packet_end_ns: Option<u64>,
+    events_discarded: u64,
+}
+
+/// Infers the packet boundaries of each stream of each timeline from the
+/// events' packet context, and the packet, discarded events and stream
+/// messages at those boundaries
+#[derive(Clone, Debug, Default)]
+pub struct PacketBoundaryTracker {
+    streams: HashMap<(TimelineId, u64), StreamState>,
+}
+
+/// The packet context fields telling the packets of a stream apart
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct PacketContext {
+    seq_num: Option<u64>,
+    events_discarded: Option<u64>,
+}
+
+impl PacketBoundaryTracker {
+    /// The messages preceding the event on its stream, given the begin and end
+    /// timestamps of its packet
+    pub fn observe(
+        &mut self,
+        timeline_id: TimelineId,
+        event: &OwnedEvent,
+        packet_timestamps_ns: (Option<u64>, Option<u64>),
+    ) -> Vec<InferredMessage> {
+        let packet = PacketContext {
+            seq_num: packet_context_seq_num(event),
+            events_discarded: packet_context_events_discarded(event),
+        };
+        self.observe_packet(timeline_id, event.stream_id, packet, packet_timestamps_ns)
+    }
+
+    fn observe_packet(
+        &mut self,
+        timeline_id: TimelineId,
+        stream_id: u64,
+        packet_context: PacketContext,
+        (begin_ns, end_ns): (Option<u64>, Option<u64>),
+    ) -> Vec<InferredMessage> {
+        let msg = |message_type, timestamp_ns, discarded_events| InferredMessage {
+            message_type,
+            stream_id: Some(stream_id),
+            timestamp_ns,
+            discarded_events,
+        };
+        let mut messages = Vec::new();
+        let state = match self.streams.entry((timeline_id, stream_id)) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                messages.push(msg(MessageType::StreamBeginning, begin_ns, None));
+                e.insert(Default::default())
+            }
+        };
+
+        // Without packet context fields the packets can't be told apart
+        let packet = (packet_context.seq_num, begin_ns);
+        if packet != (None, None) && state.packet != Some(packet) {
+            if state.packet.is_some() {
+                messages.push(msg(MessageType::PacketEnd, state.packet_end_ns, None));
+            }
+            if let Some(discarded) = packet_context.events_discarded {
+                if discarded > state.events_discarded {
+                    messages.push(msg(
+                        MessageType::DiscardedEvents,
+                        begin_ns,
+                        Some(discarded - state.events_discarded),
+                    ));
+                }
+                state.events_discarded = discarded;
+            }
+            messages.push(msg(MessageType::PacketBeginning, begin_ns, None));
+            state.packet = Some(packet);
+        }
+        state.packet_end_ns = end_ns;
+        messages
+    }
+
+    /// The end of the current packet and of the stream, for every stream seen
+    pub fn finish(&mut self) -> Vec<(TimelineId, InferredMessage)> {
+        let mut streams: Vec<_> = self.streams.drain().collect();
+        streams.sort_by_key(|((_, stream_id), _)| *stream_id);
+        let mut messages = Vec::new();
+        for ((timeline_id, stream_id), state) in streams.into_iter() {
+            let end = |message_type| InferredMessage {
+                message_type,
+                stream_id: Some(stream_id),
+                timestamp_ns: state.packet_end_ns,
+                discarded_events: None,
+            };
+            if state.packet.is_some() {
+                messages.push((timeline_id, end(MessageType::PacketEnd)));
+            }
+            messages.push((timeline_id, end(MessageType::StreamEnd)));
+        }
+        messages
+    }
+}
+
+/// Log the messages, or ingest them as events on the timeline, as configured
+pub async fn handle_messages(
+    cfg: &MessageHandlingConfig,
+    timeline_id: TimelineId,
+    messages: &[InferredMessage],
+    ordering: &mut u128,
+    drops: &mut DropCounts,
+    client: &mut Client,
+) -> Result<(), Error> {
+    for msg in messages.iter() {
+        match cfg.action(msg.message_type) {
+            MessageAction::Ignore => (),
+            MessageAction::Log => info!("{msg}"),
+            MessageAction::Event => {
+                let event = CtfEvent::message(msg, client).await?;
+                client.open_timeline(timeline_id).await?;
+                if !client.send_event(*ordering, event.attr_kvs()).await? {
+                    drops.count(DropReason::IngestError);
+                }
+                *ordering += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn message_event_names() {
+        assert_eq!(
+            MessageType::PacketBeginning.event_name(),
+            "modality.ctf.packet_beginning"
+        );
+        assert_eq!(
+            MessageType::MessageIteratorInactivity.event_name(),
+            "modality.ctf.message_iterator_inactivity"
+        );
+    }
+
+    #[test]
+    fn default_handling_only_ingests_events() {
+        let cfg = MessageHandlingConfig::default();
+        assert_eq!(cfg.action(MessageType::Event), MessageAction::Event);
+        assert_eq!(cfg.action(MessageType::PacketEnd), MessageAction::Ignore);
+        assert!(!cfg.tracks_packet_boundaries());
+    }
+
+    #[test]
+    fn packet_boundaries() {
+        let tid = TimelineId::from(uuid::Uuid::from_u128(1));
+        let msg = |message_type, timestamp_ns, discarded_events| InferredMessage {
+            message_type,
+            stream_id: Some(3),
+            timestamp_ns,
+            discarded_events,
+        };
+        let packet = |seq_num, events_discarded| PacketContext {
+            seq_num: Some(seq_num),
+            events_discarded: Some(events_discarded),
+        };
+        let mut tracker = PacketBoundaryTracker::default();
+
+        assert_eq!(
+            tracker.observe_packet(tid, 3, packet(0, 0), (Some(10), Some(20))),
+            vec![
+                msg(MessageType::StreamBeginning, Some(10), None),
+                msg(MessageType::PacketBeginning, Some(10), None),
+            ]
+        );
+        // Same packet
+        assert!(tracker
+            .observe_packet(tid, 3, packet(0, 0), (Some(10), Some(20)))
+            .is_empty());
+        assert_eq!(
+            tracker.observe_packet(tid, 3, packet(1, 4), (Some(30), Some(40))),
+            vec![
+                msg(MessageType::PacketEnd, Some(20), None),
+                msg(MessageType::DiscardedEvents, Some(30), Some(4)),
+                msg(MessageType::PacketBeginning, Some(30), None),
+            ]
+        );
+        // Without packet context fields the packets can't be told apart
+        let mut unknown = PacketBoundaryTracker::default();
+        assert_eq!(
+            unknown.observe_packet(tid, 3, PacketContext::default(), (None, None)),
+            vec![msg(MessageType::StreamBeginning, None, None)]
+        );
+        assert_eq!(
+            unknown.finish(),
+            vec![(tid, msg(MessageType::StreamEnd, None, None))]
+        );
+
+        assert_eq!(
+            tracker.finish(),
+            vec![
+                (tid, msg(MessageType::PacketEnd, Some(40), None)),
+                (tid, msg(MessageType::StreamEnd, Some(40), None)),
+            ]
+        );
+        assert!(tracker.finish().is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+//! Reading the CTF metadata files of trace directories directly, for what's
+//! needed before (or without) handing the inputs to babeltrace
+
+use regex::Regex;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+const METADATA_PACKET_MAGIC: u32 = 0x75D1_1D57;
+const METADATA_PACKET_HEADER_LEN: usize = 37;
+
+/// The TSDL text of the trace directory's metadata file, if it has one
+pub fn read_metadata_text(input: &Path) -> io::Result<Option<String>> {
+    let path = input.join("metadata");
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(metadata_text(&fs::read(&path)?)))
+}
+
+/// The TSDL text of a plain text or packetized metadata file
+pub fn metadata_text(data: &[u8]) -> String {
+    let little_endian = match packet_endianness(data) {
+        Some(le) => le,
+        None => return String::from_utf8_lossy(data).into_owned(),
+    };
+
+    let mut text = Vec::new();
+    let mut rest = data;
+    while rest.len() >= METADATA_PACKET_HEADER_LEN && packet_endianness(rest).is_some() {
+        let u32_at = |i: usize| {
+            let b = [rest[i], rest[i + 1], rest[i + 2], rest[i + 3]];
+            if little_endian {
+                u32::from_le_bytes(b)
+            } else {
+                u32::from_be_bytes(b)
+            }
+        };
+        // Sizes in bits, after the magic, UUID, and checksum
+        let content_size = u32_at(24) as usize / 8;
+        let packet_size = u32_at(28) as usize / 8;
+        if content_size < METADATA_PACKET_HEADER_LEN || packet_size < content_size {
+            break;
+        }
+        let end = content_size.min(rest.len());
+        text.extend_from_slice(&rest[METADATA_PACKET_HEADER_LEN..end]);
+        rest = &rest[packet_size.min(rest.len())..];
+    }
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+/// Whether the data starts with a little-endian (true) or
+/// big-endian (false) metadata packet magic
+fn packet_endianness(data: &[u8]) -> Option<bool> {
+    let magic = [*data.first()?, *data.get(1)?, *data.get(2)?, *data.get(3)?];
+    if u32::from_le_bytes(magic) == METADATA_PACKET_MAGIC {
+        Some(true)
+    } else if u32::from_be_bytes(magic) == METADATA_PACKET_MAGIC {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Group the trace directories by the trace UUID of their metadata,
+/// in the order the UUIDs first appear
+pub fn group_inputs_by_trace_uuid(
+    inputs: &[PathBuf],
+) -> io::Result<Vec<(Option<Uuid>, Vec<PathBuf>)>> {
+    let mut groups: Vec<(Option<Uuid>, Vec<PathBuf>)> = Vec::new();
+    for input in inputs.iter() {
+        let uuid = read_metadata_text(input)?.as_deref().and_then(trace_uuid);
+        match groups.iter_mut().find(|(u, _)| *u == uuid) {
+            Some((_, paths)) => paths.push(input.clone()),
+            None => groups.push((uuid, vec![input.clone()])),
+        }
+    }
+    Ok(groups)
+}
+
+/// The `uuid` of the TSDL `trace` block
+pub fn trace_uuid(text: &str) -> Option<Uuid> {
+    // Regex is an infallible constant
+    let uuid = Regex::new(r#"\buuid\s*=\s*"([0-9a-fA-F-]+)"\s*;"#).unwrap();
+    uuid.captures(trace_block(text)?)?[1].parse().ok()
+}
+
+/// The body of the TSDL `trace` block
+fn trace_block(text: &str) -> Option<&str> {
+    // Regex is an infallible constant
+    let trace_start = Regex::new(r"\btrace\s*\{").unwrap();
+    let start = trace_start.find(text)?.end();
+
+    // The block nests the packet header struct
+    let mut depth = 1;
+    let end = text[start..].char_indices().find_map(|(i, c)| {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => (),
+        }
+        (depth == 0).then_some(start + i)
+    })?;
+    Some(&text[start..end])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const METADATA: &str = r#"/* CTF 1.8 */
+trace {
+    major = 1;
+    minor = 8;
+    byte_order = le;
+    packet.header := struct {
+        uint32_t magic;
+        uint8_t  uuid[16];
+    };
+    uuid = "5b6ba59e-8c33-4ba3-ba4a-24a2b5c4c3c7";
+};
+env {
+    tracer_name = "lttng-modules";
+};
+"#;
+
+    #[test]
+    fn packetized_metadata() {
+        let mut data = Vec::new();
+        for chunk in [&METADATA[..40], &METADATA[40..]] {
+            let content_size = METADATA_PACKET_HEADER_LEN + chunk.len();
+            let packet_size = content_size + 3;
+            data.extend_from_slice(&METADATA_PACKET_MAGIC.to_le_bytes());
+            data.extend_from_slice(&[0; 16]); // UUID
+            data.extend_from_slice(&0_u32.to_le_bytes()); // Checksum
+            data.extend_from_slice(&((content_size * 8) as u32).to_le_bytes());
+            data.extend_from_slice(&((packet_size * 8) as u32).to_le_bytes());
+            data.extend_from_slice(&[0, 0, 0, 1, 8]);
+            data.extend_from_slice(chunk.as_bytes());
+            data.extend_from_slice(&[0; 3]); // Padding
+        }
+        assert_eq!(metadata_text(&data), METADATA);
+        assert_eq!(metadata_text(METADATA.as_bytes()), METADATA);
+    }
+
+    #[test]
+    fn metadata_trace_uuid() {
+        assert_eq!(
+            trace_uuid(METADATA),
+            Some("5b6ba59e-8c33-4ba3-ba4a-24a2b5c4c3c7".parse().unwrap())
+        );
+        assert_eq!(trace_uuid("trace { major = 1; };"), None);
+    }
+}
@@ -1,6 +1,10 @@
-use crate::types::LoggingLevel;
+use crate::types::{
+    EventLogLevel, IngestErrorPolicy, LogBackend, LoggingLevel, MergeStreamNamespace, Profile,
+    UnknownKeyPolicy,
+};
 use clap::Parser;
 use std::path::PathBuf;
+use tracing::level_filters::LevelFilter;
 use url::Url;
 use uuid::Uuid;
 
@@ -49,6 +53,41 @@ pub struct ReflectorOpts {
     /// Use the provided UUID as the run ID instead of generating a random one
     #[clap(long, name = "run-uuid", help_heading = "REFLECTOR CONFIGURATION")]
     pub run_id: Option<Uuid>,
+
+    /// Increase the plugin's log verbosity (-v info, -vv debug, -vvv trace),
+    /// takes precedence over RUST_LOG. Doesn't affect the babeltrace log-level.
+    #[clap(
+        short = 'v',
+        long = "verbose",
+        action = clap::ArgAction::Count,
+        conflicts_with = "quiet",
+        help_heading = "REFLECTOR CONFIGURATION"
+    )]
+    pub verbose: u8,
+
+    /// Decrease the plugin's log verbosity (-q errors only, -qq nothing),
+    /// takes precedence over RUST_LOG
+    #[clap(
+        short = 'q',
+        long = "quiet",
+        action = clap::ArgAction::Count,
+        help_heading = "REFLECTOR CONFIGURATION"
+    )]
+    pub quiet: u8,
+}
+
+impl ReflectorOpts {
+    /// The plugin log level of the verbosity flags, `None` when none were given
+    pub fn log_level_filter(&self) -> Option<LevelFilter> {
+        Some(match (self.verbose, self.quiet) {
+            (0, 0) => return None,
+            (0, 1) => LevelFilter::ERROR,
+            (0, _) => LevelFilter::OFF,
+            (1, _) => LevelFilter::INFO,
+            (2, _) => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        })
+    }
 }
 
 #[derive(Parser, Debug, Clone, Default)]
@@ -60,10 +99,31 @@ pub struct BabeltraceOpts {
     #[clap(long, name = "trace-uuid", help_heading = "BABELTRACE CONFIGURATION")]
     pub trace_uuid: Option<Uuid>,
 
+    /// Name of the trace environment entry the trace UUID is derived from, when
+    /// trace-uuid isn't provided
+    #[clap(
+        long,
+        name = "trace-uuid-from-env",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub trace_uuid_from_env: Option<String>,
+
     /// Logging level for libbabeltrace
     #[clap(long, name = "log-level", help_heading = "BABELTRACE CONFIGURATION")]
     pub log_level: Option<LoggingLevel>,
 
+    /// Semantic mapping profile (none, auto, linux-kernel, lttng-ust, zephyr, barectf-generic)
+    #[clap(long, name = "profile", help_heading = "BABELTRACE CONFIGURATION")]
+    pub profile: Option<Profile>,
+
+    /// Only ingest events whose event class log level is at least as severe as this level
+    #[clap(
+        long,
+        name = "min-log-level",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub min_log_level: Option<EventLogLevel>,
+
     /// Merge all streams into the stream with the given ID, producing a single timeline.
     #[clap(
         long,
@@ -71,4 +131,236 @@ pub struct BabeltraceOpts {
         help_heading = "BABELTRACE CONFIGURATION"
     )]
     pub merge_stream_id: Option<u64>,
+
+    /// Namespace the payload attrs of the merged streams by their originating stream
+    /// (none, stream-id, stream-name) [default: none]
+    #[clap(
+        long,
+        name = "merge-stream-namespace",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub merge_stream_namespace: Option<MergeStreamNamespace>,
+
+    /// Place the payload-derived event attrs under `event.<prefix>.<field>`
+    /// instead of `event.<field>`
+    #[clap(
+        long,
+        name = "event-attr-prefix",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub event_attr_prefix: Option<String>,
+
+    /// A CSV or TOML file of timeline attribute key renames
+    #[clap(
+        long,
+        name = "rename-timeline-attrs-file",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub rename_timeline_attrs_file: Option<PathBuf>,
+
+    /// A CSV or TOML file of event attribute key renames
+    #[clap(
+        long,
+        name = "rename-event-attrs-file",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub rename_event_attrs_file: Option<PathBuf>,
+
+    /// A `/proc/kallsyms` dump or `System.map` file used to resolve the
+    /// symbolize-fields kernel addresses
+    #[clap(long, name = "kallsyms", help_heading = "BABELTRACE CONFIGURATION")]
+    pub kallsyms: Option<PathBuf>,
+
+    /// Emit a `modality.ingest.begin` event at the start of each timeline
+    /// carrying the run ID and plugin metadata
+    #[clap(long, help_heading = "BABELTRACE CONFIGURATION")]
+    pub emit_ingest_begin_event: bool,
+
+    /// Ingest the plugin's own operational events on a dedicated
+    /// `<plugin>.diagnostics` timeline
+    #[clap(long, help_heading = "BABELTRACE CONFIGURATION")]
+    pub diagnostics_timeline: bool,
+
+    /// Exit with a distinct non-zero code when no events were ingested
+    #[clap(long, help_heading = "BABELTRACE CONFIGURATION")]
+    pub fail_on_empty: bool,
+
+    /// Warn once more than this many distinct event attr keys were declared, not checked by default
+    #[clap(
+        long,
+        name = "event-attr-key-budget",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub event_attr_key_budget: Option<usize>,
+
+    /// Fail instead of warning when the event-attr-key-budget is exceeded
+    #[clap(long, help_heading = "BABELTRACE CONFIGURATION")]
+    pub strict_attr_key_budget: bool,
+
+    /// Tag the timelines and events with the URI of the trace directory
+    /// or lttng-live session they come from
+    #[clap(long, help_heading = "BABELTRACE CONFIGURATION")]
+    pub source_uri_attrs: bool,
+
+    /// Parse the per-CPU stream names (`<channel>_<cpu>`) into a `timeline.cpu`
+    /// timeline attr
+    #[clap(long, help_heading = "BABELTRACE CONFIGURATION")]
+    pub per_cpu_streams: bool,
+
+    /// Tag the events with the precision of their stream clock, in nanoseconds
+    #[clap(long, help_heading = "BABELTRACE CONFIGURATION")]
+    pub timestamp_precision_attrs: bool,
+
+    /// Stop ingesting the events of a timeline once it has this many
+    #[clap(long, name = "max-events", help_heading = "BABELTRACE CONFIGURATION")]
+    pub max_events_per_timeline: Option<u64>,
+
+    /// What to do when an event fails to be ingested (fail, skip, retry) [default: fail]
+    #[clap(
+        long,
+        name = "ingest-error-policy",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub on_ingest_error: Option<IngestErrorPolicy>,
+
+    /// How many times to retry sending an event with the retry ingest error policy [default: 3]
+    #[clap(long, name = "retries", help_heading = "BABELTRACE CONFIGURATION")]
+    pub ingest_error_retries: Option<u32>,
+
+    /// Keep 1 in N events of each event class
+    #[clap(long, name = "N", help_heading = "BABELTRACE CONFIGURATION")]
+    pub sample_rate: Option<u64>,
+
+    /// UTC time, in nanoseconds since the Unix epoch, of the origin of relative clocks.
+    /// Event timestamps on those clocks are rebased onto it
+    #[clap(
+        long,
+        name = "wall-clock-origin-ns",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub wall_clock_origin_ns: Option<u64>,
+
+    /// Name of the trace environment entry holding the wall-clock origin of relative
+    /// clocks, in nanoseconds since the Unix epoch
+    #[clap(
+        long,
+        name = "wall-clock-origin-env",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub wall_clock_origin_env: Option<String>,
+
+    /// Nanoseconds added to every event timestamp, so clocks anchored before the
+    /// Unix epoch still produce timestamps
+    #[clap(
+        long,
+        name = "timestamp-shift-ns",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub timestamp_shift_ns: Option<u64>,
+
+    /// Name of the trace environment entry holding a clock correction in nanoseconds
+    /// (e.g. a PTP offset), added to the event timestamps
+    #[clap(
+        long,
+        name = "clock-correction-env",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub clock_correction_env: Option<String>,
+
+    /// Name of the event class carrying clock sync information, its correction applies
+    /// to the subsequent events of the stream
+    #[clap(
+        long,
+        name = "clock-correction-event",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub clock_correction_event: Option<String>,
+
+    /// Payload field of the clock sync event holding the correction in nanoseconds
+    /// [default: offset_ns]
+    #[clap(
+        long,
+        name = "clock-correction-field",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub clock_correction_field: Option<String>,
+
+    /// Name of the event class whose payload fields are applied as timeline attrs
+    /// of the emitting stream, disabled unless set
+    #[clap(
+        long,
+        name = "timeline-attrs-event",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub timeline_attrs_event: Option<String>,
+
+    /// On interrupt, keep ingesting the already decoded events and wait for the
+    /// backend to acknowledge them for up to this many milliseconds [default: 5000]
+    #[clap(
+        long,
+        name = "drain-timeout-ms",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub drain_timeout_ms: Option<u64>,
+
+    /// Ingest endpoint to fail over to when the protocol parent URL can't be
+    /// reached or its connection fails, can be given multiple times
+    #[clap(
+        long = "ingest-failover-url",
+        name = "failover URL",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub ingest_failover_urls: Vec<Url>,
+
+    /// File to read the auth token hex string from, instead of the given auth token,
+    /// re-read to re-authenticate when the connection fails
+    #[clap(
+        long,
+        name = "auth-token-file",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub auth_token_file: Option<PathBuf>,
+
+    /// Command whose stdout is the auth token hex string, run with `sh -c`
+    /// instead of using the given auth token, and again to re-authenticate
+    #[clap(
+        long,
+        name = "auth-token-command",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub auth_token_command: Option<String>,
+
+    /// Write the plugin logs to this file instead of stderr, rotating it
+    /// once it reaches log-file-max-size bytes
+    #[clap(long, name = "log-file", help_heading = "BABELTRACE CONFIGURATION")]
+    pub log_file: Option<PathBuf>,
+
+    /// Size in bytes at which the log file is rotated [default: 10485760]
+    #[clap(
+        long,
+        name = "log-file-max-size",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub log_file_max_size: Option<u64>,
+
+    /// Number of rotated log files kept alongside the current one [default: 5]
+    #[clap(
+        long,
+        name = "log-file-max-files",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub log_file_max_files: Option<usize>,
+
+    /// Where the plugin logs go (stderr, syslog, journald) [default: stderr]
+    #[clap(long, name = "log-backend", help_heading = "BABELTRACE CONFIGURATION")]
+    pub log_backend: Option<LogBackend>,
+
+    /// What to do with the unknown keys of the plugin configuration
+    /// (warn, fail, ignore) [default: warn]
+    #[clap(
+        long,
+        name = "on-unknown-key",
+        help_heading = "BABELTRACE CONFIGURATION"
+    )]
+    pub on_unknown_key: Option<UnknownKeyPolicy>,
 }
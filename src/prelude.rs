@@ -1,7 +1,8 @@
 pub use crate::attrs::{EventAttrKey, EventAttrKeyExt, TimelineAttrKey, TimelineAttrKeyExt};
 pub use crate::client::Client;
-pub use crate::config::{CtfConfig, ImportConfig, LttngLiveConfig, PluginConfig};
+pub use crate::config::{ClockClassConfig, CtfConfig, ImportConfig, LttngLiveConfig, PluginConfig};
 pub use crate::event::CtfEvent;
+pub use crate::mapper::{register_field_mapper, FieldMapper, FieldMapperContext, FieldMappers};
 pub use crate::opts::{BabeltraceOpts, ReflectorOpts};
 pub use crate::properties::{CtfProperties, CtfStreamProperties, CtfTraceProperties};
 pub use crate::types::Interruptor;
@@ -0,0 +1,107 @@
+//! The timelines an import would create, for the `--list-timelines` mode:
+//! their names and IDs as derived from the trace metadata and the
+//! configuration, without connecting to the ingest endpoint.
+
+use crate::config::PluginConfig;
+use crate::error::Error;
+use crate::properties::{
+    derived_stream_name, derived_timeline_id, stream_timeline_overrides, trace_uuid_override,
+    TimelineNameRules,
+};
+use babeltrace2_sys::{StreamProperties, TraceProperties};
+use modality_api::TimelineId;
+use std::collections::BTreeSet;
+use std::fmt;
+use uuid::Uuid;
+
+#[derive(Clone, Debug)]
+pub struct TimelinesPreview {
+    /// The trace UUID the timeline IDs are derived from, `None` when
+    /// a random one is generated for each import
+    trace_uuid: Option<Uuid>,
+    trace_uuid_source: &'static str,
+    timelines: Vec<TimelinePreview>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TimelinePreview {
+    /// `None` when derived from a random trace UUID
+    pub timeline_id: Option<TimelineId>,
+    pub timeline_name: String,
+    pub stream: StreamProperties,
+}
+
+impl TimelinesPreview {
+    pub fn new(
+        cfg: &PluginConfig,
+        t: &TraceProperties,
+        s: &BTreeSet<StreamProperties>,
+    ) -> Result<Self, Error> {
+        let (trace_uuid, trace_uuid_source) = match trace_uuid_override(cfg, t) {
+            Some(uuid) if cfg.trace_uuid.is_some() => (Some(uuid), "trace-uuid"),
+            Some(uuid) => (Some(uuid), "trace-uuid-from-env"),
+            None if t.uuid.is_some() => (t.uuid, "trace metadata"),
+            None => (None, "random for each import"),
+        };
+        let name_rules = TimelineNameRules::new(cfg, s);
+        let timelines = s
+            .iter()
+            // Merged streams all end up on the merge stream's timeline
+            .filter(|stream| {
+                cfg.merge_stream_id
+                    .map(|id| id == stream.id)
+                    .unwrap_or(true)
+            })
+            .map(|stream| {
+                let (timeline_id, timeline_name) =
+                    stream_timeline_overrides(cfg, &name_rules, stream);
+                TimelinePreview {
+                    timeline_id: timeline_id
+                        .or_else(|| trace_uuid.map(|uuid| derived_timeline_id(&uuid, stream))),
+                    timeline_name: timeline_name.unwrap_or_else(|| derived_stream_name(stream)),
+                    stream: stream.clone(),
+                }
+            })
+            .collect();
+        Ok(Self {
+            trace_uuid,
+            trace_uuid_source,
+            timelines,
+        })
+    }
+}
+
+impl fmt::Display for TimelinesPreview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.trace_uuid {
+            Some(uuid) => writeln!(f, "Trace UUID: {uuid} ({})", self.trace_uuid_source)?,
+            None => writeln!(f, "Trace UUID: <none> ({})", self.trace_uuid_source)?,
+        }
+        writeln!(f, "Timelines")?;
+        for t in self.timelines.iter() {
+            writeln!(f, "  {}", t.timeline_name)?;
+            match t.timeline_id {
+                Some(id) => writeln!(f, "    id: {id}")?,
+                None => writeln!(f, "    id: <random>")?,
+            }
+            writeln!(
+                f,
+                "    stream: {} ({})",
+                t.stream.id,
+                derived_stream_name(&t.stream)
+            )?;
+            if let Some(c) = &t.stream.clock {
+                write!(
+                    f,
+                    "    clock: frequency={} offset-seconds={} offset-cycles={} unix-epoch-origin={}",
+                    c.frequency, c.offset_seconds, c.offset_cycles, c.unix_epoch_origin
+                )?;
+                if let Some(name) = &c.name {
+                    write!(f, " name={name}")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
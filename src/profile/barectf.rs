@@ -0,0 +1,12 @@
+//! Generic barectf traces, which follow no field naming convention
+
+use super::{ProfileRules, TimelineStrategy};
+
+pub(super) fn rules() -> ProfileRules {
+    // Name the timelines after the data stream type, dropping
+    // the numeric suffix platforms add to the stream file names
+    ProfileRules::default().timeline_strategy(TimelineStrategy::stream_name_pattern(
+        r"^([A-Za-z_]\w*?)_\d+$",
+        "$1",
+    ))
+}
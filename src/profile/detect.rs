@@ -0,0 +1,153 @@
+//! Pick a profile for `profile = "auto"` from the tracer identity and domain
+//! of the trace environment, and the event class names
+
+use crate::properties::trace::env_value;
+use crate::properties::{TracerIdentity, TracerKind};
+use crate::types::Profile;
+use babeltrace2_sys::{EnvValue, TraceProperties};
+use std::collections::BTreeSet;
+use tracing::info;
+
+const ZEPHYR_EVENTS: &[&str] = &[
+    "thread_switched_in",
+    "thread_switched_out",
+    "thread_create",
+    "isr_enter",
+    "isr_exit",
+    "idle",
+];
+
+/// The detected profile, and why it was picked
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProfileDetection {
+    pub profile: Profile,
+    pub reason: String,
+}
+
+impl ProfileDetection {
+    pub fn log(&self) {
+        info!(profile = %self.profile, "Selected the '{}' profile, {}", self.profile, self.reason);
+    }
+}
+
+/// Detect the profile from the tracer identity, the `domain` trace environment
+/// entry and the event class names
+pub fn detect(
+    tracer: Option<&TracerIdentity>,
+    domain: Option<&str>,
+    event_names: &BTreeSet<String>,
+) -> ProfileDetection {
+    let found = |profile, reason: String| ProfileDetection { profile, reason };
+
+    if let Some(tracer) = tracer {
+        let reason = format!("the tracer_name is '{}'", tracer.name);
+        match tracer.kind() {
+            TracerKind::LttngModules => return found(Profile::LinuxKernel, reason),
+            TracerKind::LttngUst => return found(Profile::LttngUst, reason),
+            TracerKind::Barectf => return found(Profile::BarectfGeneric, reason),
+            TracerKind::Other => (),
+        }
+    }
+    match domain {
+        Some("kernel") => return found(Profile::LinuxKernel, "the domain is 'kernel'".to_owned()),
+        Some("ust") => return found(Profile::LttngUst, "the domain is 'ust'".to_owned()),
+        _ => (),
+    }
+
+    let zephyr_events = ZEPHYR_EVENTS
+        .iter()
+        .filter(|n| event_names.contains(**n))
+        .count();
+    if zephyr_events >= 2 {
+        return found(
+            Profile::Zephyr,
+            format!("{zephyr_events} event classes match the Zephyr tracing events"),
+        );
+    }
+    if event_names.contains("sched_switch") {
+        return found(
+            Profile::LinuxKernel,
+            "the trace has the kernel 'sched_switch' event class".to_owned(),
+        );
+    }
+    if event_names.iter().any(|n| n.starts_with("lttng_ust_")) {
+        return found(
+            Profile::LttngUst,
+            "the trace has 'lttng_ust_' event classes".to_owned(),
+        );
+    }
+    found(
+        Profile::None,
+        "no tracer convention was recognized".to_owned(),
+    )
+}
+
+/// Detect the profile from the babeltrace trace properties and the event
+/// class names seen so far
+pub fn detect_from_trace_properties(
+    t: &TraceProperties,
+    event_names: &BTreeSet<String>,
+) -> ProfileDetection {
+    let tracer = TracerIdentity::from_trace_properties(t);
+    let domain = match env_value(t, "domain") {
+        Some(EnvValue::String(s)) => Some(s.trim()),
+        _ => None,
+    };
+    detect(tracer.as_ref(), domain, event_names)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn tracer(name: &str) -> TracerIdentity {
+        TracerIdentity {
+            name: name.to_owned(),
+            major: Some(2),
+            minor: None,
+            patch: None,
+        }
+    }
+
+    fn names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn detect_profiles() {
+        let none = BTreeSet::new();
+        assert_eq!(
+            detect(Some(&tracer("lttng-modules")), Some("ust"), &none),
+            ProfileDetection {
+                profile: Profile::LinuxKernel,
+                reason: "the tracer_name is 'lttng-modules'".to_owned(),
+            }
+        );
+        assert_eq!(
+            detect(Some(&tracer("lttng-ust")), None, &none).profile,
+            Profile::LttngUst
+        );
+        assert_eq!(
+            detect(Some(&tracer("barectf")), None, &none).profile,
+            Profile::BarectfGeneric
+        );
+        assert_eq!(
+            detect(Some(&tracer("custom")), Some("kernel"), &none).profile,
+            Profile::LinuxKernel
+        );
+        assert_eq!(detect(None, Some("ust"), &none).profile, Profile::LttngUst);
+
+        let zephyr = names(&["thread_switched_in", "thread_switched_out", "isr_enter"]);
+        assert_eq!(detect(None, None, &zephyr).profile, Profile::Zephyr);
+        assert_eq!(
+            detect(None, None, &names(&["sched_switch", "isr_enter"])).profile,
+            Profile::LinuxKernel
+        );
+        assert_eq!(
+            detect(None, None, &names(&["lttng_ust_statedump:start"])).profile,
+            Profile::LttngUst
+        );
+        assert_eq!(detect(None, None, &none).profile, Profile::None);
+    }
+}
@@ -0,0 +1,20 @@
+//! LTTng kernel traces (lttng-modules)
+
+use super::{lttng, ProfileRules};
+
+pub(super) fn rules() -> ProfileRules {
+    lttng::context_rules("pid", "tid")
+        .rename_event_attr(
+            "event.internal.ctf.common_context.prio",
+            "event.thread.priority",
+        )
+        .and(lttng::per_cpu_timeline_rules("kernel"))
+        // A wakeup interacts with the switch to the woken thread, on its next CPU
+        .pairing_rule("sched_wakeup", "tid", "sched_switch", "next_tid")
+        // Kernel code and allocation addresses
+        .hex_field("ip")
+        .hex_field("call_site")
+        .hex_field("ptr")
+        .symbolize_field("ip")
+        .symbolize_field("call_site")
+}
@@ -0,0 +1,22 @@
+//! Conventions shared by the LTTng kernel and userspace tracers
+
+use super::{ProfileRules, TimelineStrategy};
+
+const COMMON_CONTEXT: &str = "event.internal.ctf.common_context";
+
+/// Promote the `add-context` process and thread contexts to top-level event attrs
+pub(super) fn context_rules(pid: &str, tid: &str) -> ProfileRules {
+    ProfileRules::default()
+        .rename_event_attr(&format!("{COMMON_CONTEXT}.{pid}"), "event.process.pid")
+        .rename_event_attr(&format!("{COMMON_CONTEXT}.{tid}"), "event.thread.id")
+        .rename_event_attr(&format!("{COMMON_CONTEXT}.procname"), "event.process.name")
+}
+
+/// Name the per-CPU channel streams (`<channel>_<cpu>`) after their CPU,
+/// e.g. `kernel/cpu3`, or `kernel/channel1/cpu3` with several channels,
+/// and tag them with it
+pub(super) fn per_cpu_timeline_rules(timeline_prefix: &str) -> ProfileRules {
+    ProfileRules::default().timeline_strategy(TimelineStrategy::PerCpu {
+        timeline_prefix: timeline_prefix.to_owned(),
+    })
+}
@@ -0,0 +1,14 @@
+//! LTTng userspace traces (lttng-ust)
+
+use super::{lttng, ProfileRules};
+
+pub(super) fn rules() -> ProfileRules {
+    lttng::context_rules("vpid", "vtid")
+        .and(lttng::per_cpu_timeline_rules("ust"))
+        // The `ip` context and the lttng_ust_cyg_profile instrumentation
+        .hex_field("addr")
+        .hex_field("call_site")
+        .symbolize_field("addr")
+        .symbolize_field("call_site")
+        .rename_event_attr("event.internal.ctf.common_context.ip", "event.ip")
+}
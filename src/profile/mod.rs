@@ -0,0 +1,223 @@
+//! Semantic mapping profiles, bundles of the mapping rules that suit the
+//! traces of a particular tracer.
+//!
+//! Each profile is a module providing its [`ProfileRules`], built by composing
+//! the rules of the conventions it follows. The rules are merged under the user's
+//! configuration: user renames, timeline name patterns and pairing rules take
+//! precedence, and the field sets are extended.
+
+use crate::config::{AttrKeyRename, PairingRule, PluginConfig, TimelineNamePattern};
+use crate::types::{FieldType, Profile};
+use std::collections::{BTreeMap, BTreeSet};
+
+mod barectf;
+pub mod detect;
+mod linux_kernel;
+mod lttng;
+mod lttng_ust;
+mod zephyr;
+
+/// The mapping rules of a profile
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProfileRules {
+    /// Event attr renames, e.g. promoting context fields to top-level event attrs
+    pub rename_event_attrs: Vec<AttrKeyRename>,
+
+    /// How the streams map to timelines
+    pub timeline_strategy: TimelineStrategy,
+
+    /// The interactions between the tracer's event classes
+    pub pairing_rules: Vec<PairingRule>,
+
+    /// Payload fields, without the `event.` prefix, to render as hex strings
+    pub hex_fields: BTreeSet<String>,
+
+    /// Payload fields, without the `event.` prefix, holding code addresses
+    pub symbolize_fields: BTreeSet<String>,
+
+    /// Payload field type coercions, keyed without the `event.` prefix
+    pub field_types: BTreeMap<String, FieldType>,
+}
+
+/// How a profile maps the trace's streams to timelines
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TimelineStrategy {
+    /// A timeline per stream, named after the stream
+    #[default]
+    PerStream,
+
+    /// A timeline per per-CPU stream (`<channel>_<cpu>`), named after its CPU
+    /// under the prefix and tagged with it, see
+    /// [`crate::properties::per_cpu_timeline_name`]
+    PerCpu { timeline_prefix: String },
+
+    /// A timeline per stream, named by the first matching stream name pattern
+    StreamNamePatterns(Vec<TimelineNamePattern>),
+}
+
+impl TimelineStrategy {
+    fn stream_name_pattern(stream_name: &str, timeline_name: &str) -> Self {
+        TimelineStrategy::StreamNamePatterns(vec![TimelineNamePattern {
+            // The profiles' patterns are constants
+            stream_name: stream_name.parse().unwrap(),
+            timeline_name: timeline_name.to_owned(),
+        }])
+    }
+}
+
+impl ProfileRules {
+    /// The rules of the given profile
+    pub fn for_profile(profile: Profile) -> Self {
+        match profile {
+            // Resolved once the trace is known, see the detect module
+            Profile::None | Profile::Auto => Self::default(),
+            Profile::LinuxKernel => linux_kernel::rules(),
+            Profile::LttngUst => lttng_ust::rules(),
+            Profile::Zephyr => zephyr::rules(),
+            Profile::BarectfGeneric => barectf::rules(),
+        }
+    }
+
+    /// Combine with another set of rules, the rules of `self` come first
+    pub fn and(mut self, other: ProfileRules) -> Self {
+        self.rename_event_attrs.extend(other.rename_event_attrs);
+        if self.timeline_strategy == TimelineStrategy::PerStream {
+            self.timeline_strategy = other.timeline_strategy;
+        }
+        self.pairing_rules.extend(other.pairing_rules);
+        self.hex_fields.extend(other.hex_fields);
+        self.symbolize_fields.extend(other.symbolize_fields);
+        for (k, v) in other.field_types.into_iter() {
+            self.field_types.entry(k).or_insert(v);
+        }
+        self
+    }
+
+    fn rename_event_attr(mut self, original: &str, new: &str) -> Self {
+        self.rename_event_attrs.push(AttrKeyRename {
+            original: original.to_owned(),
+            new: new.to_owned(),
+        });
+        self
+    }
+
+    fn timeline_strategy(mut self, timeline_strategy: TimelineStrategy) -> Self {
+        self.timeline_strategy = timeline_strategy;
+        self
+    }
+
+    fn pairing_rule(
+        mut self,
+        source_event: &str,
+        source_field: &str,
+        destination_event: &str,
+        destination_field: &str,
+    ) -> Self {
+        self.pairing_rules.push(PairingRule {
+            source_event: source_event.to_owned(),
+            source_field: source_field.to_owned(),
+            destination_event: destination_event.to_owned(),
+            destination_field: destination_field.to_owned(),
+        });
+        self
+    }
+
+    fn hex_field(mut self, field: &str) -> Self {
+        self.hex_fields.insert(field.to_owned());
+        self
+    }
+
+    fn symbolize_field(mut self, field: &str) -> Self {
+        self.symbolize_fields.insert(field.to_owned());
+        self
+    }
+
+    /// Merge the rules under the configuration
+    pub fn merge_into(self, cfg: &mut PluginConfig) {
+        let prefixed = |k: String| match cfg.event_mapping.event_attr_prefix.as_deref() {
+            Some(p) => format!("{}.{k}", p.strip_prefix("event.").unwrap_or(p)),
+            None => k,
+        };
+        let hex_fields: Vec<String> = self.hex_fields.into_iter().map(prefixed).collect();
+        let symbolize_fields: Vec<String> =
+            self.symbolize_fields.into_iter().map(prefixed).collect();
+        let field_types: Vec<(String, FieldType)> = self
+            .field_types
+            .into_iter()
+            .map(|(k, v)| (prefixed(k), v))
+            .collect();
+
+        // Later renames win, so the profile's go first
+        let mut renames = self.rename_event_attrs;
+        renames.append(&mut cfg.rename_event_attrs);
+        cfg.rename_event_attrs = renames;
+        match self.timeline_strategy {
+            TimelineStrategy::PerStream => (),
+            TimelineStrategy::PerCpu { timeline_prefix } => {
+                cfg.per_cpu_streams = true;
+                cfg.event_mapping.cpu_attrs = true;
+                if cfg.per_cpu_timeline_prefix.is_none() {
+                    cfg.per_cpu_timeline_prefix = Some(timeline_prefix);
+                }
+            }
+            TimelineStrategy::StreamNamePatterns(patterns) => {
+                cfg.timeline_name_patterns.extend(patterns)
+            }
+        }
+        // A destination event pairs with the first matching rule, the user's go first
+        cfg.pairing_rules.extend(self.pairing_rules);
+        cfg.event_mapping.hex_fields.extend(hex_fields);
+        cfg.event_mapping.symbolize_fields.extend(symbolize_fields);
+        for (k, v) in field_types.into_iter() {
+            cfg.event_mapping.field_types.entry(k).or_insert(v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn merge_under_user_config() {
+        let mut cfg = PluginConfig {
+            rename_event_attrs: vec![AttrKeyRename {
+                original: "event.internal.ctf.common_context.procname".to_owned(),
+                new: "event.comm".to_owned(),
+            }],
+            ..Default::default()
+        };
+        cfg.event_mapping.event_attr_prefix = Some("ctf".to_owned());
+        ProfileRules::for_profile(Profile::LinuxKernel).merge_into(&mut cfg);
+
+        // The user rename of the same key comes last and wins
+        assert_eq!(
+            cfg.rename_event_attrs.last().unwrap().new,
+            "event.comm".to_owned()
+        );
+        assert!(cfg.event_mapping.symbolize_fields.contains("ctf.ip"));
+        assert!(cfg.timeline_name_patterns.is_empty());
+        assert_eq!(cfg.per_cpu_timeline_prefix.as_deref(), Some("kernel"));
+        assert!(cfg.per_cpu_streams);
+        assert!(cfg.event_mapping.cpu_attrs);
+        assert_eq!(cfg.pairing_rules.len(), 1);
+        assert_eq!(cfg.pairing_rules[0].destination_event, "sched_switch");
+
+        let mut cfg = PluginConfig::default();
+        ProfileRules::for_profile(Profile::Zephyr).merge_into(&mut cfg);
+        assert_eq!(
+            cfg.timeline_name_patterns,
+            vec![TimelineNamePattern {
+                stream_name: r"^channel0_0$".parse().unwrap(),
+                timeline_name: "zephyr".to_owned(),
+            }]
+        );
+        assert!(!cfg.per_cpu_streams);
+        assert!(!cfg.event_mapping.cpu_attrs);
+
+        let mut cfg = PluginConfig::default();
+        ProfileRules::for_profile(Profile::None).merge_into(&mut cfg);
+        assert_eq!(cfg, PluginConfig::default());
+    }
+}
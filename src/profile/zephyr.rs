@@ -0,0 +1,14 @@
+//! Zephyr RTOS CTF tracing (`CONFIG_TRACING_CTF`)
+
+use super::{ProfileRules, TimelineStrategy};
+
+pub(super) fn rules() -> ProfileRules {
+    ProfileRules::default()
+        // Zephyr writes a single stream
+        .timeline_strategy(TimelineStrategy::stream_name_pattern(
+            r"^channel0_0$",
+            "zephyr",
+        ))
+        // Thread IDs are the addresses of the thread structures
+        .hex_field("thread_id")
+}
@@ -1,47 +1,152 @@
 use crate::client::Client;
+use crate::config::PluginConfig;
 use crate::error::Error;
-use babeltrace2_sys::{StreamId, StreamProperties, TraceProperties};
+use babeltrace2_sys::{EnvValue, StreamId, StreamProperties, TraceProperties};
 use modality_api::{AttrVal, TimelineId};
 use modality_ingest_protocol::InternedAttrKey;
+use regex::Regex;
 use std::collections::{BTreeMap, BTreeSet};
+use tracing::warn;
 use uuid::Uuid;
 
-pub use stream::CtfStreamProperties;
-pub use trace::CtfTraceProperties;
+pub(crate) use stream::{derived_stream_name, derived_stream_timeline_id, derived_timeline_id};
+pub use stream::{
+    fs_source_uri, per_cpu_stream, per_cpu_timeline_name, stream_file_path, CtfStreamProperties,
+};
+pub use trace::{fs_trace_name, CtfTraceProperties, TracerIdentity, TracerKind};
 
 pub(crate) mod stream;
 pub(crate) mod trace;
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct CtfProperties {
+    /// The resolved trace UUID the timeline IDs are derived from
+    pub trace_uuid: Uuid,
+    /// The trace UUID of the CTF metadata, regardless of the configured overrides
+    pub metadata_trace_uuid: Option<Uuid>,
     pub trace: CtfTraceProperties,
     pub streams: BTreeMap<StreamId, CtfStreamProperties>,
 }
 
 impl CtfProperties {
     pub async fn new(
-        run_id: Option<Uuid>,
-        trace_uuid_override: Option<Uuid>,
+        cfg: &PluginConfig,
         t: &TraceProperties,
         s: &BTreeSet<StreamProperties>,
         client: &mut Client,
     ) -> Result<Self, Error> {
         // TimelineIds are a composite of the trace UUID and the stream ID
-        // Use the override if present, otherwise use the trace's UUID
         // Fallback to making a new random UUID
+        let trace_uuid_override = trace_uuid_override(cfg, t);
+        if cfg.import.shard.is_some() && trace_uuid_override.or(t.uuid).is_none() {
+            return Err(Error::ShardWithoutTraceUuid);
+        }
         let trace_uuid = trace_uuid_override.or(t.uuid).unwrap_or_else(Uuid::new_v4);
 
+        // The shards of an import must also agree on the run ID
+        let run_id = if cfg.import.shard.is_some() && cfg.generated_run_id {
+            shard_run_id(&trace_uuid)
+        } else {
+            cfg.run_id.unwrap_or_else(Uuid::new_v4)
+        };
+
+        let wall_clock_origin_ns = cfg.wall_clock_origin_ns.or_else(|| {
+            let key = cfg.wall_clock_origin_env.as_deref()?;
+            let ns = env_ns(t, key)?;
+            if ns < 0 {
+                warn!("The trace environment entry '{key}' is a negative wall-clock origin");
+                return None;
+            }
+            Some(ns as u64)
+        });
+        let clock_correction_ns = cfg
+            .clock_correction_env
+            .as_deref()
+            .and_then(|key| env_ns(t, key));
+
+        let name_rules = TimelineNameRules::new(cfg, s);
+
         let stream_count = s.len() as u64;
-        let trace =
-            CtfTraceProperties::new(run_id, trace_uuid_override, stream_count, t, client).await?;
+        let trace = CtfTraceProperties::new(
+            run_id,
+            trace_uuid_override,
+            stream_count,
+            &cfg.exclude_trace_env,
+            t,
+            client,
+        )
+        .await?;
         let mut streams = BTreeMap::default();
         for stream in s.iter() {
-            streams.insert(
-                stream.id,
-                CtfStreamProperties::new(&trace_uuid, stream, client).await?,
-            );
+            let (timeline_id, timeline_name) = stream_timeline_overrides(cfg, &name_rules, stream);
+            let mut props = CtfStreamProperties::new(
+                &trace_uuid,
+                timeline_id,
+                timeline_name,
+                wall_clock_origin_ns,
+                clock_correction_ns,
+                stream,
+                client,
+            )
+            .await?;
+            if let Some(shift) = cfg.timestamp_shift_ns {
+                props.set_timestamp_shift_ns(shift, client).await?;
+            }
+            if cfg.per_cpu_streams {
+                if let Some((_, cpu)) = per_cpu_stream(props.stream_name()) {
+                    props.set_cpu(cpu, client).await?;
+                }
+            }
+            streams.insert(stream.id, props);
+        }
+        for stream_id in cfg.stream_timeline_map.keys() {
+            if !streams.contains_key(stream_id) {
+                warn!("The stream-timeline-map entry for stream ID {stream_id} doesn't match any stream in the trace");
+            }
+        }
+        for stream_id in cfg.stream_timeline_names.keys() {
+            if !streams.contains_key(stream_id) {
+                warn!("The stream-timeline-names entry for stream ID {stream_id} doesn't match any stream in the trace");
+            }
+        }
+        Ok(Self {
+            trace_uuid,
+            metadata_trace_uuid: t.uuid,
+            trace,
+            streams,
+        })
+    }
+
+    /// Tag all the streams with the same source URI, see
+    /// [`CtfStreamProperties::set_source_uri`]
+    pub async fn set_source_uri(&mut self, uri: &str, client: &mut Client) -> Result<(), Error> {
+        for s in self.streams.values_mut() {
+            s.set_source_uri(uri.to_owned(), client).await?;
+        }
+        Ok(())
+    }
+
+    /// Offset the event timestamps of all the streams, see
+    /// [`CtfStreamProperties::set_clock_class_offset_ns`]
+    pub async fn set_clock_class_offset_ns(
+        &mut self,
+        offset_ns: i64,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        for s in self.streams.values_mut() {
+            s.set_clock_class_offset_ns(offset_ns, client).await?;
         }
-        Ok(Self { trace, streams })
+        Ok(())
+    }
+
+    /// Name the trace after where it comes from when it's anonymous, see
+    /// [`CtfTraceProperties::set_fallback_name`]
+    pub async fn set_fallback_trace_name(
+        &mut self,
+        name: String,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        self.trace.set_fallback_name(name, client).await
     }
 
     #[allow(clippy::type_complexity)]
@@ -57,3 +162,119 @@ impl CtfProperties {
         }))
     }
 }
+
+/// The trace UUID override if present, then the one derived from the trace environment
+pub(crate) fn trace_uuid_override(cfg: &PluginConfig, t: &TraceProperties) -> Option<Uuid> {
+    cfg.trace_uuid.or_else(|| {
+        let key = cfg.trace_uuid_from_env.as_deref()?;
+        env_uuid(t, key)
+    })
+}
+
+/// How the timelines are named after their derived stream names, the
+/// `timeline-name-patterns` and the per-CPU timeline names of the profiles
+pub(crate) struct TimelineNameRules<'a> {
+    patterns: Vec<(&'a Regex, &'a str)>,
+    /// The per-CPU timeline name prefix, and whether the trace has several per-CPU channels
+    per_cpu: Option<(&'a str, bool)>,
+}
+
+impl<'a> TimelineNameRules<'a> {
+    pub(crate) fn new(cfg: &'a PluginConfig, s: &BTreeSet<StreamProperties>) -> Self {
+        let patterns = cfg
+            .timeline_name_patterns
+            .iter()
+            .map(|p| (p.stream_name.regex(), p.timeline_name.as_str()))
+            .collect();
+        let per_cpu = cfg.per_cpu_timeline_prefix.as_deref().map(|prefix| {
+            let channels: BTreeSet<String> = s
+                .iter()
+                .filter_map(|stream| {
+                    per_cpu_stream(&derived_stream_name(stream)).map(|(c, _)| c.to_owned())
+                })
+                .collect();
+            (prefix, channels.len() > 1)
+        });
+        Self { patterns, per_cpu }
+    }
+
+    /// The timeline name of the first pattern matching the derived stream name,
+    /// with the pattern's capture groups expanded, or else the per-CPU timeline name
+    fn timeline_name(&self, s: &StreamProperties) -> Option<String> {
+        let stream_name = derived_stream_name(s);
+        self.patterns
+            .iter()
+            .find_map(|(re, timeline_name)| {
+                let caps = re.captures(&stream_name)?;
+                let mut name = String::new();
+                caps.expand(timeline_name, &mut name);
+                Some(name)
+            })
+            .or_else(|| {
+                let (prefix, multiple_channels) = self.per_cpu?;
+                per_cpu_timeline_name(prefix, multiple_channels, &stream_name)
+            })
+    }
+}
+
+/// The configured timeline ID and name of the stream's timeline, if any
+pub(crate) fn stream_timeline_overrides(
+    cfg: &PluginConfig,
+    name_rules: &TimelineNameRules,
+    s: &StreamProperties,
+) -> (Option<TimelineId>, Option<String>) {
+    let timeline_id = cfg
+        .stream_timeline_map
+        .get(&s.id)
+        .map(|tid| TimelineId::from(*tid));
+    let timeline_name = cfg
+        .stream_timeline_names
+        .get(&s.id)
+        .cloned()
+        .or_else(|| name_rules.timeline_name(s));
+    (timeline_id, timeline_name)
+}
+
+/// Name of the UUIDv5 run ID shards derive from the trace UUID
+const SHARD_RUN_ID_NAME: &[u8] = b"modality-ctf-shard-run-id";
+
+/// The run ID the shards of an import agree on without configuring one
+pub fn shard_run_id(trace_uuid: &Uuid) -> Uuid {
+    Uuid::new_v5(trace_uuid, SHARD_RUN_ID_NAME)
+}
+
+/// Namespace of the UUIDv5 trace UUIDs derived from trace environment entries
+const TRACE_UUID_ENV_NAMESPACE: Uuid = Uuid::from_u128(0x9c04_6a03_0ec4_4968_a6eb_08a5_b9df_e3e0);
+
+/// Derive a trace UUID from a trace environment entry, a UUID string is used as is
+fn env_uuid(t: &TraceProperties, key: &str) -> Option<Uuid> {
+    let value = match trace::env_value(t, key) {
+        Some(EnvValue::Integer(i)) => i.to_string(),
+        Some(EnvValue::String(s)) => s.trim().to_owned(),
+        None => {
+            warn!("The trace environment doesn't contain the entry '{key}'");
+            return None;
+        }
+    };
+    Some(
+        value
+            .parse::<Uuid>()
+            .unwrap_or_else(|_| Uuid::new_v5(&TRACE_UUID_ENV_NAMESPACE, value.as_bytes())),
+    )
+}
+
+/// Lookup a trace environment entry holding a nanosecond value
+fn env_ns(t: &TraceProperties, key: &str) -> Option<i64> {
+    match trace::env_value(t, key) {
+        Some(EnvValue::Integer(ns)) => Some(*ns),
+        Some(EnvValue::String(ns)) if ns.trim().parse::<i64>().is_ok() => ns.trim().parse().ok(),
+        Some(_) => {
+            warn!("The trace environment entry '{key}' isn't a nanosecond value");
+            None
+        }
+        None => {
+            warn!("The trace environment doesn't contain the entry '{key}'");
+            None
+        }
+    }
+}
@@ -1,11 +1,16 @@
 use crate::attrs::{TimelineAttrKey, TIMELINE_INGEST_SOURCE_VAL};
 use crate::client::Client;
+use crate::clock::{self, CLOCK_CORRECTION_SOURCE_ENV};
 use crate::error::Error;
-use babeltrace2_sys::StreamProperties;
-use modality_api::{AttrVal, BigInt, TimelineId};
+use crate::event::root_scalar_field;
+use crate::index::PacketIndex;
+use babeltrace2_sys::{OwnedEvent, ScalarField, StreamProperties};
+use modality_api::{AttrVal, BigInt, Nanoseconds, TimelineId};
 use modality_ingest_protocol::InternedAttrKey;
 use std::collections::HashMap;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
 use uuid::Uuid;
 
 const CLOCK_STYLE_RELATIVE: &str = "relative";
@@ -14,37 +19,60 @@ const CLOCK_STYLE_UTC: &str = "utc";
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct CtfStreamProperties {
     timeline_id: TimelineId,
+    timeline_name: String,
+    stream_name: String,
+    wall_clock_origin_ns: Option<u64>,
+    clock_correction_ns: Option<i64>,
+    timestamp_shift_ns: Option<u64>,
+    clock_class_offset_ns: Option<i64>,
+    clock: Option<StreamClock>,
+    source_uri: Option<String>,
     attrs: HashMap<InternedAttrKey, AttrVal>,
 }
 
+/// The stream clock parameters needed to convert raw cycle values
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct StreamClock {
+    frequency: u64,
+    offset_seconds: i64,
+    offset_cycles: u64,
+    precision: u64,
+}
+
+impl StreamClock {
+    /// The clock precision, converted from cycles to nanoseconds and rounded up
+    fn precision_ns(&self) -> u64 {
+        let ns = u128::from(self.precision) * 1_000_000_000;
+        let freq = u128::from(self.frequency.max(1));
+        u64::try_from((ns + freq - 1) / freq).unwrap_or(u64::MAX)
+    }
+
+    /// Nanoseconds from the clock origin of a cycle value, the same way
+    /// babeltrace computes event clock snapshots
+    fn cycles_to_ns(&self, cycles: u64) -> Option<u64> {
+        let cycles = i128::from(self.offset_cycles) + i128::from(cycles);
+        let ns = i128::from(self.offset_seconds) * 1_000_000_000
+            + cycles * 1_000_000_000 / i128::from(self.frequency.max(1));
+        u64::try_from(ns).ok()
+    }
+}
+
 impl CtfStreamProperties {
     pub async fn new(
         trace_uuid: &Uuid,
+        timeline_id_override: Option<TimelineId>,
+        timeline_name_override: Option<String>,
+        wall_clock_origin_ns: Option<u64>,
+        clock_correction_ns: Option<i64>,
         s: &StreamProperties,
         client: &mut Client,
     ) -> Result<Self, Error> {
         let mut attrs = HashMap::default();
-        let timeline_id = TimelineId::from(Uuid::new_v5(trace_uuid, &s.id.to_le_bytes()));
-
-        // The stream name produced by babeltrace is the path to the stream file within
-        // a trace. This is rather ugly and hard to write specs against
-        // (event @ "/some annoyingly/long path to/a trace/stream_0").
-        // So instead of making a timeline name directly from a stream name, we first
-        // attempt to use the file name component if possible, and fallback to
-        // "stream{stream_id}" which is the default naming convention used within
-        // the LTTng ecosystem when not provided by babeltrace.
-        let stream_name_from_path = s.name.as_ref().and_then(|sn| {
-            let p = Path::new(sn);
-            if p.exists() {
-                p.file_name().map(|s| s.to_string_lossy())
-            } else {
-                None
-            }
-        });
-        let stream_name = stream_name_from_path
-            .map(|s| s.to_string())
-            .or_else(|| s.name.clone())
-            .unwrap_or_else(|| format!("stream{}", s.id));
+        let timeline_id =
+            timeline_id_override.unwrap_or_else(|| derived_timeline_id(trace_uuid, s));
+
+        let stream_name = derived_stream_name(s);
+        let timeline_name = timeline_name_override.unwrap_or_else(|| stream_name.clone());
 
         attrs.insert(
             client
@@ -54,14 +82,14 @@ impl CtfStreamProperties {
         );
         attrs.insert(
             client.interned_timeline_key(TimelineAttrKey::Name).await?,
-            stream_name.clone().into(),
+            timeline_name.clone().into(),
         );
 
         attrs.insert(
             client
                 .interned_timeline_key(TimelineAttrKey::StreamName)
                 .await?,
-            stream_name.into(),
+            stream_name.clone().into(),
         );
         attrs.insert(
             client
@@ -77,6 +105,14 @@ impl CtfStreamProperties {
             TIMELINE_INGEST_SOURCE_VAL.into(),
         );
 
+        // Only relative clocks are rebased onto the wall-clock origin
+        let wall_clock_origin_ns = wall_clock_origin_ns.filter(|_| {
+            s.clock
+                .as_ref()
+                .map(|c| !c.unix_epoch_origin)
+                .unwrap_or(false)
+        });
+
         if let Some(c) = &s.clock {
             attrs.insert(
                 client
@@ -142,7 +178,7 @@ impl CtfStreamProperties {
                 client
                     .interned_timeline_key(TimelineAttrKey::ClockStyle)
                     .await?,
-                String::from(if c.unix_epoch_origin {
+                String::from(if c.unix_epoch_origin || wall_clock_origin_ns.is_some() {
                     CLOCK_STYLE_UTC
                 } else {
                     CLOCK_STYLE_RELATIVE
@@ -151,14 +187,417 @@ impl CtfStreamProperties {
             );
         }
 
-        Ok(Self { timeline_id, attrs })
+        if let Some(origin) = wall_clock_origin_ns {
+            attrs.insert(
+                client
+                    .interned_timeline_key(TimelineAttrKey::StreamClockWallClockOrigin)
+                    .await?,
+                Nanoseconds::from(origin).into(),
+            );
+        }
+
+        if let Some(correction) = clock_correction_ns {
+            attrs.extend(
+                clock::correction_attr_kvs(correction, CLOCK_CORRECTION_SOURCE_ENV, client).await?,
+            );
+        }
+
+        Ok(Self {
+            timeline_id,
+            timeline_name,
+            stream_name,
+            wall_clock_origin_ns,
+            clock_correction_ns,
+            timestamp_shift_ns: None,
+            clock_class_offset_ns: None,
+            clock: s.clock.as_ref().map(|c| StreamClock {
+                frequency: c.frequency,
+                offset_seconds: c.offset_seconds,
+                offset_cycles: c.offset_cycles,
+                precision: c.precision,
+            }),
+            source_uri: None,
+            attrs,
+        })
     }
 
     pub fn timeline_id(&self) -> TimelineId {
         self.timeline_id
     }
 
+    /// The `timeline.name` attr value
+    pub fn timeline_name(&self) -> &str {
+        &self.timeline_name
+    }
+
+    /// The `timeline.stream_name` attr value
+    pub fn stream_name(&self) -> &str {
+        &self.stream_name
+    }
+
+    /// The URI of the trace directory or lttng-live session the stream comes from,
+    /// when `source-uri-attrs` is enabled
+    pub fn source_uri(&self) -> Option<&str> {
+        self.source_uri.as_deref()
+    }
+
+    pub async fn set_source_uri(&mut self, uri: String, client: &mut Client) -> Result<(), Error> {
+        self.attrs.insert(
+            client
+                .interned_timeline_key(TimelineAttrKey::SourceUri)
+                .await?,
+            uri.clone().into(),
+        );
+        self.source_uri = Some(uri);
+        Ok(())
+    }
+
+    /// The wall-clock origin event timestamps are rebased onto, for relative clocks
+    pub fn wall_clock_origin_ns(&self) -> Option<u64> {
+        self.wall_clock_origin_ns
+    }
+
+    /// Tag a per-CPU stream with its CPU, see [`per_cpu_stream`]
+    pub async fn set_cpu(&mut self, cpu: u32, client: &mut Client) -> Result<(), Error> {
+        self.attrs.insert(
+            client.interned_timeline_key(TimelineAttrKey::Cpu).await?,
+            BigInt::new_attr_val(cpu.into()),
+        );
+        Ok(())
+    }
+
+    /// Shift the event timestamps by a fixed amount, see `timestamp-shift-ns`
+    pub async fn set_timestamp_shift_ns(
+        &mut self,
+        shift_ns: u64,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        self.attrs.insert(
+            client
+                .interned_timeline_key(TimelineAttrKey::StreamClockTimestampShift)
+                .await?,
+            BigInt::new_attr_val(shift_ns.into()),
+        );
+        self.timestamp_shift_ns = Some(shift_ns);
+        Ok(())
+    }
+
+    /// Offset the event timestamps like a clock class offset would, for sources
+    /// without the `clock-class-offset-*` component parameters (lttng-live)
+    pub async fn set_clock_class_offset_ns(
+        &mut self,
+        offset_ns: i64,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        self.attrs.insert(
+            client
+                .interned_timeline_key(TimelineAttrKey::StreamClockClassOffset)
+                .await?,
+            offset_ns.into(),
+        );
+        self.clock_class_offset_ns = Some(offset_ns);
+        Ok(())
+    }
+
+    /// The offset to add to event timestamps, combining the wall-clock origin, the
+    /// clock correction, the timestamp shift, and the clock class offset. A correction
+    /// from a clock sync event takes precedence over the one from the trace environment.
+    pub fn timestamp_offset_ns(&self, sync_event_correction_ns: Option<i64>) -> Option<i128> {
+        let correction = sync_event_correction_ns.or(self.clock_correction_ns);
+        if self.wall_clock_origin_ns.is_none()
+            && correction.is_none()
+            && self.timestamp_shift_ns.is_none()
+            && self.clock_class_offset_ns.is_none()
+        {
+            return None;
+        }
+        Some(
+            i128::from(self.wall_clock_origin_ns.unwrap_or(0))
+                + i128::from(correction.unwrap_or(0))
+                + i128::from(self.timestamp_shift_ns.unwrap_or(0))
+                + i128::from(self.clock_class_offset_ns.unwrap_or(0)),
+        )
+    }
+
+    /// The precision of the stream clock in nanoseconds, when it's non-zero
+    pub fn timestamp_precision_ns(&self) -> Option<u64> {
+        self.clock.map(|c| c.precision_ns()).filter(|ns| *ns != 0)
+    }
+
+    /// Add the packet count, total size, and covered time range of the stream's
+    /// packet index as timeline attrs
+    pub async fn set_packet_index(
+        &mut self,
+        index: &PacketIndex,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        self.attrs.insert(
+            client
+                .interned_timeline_key(TimelineAttrKey::PacketIndexPacketCount)
+                .await?,
+            BigInt::new_attr_val(index.packet_count.into()),
+        );
+        self.attrs.insert(
+            client
+                .interned_timeline_key(TimelineAttrKey::PacketIndexTotalSize)
+                .await?,
+            BigInt::new_attr_val(index.total_size.into()),
+        );
+
+        // Offset like the event timestamps are
+        let offset = self.timestamp_offset_ns(None).unwrap_or(0);
+        let to_ns = |cycles: Option<u64>| {
+            let ns = self.clock?.cycles_to_ns(cycles?)?;
+            Some((i128::from(ns) + offset).clamp(0, u64::MAX.into()) as u64)
+        };
+        let range = [
+            (
+                TimelineAttrKey::PacketIndexBeginNs,
+                to_ns(index.timestamp_begin),
+            ),
+            (
+                TimelineAttrKey::PacketIndexEndNs,
+                to_ns(index.timestamp_end),
+            ),
+        ];
+        for (key, ns) in range {
+            if let Some(ns) = ns {
+                self.attrs.insert(
+                    client.interned_timeline_key(key).await?,
+                    Nanoseconds::from(ns).into(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The time range the packet index covers, in nanoseconds like the
+    /// event clock snapshots (without the timestamp offsets)
+    pub fn packet_index_range_ns(&self, index: &PacketIndex) -> Option<(u64, u64)> {
+        let clock = self.clock?;
+        Some((
+            clock.cycles_to_ns(index.timestamp_begin?)?,
+            clock.cycles_to_ns(index.timestamp_end?)?,
+        ))
+    }
+
+    /// The `timestamp_begin` and `timestamp_end` packet context fields of
+    /// the event's packet, converted from stream clock cycles to nanoseconds
+    pub fn packet_timestamps_ns(&self, event: &OwnedEvent) -> (Option<u64>, Option<u64>) {
+        let (clock, f) = match (self.clock, event.properties.packet_context.as_ref()) {
+            (Some(c), Some(f)) => (c, f),
+            _ => return (None, None),
+        };
+        let ns = |name| match root_scalar_field(f, name)? {
+            ScalarField::UnsignedInteger(cycles) => clock.cycles_to_ns(*cycles),
+            _ => None,
+        };
+        (ns("timestamp_begin"), ns("timestamp_end"))
+    }
+
     pub fn attr_kvs(&self) -> Vec<(InternedAttrKey, AttrVal)> {
         self.attrs.clone().into_iter().collect()
     }
 }
+
+/// The path of a stream's file within the input trace directories, from its
+/// stream name.
+///
+/// babeltrace reports the path as a string that may have been lossily decoded
+/// from a non-UTF-8 path, so when it doesn't exist as is it's matched component
+/// by component against the lossily decoded input directory entries.
+pub fn stream_file_path(inputs: &[PathBuf], name: &str) -> Option<PathBuf> {
+    if Path::new(name).exists() {
+        return Some(PathBuf::from(name));
+    }
+    inputs.iter().find_map(|input| {
+        let rel_path = name.strip_prefix(input.to_string_lossy().as_ref())?;
+        rel_path
+            .split('/')
+            .filter(|c| !c.is_empty())
+            .try_fold(input.clone(), |dir, component| {
+                fs::read_dir(&dir)
+                    .ok()?
+                    .filter_map(|e| e.ok())
+                    .find(|e| e.file_name().to_string_lossy() == component)
+                    .map(|e| e.path())
+            })
+            .filter(|p| p.is_file())
+    })
+}
+
+/// The `file://` URI of the input trace directory containing the stream's files
+pub fn fs_source_uri(inputs: &[PathBuf], s: &StreamProperties) -> Option<String> {
+    let canonical = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_owned());
+    let stream_path = s
+        .name
+        .as_deref()
+        .and_then(|n| stream_file_path(inputs, n))
+        .map(|p| canonical(&p));
+    let input = inputs
+        .iter()
+        .map(|i| canonical(i))
+        .find(|i| {
+            stream_path
+                .as_deref()
+                .map(|p| p.starts_with(i))
+                .unwrap_or(false)
+        })
+        .or_else(|| match inputs {
+            [input] => Some(canonical(input)),
+            _ => None,
+        })?;
+    Some(
+        Url::from_directory_path(&input)
+            .map(|u| u.to_string())
+            .unwrap_or_else(|_| input.display().to_string()),
+    )
+}
+
+/// The timeline ID derived from the trace UUID and the stream ID
+pub(crate) fn derived_timeline_id(trace_uuid: &Uuid, s: &StreamProperties) -> TimelineId {
+    derived_stream_timeline_id(trace_uuid, s.id)
+}
+
+pub(crate) fn derived_stream_timeline_id(trace_uuid: &Uuid, stream_id: u64) -> TimelineId {
+    TimelineId::from(Uuid::new_v5(trace_uuid, &stream_id.to_le_bytes()))
+}
+
+/// The stream name used for the `timeline.stream_name` attr and the default `timeline.name`.
+///
+/// The stream name produced by babeltrace is the path to the stream file within
+/// a trace. This is rather ugly and hard to write specs against
+/// (event @ "/some annoyingly/long path to/a trace/stream_0").
+/// So instead of making a timeline name directly from a stream name, we first
+/// attempt to use the file name component if possible, and fallback to
+/// "stream{stream_id}" which is the default naming convention used within
+/// the LTTng ecosystem when not provided by babeltrace.
+pub(crate) fn derived_stream_name(s: &StreamProperties) -> String {
+    let stream_name_from_path = s.name.as_ref().and_then(|sn| {
+        let p = Path::new(sn);
+        if p.exists() {
+            p.file_name().map(|s| s.to_string_lossy())
+        } else {
+            None
+        }
+    });
+    stream_name_from_path
+        .map(|s| s.to_string())
+        .or_else(|| s.name.clone())
+        .unwrap_or_else(|| format!("stream{}", s.id))
+}
+
+/// The channel and CPU of a per-CPU stream, from the conventional
+/// `<channel>_<cpu>` stream file name of LTTng (e.g. `channel0_3`)
+pub fn per_cpu_stream(stream_name: &str) -> Option<(&str, u32)> {
+    let (channel, cpu) = stream_name.rsplit_once('_')?;
+    let valid_channel = channel
+        .chars()
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_')
+        .unwrap_or(false)
+        && channel.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if !valid_channel || cpu.is_empty() || !cpu.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((channel, cpu.parse().ok()?))
+}
+
+/// The timeline name of a per-CPU stream, `<prefix>/cpu<cpu>`, or
+/// `<prefix>/<channel>/cpu<cpu>` when the trace has several per-CPU channels
+pub fn per_cpu_timeline_name(
+    prefix: &str,
+    multiple_channels: bool,
+    stream_name: &str,
+) -> Option<String> {
+    let (channel, cpu) = per_cpu_stream(stream_name)?;
+    Some(if multiple_channels {
+        format!("{prefix}/{channel}/cpu{cpu}")
+    } else {
+        format!("{prefix}/cpu{cpu}")
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn non_utf8_stream_file_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let input = root.path().join(OsStr::from_bytes(b"trace-caf\xe9"));
+        fs::create_dir(&input).unwrap();
+        let stream_file = input.join(OsStr::from_bytes(b"chan\xff_0"));
+        fs::write(&stream_file, [0]).unwrap();
+
+        let name = stream_file.to_string_lossy().into_owned();
+        assert_eq!(stream_file_path(&[input], &name), Some(stream_file));
+    }
+
+    #[test]
+    fn packet_timestamp_cycles_to_ns() {
+        let clock = StreamClock {
+            frequency: 1_000_000,
+            offset_seconds: 2,
+            offset_cycles: 500,
+            precision: 0,
+        };
+        assert_eq!(clock.cycles_to_ns(1_500), Some(2_002_000_000));
+        let clock = StreamClock {
+            offset_seconds: -1,
+            ..clock
+        };
+        assert_eq!(clock.cycles_to_ns(0), None);
+    }
+
+    #[test]
+    fn clock_precision_to_ns() {
+        let clock = StreamClock {
+            frequency: 1_000_000_000,
+            offset_seconds: 0,
+            offset_cycles: 0,
+            precision: 20,
+        };
+        assert_eq!(clock.precision_ns(), 20);
+        let clock = StreamClock {
+            frequency: 32_768,
+            precision: 1,
+            ..clock
+        };
+        assert_eq!(clock.precision_ns(), 30_518);
+        let clock = StreamClock {
+            precision: 0,
+            ..clock
+        };
+        assert_eq!(clock.precision_ns(), 0);
+    }
+
+    #[test]
+    fn per_cpu_stream_names() {
+        assert_eq!(per_cpu_stream("channel0_3"), Some(("channel0", 3)));
+        assert_eq!(per_cpu_stream("my_chan_12"), Some(("my_chan", 12)));
+        assert_eq!(per_cpu_stream("metadata"), None);
+        assert_eq!(per_cpu_stream("stream0"), None);
+        assert_eq!(per_cpu_stream("channel0_"), None);
+        assert_eq!(per_cpu_stream("_3"), None);
+        assert_eq!(per_cpu_stream("0chan_3"), None);
+        assert_eq!(per_cpu_stream("chan_+3"), None);
+    }
+
+    #[test]
+    fn per_cpu_timeline_names() {
+        assert_eq!(
+            per_cpu_timeline_name("kernel", false, "channel0_3").as_deref(),
+            Some("kernel/cpu3")
+        );
+        assert_eq!(
+            per_cpu_timeline_name("kernel", true, "channel1_3").as_deref(),
+            Some("kernel/channel1/cpu3")
+        );
+        assert_eq!(per_cpu_timeline_name("kernel", true, "metadata"), None);
+    }
+}
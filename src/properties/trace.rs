@@ -4,19 +4,27 @@ use crate::error::Error;
 use babeltrace2_sys::{EnvValue, TraceProperties};
 use modality_api::{AttrVal, BigInt};
 use modality_ingest_protocol::InternedAttrKey;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use tracing::debug;
 use uuid::Uuid;
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct CtfTraceProperties {
+    run_id: Uuid,
+    tracer: Option<TracerIdentity>,
+    /// Neither named nor with a `hostname` environment entry
+    anonymous: bool,
     attrs: HashMap<InternedAttrKey, AttrVal>,
 }
 
 impl CtfTraceProperties {
     pub async fn new(
-        run_id: Option<Uuid>,
+        run_id: Uuid,
         trace_uuid_override: Option<Uuid>,
         stream_count: u64,
+        exclude_env: &BTreeSet<String>,
         t: &TraceProperties,
         client: &mut Client,
     ) -> Result<Self, Error> {
@@ -24,7 +32,7 @@ impl CtfTraceProperties {
 
         attrs.insert(
             client.interned_timeline_key(TimelineAttrKey::RunId).await?,
-            run_id.unwrap_or_else(Uuid::new_v4).to_string().into(),
+            run_id.to_string().into(),
         );
 
         if let Some(uuid) = trace_uuid_override.or(t.uuid) {
@@ -57,7 +65,7 @@ impl CtfTraceProperties {
         }
 
         if let Some(e) = &t.env {
-            for (k, v) in e.entries() {
+            for (k, v) in e.entries().filter(|(k, _)| !exclude_env.contains(*k)) {
                 let key = TimelineAttrKey::TraceEnv(k.to_owned());
                 attrs.insert(
                     client.interned_timeline_key(key).await?,
@@ -69,10 +77,145 @@ impl CtfTraceProperties {
             }
         }
 
-        Ok(Self { attrs })
+        let tracer = TracerIdentity::from_trace_properties(t);
+        if let Some(tracer) = &tracer {
+            debug!(tracer = %tracer.name, kind = ?tracer.kind(), "Identified tracer");
+            attrs.insert(
+                client
+                    .interned_timeline_key(TimelineAttrKey::TracerName)
+                    .await?,
+                tracer.name.clone().into(),
+            );
+            if let Some(version) = tracer.version() {
+                attrs.insert(
+                    client
+                        .interned_timeline_key(TimelineAttrKey::TracerVersion)
+                        .await?,
+                    version.into(),
+                );
+            }
+        }
+
+        let anonymous = t.name.is_none() && env_value(t, "hostname").is_none();
+
+        Ok(Self {
+            run_id,
+            tracer,
+            anonymous,
+            attrs,
+        })
+    }
+
+    /// The run ID of the trace's timelines
+    pub fn run_id(&self) -> Uuid {
+        self.run_id
+    }
+
+    /// The tracer that produced the trace, if the trace environment identifies one
+    pub fn tracer(&self) -> Option<&TracerIdentity> {
+        self.tracer.as_ref()
+    }
+
+    /// Set the `timeline.internal.ctf.trace.name` of an anonymous trace, one
+    /// without a name or a `hostname` environment entry, to a name derived
+    /// from where it comes from, see [`fs_trace_name`]
+    pub async fn set_fallback_name(
+        &mut self,
+        name: String,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        if self.anonymous {
+            self.attrs.insert(
+                client
+                    .interned_timeline_key(TimelineAttrKey::TraceName)
+                    .await?,
+                name.into(),
+            );
+        }
+        Ok(())
     }
 
     pub fn attr_kvs(&self) -> Vec<(InternedAttrKey, AttrVal)> {
         self.attrs.clone().into_iter().collect()
     }
 }
+
+/// The fallback name of an anonymous trace imported from the given input
+/// trace directories, the name of the first one
+pub fn fs_trace_name(inputs: &[PathBuf]) -> Option<String> {
+    let input = inputs.first()?;
+    let input = fs::canonicalize(input).unwrap_or_else(|_| input.to_owned());
+    input.file_name().map(|n| n.to_string_lossy().into_owned())
+}
+
+/// Well-known tracer families, used to select tracer specific mapping conventions
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum TracerKind {
+    LttngModules,
+    LttngUst,
+    Barectf,
+    Other,
+}
+
+/// Tracer identity from the conventional `tracer_name` and
+/// `tracer_major`, `tracer_minor`, `tracer_patch` trace environment entries
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TracerIdentity {
+    pub name: String,
+    pub major: Option<i64>,
+    pub minor: Option<i64>,
+    pub patch: Option<i64>,
+}
+
+impl TracerIdentity {
+    pub fn from_trace_properties(t: &TraceProperties) -> Option<Self> {
+        let name = match env_value(t, "tracer_name")? {
+            EnvValue::String(s) => s.trim().to_owned(),
+            EnvValue::Integer(_) => return None,
+        };
+        let int_env = |key: &str| match env_value(t, key) {
+            Some(EnvValue::Integer(int)) => Some(*int),
+            Some(EnvValue::String(s)) => s.trim().parse::<i64>().ok(),
+            None => None,
+        };
+        Some(Self {
+            name,
+            major: int_env("tracer_major"),
+            minor: int_env("tracer_minor"),
+            patch: int_env("tracer_patch"),
+        })
+    }
+
+    /// Dotted version string, available when at least the major version is known
+    pub fn version(&self) -> Option<String> {
+        let major = self.major?;
+        Some(match (self.minor, self.patch) {
+            (Some(minor), Some(patch)) => format!("{major}.{minor}.{patch}"),
+            (Some(minor), None) => format!("{major}.{minor}"),
+            _ => major.to_string(),
+        })
+    }
+
+    pub fn kind(&self) -> TracerKind {
+        match self.name.as_str() {
+            "lttng-modules" => TracerKind::LttngModules,
+            "lttng-ust" => TracerKind::LttngUst,
+            "barectf" => TracerKind::Barectf,
+            _ => TracerKind::Other,
+        }
+    }
+}
+
+/// Lookup a trace environment entry by key
+pub(crate) fn env_value<'a>(t: &'a TraceProperties, key: &str) -> Option<&'a EnvValue> {
+    t.env.as_ref().and_then(|e| {
+        e.entries().find_map(|(k, v)| {
+            let k: &str = k.as_ref();
+            if k == key {
+                Some(v)
+            } else {
+                None
+            }
+        })
+    })
+}
@@ -0,0 +1,21 @@
+//! Running as a child plugin of modality-reflector.
+//!
+//! The reflector launches its plugins with its configuration file in the
+//! `MODALITY_REFLECTOR_CONFIG` environment variable and the auth token in
+//! `MODALITY_AUTH_TOKEN`. The plugins send their data to the reflector's
+//! `protocol-child-port`, and are stopped with `SIGTERM`.
+
+use std::io;
+
+/// Have the kernel send `SIGTERM` to this process when the reflector exits,
+/// so an orphaned plugin drains and stops like it would when the reflector stops it
+pub fn exit_with_parent() -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: prctl with PR_SET_PDEATHSIG only sets a process attribute
+        if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,348 @@
+//! A minimal LTTng relay daemon live viewer protocol client, only used to
+//! query the sessions a relay daemon serves, and the matching of the
+//! sessions against the lttng-live URL.
+//!
+//! See `lttng-viewer-abi.h` in the LTTng tools sources. All the protocol
+//! structures are packed and big-endian.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use url::Url;
+
+pub const LTTNG_RELAYD_DEFAULT_PORT: u16 = 5344;
+
+const VIEWER_CMD_CONNECT: u32 = 1;
+const VIEWER_CMD_LIST_SESSIONS: u32 = 2;
+const VIEWER_CLIENT_COMMAND: u32 = 1;
+const VIEWER_PROTOCOL_MAJOR: u32 = 2;
+const VIEWER_PROTOCOL_MINOR: u32 = 4;
+
+const HOST_NAME_MAX: usize = 64;
+const NAME_MAX: usize = 255;
+
+/// A tracing session served by the relay daemon
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelaydSession {
+    pub id: u64,
+    pub hostname: String,
+    pub name: String,
+    pub streams: u32,
+    pub clients: u32,
+}
+
+/// The TGTHOST and SESSION components of a `net://RDHOST/host/TGTHOST/SESSION`
+/// URL, either may contain `*` and `?` wildcards
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionPattern {
+    pub hostname: String,
+    pub name: String,
+}
+
+impl SessionPattern {
+    pub fn from_url(url: &Url) -> Option<Self> {
+        let mut segments = url.path_segments()?;
+        match (
+            segments.next(),
+            segments.next(),
+            segments.next(),
+            segments.next(),
+        ) {
+            (Some("host"), Some(hostname), Some(name), None) => Some(Self {
+                hostname: hostname.to_owned(),
+                name: name.to_owned(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn has_wildcards(&self) -> bool {
+        [&self.hostname, &self.name]
+            .iter()
+            .any(|p| p.contains(['*', '?']))
+    }
+
+    pub fn matches(&self, session: &RelaydSession) -> bool {
+        glob_match(&self.hostname, &session.hostname) && glob_match(&self.name, &session.name)
+    }
+}
+
+impl fmt::Display for SessionPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.hostname, self.name)
+    }
+}
+
+/// The URL of a session, the given URL with its TGTHOST and SESSION replaced
+pub fn session_url(url: &Url, session: &RelaydSession) -> Url {
+    let mut url = url.clone();
+    url.set_path(&format!("/host/{}/{}", session.hostname, session.name));
+    url
+}
+
+/// Match `s` against a pattern where `*` matches any sequence of
+/// characters and `?` any single character
+fn glob_match(pattern: &str, s: &str) -> bool {
+    let (p, s): (Vec<char>, Vec<char>) = (pattern.chars().collect(), s.chars().collect());
+    let (mut pi, mut si) = (0, 0);
+    // The last `*` seen and the position in `s` it's matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if pi < p.len() && (p[pi] == '?' || p[pi] == s[si]) {
+            pi += 1;
+            si += 1;
+        } else if let Some((star_pi, star_si)) = star {
+            pi = star_pi + 1;
+            si = star_si + 1;
+            star = Some((star_pi, si));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// Connect to the relay daemon as a live viewer and list its sessions
+pub fn list_sessions(addr: &SocketAddr, timeout: Duration) -> io::Result<Vec<RelaydSession>> {
+    let mut sock = TcpStream::connect_timeout(addr, timeout)?;
+    sock.set_read_timeout(Some(timeout))?;
+    sock.set_write_timeout(Some(timeout))?;
+
+    // struct lttng_viewer_connect
+    let mut connect = Vec::with_capacity(20);
+    connect.extend_from_slice(&0_u64.to_be_bytes());
+    connect.extend_from_slice(&VIEWER_PROTOCOL_MAJOR.to_be_bytes());
+    connect.extend_from_slice(&VIEWER_PROTOCOL_MINOR.to_be_bytes());
+    connect.extend_from_slice(&VIEWER_CLIENT_COMMAND.to_be_bytes());
+    send_cmd(&mut sock, VIEWER_CMD_CONNECT, &connect)?;
+    let mut reply = [0; 20];
+    sock.read_exact(&mut reply)?;
+    let major = u32::from_be_bytes([reply[8], reply[9], reply[10], reply[11]]);
+    if major != VIEWER_PROTOCOL_MAJOR {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported relay daemon viewer protocol major version {major}"),
+        ));
+    }
+
+    send_cmd(&mut sock, VIEWER_CMD_LIST_SESSIONS, &[])?;
+    let mut count = [0; 4];
+    sock.read_exact(&mut count)?;
+    let count = u32::from_be_bytes(count);
+    let mut sessions = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        // struct lttng_viewer_session
+        let mut s = [0; 20 + HOST_NAME_MAX + NAME_MAX];
+        sock.read_exact(&mut s)?;
+        sessions.push(parse_session(&s));
+    }
+    Ok(sessions)
+}
+
+fn send_cmd(sock: &mut TcpStream, cmd: u32, payload: &[u8]) -> io::Result<()> {
+    // struct lttng_viewer_cmd
+    let mut buf = Vec::with_capacity(16 + payload.len());
+    buf.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    buf.extend_from_slice(&cmd.to_be_bytes());
+    buf.extend_from_slice(&0_u32.to_be_bytes());
+    buf.extend_from_slice(payload);
+    sock.write_all(&buf)
+}
+
+fn parse_session(s: &[u8]) -> RelaydSession {
+    let u32_at = |i: usize| u32::from_be_bytes([s[i], s[i + 1], s[i + 2], s[i + 3]]);
+    let c_str = |b: &[u8]| {
+        let end = b.iter().position(|c| *c == 0).unwrap_or(b.len());
+        String::from_utf8_lossy(&b[..end]).into_owned()
+    };
+    let mut id = [0; 8];
+    id.copy_from_slice(&s[..8]);
+    RelaydSession {
+        id: u64::from_be_bytes(id),
+        clients: u32_at(12),
+        streams: u32_at(16),
+        hostname: c_str(&s[20..20 + HOST_NAME_MAX]),
+        name: c_str(&s[20 + HOST_NAME_MAX..]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn session(hostname: &str, name: &str) -> RelaydSession {
+        RelaydSession {
+            id: 1,
+            hostname: hostname.to_owned(),
+            name: name.to_owned(),
+            streams: 0,
+            clients: 0,
+        }
+    }
+
+    #[test]
+    fn session_pattern_matching() {
+        let url = Url::parse("net://relayd/host/*/autotest-*").unwrap();
+        let pattern = SessionPattern::from_url(&url).unwrap();
+        assert!(pattern.has_wildcards());
+        assert!(pattern.matches(&session("rig-a", "autotest-20240101-120000")));
+        assert!(pattern.matches(&session("rig-b", "autotest-")));
+        assert!(!pattern.matches(&session("rig-a", "manual-20240101")));
+
+        let exact = Url::parse("net://relayd/host/rig-a/kernel").unwrap();
+        let exact = SessionPattern::from_url(&exact).unwrap();
+        assert!(!exact.has_wildcards());
+        assert!(exact.matches(&session("rig-a", "kernel")));
+        assert!(!exact.matches(&session("rig-b", "kernel")));
+
+        assert!(glob_match("rig-?-*-end", "rig-a-x-y-end"));
+        assert!(!glob_match("rig-?", "rig-ab"));
+        assert!(glob_match("*a", "*ba"));
+        assert_eq!(
+            session_url(&url, &session("rig-a", "autotest-1")).as_str(),
+            "net://relayd/host/rig-a/autotest-1"
+        );
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "a"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("**", "abc"));
+        assert!(!glob_match("?", ""));
+        assert!(glob_match("?", "é"));
+        assert!(glob_match("kernel", "kernel"));
+        assert!(!glob_match("kernel", "kernel2"));
+        assert!(!glob_match("kernel2", "kernel"));
+        assert!(glob_match("*-*-end", "a-b-c-end"));
+        assert!(!glob_match("*-*-end", "a-b-c-ends"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(glob_match("*?", "x"));
+        assert!(!glob_match("?*?", "x"));
+    }
+
+    #[test]
+    fn session_pattern_from_url() {
+        let pattern = |url: &str| SessionPattern::from_url(&Url::parse(url).unwrap());
+        assert_eq!(
+            pattern("net://relayd:5344/host/rig-a/kernel"),
+            Some(SessionPattern {
+                hostname: "rig-a".to_owned(),
+                name: "kernel".to_owned(),
+            })
+        );
+        assert_eq!(
+            pattern("net://relayd/host/rig-*/ust*").unwrap().to_string(),
+            "rig-*/ust*"
+        );
+        assert_eq!(pattern("net://relayd"), None);
+        assert_eq!(pattern("net://relayd/host/rig-a"), None);
+        assert_eq!(pattern("net://relayd/host/rig-a/kernel/extra"), None);
+        assert_eq!(pattern("net://relayd/session/rig-a/kernel"), None);
+
+        let url = Url::parse("net://relayd:5345/host/*/*").unwrap();
+        assert_eq!(
+            session_url(&url, &session("rig-b", "ust")).as_str(),
+            "net://relayd:5345/host/rig-b/ust"
+        );
+    }
+
+    fn session_bytes(id: u64, clients: u32, streams: u32, hostname: &[u8], name: &[u8]) -> Vec<u8> {
+        let mut s = vec![0; 20 + HOST_NAME_MAX + NAME_MAX];
+        s[..8].copy_from_slice(&id.to_be_bytes());
+        s[8..12].copy_from_slice(&1_u32.to_be_bytes()); // live_timer
+        s[12..16].copy_from_slice(&clients.to_be_bytes());
+        s[16..20].copy_from_slice(&streams.to_be_bytes());
+        s[20..20 + hostname.len()].copy_from_slice(hostname);
+        s[20 + HOST_NAME_MAX..20 + HOST_NAME_MAX + name.len()].copy_from_slice(name);
+        s
+    }
+
+    #[test]
+    fn session_parsing() {
+        assert_eq!(
+            parse_session(&session_bytes(7, 2, 12, b"rig-a", b"autotest-1")),
+            RelaydSession {
+                id: 7,
+                hostname: "rig-a".to_owned(),
+                name: "autotest-1".to_owned(),
+                streams: 12,
+                clients: 2,
+            }
+        );
+
+        // Names filling their whole buffer have no terminating nul
+        let hostname = [b'h'; HOST_NAME_MAX];
+        let name = [b'n'; NAME_MAX];
+        let parsed = parse_session(&session_bytes(u64::MAX, 0, 0, &hostname, &name));
+        assert_eq!(parsed.id, u64::MAX);
+        assert_eq!(parsed.hostname, "h".repeat(HOST_NAME_MAX));
+        assert_eq!(parsed.name, "n".repeat(NAME_MAX));
+    }
+
+    /// Serve a single viewer connection, replying to the connect command with the
+    /// protocol major version and to the list sessions command with the sessions
+    fn serve_sessions(major: u32, sessions: Vec<Vec<u8>>) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let read_cmd = |sock: &mut TcpStream| {
+                let mut header = [0; 16];
+                sock.read_exact(&mut header).unwrap();
+                let len = u64::from_be_bytes(header[..8].try_into().unwrap());
+                let mut payload = vec![0; len as usize];
+                sock.read_exact(&mut payload).unwrap();
+                u32::from_be_bytes(header[8..12].try_into().unwrap())
+            };
+
+            assert_eq!(read_cmd(&mut sock), VIEWER_CMD_CONNECT);
+            let mut reply = vec![0; 20];
+            reply[8..12].copy_from_slice(&major.to_be_bytes());
+            reply[12..16].copy_from_slice(&VIEWER_PROTOCOL_MINOR.to_be_bytes());
+            sock.write_all(&reply).unwrap();
+            if major != VIEWER_PROTOCOL_MAJOR {
+                return;
+            }
+
+            assert_eq!(read_cmd(&mut sock), VIEWER_CMD_LIST_SESSIONS);
+            sock.write_all(&(sessions.len() as u32).to_be_bytes())
+                .unwrap();
+            for s in sessions.iter() {
+                sock.write_all(s).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn list_relayd_sessions() {
+        let timeout = Duration::from_secs(5);
+        let addr = serve_sessions(
+            VIEWER_PROTOCOL_MAJOR,
+            vec![
+                session_bytes(1, 0, 4, b"rig-a", b"kernel"),
+                session_bytes(2, 1, 8, b"rig-b", b"ust"),
+            ],
+        );
+        let sessions = list_sessions(&addr, timeout).unwrap();
+        assert_eq!(
+            sessions
+                .iter()
+                .map(|s| (s.id, s.hostname.as_str(), s.name.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(1, "rig-a", "kernel"), (2, "rig-b", "ust")]
+        );
+
+        let addr = serve_sessions(VIEWER_PROTOCOL_MAJOR + 1, Vec::new());
+        let err = list_sessions(&addr, timeout).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,111 @@
+//! The field to attr key mapping report of the importer's `mapping-report`
+//! setting: for each event class, the attr key every CTF field ends up as
+//! once the renames and event transforms are applied.
+//!
+//! Like the `--inspect` schemas, each event class is reported from its
+//! first ingested event.
+
+use crate::client::Client;
+use crate::config::EventMappingConfig;
+use crate::error::Error;
+use crate::event::{field_attr_keys, CtfEvent};
+use crate::interaction::RemoteTimelineLookup;
+use crate::mapper::FieldMappers;
+use crate::symbols::Symbolizer;
+use babeltrace2_sys::OwnedEvent;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MappingReport {
+    #[serde(rename = "event-classes")]
+    event_classes: Vec<EventClassMapping>,
+
+    #[serde(skip)]
+    recorded: BTreeSet<(u64, u64)>,
+
+    /// The run's field mappers, the events are ingested with the same ones
+    #[serde(skip)]
+    mappers: FieldMappers,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EventClassMapping {
+    pub stream_id: u64,
+    pub id: u64,
+    pub name: Option<String>,
+    pub fields: Vec<FieldMapping>,
+
+    /// Every attr key of the ingested event, including the
+    /// ones that aren't derived from a field
+    pub attr_keys: BTreeSet<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FieldMapping {
+    /// The CTF field path, rooted at its scope
+    /// (`payload`, `common_context`, `specific_context`, `packet_context`)
+    pub path: String,
+
+    /// `None` when an event transform removed the field or moved it to some other key
+    pub attr_key: Option<String>,
+}
+
+impl MappingReport {
+    pub fn new(mappers: &FieldMappers) -> Self {
+        Self {
+            mappers: mappers.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn contains(&self, event: &OwnedEvent) -> bool {
+        self.recorded
+            .contains(&(event.stream_id, event.class_properties.id))
+    }
+
+    /// Record the mapping of the event's class, given the event as it was ingested
+    /// and what it was mapped with
+    pub fn record(
+        &mut self,
+        event: &OwnedEvent,
+        cfg: &EventMappingConfig,
+        symbolizer: &Symbolizer,
+        remote_timelines: RemoteTimelineLookup<'_>,
+        ingested: &CtfEvent,
+        client: &Client,
+    ) -> Result<(), Error> {
+        if self.contains(event) {
+            return Ok(());
+        }
+
+        let attr_keys: BTreeSet<String> = ingested
+            .attr_kvs()
+            .iter()
+            .filter_map(|(k, _)| client.event_key_name(k).map(str::to_owned))
+            .collect();
+        let fields = field_attr_keys(event, cfg, symbolizer, remote_timelines, &self.mappers)?
+            .into_iter()
+            .map(|(path, key)| {
+                let key = client.renamed_event_key(&key.to_string());
+                FieldMapping {
+                    path,
+                    attr_key: attr_keys.contains(&key).then_some(key),
+                }
+            })
+            .collect();
+
+        self.recorded
+            .insert((event.stream_id, event.class_properties.id));
+        self.event_classes.push(EventClassMapping {
+            stream_id: event.stream_id,
+            id: event.class_properties.id,
+            name: event.class_properties.name.clone(),
+            fields,
+            attr_keys,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use crate::config::LttngLiveConfig;
+use std::time::{Duration, Instant};
+
+/// Tracks consecutive libbabeltrace `TryAgain` statuses and yields
+/// how long to wait before running the graph again.
+///
+/// When a maximum retry duration is configured, the delay doubles on each
+/// consecutive retry (up to the maximum) with jitter applied, otherwise
+/// the fixed retry duration is used.
+#[derive(Clone, Debug)]
+pub struct RetryBackoff {
+    base: Duration,
+    max: Option<Duration>,
+    max_retries: Option<u64>,
+    max_idle: Option<Duration>,
+    retries: u64,
+    idle_since: Option<Instant>,
+}
+
+impl RetryBackoff {
+    pub fn new(
+        base: Duration,
+        max: Option<Duration>,
+        max_retries: Option<u64>,
+        max_idle: Option<Duration>,
+    ) -> Self {
+        Self {
+            base,
+            max: max.map(|m| m.max(base)),
+            max_retries,
+            max_idle,
+            retries: 0,
+            idle_since: None,
+        }
+    }
+
+    pub fn from_config(cfg: &LttngLiveConfig) -> Self {
+        Self::new(
+            Duration::from_micros(cfg.retry_duration_us.into()),
+            cfg.max_retry_duration_us
+                .map(|us| Duration::from_micros(us.into())),
+            cfg.max_retries,
+            cfg.max_idle_duration_us
+                .map(|us| Duration::from_micros(us.into())),
+        )
+    }
+
+    /// Call when the graph made progress
+    pub fn reset(&mut self) {
+        self.retries = 0;
+        self.idle_since = None;
+    }
+
+    /// Number of consecutive retries since the last reset
+    pub fn retries(&self) -> u64 {
+        self.retries
+    }
+
+    /// Returns the duration to wait before the next retry, or `None` if
+    /// either the retry limit or the idle time limit has been reached.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let idle_since = *self.idle_since.get_or_insert(now);
+
+        if let Some(max_retries) = self.max_retries {
+            if self.retries >= max_retries {
+                return None;
+            }
+        }
+        if let Some(max_idle) = self.max_idle {
+            if now.duration_since(idle_since) >= max_idle {
+                return None;
+            }
+        }
+
+        let delay = match self.max {
+            None => self.base,
+            Some(max) => {
+                let exp = self.retries.min(u32::MAX as u64) as u32;
+                let delay = self
+                    .base
+                    .checked_mul(2_u32.saturating_pow(exp))
+                    .unwrap_or(max)
+                    .min(max);
+                jitter(delay)
+            }
+        };
+
+        self.retries = self.retries.saturating_add(1);
+        Some(delay)
+    }
+}
+
+/// Equal jitter, yields a duration in [d/2, d]
+fn jitter(d: Duration) -> Duration {
+    let half_us = (d.as_micros() / 2).min(u64::MAX as u128) as u64;
+    Duration::from_micros(half_us + fastrand::u64(0..=half_us))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fixed_delay_without_max() {
+        let base = Duration::from_millis(10);
+        let mut b = RetryBackoff::new(base, None, None, None);
+        for _ in 0..10 {
+            assert_eq!(b.next_delay(), Some(base));
+        }
+    }
+
+    #[test]
+    fn exponential_delay_is_capped() {
+        let base = Duration::from_millis(10);
+        let max = Duration::from_millis(80);
+        let mut b = RetryBackoff::new(base, Some(max), None, None);
+        let mut upper = base;
+        for _ in 0..10 {
+            let d = b.next_delay().unwrap();
+            assert!(d >= upper / 2);
+            assert!(d <= upper);
+            upper = (upper * 2).min(max);
+        }
+    }
+
+    #[test]
+    fn max_retries_and_reset() {
+        let base = Duration::from_millis(1);
+        let mut b = RetryBackoff::new(base, None, Some(2), None);
+        assert!(b.next_delay().is_some());
+        assert!(b.next_delay().is_some());
+        assert_eq!(b.next_delay(), None);
+        b.reset();
+        assert_eq!(b.retries(), 0);
+        assert!(b.next_delay().is_some());
+    }
+
+    #[test]
+    fn max_idle() {
+        let base = Duration::from_millis(1);
+        let mut b = RetryBackoff::new(base, None, None, Some(Duration::ZERO));
+        assert_eq!(b.next_delay(), None);
+    }
+}
@@ -0,0 +1,282 @@
+//! Import-time event sampling, per event class and timeline.
+//!
+//! Event classes are either sampled 1-in-N, keeping the first of every N events,
+//! or, for the `decaying-classes`, sampled with a decaying probability per time
+//! window of the event timestamps: the first `decaying-threshold` events of a window
+//! are kept and the n-th one after that with a probability of `decaying-threshold / n`.
+//! This isn't a fixed-size reservoir, events can't be held back until a window ends
+//! without breaking the timeline ordering, so the kept events of a window grow
+//! logarithmically with its event count.
+//!
+//! Each kept event records the number of events it stands for, the inverse of its
+//! probability of being kept, so the counts can be compensated in analysis.
+
+use crate::attrs::TimelineAttrKey;
+use crate::client::Client;
+use crate::config::SamplingConfig;
+use crate::error::Error;
+use babeltrace2_sys::OwnedEvent;
+use modality_api::{AttrVal, BigInt, TimelineId};
+use modality_ingest_protocol::InternedAttrKey;
+use std::collections::HashMap;
+
+pub const DEFAULT_DECAYING_THRESHOLD: u64 = 1000;
+pub const DEFAULT_DECAYING_WINDOW_MS: u64 = 1000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Sampled {
+    /// The event's class isn't sampled
+    Unsampled,
+    /// The event was kept, standing for `rate` events
+    Kept {
+        rate: f64,
+    },
+    Dropped,
+}
+
+#[derive(Debug)]
+pub struct Sampler {
+    cfg: SamplingConfig,
+    rng: fastrand::Rng,
+    classes: HashMap<(TimelineId, String), ClassState>,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct ClassState {
+    /// Events seen, since the start of the window for the decaying classes
+    count: u64,
+    window: Option<u64>,
+}
+
+impl Sampler {
+    pub fn from_config(cfg: &SamplingConfig) -> Self {
+        Self::with_rng(cfg, fastrand::Rng::new())
+    }
+
+    fn with_rng(cfg: &SamplingConfig, rng: fastrand::Rng) -> Self {
+        Self {
+            cfg: cfg.clone(),
+            rng,
+            classes: Default::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.cfg.sample_rate.is_some()
+            || !self.cfg.class_sample_rates.is_empty()
+            || !self.cfg.decaying_classes.is_empty()
+    }
+
+    pub fn sample(&mut self, timeline_id: TimelineId, event: &OwnedEvent) -> Sampled {
+        if !self.is_enabled() {
+            return Sampled::Unsampled;
+        }
+        let class = event.class_properties.name.as_deref().unwrap_or_default();
+        let timestamp = event.clock_snapshot.and_then(|c| u64::try_from(c).ok());
+        self.sample_class(timeline_id, class, timestamp)
+    }
+
+    fn sample_class(
+        &mut self,
+        timeline_id: TimelineId,
+        class: &str,
+        timestamp_ns: Option<u64>,
+    ) -> Sampled {
+        if self.cfg.decaying_classes.contains(class) {
+            let size = self.cfg.decaying_threshold().max(1);
+            let window_ns = self
+                .cfg
+                .decaying_window_ms()
+                .max(1)
+                .saturating_mul(1_000_000);
+            let window = timestamp_ns.map(|ts| ts / window_ns);
+            let state = self
+                .classes
+                .entry((timeline_id, class.to_owned()))
+                .or_default();
+            // Events without a timestamp stay in the current window
+            if window.is_some() && window != state.window {
+                state.window = window;
+                state.count = 0;
+            }
+            state.count += 1;
+            let n = state.count;
+            if n <= size {
+                Sampled::Kept { rate: 1.0 }
+            } else if self.rng.u64(0..n) < size {
+                Sampled::Kept {
+                    rate: n as f64 / size as f64,
+                }
+            } else {
+                Sampled::Dropped
+            }
+        } else {
+            let rate = match self.cfg.rate(class) {
+                Some(rate) if rate > 1 => rate,
+                _ => return Sampled::Unsampled,
+            };
+            let state = self
+                .classes
+                .entry((timeline_id, class.to_owned()))
+                .or_default();
+            let n = state.count;
+            state.count += 1;
+            if n % rate == 0 {
+                Sampled::Kept { rate: rate as f64 }
+            } else {
+                Sampled::Dropped
+            }
+        }
+    }
+
+    /// The configured sample rates, as timeline attrs
+    pub async fn timeline_attr_kvs(
+        &self,
+        client: &mut Client,
+    ) -> Result<Vec<(InternedAttrKey, AttrVal)>, Error> {
+        let mut attrs = Vec::new();
+        if let Some(rate) = self.cfg.sample_rate {
+            attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::SampleRate)
+                    .await?,
+                BigInt::new_attr_val(rate.into()),
+            ));
+        }
+        for (class, rate) in self.cfg.class_sample_rates.iter() {
+            attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::EventClassSampleRate(class.clone()))
+                    .await?,
+                BigInt::new_attr_val((*rate).into()),
+            ));
+        }
+        if !self.cfg.decaying_classes.is_empty() {
+            attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::SampleDecayingWindowMs)
+                    .await?,
+                BigInt::new_attr_val(self.cfg.decaying_window_ms().into()),
+            ));
+        }
+        for class in self.cfg.decaying_classes.iter() {
+            attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::EventClassDecayingThreshold(
+                        class.clone(),
+                    ))
+                    .await?,
+                BigInt::new_attr_val(self.cfg.decaying_threshold().into()),
+            ));
+        }
+        Ok(attrs)
+    }
+}
+
+impl SamplingConfig {
+    /// The 1-in-N sample rate of an event class
+    pub fn rate(&self, class: &str) -> Option<u64> {
+        self.class_sample_rates
+            .get(class)
+            .copied()
+            .or(self.sample_rate)
+    }
+
+    pub fn decaying_threshold(&self) -> u64 {
+        self.decaying_threshold
+            .unwrap_or(DEFAULT_DECAYING_THRESHOLD)
+    }
+
+    pub fn decaying_window_ms(&self) -> u64 {
+        self.decaying_window_ms
+            .unwrap_or(DEFAULT_DECAYING_WINDOW_MS)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use uuid::Uuid;
+
+    #[test]
+    fn one_in_n_per_class_and_timeline() {
+        let cfg = SamplingConfig {
+            sample_rate: Some(3),
+            class_sample_rates: vec![("rare".to_owned(), 1)].into_iter().collect(),
+            ..Default::default()
+        };
+        let mut sampler = Sampler::with_rng(&cfg, fastrand::Rng::with_seed(1));
+        let (a, b) = (
+            TimelineId::from(Uuid::from_u128(1)),
+            TimelineId::from(Uuid::from_u128(2)),
+        );
+        let kept = Sampled::Kept { rate: 3.0 };
+        assert_eq!(sampler.sample_class(a, "sched_switch", None), kept);
+        assert_eq!(
+            sampler.sample_class(a, "sched_switch", None),
+            Sampled::Dropped
+        );
+        assert_eq!(sampler.sample_class(b, "sched_switch", None), kept);
+        assert_eq!(
+            sampler.sample_class(a, "sched_switch", None),
+            Sampled::Dropped
+        );
+        assert_eq!(sampler.sample_class(a, "sched_switch", None), kept);
+        assert_eq!(sampler.sample_class(a, "rare", None), Sampled::Unsampled);
+    }
+
+    #[test]
+    fn decaying_per_window() {
+        let cfg = SamplingConfig {
+            decaying_classes: vec!["irq".to_owned()].into_iter().collect(),
+            decaying_threshold: Some(10),
+            decaying_window_ms: Some(1),
+            ..Default::default()
+        };
+        let mut sampler = Sampler::with_rng(&cfg, fastrand::Rng::with_seed(1));
+        let tid = TimelineId::from(Uuid::from_u128(1));
+
+        let sample_window = |sampler: &mut Sampler, window: u64| {
+            (0..1000)
+                .map(|i| sampler.sample_class(tid, "irq", Some(window * 1_000_000 + i)))
+                .collect::<Vec<_>>()
+        };
+        let samples = sample_window(&mut sampler, 0);
+        assert!(samples[..10]
+            .iter()
+            .all(|s| *s == Sampled::Kept { rate: 1.0 }));
+        let kept = samples.iter().filter(|s| **s != Sampled::Dropped).count();
+        assert!(kept > 10 && kept < 200, "kept {kept}");
+
+        // The rates of the kept events add up to about the event count
+        let total: f64 = samples
+            .iter()
+            .filter_map(|s| match s {
+                Sampled::Kept { rate } => Some(*rate),
+                _ => None,
+            })
+            .sum();
+        assert!((300.0..3000.0).contains(&total), "total {total}");
+
+        // A new window starts over with the threshold
+        let samples = sample_window(&mut sampler, 1);
+        assert_eq!(samples[0], Sampled::Kept { rate: 1.0 });
+    }
+
+    #[test]
+    fn decaying_window_saturates() {
+        let cfg = SamplingConfig {
+            decaying_classes: vec!["irq".to_owned()].into_iter().collect(),
+            decaying_threshold: Some(1),
+            decaying_window_ms: Some(u64::MAX),
+            ..Default::default()
+        };
+        let mut sampler = Sampler::with_rng(&cfg, fastrand::Rng::with_seed(1));
+        let tid = TimelineId::from(Uuid::from_u128(1));
+        assert_eq!(
+            sampler.sample_class(tid, "irq", Some(u64::MAX)),
+            Sampled::Kept { rate: 1.0 }
+        );
+    }
+}
@@ -0,0 +1,370 @@
+//! Actionable errors for the plugin configuration table.
+//!
+//! The plugin configuration flattens several sections into the one `[metadata]`
+//! table, and deserializing it reports a bare TOML error without the key it's
+//! about. When it fails, each key is deserialized again on its own (the fields
+//! all have defaults) descending into the nested tables and arrays, to find
+//! the path of the offending key.
+//!
+//! Since every field has a default, a misspelled key is silently ignored.
+//! The ignored keys are collected while deserializing, with `serde_ignored`.
+//! It can't see through `#[serde(flatten)]` though, so the sections sharing the
+//! table are deserialized from it on their own rather than flattened, and a key
+//! is unknown when none of them took it. The setting an unknown key was likely
+//! meant to be is the closest of the field names of its table.
+
+use modality_reflector_config::TomlValue;
+use serde::de::{self, DeserializeOwned, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("The [metadata] key '{0}' is invalid. {1}")]
+    InvalidKey(KeyPath, String),
+
+    #[error("The [metadata] table is invalid. {0}")]
+    InvalidTable(String),
+
+    #[error("Unknown [metadata] keys {0}. Fix them, or set on-unknown-key to 'warn' or 'ignore'.")]
+    UnknownKeys(String),
+
+    #[error("The [metadata] key '{0}' requires '{1}' to also be set.")]
+    MissingKey(&'static str, &'static str),
+}
+
+/// A key of the table that isn't a setting, and the setting it was likely meant to be
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownKey {
+    pub path: KeyPath,
+    pub did_you_mean: Option<KeyPath>,
+}
+
+impl UnknownKey {
+    /// The unknown key, suggesting the closest of the field names of its table
+    pub fn new<'a, I>(path: KeyPath, field_names: I) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let did_you_mean = path
+            .last_key()
+            .and_then(|key| did_you_mean(key, field_names))
+            .map(|name| path.with_last_key(name));
+        UnknownKey { path, did_you_mean }
+    }
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}'", self.path)?;
+        if let Some(key) = &self.did_you_mean {
+            write!(f, " (did you mean '{key}'?)")?;
+        }
+        Ok(())
+    }
+}
+
+/// A key of the `[metadata]` table, e.g. `attr-limits.max-string-len` or `elf-binaries[1].path`
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct KeyPath(Vec<KeySegment>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeySegment {
+    Key(String),
+    Index(usize),
+}
+
+impl KeyPath {
+    pub fn push(&mut self, segment: KeySegment) {
+        self.0.push(segment);
+    }
+
+    pub fn pop(&mut self) {
+        self.0.pop();
+    }
+
+    /// The number of segments, 1 for the keys of the table itself
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The key of the table this path starts at
+    pub fn first_key(&self) -> Option<&str> {
+        match self.0.first() {
+            Some(KeySegment::Key(k)) => Some(k),
+            _ => None,
+        }
+    }
+
+    pub fn last_key(&self) -> Option<&str> {
+        match self.0.last() {
+            Some(KeySegment::Key(k)) => Some(k),
+            _ => None,
+        }
+    }
+
+    /// The sibling key of the same table
+    pub fn with_last_key(&self, key: &str) -> KeyPath {
+        let mut path = self.clone();
+        path.pop();
+        path.push(KeySegment::Key(key.to_owned()));
+        path
+    }
+
+    /// Nest the value at this path of an otherwise empty table
+    pub fn wrap(&self, value: TomlValue) -> TomlValue {
+        self.0
+            .iter()
+            .rev()
+            .fold(value, |inner, segment| match segment {
+                KeySegment::Key(k) => {
+                    TomlValue::Table(std::iter::once((k.clone(), inner)).collect())
+                }
+                KeySegment::Index(_) => TomlValue::Array(vec![inner]),
+            })
+    }
+}
+
+impl From<&str> for KeyPath {
+    fn from(key: &str) -> Self {
+        KeyPath(vec![KeySegment::Key(key.to_owned())])
+    }
+}
+
+impl From<&serde_ignored::Path<'_>> for KeyPath {
+    fn from(path: &serde_ignored::Path<'_>) -> Self {
+        use serde_ignored::Path;
+        match path {
+            Path::Root => KeyPath::default(),
+            Path::Seq { parent, index } => {
+                let mut p = KeyPath::from(*parent);
+                p.push(KeySegment::Index(*index));
+                p
+            }
+            Path::Map { parent, key } => {
+                let mut p = KeyPath::from(*parent);
+                p.push(KeySegment::Key(key.clone()));
+                p
+            }
+            Path::Some { parent }
+            | Path::NewtypeStruct { parent }
+            | Path::NewtypeVariant { parent } => KeyPath::from(*parent),
+        }
+    }
+}
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                KeySegment::Key(k) if i == 0 => write!(f, "{k}")?,
+                KeySegment::Key(k) => write!(f, ".{k}")?,
+                KeySegment::Index(idx) => write!(f, "[{idx}]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Deserialize the configuration table, along with the paths of the keys it ignored,
+/// reporting the offending key path on failure
+pub fn deserialize_table<T: DeserializeOwned>(
+    table: TomlValue,
+) -> Result<(T, Vec<KeyPath>), ConfigError> {
+    let mut ignored = Vec::new();
+    match serde_ignored::deserialize(table.clone(), |path| ignored.push(KeyPath::from(&path))) {
+        Ok(cfg) => Ok((cfg, ignored)),
+        Err(e) => {
+            let mut path = KeyPath::default();
+            match locate::<T>(&mut path, &table) {
+                Some((path, message)) if path != KeyPath::default() => {
+                    Err(ConfigError::InvalidKey(path, message))
+                }
+                _ => Err(ConfigError::InvalidTable(e.to_string())),
+            }
+        }
+    }
+}
+
+/// The field names of a struct, as `Deserialize` gives them to the deserializer.
+/// A struct with flattened fields doesn't have them.
+pub fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut names = FieldNames(&[]);
+    let _ = T::deserialize(&mut names);
+    names.0
+}
+
+struct FieldNames(&'static [&'static str]);
+
+impl<'de> Deserializer<'de> for &mut FieldNames {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0 = fields;
+        Err(de::Error::custom("field names only"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+/// Find the deepest key whose value fails to deserialize on its own
+fn locate<T: DeserializeOwned>(path: &mut KeyPath, value: &TomlValue) -> Option<(KeyPath, String)> {
+    let message = path.wrap(value.clone()).try_into::<T>().err()?.to_string();
+    let children: Vec<(KeySegment, &TomlValue)> = match value {
+        TomlValue::Table(t) => t
+            .iter()
+            .map(|(k, v)| (KeySegment::Key(k.clone()), v))
+            .collect(),
+        TomlValue::Array(a) => a
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (KeySegment::Index(i), v))
+            .collect(),
+        _ => Vec::new(),
+    };
+    for (segment, child) in children.into_iter() {
+        path.push(segment);
+        // A child of a table with required fields can't deserialize on its own
+        let located = locate::<T>(path, child).filter(|(_, m)| !m.starts_with("missing field"));
+        path.pop();
+        if located.is_some() {
+            return located;
+        }
+    }
+    Some((path.clone(), message))
+}
+
+/// The candidate closest to `s` when it's close enough to be a likely typo
+pub fn did_you_mean<'a, I>(s: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let max_distance = (s.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(s, c), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// The Levenshtein distance between the strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::PluginConfig;
+    use pretty_assertions::assert_eq;
+
+    fn error(toml: &str) -> String {
+        let table: TomlValue = toml::from_str(toml).unwrap();
+        PluginConfig::from_table(table).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn invalid_key_paths() {
+        let e = error("drain-timeout-ms = 'soon'");
+        assert!(e.starts_with("The [metadata] key 'drain-timeout-ms' is invalid."));
+        assert!(e.contains("expected u64"));
+
+        let e = error("log-level = 'info'\n[attr-limits]\nmax-string-len = -1");
+        assert!(e.starts_with("The [metadata] key 'attr-limits.max-string-len' is invalid."));
+
+        let e = error("[[elf-binaries]]\npath = '/bin/a'\n[[elf-binaries]]\npath = 1");
+        assert!(e.starts_with("The [metadata] key 'elf-binaries[1].path' is invalid."));
+
+        assert!(error("on-ingest-error = 'retyr'").starts_with(
+            "The [metadata] key 'on-ingest-error' is invalid. Invalid ingest error policy 'retyr', expected one of 'fail', 'skip', 'retry'. Did you mean 'retry'?"
+        ));
+    }
+
+    #[test]
+    fn unknown_keys_with_suggestions() {
+        let table: TomlValue = toml::from_str(
+            r#"
+trace-uuuid = 'a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d1'
+log-level = 'info'
+not-a-setting = 1
+
+[attr-limits]
+max-string-len = 16
+max-atrs-per-event = 4
+
+[aggregations.sched_stat_runtime]
+fields = ['runtime']
+bucket-ms = 100
+
+[[elf-binaries]]
+path = 'app.elf'
+laod-address = 0
+"#,
+        )
+        .unwrap();
+        let (_, ignored) = PluginConfig::from_table(table).unwrap();
+        let mut unknown: Vec<String> = PluginConfig::find_unknown_keys(ignored)
+            .iter()
+            .map(|k| k.to_string())
+            .collect();
+        unknown.sort();
+        assert_eq!(
+            unknown,
+            vec![
+                "'attr-limits.max-atrs-per-event' (did you mean 'attr-limits.max-attrs-per-event'?)",
+                "'elf-binaries[0].laod-address' (did you mean 'elf-binaries[0].load-address'?)",
+                "'not-a-setting'",
+                "'trace-uuuid' (did you mean 'trace-uuid'?)",
+            ]
+        );
+    }
+
+    #[test]
+    fn struct_field_names() {
+        assert_eq!(
+            field_names::<crate::config::ElfBinaryConfig>(),
+            &["path", "load-address"]
+        );
+        assert!(field_names::<PluginConfig>().contains(&"on-unknown-key"));
+        assert!(!field_names::<PluginConfig>().contains(&"kallsyms"));
+        assert!(field_names::<u64>().is_empty());
+    }
+
+    #[test]
+    fn suggestions() {
+        let keys = ["log-level", "min-log-level", "profile"];
+        assert_eq!(did_you_mean("log-levl", keys), Some("log-level"));
+        assert_eq!(did_you_mean("profiel", keys), Some("profile"));
+        assert_eq!(did_you_mean("timeout", keys), None);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
@@ -0,0 +1,159 @@
+//! The per-shard views of fs trace directories, so each importer shard only
+//! decodes its own stream files.
+//!
+//! A shard directory holds symlinks to the trace's `metadata`, the stream files
+//! assigned to the shard and their `index/<stream file>.idx` packet indexes.
+//! The stream files keep their names, so the derived stream and timeline names
+//! are the same as when importing the whole trace.
+
+use crate::types::Shard;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_SHARD_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary trace directory with the shard's stream files of an input
+/// trace directory, removed when dropped
+#[derive(Debug)]
+pub struct ShardDir {
+    path: PathBuf,
+}
+
+impl ShardDir {
+    /// Create the shard's view of the input trace directory, `None` when none of
+    /// its stream files belong to the shard
+    pub fn new(shard: Shard, input: &Path) -> io::Result<Option<Self>> {
+        let files = shard_stream_files(shard, input)?;
+        if files.is_empty() {
+            return Ok(None);
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "modality-ctf-shard-{}-{}",
+            process::id(),
+            NEXT_SHARD_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir(&path)?;
+        let dir = ShardDir { path };
+
+        let input = fs::canonicalize(input)?;
+        symlink(input.join("metadata"), dir.path.join("metadata"))?;
+        let index_dir = input.join("index");
+        for file_name in files.iter() {
+            symlink(input.join(file_name), dir.path.join(file_name))?;
+            let mut index_file_name = file_name.clone();
+            index_file_name.push(".idx");
+            let index_path = index_dir.join(&index_file_name);
+            if index_path.exists() {
+                let shard_index_dir = dir.path.join("index");
+                if !shard_index_dir.exists() {
+                    fs::create_dir(&shard_index_dir)?;
+                }
+                symlink(index_path, shard_index_dir.join(index_file_name))?;
+            }
+        }
+        Ok(Some(dir))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ShardDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The names of the input's stream files that belong to the shard. The stream
+/// files are the regular, non-hidden files next to the metadata, each shard gets
+/// the ones whose position in the sorted list modulo the shard count is its index.
+fn shard_stream_files(shard: Shard, input: &Path) -> io::Result<Vec<std::ffi::OsString>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(input)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "metadata" || name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if entry.path().is_file() {
+            files.push(name);
+        }
+    }
+    files.sort();
+    Ok(files
+        .into_iter()
+        .enumerate()
+        .filter(|(pos, _)| shard.contains(*pos as u64))
+        .map(|(_, name)| name)
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn shard_dirs() {
+        let trace = tempfile::tempdir().unwrap();
+        fs::write(trace.path().join("metadata"), b"/* CTF 1.8 */").unwrap();
+        fs::create_dir(trace.path().join("index")).unwrap();
+        for cpu in 0..3 {
+            fs::write(trace.path().join(format!("channel0_{cpu}")), [cpu]).unwrap();
+            fs::write(
+                trace
+                    .path()
+                    .join("index")
+                    .join(format!("channel0_{cpu}.idx")),
+                [cpu],
+            )
+            .unwrap();
+        }
+        fs::write(trace.path().join(".hidden"), b"").unwrap();
+
+        let shard = ShardDir::new(Shard { index: 0, count: 2 }, trace.path())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            file_names(shard.path()),
+            vec!["channel0_0", "channel0_2", "index", "metadata"]
+        );
+        assert_eq!(
+            file_names(&shard.path().join("index")),
+            vec!["channel0_0.idx", "channel0_2.idx"]
+        );
+        assert_eq!(fs::read(shard.path().join("channel0_2")).unwrap(), [2]);
+
+        let other = ShardDir::new(Shard { index: 1, count: 2 }, trace.path())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            file_names(other.path()),
+            vec!["channel0_1", "index", "metadata"]
+        );
+
+        let path = shard.path().to_owned();
+        drop(shard);
+        assert!(!path.exists());
+        assert!(trace.path().join("channel0_0").exists());
+
+        assert!(ShardDir::new(Shard { index: 3, count: 4 }, trace.path())
+            .unwrap()
+            .is_none());
+    }
+}
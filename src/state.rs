@@ -0,0 +1,481 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::debug;
+use uuid::Uuid;
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("Encountered an IO error while accessing the state file '{0}'. {1}")]
+    Io(PathBuf, #[source] io::Error),
+
+    #[error("Encountered an IO error while computing the digest of trace input '{0}'. {1}")]
+    DigestIo(PathBuf, #[source] io::Error),
+
+    #[error("The state file '{0}' is malformed. {1}")]
+    Deserialize(PathBuf, #[source] toml::de::Error),
+
+    #[error("Failed to serialize the state file. {0}")]
+    Serialize(#[from] toml::ser::Error),
+}
+
+/// Persistent record of the traces that have been imported, keyed
+/// by the trace content digest.
+///
+/// Each record keeps the run ID and timeline IDs used for the trace so
+/// a repeated import of the same trace appends to the same timelines.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct IngestState {
+    pub traces: BTreeMap<String, TraceRecord>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct TraceRecord {
+    /// The input paths the trace was imported from, informational only.
+    /// Non-UTF-8 paths are recorded lossily since TOML strings are UTF-8.
+    #[serde(serialize_with = "serialize_lossy_paths")]
+    pub inputs: Vec<PathBuf>,
+
+    /// The run ID used for the trace's timelines
+    pub run_id: Option<Uuid>,
+
+    /// The resolved trace UUID the timeline IDs were derived from
+    pub trace_uuid: Option<Uuid>,
+
+    /// The import was interrupted, the next import of the trace resumes after
+    /// the last event imported on each stream
+    pub partial: bool,
+
+    pub streams: Vec<StreamRecord>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct StreamRecord {
+    pub stream_id: u64,
+    pub timeline_id: Uuid,
+
+    /// The ordering value to continue the timeline from, as a string since
+    /// TOML integers are signed 64-bit
+    #[serde(
+        serialize_with = "serialize_ordering",
+        deserialize_with = "deserialize_ordering"
+    )]
+    pub next_ordering: u128,
+
+    /// The packet sequence number of the last event imported on the stream
+    pub last_packet_seq_num: Option<u64>,
+
+    /// The clock snapshot of the last event imported on the stream
+    pub last_clock_snapshot: Option<i64>,
+}
+
+/// The position of the last event imported on a stream, used to skip the
+/// events of overlapping LTTng snapshots of the same session
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct StreamMark {
+    pub packet_seq_num: Option<u64>,
+    pub clock_snapshot: Option<i64>,
+}
+
+impl StreamMark {
+    /// Returns true if an event at this position was already imported.
+    ///
+    /// Earlier packets were imported entirely, within the same packet
+    /// (or without packet sequence numbers) the clock snapshot decides.
+    pub fn covers(&self, packet_seq_num: Option<u64>, clock_snapshot: Option<i64>) -> bool {
+        match (self.packet_seq_num, packet_seq_num) {
+            (Some(mark), Some(seq)) if seq < mark => return true,
+            (Some(mark), Some(seq)) if seq > mark => return false,
+            _ => (),
+        }
+        match (self.clock_snapshot, clock_snapshot) {
+            (Some(mark), Some(ts)) => ts <= mark,
+            _ => false,
+        }
+    }
+
+    pub fn advance(&mut self, packet_seq_num: Option<u64>, clock_snapshot: Option<i64>) {
+        self.packet_seq_num = self.packet_seq_num.max(packet_seq_num);
+        self.clock_snapshot = self.clock_snapshot.max(clock_snapshot);
+    }
+}
+
+impl From<&StreamRecord> for StreamMark {
+    fn from(r: &StreamRecord) -> Self {
+        Self {
+            packet_seq_num: r.last_packet_seq_num,
+            clock_snapshot: r.last_clock_snapshot,
+        }
+    }
+}
+
+impl IngestState {
+    /// Load the state file, a missing file is an empty state
+    pub fn load(path: &Path) -> Result<Self, StateError> {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                toml::from_str(&content).map_err(|e| StateError::Deserialize(path.to_owned(), e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(StateError::Io(path.to_owned(), e)),
+        }
+    }
+
+    /// Write the state file, replacing it atomically
+    pub fn store(&self, path: &Path) -> Result<(), StateError> {
+        let content = toml::to_string_pretty(self)?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, content).map_err(|e| StateError::Io(tmp_path.clone(), e))?;
+        fs::rename(&tmp_path, path).map_err(|e| StateError::Io(path.to_owned(), e))
+    }
+
+    pub fn contains(&self, digest: &str) -> bool {
+        self.traces.contains_key(digest)
+    }
+
+    /// The trace was recorded and its import wasn't interrupted
+    pub fn is_imported(&self, digest: &str) -> bool {
+        self.traces.get(digest).map(|r| !r.partial).unwrap_or(false)
+    }
+
+    pub fn get(&self, digest: &str) -> Option<&TraceRecord> {
+        self.traces.get(digest)
+    }
+
+    /// Find a recorded trace with the given trace UUID, used when
+    /// the content of a trace changed since it was imported (e.g. it grew)
+    pub fn find_by_trace_uuid(&self, trace_uuid: &Uuid) -> Option<&TraceRecord> {
+        self.traces
+            .values()
+            .find(|r| r.trace_uuid.as_ref() == Some(trace_uuid))
+    }
+
+    pub fn insert(&mut self, digest: String, record: TraceRecord) {
+        self.traces.insert(digest, record);
+    }
+}
+
+/// Compute a digest over the content of the trace input directories.
+///
+/// Every regular file (metadata, stream packet files, and indexes) contributes
+/// its path relative to the input directory and its content, in sorted order
+/// so the digest doesn't depend on where the trace was copied to.
+pub fn trace_digest(inputs: &[PathBuf]) -> Result<String, StateError> {
+    let mut hasher = sha1_smol::Sha1::new();
+    for input in inputs.iter() {
+        let mut files = Vec::new();
+        collect_files(input, input, &mut files)
+            .map_err(|e| StateError::DigestIo(input.clone(), e))?;
+        files.sort();
+        for (rel_path, path) in files.into_iter() {
+            hasher.update(rel_path.as_os_str().as_bytes());
+            hash_file(&path, &mut hasher).map_err(|e| StateError::DigestIo(path.clone(), e))?;
+        }
+    }
+    Ok(hasher.digest().to_string())
+}
+
+/// Hash the length and content of a file, streaming it since stream
+/// files can be much larger than memory
+fn hash_file(path: &Path, hasher: &mut sha1_smol::Sha1) -> io::Result<()> {
+    let file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    hasher.update(&len.to_le_bytes());
+    let mut reader = BufReader::with_capacity(DIGEST_BUFFER_SIZE, file.take(len));
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        hasher.update(buf);
+        let n = buf.len();
+        reader.consume(n);
+    }
+    Ok(())
+}
+
+const DIGEST_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Finds the traces under a watched directory that weren't imported yet.
+///
+/// Every directory with a CTF `metadata` file is a trace. A trace is only reported
+/// once its digest is the same on two consecutive scans, so the traces that are
+/// still being copied into the directory aren't imported partially.
+#[derive(Clone, Debug)]
+pub struct TraceWatcher {
+    dir: PathBuf,
+    digests: HashMap<PathBuf, String>,
+}
+
+impl TraceWatcher {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            digests: Default::default(),
+        }
+    }
+
+    /// The trace directories that settled since the previous scan and aren't recorded
+    /// in the state
+    pub fn scan(&mut self, state: &IngestState) -> Result<Vec<PathBuf>, StateError> {
+        let mut trace_dirs = Vec::new();
+        find_trace_dirs(&self.dir, &mut trace_dirs)
+            .map_err(|e| StateError::DigestIo(self.dir.clone(), e))?;
+        trace_dirs.sort();
+        let mut digests = HashMap::with_capacity(trace_dirs.len());
+        let mut settled = Vec::new();
+        for dir in trace_dirs.into_iter() {
+            // The trace may be removed or still be written to while it's read
+            let digest = match trace_digest(std::slice::from_ref(&dir)) {
+                Ok(digest) => digest,
+                Err(e) => {
+                    debug!("Skipping watched trace '{}' for now. {e}", dir.display());
+                    continue;
+                }
+            };
+            if !state.is_imported(&digest) && self.digests.get(&dir) == Some(&digest) {
+                settled.push(dir.clone());
+            }
+            digests.insert(dir, digest);
+        }
+        self.digests = digests;
+        Ok(settled)
+    }
+}
+
+fn find_trace_dirs(dir: &Path, trace_dirs: &mut Vec<PathBuf>) -> io::Result<()> {
+    if dir.join("metadata").is_file() {
+        trace_dirs.push(dir.to_owned());
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_trace_dirs(&path, trace_dirs)?;
+        }
+    }
+    Ok(())
+}
+
+/// The total size in bytes of the regular files of the trace input directories
+pub fn trace_size(inputs: &[PathBuf]) -> Result<u64, StateError> {
+    let mut size = 0;
+    for input in inputs.iter() {
+        let mut files = Vec::new();
+        collect_files(input, input, &mut files)
+            .map_err(|e| StateError::DigestIo(input.clone(), e))?;
+        for (_, path) in files.into_iter() {
+            let meta = fs::metadata(&path).map_err(|e| StateError::DigestIo(path.clone(), e))?;
+            size += meta.len();
+        }
+    }
+    Ok(size)
+}
+
+fn serialize_lossy_paths<S: Serializer>(
+    paths: &[PathBuf],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(paths.iter().map(|p| p.to_string_lossy()))
+}
+
+fn serialize_ordering<S: Serializer>(ordering: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(ordering)
+}
+
+/// State files written before the ordering was recorded as a string have an integer
+fn deserialize_ordering<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Ordering {
+        Integer(u64),
+        String(String),
+    }
+    match Ordering::deserialize(deserializer)? {
+        Ordering::Integer(o) => Ok(o.into()),
+        Ordering::String(o) => o.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if path.is_file() {
+            let rel_path = path.strip_prefix(root).unwrap_or(&path).to_owned();
+            files.push((rel_path, path));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn digest_is_location_independent() {
+        let a = tempfile::tempdir().unwrap();
+        let b = tempfile::tempdir().unwrap();
+        for dir in [a.path(), b.path()] {
+            fs::create_dir(dir.join("index")).unwrap();
+            fs::write(dir.join("metadata"), b"/* CTF 1.8 */").unwrap();
+            fs::write(dir.join("stream_0"), [1, 2, 3]).unwrap();
+            fs::write(dir.join("index").join("stream_0.idx"), [4]).unwrap();
+        }
+        let da = trace_digest(&[a.path().to_owned()]).unwrap();
+        let db = trace_digest(&[b.path().to_owned()]).unwrap();
+        assert_eq!(da, db);
+
+        fs::write(b.path().join("stream_0"), [1, 2, 4]).unwrap();
+        let db = trace_digest(&[b.path().to_owned()]).unwrap();
+        assert_ne!(da, db);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.toml");
+        assert_eq!(IngestState::load(&path).unwrap(), IngestState::default());
+
+        let mut state = IngestState::default();
+        state.insert(
+            "abc".to_owned(),
+            TraceRecord {
+                inputs: vec![PathBuf::from("path/trace")],
+                run_id: Uuid::from_u128(1).into(),
+                trace_uuid: Uuid::from_u128(2).into(),
+                streams: vec![StreamRecord {
+                    stream_id: 0,
+                    timeline_id: Uuid::from_u128(3),
+                    next_ordering: u128::from(u64::MAX) + 10,
+                    last_packet_seq_num: Some(4),
+                    last_clock_snapshot: Some(1000),
+                }],
+                partial: false,
+            },
+        );
+        state.store(&path).unwrap();
+        assert_eq!(IngestState::load(&path).unwrap(), state);
+        assert!(state.contains("abc"));
+        assert!(state.is_imported("abc"));
+        assert_eq!(
+            state.find_by_trace_uuid(&Uuid::from_u128(2)),
+            state.get("abc")
+        );
+        assert_eq!(state.find_by_trace_uuid(&Uuid::from_u128(1)), None);
+    }
+
+    #[test]
+    fn ordering_as_string() {
+        let state: IngestState = toml::from_str(
+            r#"
+            [traces.abc]
+            partial = true
+            [[traces.abc.streams]]
+            stream-id = 0
+            timeline-id = '00000000-0000-0000-0000-000000000003'
+            next-ordering = 10
+            [[traces.abc.streams]]
+            stream-id = 1
+            timeline-id = '00000000-0000-0000-0000-000000000004'
+            next-ordering = '18446744073709551625'
+            "#,
+        )
+        .unwrap();
+        let record = state.get("abc").unwrap();
+        assert_eq!(record.streams[0].next_ordering, 10);
+        assert_eq!(record.streams[1].next_ordering, u128::from(u64::MAX) + 10);
+        assert!(state.contains("abc"));
+        assert!(!state.is_imported("abc"));
+
+        let content = toml::to_string_pretty(&state).unwrap();
+        assert!(content.contains("next-ordering = '18446744073709551625'"));
+    }
+
+    #[test]
+    fn non_utf8_inputs() {
+        use std::ffi::OsStr;
+
+        // File names that only differ in their non-UTF-8 bytes
+        let a = tempfile::tempdir().unwrap();
+        let b = tempfile::tempdir().unwrap();
+        fs::write(a.path().join(OsStr::from_bytes(b"stream_\xfe")), [1]).unwrap();
+        fs::write(b.path().join(OsStr::from_bytes(b"stream_\xff")), [1]).unwrap();
+        let da = trace_digest(&[a.path().to_owned()]).unwrap();
+        let db = trace_digest(&[b.path().to_owned()]).unwrap();
+        assert_ne!(da, db);
+
+        let path = a.path().join("state.toml");
+        let mut state = IngestState::default();
+        state.insert(
+            da,
+            TraceRecord {
+                inputs: vec![PathBuf::from(OsStr::from_bytes(b"path/caf\xe9"))],
+                ..Default::default()
+            },
+        );
+        state.store(&path).unwrap();
+        let loaded = IngestState::load(&path).unwrap();
+        assert_eq!(
+            loaded.traces.values().next().unwrap().inputs,
+            vec![PathBuf::from("path/caf\u{FFFD}")]
+        );
+    }
+
+    #[test]
+    fn watcher_reports_settled_traces() {
+        let dir = tempfile::tempdir().unwrap();
+        let trace_a = dir.path().join("a");
+        let trace_b = dir.path().join("sessions").join("b").join("kernel");
+        for trace in [&trace_a, &trace_b] {
+            fs::create_dir_all(trace).unwrap();
+            fs::write(trace.join("metadata"), b"/* CTF 1.8 */").unwrap();
+            fs::write(trace.join("channel0_0"), [1, 2, 3]).unwrap();
+        }
+        fs::create_dir(dir.path().join("empty")).unwrap();
+
+        let mut watcher = TraceWatcher::new(dir.path().to_owned());
+        let mut state = IngestState::default();
+        // Nothing has settled yet
+        assert_eq!(watcher.scan(&state).unwrap(), Vec::<PathBuf>::new());
+
+        // Still being written to
+        fs::write(trace_b.join("channel0_1"), [4]).unwrap();
+        assert_eq!(watcher.scan(&state).unwrap(), vec![trace_a.clone()]);
+        assert_eq!(
+            watcher.scan(&state).unwrap(),
+            vec![trace_a.clone(), trace_b.clone()]
+        );
+
+        state.insert(
+            trace_digest(&[trace_a.clone()]).unwrap(),
+            TraceRecord::default(),
+        );
+        assert_eq!(watcher.scan(&state).unwrap(), vec![trace_b]);
+    }
+
+    #[test]
+    fn stream_mark_covers() {
+        let mark = StreamMark {
+            packet_seq_num: Some(4),
+            clock_snapshot: Some(1000),
+        };
+        assert!(mark.covers(Some(3), Some(2000)));
+        assert!(mark.covers(Some(4), Some(1000)));
+        assert!(!mark.covers(Some(4), Some(1001)));
+        assert!(!mark.covers(Some(5), Some(10)));
+        assert!(mark.covers(None, Some(999)));
+        assert!(!mark.covers(None, None));
+        assert!(!StreamMark::default().covers(Some(0), Some(0)));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::attrs::TimelineAttrKey;
+use crate::client::Client;
+use crate::error::Error;
+use babeltrace2_sys::{OwnedEvent, OwnedField, ScalarField};
+use modality_api::{AttrVal, BigInt, Nanoseconds};
+use modality_ingest_protocol::InternedAttrKey;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Per-timeline event statistics, accumulated during ingest and
+/// written as timeline attrs once ingest completes
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct TimelineStats {
+    event_count: u64,
+    min_timestamp: Option<u64>,
+    max_timestamp: Option<u64>,
+    event_class_counts: BTreeMap<String, u64>,
+    capped_event_count: u64,
+}
+
+impl TimelineStats {
+    pub fn record(&mut self, event: &OwnedEvent) {
+        self.event_count += 1;
+
+        if let Some(ts) = event.clock_snapshot.and_then(|c| u64::try_from(c).ok()) {
+            self.min_timestamp = Some(self.min_timestamp.map_or(ts, |min| min.min(ts)));
+            self.max_timestamp = Some(self.max_timestamp.map_or(ts, |max| max.max(ts)));
+        }
+
+        if let Some(name) = event.class_properties.name.as_ref() {
+            *self.event_class_counts.entry(name.clone()).or_default() += 1;
+        }
+    }
+
+    pub fn event_count(&self) -> u64 {
+        self.event_count
+    }
+
+    /// Count an event left out because the timeline reached the max-events-per-timeline
+    pub fn record_capped(&mut self) {
+        self.capped_event_count += 1;
+    }
+
+    pub fn capped_event_count(&self) -> u64 {
+        self.capped_event_count
+    }
+
+    pub async fn attr_kvs(
+        &self,
+        client: &mut Client,
+    ) -> Result<Vec<(InternedAttrKey, AttrVal)>, Error> {
+        let mut attrs = Vec::with_capacity(3 + self.event_class_counts.len());
+
+        attrs.push((
+            client
+                .interned_timeline_key(TimelineAttrKey::EventCount)
+                .await?,
+            BigInt::new_attr_val(self.event_count.into()),
+        ));
+        if let Some(ts) = self.min_timestamp {
+            attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::MinTimestamp)
+                    .await?,
+                Nanoseconds::from(ts).into(),
+            ));
+        }
+        if let Some(ts) = self.max_timestamp {
+            attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::MaxTimestamp)
+                    .await?,
+                Nanoseconds::from(ts).into(),
+            ));
+        }
+        for (name, count) in self.event_class_counts.iter() {
+            attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::EventClassCount(name.clone()))
+                    .await?,
+                BigInt::new_attr_val((*count).into()),
+            ));
+        }
+        if self.capped_event_count != 0 {
+            attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::CappedEventCount)
+                    .await?,
+                BigInt::new_attr_val(self.capped_event_count.into()),
+            ));
+        }
+
+        Ok(attrs)
+    }
+}
+
+/// Number of rows of the event rate table
+const RATE_ROWS: u64 = 20;
+
+/// Whole-trace statistics for the importer's `--stats` mode,
+/// gathered without any ingest connection
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct TraceStats {
+    event_count: u64,
+    event_class_counts: BTreeMap<String, u64>,
+
+    /// Stream ID to (min, max) timestamp
+    stream_ranges: BTreeMap<u64, (u64, u64)>,
+
+    /// Event count of each second since the Unix epoch (or clock origin)
+    events_per_second: BTreeMap<u64, u64>,
+
+    /// Event count of each payload size power-of-two bucket,
+    /// keyed by the bucket's upper bound in bytes
+    payload_sizes: BTreeMap<u64, u64>,
+}
+
+impl TraceStats {
+    pub fn record(&mut self, event: &OwnedEvent) {
+        self.event_count += 1;
+        let name = event
+            .class_properties
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("<id {}>", event.class_properties.id));
+        *self.event_class_counts.entry(name).or_default() += 1;
+
+        if let Some(ts) = event.clock_snapshot.and_then(|c| u64::try_from(c).ok()) {
+            let range = self
+                .stream_ranges
+                .entry(event.stream_id)
+                .or_insert((ts, ts));
+            range.0 = range.0.min(ts);
+            range.1 = range.1.max(ts);
+            *self
+                .events_per_second
+                .entry(ts / 1_000_000_000)
+                .or_default() += 1;
+        }
+
+        let size = event
+            .properties
+            .payload
+            .as_ref()
+            .map(payload_size)
+            .unwrap_or(0);
+        *self
+            .payload_sizes
+            .entry(size.max(1).next_power_of_two())
+            .or_default() += 1;
+    }
+
+    /// Event counts over evenly sized intervals spanning the trace, as
+    /// (interval start second, interval length in seconds, count)
+    pub fn event_rates(&self) -> Vec<(u64, u64, u64)> {
+        let (first, last) = match (
+            self.events_per_second.keys().next(),
+            self.events_per_second.keys().next_back(),
+        ) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Vec::new(),
+        };
+        let interval = (last - first + 1).div_ceil(RATE_ROWS);
+        let mut rates: BTreeMap<u64, u64> = BTreeMap::new();
+        for (sec, count) in self.events_per_second.iter() {
+            *rates
+                .entry(first + (sec - first) / interval * interval)
+                .or_default() += count;
+        }
+        rates
+            .into_iter()
+            .map(|(start, count)| (start, interval, count))
+            .collect()
+    }
+}
+
+/// Approximate encoded size of the payload fields in bytes
+fn payload_size(f: &OwnedField) -> u64 {
+    match f {
+        OwnedField::Scalar(_, s) => match s {
+            ScalarField::Bool(_) => 1,
+            ScalarField::SinglePrecisionReal(_) => 4,
+            ScalarField::String(s) => s.len() as u64 + 1,
+            ScalarField::UnsignedInteger(_)
+            | ScalarField::SignedInteger(_)
+            | ScalarField::DoublePrecisionReal(_)
+            | ScalarField::UnsignedEnumeration(_, _)
+            | ScalarField::SignedEnumeration(_, _) => 8,
+        },
+        OwnedField::Structure(_, fields) => fields.iter().map(payload_size).sum(),
+    }
+}
+
+impl fmt::Display for TraceStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Events: {}", self.event_count)?;
+
+        writeln!(f, "Event classes")?;
+        let mut classes: Vec<_> = self.event_class_counts.iter().collect();
+        classes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (name, count) in classes.into_iter() {
+            writeln!(f, "  {name}: {count}")?;
+        }
+
+        writeln!(f, "Streams")?;
+        for (id, (min, max)) in self.stream_ranges.iter() {
+            writeln!(
+                f,
+                "  stream {id}: {min} .. {max} ({:.3} s)",
+                (max - min) as f64 / 1e9
+            )?;
+        }
+
+        writeln!(f, "Events per second")?;
+        for (start, interval, count) in self.event_rates().into_iter() {
+            writeln!(
+                f,
+                "  {start} s +{interval} s: {:.1}",
+                count as f64 / interval as f64
+            )?;
+        }
+
+        writeln!(f, "Payload sizes")?;
+        for (upper, count) in self.payload_sizes.iter() {
+            writeln!(f, "  <= {upper} bytes: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode and mapping throughput, for the importer's `--bench` mode.
+/// The decoders run on their own threads, so the decode time is the time the
+/// mapping waited for decoded events, and the mapping time is the rest of the run.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct PipelineBench {
+    event_count: u64,
+    trace_bytes: u64,
+    decode: Duration,
+    mapping: Duration,
+}
+
+impl PipelineBench {
+    pub fn add_trace_bytes(&mut self, bytes: u64) {
+        self.trace_bytes += bytes;
+    }
+
+    /// Record the time spent waiting for the decoders to produce an event
+    pub fn record_decode(&mut self, elapsed: Duration) {
+        self.event_count += 1;
+        self.decode += elapsed;
+    }
+
+    /// Attribute the time of the whole run not spent waiting for the decoders to the mapping
+    pub fn finish(&mut self, total: Duration) {
+        self.mapping = total.saturating_sub(self.decode);
+    }
+
+    /// Events/sec and MB/sec over the given time
+    fn throughput(&self, elapsed: Duration) -> (f64, f64) {
+        let secs = elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return (0.0, 0.0);
+        }
+        (
+            self.event_count as f64 / secs,
+            self.trace_bytes as f64 / 1e6 / secs,
+        )
+    }
+}
+
+impl fmt::Display for PipelineBench {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Events: {}", self.event_count)?;
+        writeln!(f, "Trace size: {:.3} MB", self.trace_bytes as f64 / 1e6)?;
+        for (stage, elapsed) in [
+            ("Decode", self.decode),
+            ("Mapping", self.mapping),
+            ("Decode + mapping", self.decode + self.mapping),
+        ] {
+            let (events_per_sec, mb_per_sec) = self.throughput(elapsed);
+            writeln!(
+                f,
+                "{stage}: {:.3} s, {events_per_sec:.1} events/s, {mb_per_sec:.3} MB/s",
+                elapsed.as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn event_rates_and_payload_sizes() {
+        let mut stats = TraceStats::default();
+        stats.events_per_second = (0..45_u64).map(|s| (100 + s, s + 1)).collect();
+        let rates = stats.event_rates();
+        assert_eq!(rates.len(), 15);
+        assert_eq!(rates[0], (100, 3, 1 + 2 + 3));
+        assert_eq!(rates[14], (142, 3, 43 + 44 + 45));
+
+        use OwnedField::{Scalar, Structure};
+        let payload = Structure(
+            None,
+            vec![
+                Scalar(None, ScalarField::String("abc".to_owned())),
+                Scalar(None, ScalarField::UnsignedInteger(1)),
+                Scalar(None, ScalarField::Bool(true)),
+            ],
+        );
+        assert_eq!(payload_size(&payload), 4 + 8 + 1);
+    }
+
+    #[test]
+    fn bench_throughput() {
+        let mut bench = PipelineBench::default();
+        bench.add_trace_bytes(4_000_000);
+        for _ in 0..4 {
+            bench.record_decode(Duration::from_millis(250));
+        }
+        bench.finish(Duration::from_secs(3));
+        assert_eq!(bench.event_count, 4);
+        assert_eq!(bench.throughput(bench.decode), (4.0, 4.0));
+        assert_eq!(bench.throughput(bench.mapping), (2.0, 2.0));
+        assert_eq!(
+            PipelineBench::default().throughput(Duration::ZERO),
+            (0.0, 0.0)
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use crate::config::{ElfBinaryConfig, EventMappingConfig};
+use addr2line::object::{self, Object, ObjectSegment, ObjectSymbol, SymbolKind};
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::debug;
+
+#[derive(Debug, Error)]
+pub enum SymbolError {
+    #[error("Encountered an IO error while reading the symbol file '{0}'. {1}")]
+    Io(PathBuf, #[source] io::Error),
+
+    #[error("The symbol file '{0}' doesn't contain any text symbols with non-zero addresses. Check the kptr_restrict setting of the target")]
+    NoKernelSymbols(PathBuf),
+
+    #[error("The ELF binary '{0}' doesn't contain any function symbols or DWARF debug info")]
+    NoElfSymbols(PathBuf),
+
+    #[error("Failed to parse the ELF binary '{0}'. {1}")]
+    Elf(PathBuf, #[source] object::Error),
+
+    #[error("Failed to read the DWARF debug info of the ELF binary '{0}'. {1}")]
+    Dwarf(PathBuf, #[source] addr2line::gimli::Error),
+}
+
+/// A resolved address
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+
+    /// Offset of the address from the start of the symbol
+    pub offset: u64,
+
+    /// The kernel module containing the symbol
+    pub module: Option<String>,
+
+    /// Source `file:line` of the address, when debug info is available
+    pub location: Option<String>,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if self.offset != 0 {
+            write!(f, "+{:#x}", self.offset)?;
+        }
+        if let Some(module) = &self.module {
+            write!(f, " [{module}]")?;
+        }
+        Ok(())
+    }
+}
+
+/// The symbol tables used to resolve the `symbolize-fields` address fields
+#[derive(Debug, Default)]
+pub struct Symbolizer {
+    kallsyms: Option<KernelSymbols>,
+    elf_binaries: Vec<ElfSymbols>,
+}
+
+impl Symbolizer {
+    pub fn from_config(cfg: &EventMappingConfig) -> Result<Self, SymbolError> {
+        let kallsyms = cfg
+            .kallsyms
+            .as_deref()
+            .map(KernelSymbols::load)
+            .transpose()?;
+        let elf_binaries = cfg
+            .elf_binaries
+            .iter()
+            .map(ElfSymbols::load)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            kallsyms,
+            elf_binaries,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kallsyms.is_none() && self.elf_binaries.is_empty()
+    }
+
+    /// The ELF binaries mapped at the address take precedence over the kernel symbols
+    pub fn symbolize(&self, addr: u64) -> Option<Symbol> {
+        self.elf_binaries
+            .iter()
+            .find(|e| e.contains(addr))
+            .and_then(|e| e.symbolize(addr))
+            .or_else(|| self.kallsyms.as_ref().and_then(|k| k.symbolize(addr)))
+    }
+}
+
+/// Kernel text symbols from a `/proc/kallsyms` dump or a `System.map` file
+#[derive(Clone, Debug, Default)]
+pub struct KernelSymbols {
+    /// Address, name, and module, sorted by address
+    symbols: Vec<(u64, String, Option<String>)>,
+}
+
+impl KernelSymbols {
+    pub fn load(path: &Path) -> Result<Self, SymbolError> {
+        let content = fs::read_to_string(path).map_err(|e| SymbolError::Io(path.to_owned(), e))?;
+        let symbols = Self::parse(&content);
+        if symbols.symbols.is_empty() {
+            return Err(SymbolError::NoKernelSymbols(path.to_owned()));
+        }
+        debug!(
+            path = %path.display(),
+            count = symbols.symbols.len(),
+            "Loaded kernel symbols"
+        );
+        Ok(symbols)
+    }
+
+    /// Parse lines of the form `<hex address> <type> <name> [module]`,
+    /// keeping the text symbols
+    pub fn parse(content: &str) -> Self {
+        let mut symbols = content
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let addr = u64::from_str_radix(parts.next()?, 16).ok()?;
+                let ty = parts.next()?;
+                let name = parts.next()?;
+                if addr == 0 || !matches!(ty, "t" | "T" | "w" | "W") {
+                    return None;
+                }
+                let module = parts
+                    .next()
+                    .map(|m| m.trim_start_matches('[').trim_end_matches(']').to_owned());
+                Some((addr, name.to_owned(), module))
+            })
+            .collect::<Vec<_>>();
+        symbols.sort();
+        symbols.dedup_by_key(|(addr, _, _)| *addr);
+        Self { symbols }
+    }
+
+    /// Resolve the address to the closest preceding symbol. Addresses past
+    /// the last symbol can't be bounded and aren't resolved.
+    pub fn symbolize(&self, addr: u64) -> Option<Symbol> {
+        let idx = self.symbols.partition_point(|(a, _, _)| *a <= addr);
+        if idx == 0 || idx == self.symbols.len() {
+            return None;
+        }
+        let (start, name, module) = &self.symbols[idx - 1];
+        Some(Symbol {
+            name: name.clone(),
+            offset: addr - start,
+            module: module.clone(),
+            location: None,
+        })
+    }
+}
+
+type DwarfContext =
+    addr2line::Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>;
+
+/// Function symbols and line info of an ELF binary loaded at a given address
+pub struct ElfSymbols {
+    path: PathBuf,
+    load_address: u64,
+
+    /// Range of the ELF virtual addresses covered by the loadable segments
+    vaddr_range: (u64, u64),
+
+    /// Address, size, and demangled name of the function symbols, sorted by address
+    symbols: Vec<(u64, u64, String)>,
+
+    /// Line info, if the binary has DWARF debug info
+    dwarf: Option<DwarfContext>,
+}
+
+impl fmt::Debug for ElfSymbols {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ElfSymbols")
+            .field("path", &self.path)
+            .field("load_address", &self.load_address)
+            .field("symbols", &self.symbols.len())
+            .field("dwarf", &self.dwarf.is_some())
+            .finish()
+    }
+}
+
+impl ElfSymbols {
+    pub fn load(cfg: &ElfBinaryConfig) -> Result<Self, SymbolError> {
+        let path = cfg.path.clone();
+        let data = fs::read(&path).map_err(|e| SymbolError::Io(path.clone(), e))?;
+        let obj = object::File::parse(&*data).map_err(|e| SymbolError::Elf(path.clone(), e))?;
+
+        let vaddr_range = obj
+            .segments()
+            .filter(|s| s.size() != 0)
+            .fold((u64::MAX, 0), |(start, end), s| {
+                (start.min(s.address()), end.max(s.address() + s.size()))
+            });
+
+        let mut symbols = obj
+            .symbols()
+            .chain(obj.dynamic_symbols())
+            .filter(|s| s.kind() == SymbolKind::Text && s.address() != 0)
+            .filter_map(|s| {
+                let name = s.name().ok()?;
+                let name = addr2line::demangle_auto(Cow::Borrowed(name), None).into_owned();
+                Some((s.address(), s.size(), name))
+            })
+            .collect::<Vec<_>>();
+        symbols.sort();
+        symbols.dedup_by_key(|(addr, _, _)| *addr);
+
+        let dwarf = if obj.section_by_name(".debug_info").is_some() {
+            Some(DwarfContext::new(&obj).map_err(|e| SymbolError::Dwarf(path.clone(), e))?)
+        } else {
+            None
+        };
+
+        if symbols.is_empty() && dwarf.is_none() {
+            return Err(SymbolError::NoElfSymbols(path));
+        }
+        debug!(
+            path = %path.display(),
+            count = symbols.len(),
+            dwarf = dwarf.is_some(),
+            "Loaded ELF symbols"
+        );
+
+        Ok(Self {
+            path,
+            load_address: cfg.load_address,
+            vaddr_range,
+            symbols,
+            dwarf,
+        })
+    }
+
+    /// Returns true if the address falls within the binary's loaded segments
+    pub fn contains(&self, addr: u64) -> bool {
+        match addr.checked_sub(self.load_address) {
+            Some(vaddr) => (self.vaddr_range.0..self.vaddr_range.1).contains(&vaddr),
+            None => false,
+        }
+    }
+
+    pub fn symbolize(&self, addr: u64) -> Option<Symbol> {
+        let vaddr = addr.checked_sub(self.load_address)?;
+        let location = self
+            .dwarf
+            .as_ref()
+            .and_then(|ctx| ctx.find_location(vaddr).ok().flatten())
+            .and_then(|loc| match (loc.file, loc.line) {
+                (Some(file), Some(line)) => Some(format!("{file}:{line}")),
+                (Some(file), None) => Some(file.to_owned()),
+                _ => None,
+            });
+
+        let idx = self.symbols.partition_point(|(a, _, _)| *a <= vaddr);
+        let (start, _, name) = idx
+            .checked_sub(1)
+            .map(|i| &self.symbols[i])
+            .filter(|(start, size, _)| *size == 0 || vaddr < start + size)?;
+        Some(Symbol {
+            name: name.clone(),
+            offset: vaddr - start,
+            module: None,
+            location,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const KALLSYMS: &str = "\
+0000000000000000 A fixed_percpu_data
+ffffffff81000000 T _stext
+ffffffff81001000 t do_one_initcall
+ffffffff81001200 D some_data
+ffffffff81002000 T schedule
+ffffffffc0400000 t ext4_fill_super\t[ext4]
+ffffffffc0401000 t ext4_end [ext4]
+";
+
+    #[test]
+    fn kallsyms_symbolize() {
+        let syms = KernelSymbols::parse(KALLSYMS);
+        assert_eq!(syms.symbolize(0xffffffff80000000), None);
+        assert_eq!(
+            syms.symbolize(0xffffffff81000000).unwrap().to_string(),
+            "_stext"
+        );
+        assert_eq!(
+            syms.symbolize(0xffffffff81001210).unwrap().to_string(),
+            "do_one_initcall+0x210"
+        );
+        assert_eq!(
+            syms.symbolize(0xffffffffc0400010).unwrap().to_string(),
+            "ext4_fill_super+0x10 [ext4]"
+        );
+        assert_eq!(syms.symbolize(0xffffffffc0402000), None);
+    }
+
+    #[test]
+    fn restricted_kallsyms_hint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kallsyms");
+        fs::write(
+            &path,
+            "0000000000000000 T _stext\n0000000000000000 T schedule\n",
+        )
+        .unwrap();
+        let err = KernelSymbols::load(&path).unwrap_err();
+        assert!(matches!(err, SymbolError::NoKernelSymbols(_)));
+        assert!(err.to_string().contains("kptr_restrict"));
+
+        fs::write(&path, KALLSYMS).unwrap();
+        let syms = KernelSymbols::load(&path).unwrap();
+        assert_eq!(
+            syms.symbolize(0xffffffff81002010).unwrap().to_string(),
+            "schedule+0x10"
+        );
+    }
+
+    #[test]
+    fn elf_without_symbols_has_no_kptr_restrict_hint() {
+        // A bare x86_64 ELF header, without sections or segments
+        let mut elf = vec![0_u8; 64];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2; // ELFCLASS64
+        elf[5] = 1; // ELFDATA2LSB
+        elf[6] = 1; // EV_CURRENT
+        elf[16] = 2; // ET_EXEC
+        elf[18] = 62; // EM_X86_64
+        elf[20] = 1; // EV_CURRENT
+        elf[52] = 64; // e_ehsize
+        elf[54] = 56; // e_phentsize
+        elf[58] = 64; // e_shentsize
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.elf");
+        fs::write(&path, elf).unwrap();
+        let err = ElfSymbols::load(&ElfBinaryConfig {
+            path: path.clone(),
+            load_address: 0x400000,
+        })
+        .unwrap_err();
+        assert!(matches!(err, SymbolError::NoElfSymbols(_)));
+        assert!(!err.to_string().contains("kptr_restrict"));
+
+        let err = Symbolizer::from_config(&EventMappingConfig {
+            elf_binaries: vec![ElfBinaryConfig {
+                path,
+                load_address: 0,
+            }],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(!err.to_string().contains("kptr_restrict"));
+    }
+}
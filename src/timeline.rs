@@ -0,0 +1,272 @@
+//! Registering the timelines of a trace with the backend.
+//!
+//! Each timeline gets the attrs of its trace and stream properties, the configured
+//! `additional-timeline-attributes` and `override-timeline-attributes`, and the
+//! `modality.ingest.begin` event when it's first seen. With the importer's
+//! `skip-empty-timelines` the registration of a new timeline is deferred until its
+//! first write, so every write to a timeline opens it with
+//! [`TimelineRegistry::open_timeline`].
+
+use crate::attrs::TimelineAttrKey;
+use crate::client::Client;
+use crate::config::CtfConfig;
+use crate::diagnostics::{DropCounts, DropReason};
+use crate::error::Error;
+use crate::event::CtfEvent;
+use crate::properties::CtfProperties;
+use crate::sampling::Sampler;
+use modality_api::{AttrVal, TimelineId};
+use modality_ingest_protocol::InternedAttrKey;
+use std::collections::HashMap;
+
+pub struct TimelineRegistry {
+    /// The plugin name of the `modality.ingest.begin` events
+    plugin_name: &'static str,
+    additional_attrs: Vec<(InternedAttrKey, AttrVal)>,
+    override_attrs: Vec<(InternedAttrKey, AttrVal)>,
+    skip_empty_timelines: bool,
+    pending: HashMap<TimelineId, PendingTimeline>,
+}
+
+/// A timeline whose registration is deferred until its first event,
+/// see `skip-empty-timelines`
+struct PendingTimeline {
+    attrs: HashMap<InternedAttrKey, AttrVal>,
+    /// The ingest begin event, for new timelines with `emit-ingest-begin-event`
+    ingest_begin: Option<CtfEvent>,
+}
+
+impl TimelineRegistry {
+    pub async fn new(
+        plugin_name: &'static str,
+        cfg: &CtfConfig,
+        sampler: &Sampler,
+        client: &mut Client,
+    ) -> Result<Self, Error> {
+        let timeline_attributes = &cfg.ingest.timeline_attributes;
+        let mut additional_attrs =
+            Vec::with_capacity(timeline_attributes.additional_timeline_attributes.len());
+        for kv in timeline_attributes.additional_timeline_attributes.iter() {
+            additional_attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::Custom(kv.0.to_string()))
+                    .await?,
+                kv.1.clone(),
+            ));
+        }
+        if let Some(stream_id) = cfg.plugin.merge_stream_id {
+            additional_attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::MergeStreamId)
+                    .await?,
+                modality_api::BigInt::new_attr_val(stream_id.into()),
+            ));
+        }
+        additional_attrs.extend(sampler.timeline_attr_kvs(client).await?);
+
+        let mut override_attrs =
+            Vec::with_capacity(timeline_attributes.override_timeline_attributes.len());
+        for kv in timeline_attributes.override_timeline_attributes.iter() {
+            override_attrs.push((
+                client
+                    .interned_timeline_key(TimelineAttrKey::Custom(kv.0.to_string()))
+                    .await?,
+                kv.1.clone(),
+            ));
+        }
+
+        Ok(Self {
+            plugin_name,
+            additional_attrs,
+            override_attrs,
+            skip_empty_timelines: false,
+            pending: Default::default(),
+        })
+    }
+
+    /// Defer registering new timelines until they're first opened with
+    /// [`TimelineRegistry::open_timeline`]
+    pub fn with_skip_empty_timelines(mut self, skip_empty_timelines: bool) -> Self {
+        self.skip_empty_timelines = skip_empty_timelines;
+        self
+    }
+
+    /// The `override-timeline-attributes`, to apply over in-band timeline attrs
+    pub fn override_attrs(&self) -> &[(InternedAttrKey, AttrVal)] {
+        &self.override_attrs
+    }
+
+    /// The number of timelines still deferred, the ones without events
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Send the timeline metadata for each of the trace's timelines, registering
+    /// the ones not seen before
+    pub async fn register_timelines(
+        &mut self,
+        cfg: &CtfConfig,
+        props: &CtfProperties,
+        client: &mut Client,
+        last_timeline_ordering_val: &mut HashMap<TimelineId, u128>,
+        drops: &mut DropCounts,
+    ) -> Result<(), Error> {
+        for (stream_id, tid, attr_kvs) in props.timelines() {
+            if let Some(merge_stream_id) = cfg.plugin.merge_stream_id {
+                if stream_id != merge_stream_id {
+                    continue;
+                }
+            }
+
+            let mut attrs = HashMap::new();
+            for (k, v) in attr_kvs
+                .into_iter()
+                .chain(self.additional_attrs.iter().cloned())
+                .chain(self.override_attrs.iter().cloned())
+            {
+                attrs.insert(k, v);
+            }
+
+            let is_new = !last_timeline_ordering_val.contains_key(&tid);
+            let ingest_begin = if is_new && cfg.plugin.emit_ingest_begin_event {
+                last_timeline_ordering_val.insert(tid, 1);
+                Some(CtfEvent::ingest_begin(props.trace.run_id(), self.plugin_name, client).await?)
+            } else {
+                last_timeline_ordering_val.entry(tid).or_insert(0);
+                None
+            };
+            let timeline = PendingTimeline {
+                attrs,
+                ingest_begin,
+            };
+
+            if let Some(timeline) = self.defer(tid, timeline, is_new) {
+                register_timeline(tid, timeline, drops, client).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Open the timeline to write to, registering it first when it was deferred
+    pub async fn open_timeline(
+        &mut self,
+        tid: TimelineId,
+        drops: &mut DropCounts,
+        client: &mut Client,
+    ) -> Result<(), Error> {
+        match self.pending.remove(&tid) {
+            Some(timeline) => register_timeline(tid, timeline, drops, client).await,
+            None => Ok(client.open_timeline(tid).await?),
+        }
+    }
+
+    /// Keep the timeline pending when its registration is deferred, returning the
+    /// timeline to register now otherwise
+    fn defer(
+        &mut self,
+        tid: TimelineId,
+        timeline: PendingTimeline,
+        is_new: bool,
+    ) -> Option<PendingTimeline> {
+        if !self.skip_empty_timelines {
+            Some(timeline)
+        } else if let Some(pending) = self.pending.get_mut(&tid) {
+            pending.attrs.extend(timeline.attrs);
+            None
+        } else if is_new {
+            self.pending.insert(tid, timeline);
+            None
+        } else {
+            Some(timeline)
+        }
+    }
+}
+
+async fn register_timeline(
+    tid: TimelineId,
+    timeline: PendingTimeline,
+    drops: &mut DropCounts,
+    client: &mut Client,
+) -> Result<(), Error> {
+    client.open_timeline(tid).await?;
+    client.timeline_metadata(timeline.attrs).await?;
+    if let Some(event) = timeline.ingest_begin {
+        if !client.send_event(0, event.attr_kvs()).await? {
+            drops.count(DropReason::IngestError);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn registry(skip_empty_timelines: bool) -> TimelineRegistry {
+        TimelineRegistry {
+            plugin_name: "test",
+            additional_attrs: Vec::new(),
+            override_attrs: Vec::new(),
+            skip_empty_timelines,
+            pending: Default::default(),
+        }
+    }
+
+    fn timeline(attrs: &[(u32, i64)]) -> PendingTimeline {
+        PendingTimeline {
+            attrs: attrs
+                .iter()
+                .map(|(k, v)| (InternedAttrKey::from(*k), AttrVal::from(*v)))
+                .collect(),
+            ingest_begin: None,
+        }
+    }
+
+    fn tid(n: u128) -> TimelineId {
+        TimelineId::from(uuid::Uuid::from_u128(n))
+    }
+
+    #[test]
+    fn registers_immediately_without_skip() {
+        let mut reg = registry(false);
+        assert!(reg.defer(tid(1), timeline(&[(1, 1)]), true).is_some());
+        assert!(reg.defer(tid(1), timeline(&[(1, 2)]), false).is_some());
+        assert_eq!(reg.pending_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn deferred_registration() {
+        let mut reg = registry(true);
+        let mut drops = DropCounts::default();
+        let mut client = Client::offline(Vec::new(), Vec::new());
+
+        // New timelines wait for their first write, later metadata is merged in
+        assert!(reg.defer(tid(1), timeline(&[(1, 1)]), true).is_none());
+        assert!(reg
+            .defer(tid(1), timeline(&[(1, 2), (2, 3)]), false)
+            .is_none());
+        assert_eq!(reg.pending_count(), 1);
+        let attrs = &reg.pending[&tid(1)].attrs;
+        assert_eq!(attrs.len(), 2);
+        assert_eq!(attrs[&InternedAttrKey::from(1)], AttrVal::from(2_i64));
+
+        // Timelines registered by a previous import aren't deferred
+        assert!(reg.defer(tid(2), timeline(&[(1, 1)]), false).is_some());
+        assert_eq!(reg.pending_count(), 1);
+
+        // Opening registers the pending timeline once
+        reg.open_timeline(tid(2), &mut drops, &mut client)
+            .await
+            .unwrap();
+        assert_eq!(reg.pending_count(), 1);
+        reg.open_timeline(tid(1), &mut drops, &mut client)
+            .await
+            .unwrap();
+        assert_eq!(reg.pending_count(), 0);
+        reg.open_timeline(tid(1), &mut drops, &mut client)
+            .await
+            .unwrap();
+        assert_eq!(reg.pending_count(), 0);
+    }
+}
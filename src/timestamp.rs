@@ -0,0 +1,359 @@
+//! Parsing the string-formatted timestamps of the `timestamp-formats` payload fields
+//! into nanoseconds since the Unix epoch.
+//!
+//! The custom formats are a subset of the `strftime` directives:
+//! `%Y`, `%m`, `%d`, `%H`, `%M`, `%S`, `%f` (fractional seconds, 1 to 9 digits),
+//! `%z` (`Z`, `+HH:MM` or `+HHMM`), `%s` (seconds since the Unix epoch),
+//! `%F` (`%Y-%m-%d`), `%T` (`%H:%M:%S`) and `%%`.
+
+use crate::types::TimestampFormat;
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// Nanoseconds since the Unix epoch of a timestamp string, `None` when it doesn't
+/// match the format or is before the Unix epoch
+pub fn parse_timestamp(s: &str, format: &TimestampFormat) -> Option<u64> {
+    let s = s.trim();
+    let ns = match format {
+        TimestampFormat::Rfc3339 => parse_rfc3339(s)?,
+        TimestampFormat::EpochSeconds => parse_epoch(s, NANOS_PER_SEC)?,
+        TimestampFormat::EpochMillis => parse_epoch(s, 1_000_000)?,
+        TimestampFormat::EpochMicros => parse_epoch(s, 1_000)?,
+        TimestampFormat::EpochNanos => parse_epoch(s, 1)?,
+        TimestampFormat::Custom(f) => parse_custom(s, f)?,
+    };
+    u64::try_from(ns).ok()
+}
+
+/// A decimal number of `unit_ns` units, with an optional fractional part
+fn parse_epoch(s: &str, unit_ns: i128) -> Option<i128> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    if int.is_empty() || !int.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut ns = int.parse::<i128>().ok()?.checked_mul(unit_ns)?;
+    // Digits beyond nanosecond precision are truncated
+    let mut scale = unit_ns;
+    for d in frac.bytes() {
+        scale /= 10;
+        if scale == 0 {
+            break;
+        }
+        ns += i128::from(d - b'0') * scale;
+    }
+    Some(if negative { -ns } else { ns })
+}
+
+fn parse_rfc3339(s: &str) -> Option<i128> {
+    let mut p = Parser::new(s);
+    let year = p.number(4, 4)? as i64;
+    p.literal('-')?;
+    let month = p.number(2, 2)?;
+    p.literal('-')?;
+    let day = p.number(2, 2)?;
+    p.one_of(&['T', 't', ' '])?;
+    let hour = p.number(2, 2)?;
+    p.literal(':')?;
+    let minute = p.number(2, 2)?;
+    p.literal(':')?;
+    let second = p.number(2, 2)?;
+    let nanos = if p.peek() == Some('.') {
+        p.literal('.')?;
+        p.fraction()?
+    } else {
+        0
+    };
+    // A missing offset is taken as UTC
+    let offset_s = if p.is_done() { 0 } else { p.offset()? };
+    p.is_done().then_some(())?;
+    DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        nanos,
+        offset_s,
+    }
+    .unix_nanos()
+}
+
+fn parse_custom(s: &str, format: &str) -> Option<i128> {
+    let mut p = Parser::new(s);
+    let mut epoch_s = None;
+    let mut t = DateTime::default();
+    let mut directives = format.chars();
+    while let Some(c) = directives.next() {
+        if c != '%' {
+            p.literal(c)?;
+            continue;
+        }
+        match directives.next()? {
+            'Y' => t.year = p.number(4, 4)? as i64,
+            'm' => t.month = p.number(1, 2)?,
+            'd' => t.day = p.number(1, 2)?,
+            'H' => t.hour = p.number(1, 2)?,
+            'M' => t.minute = p.number(1, 2)?,
+            'S' => t.second = p.number(1, 2)?,
+            'f' => t.nanos = p.fraction()?,
+            'z' => t.offset_s = p.offset()?,
+            's' => epoch_s = Some(p.number(1, 20)?),
+            'F' => {
+                t.year = p.number(4, 4)? as i64;
+                p.literal('-')?;
+                t.month = p.number(1, 2)?;
+                p.literal('-')?;
+                t.day = p.number(1, 2)?;
+            }
+            'T' => {
+                t.hour = p.number(1, 2)?;
+                p.literal(':')?;
+                t.minute = p.number(1, 2)?;
+                p.literal(':')?;
+                t.second = p.number(1, 2)?;
+            }
+            '%' => p.literal('%')?,
+            _ => return None,
+        }
+    }
+    p.is_done().then_some(())?;
+    match epoch_s {
+        Some(secs) => Some(i128::from(secs) * NANOS_PER_SEC + i128::from(t.nanos)),
+        None => t.unix_nanos(),
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct DateTime {
+    year: i64,
+    month: u64,
+    day: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+    nanos: u64,
+    offset_s: i64,
+}
+
+impl Default for DateTime {
+    fn default() -> Self {
+        Self {
+            year: 1970,
+            month: 1,
+            day: 1,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            nanos: 0,
+            offset_s: 0,
+        }
+    }
+}
+
+impl DateTime {
+    fn unix_nanos(&self) -> Option<i128> {
+        // Leap seconds (60) are accepted and roll over into the next minute
+        if !(1..=12).contains(&self.month)
+            || self.day == 0
+            || self.day > days_in_month(self.year, self.month)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 60
+        {
+            return None;
+        }
+        let days = days_from_civil(self.year, self.month, self.day);
+        let secs = days * 86_400 + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+            - self.offset_s;
+        Some(i128::from(secs) * NANOS_PER_SEC + i128::from(self.nanos))
+    }
+}
+
+/// The number of days of the month in the proleptic Gregorian calendar
+fn days_in_month(year: i64, month: u64) -> u64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian calendar date
+fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+struct Parser<'a> {
+    s: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn new(s: &'a str) -> Self {
+        Self { s }
+    }
+
+    fn is_done(&self) -> bool {
+        self.s.is_empty()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.s.chars().next()
+    }
+
+    fn literal(&mut self, c: char) -> Option<()> {
+        self.s = self.s.strip_prefix(c)?;
+        Some(())
+    }
+
+    fn one_of(&mut self, chars: &[char]) -> Option<()> {
+        let c = self.peek().filter(|c| chars.contains(c))?;
+        self.literal(c)
+    }
+
+    /// Between `min` and `max` ASCII digits
+    fn number(&mut self, min: usize, max: usize) -> Option<u64> {
+        let len = self
+            .s
+            .bytes()
+            .take(max)
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if len < min {
+            return None;
+        }
+        let (digits, rest) = self.s.split_at(len);
+        self.s = rest;
+        digits.parse().ok()
+    }
+
+    /// Fractional seconds digits as nanoseconds, digits beyond nanosecond
+    /// precision are truncated
+    fn fraction(&mut self) -> Option<u64> {
+        let len = self.s.bytes().take_while(|b| b.is_ascii_digit()).count();
+        if len == 0 {
+            return None;
+        }
+        let (digits, rest) = self.s.split_at(len);
+        self.s = rest;
+        let digits = &digits[..len.min(9)];
+        Some(digits.parse::<u64>().ok()? * 10_u64.pow(9 - digits.len() as u32))
+    }
+
+    /// A UTC offset in seconds, `Z` or `+HH:MM` or `+HHMM`
+    fn offset(&mut self) -> Option<i64> {
+        if self.one_of(&['Z', 'z']).is_some() {
+            return Some(0);
+        }
+        let sign = match self.peek()? {
+            '+' => 1,
+            '-' => -1,
+            _ => return None,
+        };
+        self.s = &self.s[1..];
+        let hours = self.number(2, 2)?;
+        if self.peek() == Some(':') {
+            self.literal(':')?;
+        }
+        let minutes = self.number(2, 2)?;
+        Some(sign * (hours * 3600 + minutes * 60) as i64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const TS_NS: u64 = 1_700_000_000_123_456_789;
+
+    #[test]
+    fn rfc3339_timestamps() {
+        let f = TimestampFormat::Rfc3339;
+        assert_eq!(
+            parse_timestamp("2023-11-14T22:13:20.123456789Z", &f),
+            Some(TS_NS)
+        );
+        assert_eq!(
+            parse_timestamp("2023-11-15 00:13:20.123456789+02:00", &f),
+            Some(TS_NS)
+        );
+        assert_eq!(
+            parse_timestamp("2023-11-14T22:13:20", &f),
+            Some(1_700_000_000_000_000_000)
+        );
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59Z", &f), None);
+        assert_eq!(parse_timestamp("2023-13-14T22:13:20Z", &f), None);
+        assert_eq!(parse_timestamp("2023-02-31T00:00:00Z", &f), None);
+        assert_eq!(parse_timestamp("2023-02-29T00:00:00Z", &f), None);
+        assert_eq!(parse_timestamp("2023-04-31T00:00:00Z", &f), None);
+        assert_eq!(parse_timestamp("2023-11-00T00:00:00Z", &f), None);
+        assert_eq!(
+            parse_timestamp("2024-02-29T00:00:00Z", &f),
+            Some(1_709_164_800_000_000_000)
+        );
+        assert_eq!(parse_timestamp("2100-02-29T00:00:00Z", &f), None);
+        assert_eq!(
+            parse_timestamp("2000-02-29T00:00:00Z", &f),
+            Some(951_782_400_000_000_000)
+        );
+        assert_eq!(
+            parse_timestamp("2023-12-31T00:00:00Z", &f),
+            Some(1_703_980_800_000_000_000)
+        );
+        assert_eq!(parse_timestamp("2023-11-14T22:13:20Zjunk", &f), None);
+    }
+
+    #[test]
+    fn epoch_timestamps() {
+        assert_eq!(
+            parse_timestamp("1700000000.123456789", &TimestampFormat::EpochSeconds),
+            Some(TS_NS)
+        );
+        assert_eq!(
+            parse_timestamp(" 1700000000123.456789 ", &TimestampFormat::EpochMillis),
+            Some(TS_NS)
+        );
+        assert_eq!(
+            parse_timestamp("1700000000123456789", &TimestampFormat::EpochNanos),
+            Some(TS_NS)
+        );
+        assert_eq!(parse_timestamp("-1", &TimestampFormat::EpochSeconds), None);
+        assert_eq!(
+            parse_timestamp("17e8", &TimestampFormat::EpochSeconds),
+            None
+        );
+    }
+
+    #[test]
+    fn custom_format_timestamps() {
+        let f = |s: &str| TimestampFormat::Custom(s.to_owned());
+        assert_eq!(
+            parse_timestamp("14/11/2023 22:13:20.123456789", &f("%d/%m/%Y %T.%f")),
+            Some(TS_NS)
+        );
+        assert_eq!(
+            parse_timestamp("2023-11-14 17:13:20 -0500", &f("%F %T %z")),
+            Some(1_700_000_000_000_000_000)
+        );
+        assert_eq!(
+            parse_timestamp("@1700000000", &f("@%s")),
+            Some(1_700_000_000_000_000_000)
+        );
+        assert_eq!(parse_timestamp("2023-11-14", &f("%F %T")), None);
+    }
+}
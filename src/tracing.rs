@@ -1,20 +1,518 @@
-pub fn try_init_tracing_subscriber() -> Result<(), Box<dyn std::error::Error>> {
+use crate::config::PluginConfig;
+use crate::error::Error;
+use crate::types::{LogBackend, LoggingLevel};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Install the global `tracing` subscriber, writing to the configured log backend,
+/// or for the stderr backend to the rotating log file when there's one.
+///
+/// The plugin log level is `level` when given (the verbosity flags), otherwise
+/// it comes from RUST_LOG and defaults to warn.
+pub fn try_init_tracing_subscriber(
+    cfg: &PluginConfig,
+    level: Option<LevelFilter>,
+) -> Result<(), Box<dyn std::error::Error>> {
     let builder = tracing_subscriber::fmt::Subscriber::builder();
-    let env_filter = std::env::var(tracing_subscriber::EnvFilter::DEFAULT_ENV)
-        .map(tracing_subscriber::EnvFilter::new)
-        .unwrap_or_else(|_| {
-            let level = tracing::Level::WARN;
-            tracing_subscriber::EnvFilter::new(format!(
-                "{}={},modality_ctf_import={},modality_lttng_live={}",
-                env!("CARGO_PKG_NAME").replace('-', "_"),
-                level,
-                level,
-                level,
-            ))
-        });
+    let env_filter = match level {
+        Some(level) => plugin_env_filter(level),
+        None => std::env::var(tracing_subscriber::EnvFilter::DEFAULT_ENV)
+            .map(tracing_subscriber::EnvFilter::new)
+            .unwrap_or_else(|_| plugin_env_filter(LevelFilter::WARN)),
+    };
     let builder = builder.with_env_filter(env_filter);
-    let subscriber = builder.finish();
     use tracing_subscriber::util::SubscriberInitExt;
-    subscriber.try_init()?;
+    match (cfg.log_backend, &cfg.log_file) {
+        (LogBackend::Stderr, Some(path)) => {
+            let writer = RotatingFileWriter::open(
+                path,
+                cfg.log_file_max_size
+                    .unwrap_or(PluginConfig::DEFAULT_LOG_FILE_MAX_SIZE),
+                cfg.log_file_max_files
+                    .unwrap_or(PluginConfig::DEFAULT_LOG_FILE_MAX_FILES),
+            )?;
+            builder
+                .with_ansi(false)
+                .with_writer(Mutex::new(writer))
+                .finish()
+                .try_init()?;
+        }
+        (LogBackend::Stderr, None) => builder.with_writer(io::stderr).finish().try_init()?,
+        (backend, Some(_)) => return Err(Error::LogFileWithLogBackend(backend).into()),
+        // The receiving end timestamps the lines and records their level as the priority
+        (LogBackend::Syslog, None) => builder
+            .without_time()
+            .with_ansi(false)
+            .with_level(false)
+            .with_writer(SystemLogWriter::connect(LogBackend::Syslog)?)
+            .finish()
+            .try_init()?,
+        (LogBackend::Journald, None) => builder
+            .without_time()
+            .with_ansi(false)
+            .with_level(false)
+            .with_target(false)
+            .with_writer(SystemLogWriter::connect(LogBackend::Journald)?)
+            .finish()
+            .try_init()?,
+    }
     Ok(())
 }
+
+/// The filter of the plugin crates at the given level
+fn plugin_env_filter(level: LevelFilter) -> tracing_subscriber::EnvFilter {
+    tracing_subscriber::EnvFilter::new(plugin_filter_directives(level))
+}
+
+/// The babeltrace log lines are already filtered by the babeltrace log-level,
+/// so they're only silenced along with the plugin logs
+fn plugin_filter_directives(level: LevelFilter) -> String {
+    let babeltrace_level = if level == LevelFilter::OFF {
+        LevelFilter::OFF
+    } else {
+        LevelFilter::TRACE
+    };
+    format!(
+        "{}={},modality_ctf_import={},modality_lttng_live={},modality_dmesg_import={},{}={}",
+        env!("CARGO_PKG_NAME").replace('-', "_"),
+        level,
+        level,
+        level,
+        level,
+        BABELTRACE_LOG_TARGET,
+        babeltrace_level,
+    )
+}
+
+/// A log file that is rotated once writing to it would exceed `max_size` bytes.
+///
+/// On rotation `<path>.1` becomes `<path>.2` and so on, the current file becomes
+/// `<path>.1`, and files beyond `max_files` are removed.
+/// Each log line is a single write, so lines are never split across files.
+#[derive(Debug)]
+pub struct RotatingFileWriter {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFileWriter {
+    pub fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_owned(),
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut p = self.path.clone().into_os_string();
+        p.push(format!(".{n}"));
+        p.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            match fs::remove_file(self.rotated_path(self.max_files)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => (),
+            }
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Sends each log line as a datagram to the local syslog daemon (RFC 3164 with
+/// the `user` facility) or to journald (native protocol, with the target and code
+/// location as structured fields)
+#[derive(Debug)]
+pub struct SystemLogWriter {
+    backend: LogBackend,
+    socket: UnixDatagram,
+    identifier: String,
+}
+
+impl SystemLogWriter {
+    pub fn connect(backend: LogBackend) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        match backend {
+            LogBackend::Journald => socket.connect(JOURNALD_SOCKET)?,
+            _ => socket.connect(SYSLOG_SOCKET)?,
+        }
+        let identifier = std::env::args()
+            .next()
+            .as_deref()
+            .map(Path::new)
+            .and_then(Path::file_name)
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_owned());
+        Ok(Self {
+            backend,
+            socket,
+            identifier,
+        })
+    }
+}
+
+impl<'a> MakeWriter<'a> for SystemLogWriter {
+    type Writer = SystemLogLine<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SystemLogLine {
+            writer: self,
+            priority: syslog_priority(&tracing::Level::INFO),
+            target: None,
+            code_location: None,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        SystemLogLine {
+            writer: self,
+            priority: syslog_priority(meta.level()),
+            target: Some(meta.target().to_owned()),
+            code_location: meta.file().zip(meta.line()).map(|(f, l)| (f.to_owned(), l)),
+        }
+    }
+}
+
+/// The writer of a single log line, see [`SystemLogWriter`]
+#[derive(Debug)]
+pub struct SystemLogLine<'a> {
+    writer: &'a SystemLogWriter,
+    priority: u8,
+    target: Option<String>,
+    code_location: Option<(String, u32)>,
+}
+
+impl Write for SystemLogLine<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let msg = String::from_utf8_lossy(buf);
+        let msg = msg.trim_end_matches('\n');
+        let w = self.writer;
+        let datagram = match w.backend {
+            LogBackend::Journald => {
+                let priority = self.priority.to_string();
+                let mut fields = vec![
+                    ("PRIORITY", priority.as_str()),
+                    ("SYSLOG_IDENTIFIER", w.identifier.as_str()),
+                    ("MESSAGE", msg),
+                ];
+                if let Some(target) = &self.target {
+                    fields.push(("TARGET", target.as_str()));
+                }
+                let line;
+                if let Some((file, l)) = &self.code_location {
+                    line = l.to_string();
+                    fields.push(("CODE_FILE", file.as_str()));
+                    fields.push(("CODE_LINE", line.as_str()));
+                }
+                journald_datagram(&fields)
+            }
+            _ => syslog_datagram(self.priority, &w.identifier, std::process::id(), msg),
+        };
+        w.socket.send(&datagram)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The syslog severity of a level
+fn syslog_priority(level: &tracing::Level) -> u8 {
+    match *level {
+        tracing::Level::ERROR => 3,
+        tracing::Level::WARN => 4,
+        tracing::Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+/// An RFC 3164 message of the `user` facility, the syslog daemon adds the timestamp
+/// and hostname
+fn syslog_datagram(priority: u8, identifier: &str, pid: u32, msg: &str) -> Vec<u8> {
+    const FACILITY_USER: u8 = 1;
+    format!(
+        "<{}>{identifier}[{pid}]: {msg}",
+        FACILITY_USER * 8 + priority
+    )
+    .into_bytes()
+}
+
+/// A journald native protocol message, values containing newlines use the
+/// length-prefixed binary encoding
+fn journald_datagram(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    for (k, v) in fields.iter() {
+        datagram.extend_from_slice(k.as_bytes());
+        if v.contains('\n') {
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(v.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+        datagram.extend_from_slice(v.as_bytes());
+        datagram.push(b'\n');
+    }
+    datagram
+}
+
+/// Target of the `tracing` events re-emitted from the libbabeltrace log output
+pub const BABELTRACE_LOG_TARGET: &str = "babeltrace2";
+
+/// Bridges the libbabeltrace log output into `tracing`.
+///
+/// libbabeltrace writes its log lines directly to stderr, so stderr is redirected
+/// through a pipe and each babeltrace log line is re-emitted as a `tracing` event
+/// with the `babeltrace2` target, the matching level, and the component tag.
+/// Anything else written to stderr is passed through unchanged.
+///
+/// The original stderr is restored when the bridge is dropped.
+/// Nothing is bridged when the babeltrace log level is `none`, stderr is left as is.
+pub struct BabeltraceLogBridge {
+    saved_stderr: RawFd,
+    reader: Option<thread::JoinHandle<()>>,
+}
+
+impl BabeltraceLogBridge {
+    pub fn start(log_level: LoggingLevel) -> io::Result<Option<Self>> {
+        if log_level.0 == babeltrace2_sys::LoggingLevel::None {
+            return Ok(None);
+        }
+
+        let mut fds = [0; 2];
+        // SAFETY: plain fd manipulation, every returned fd is checked and owned here
+        unsafe {
+            if libc::pipe(fds.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let [read_fd, write_fd] = fds;
+            let saved_stderr = libc::dup(libc::STDERR_FILENO);
+            if saved_stderr < 0 {
+                let e = io::Error::last_os_error();
+                libc::close(read_fd);
+                libc::close(write_fd);
+                return Err(e);
+            }
+            let passthrough_fd = libc::dup(saved_stderr);
+            if passthrough_fd < 0 || libc::dup2(write_fd, libc::STDERR_FILENO) < 0 {
+                let e = io::Error::last_os_error();
+                if passthrough_fd >= 0 {
+                    libc::close(passthrough_fd);
+                }
+                libc::close(saved_stderr);
+                libc::close(read_fd);
+                libc::close(write_fd);
+                return Err(e);
+            }
+            libc::close(write_fd);
+
+            let reader = File::from_raw_fd(read_fd);
+            let passthrough = File::from_raw_fd(passthrough_fd);
+            let reader = thread::Builder::new()
+                .name("babeltrace-log".to_owned())
+                .spawn(move || forward_log_lines(reader, passthrough))?;
+
+            Ok(Some(Self {
+                saved_stderr,
+                reader: Some(reader),
+            }))
+        }
+    }
+}
+
+impl Drop for BabeltraceLogBridge {
+    fn drop(&mut self) {
+        let _ = io::stderr().flush();
+        // SAFETY: restoring stderr closes the pipe's write end, which ends the reader thread
+        unsafe {
+            libc::dup2(self.saved_stderr, libc::STDERR_FILENO);
+            libc::close(self.saved_stderr);
+        }
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+fn forward_log_lines(reader: File, mut passthrough: File) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        let line = String::from_utf8_lossy(&buf);
+        match parse_log_line(line.trim_end()) {
+            Some(l) => emit_log_line(&l),
+            None => {
+                let _ = passthrough.write_all(&buf);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct BabeltraceLogLine<'a> {
+    level: char,
+    tag: &'a str,
+    msg: &'a str,
+}
+
+/// Parse a libbabeltrace log line of the form
+/// `MM-DD HH:MM:SS.mmm PID TID L TAG [file:line func:] msg`
+fn parse_log_line(line: &str) -> Option<BabeltraceLogLine<'_>> {
+    let mut rest = line;
+    let mut fields = [""; 6];
+    for f in fields.iter_mut() {
+        let s = rest.trim_start_matches(' ');
+        let end = s.find(' ')?;
+        *f = &s[..end];
+        rest = &s[end..];
+    }
+    let [date, time, pid, tid, level, tag] = fields;
+    let is_num = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if date.len() != 5 || !time.contains(':') || !is_num(pid) || !is_num(tid) || level.len() != 1 {
+        return None;
+    }
+    let level = level.chars().next()?;
+    if !matches!(level, 'T' | 'D' | 'I' | 'W' | 'E' | 'F') {
+        return None;
+    }
+    // The tag may be followed by '@file:line' and the function name
+    let tag = tag.split('@').next().unwrap_or(tag);
+    Some(BabeltraceLogLine {
+        level,
+        tag,
+        msg: rest.trim_start(),
+    })
+}
+
+fn emit_log_line(l: &BabeltraceLogLine<'_>) {
+    let BabeltraceLogLine { tag, msg, .. } = l;
+    match l.level {
+        'T' => tracing::trace!(target: BABELTRACE_LOG_TARGET, tag, "{msg}"),
+        'D' => tracing::debug!(target: BABELTRACE_LOG_TARGET, tag, "{msg}"),
+        'I' => tracing::info!(target: BABELTRACE_LOG_TARGET, tag, "{msg}"),
+        'W' => tracing::warn!(target: BABELTRACE_LOG_TARGET, tag, "{msg}"),
+        _ => tracing::error!(target: BABELTRACE_LOG_TARGET, tag, "{msg}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn babeltrace_log_line() {
+        assert_eq!(
+            parse_log_line(
+                "05-13 17:38:02.397 15196 15196 W PLUGIN/SRC.CTF.FS/META ctf_fs_metadata_set_trace_class@fs.c:120 Metadata is empty"
+            ),
+            Some(BabeltraceLogLine {
+                level: 'W',
+                tag: "PLUGIN/SRC.CTF.FS/META",
+                msg: "ctf_fs_metadata_set_trace_class@fs.c:120 Metadata is empty",
+            })
+        );
+        assert_eq!(parse_log_line("Caused by: something"), None);
+        assert_eq!(
+            parse_log_line("ERROR: [Babeltrace CLI] (babeltrace2.c:2548)"),
+            None
+        );
+    }
+
+    #[test]
+    fn plugin_filter_levels() {
+        let crate_name = env!("CARGO_PKG_NAME").replace('-', "_");
+        assert_eq!(
+            plugin_filter_directives(LevelFilter::INFO),
+            format!("{crate_name}=info,modality_ctf_import=info,modality_lttng_live=info,modality_dmesg_import=info,{BABELTRACE_LOG_TARGET}=trace")
+        );
+        assert_eq!(
+            plugin_filter_directives(LevelFilter::OFF),
+            format!("{crate_name}=off,modality_ctf_import=off,modality_lttng_live=off,modality_dmesg_import=off,{BABELTRACE_LOG_TARGET}=off")
+        );
+    }
+
+    #[test]
+    fn system_log_datagrams() {
+        assert_eq!(
+            syslog_datagram(4, "modality-ctf-import", 42, "Metadata is empty"),
+            b"<12>modality-ctf-import[42]: Metadata is empty".to_vec()
+        );
+        let mut expected = b"PRIORITY=3\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&7_u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb c d\n");
+        assert_eq!(
+            journald_datagram(&[("PRIORITY", "3"), ("MESSAGE", "a\nb c d")]),
+            expected
+        );
+    }
+
+    #[test]
+    fn log_file_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plugin.log");
+        let mut w = RotatingFileWriter::open(&path, 10, 2).unwrap();
+        for line in ["line 1\n", "line 2\n", "line 3\n", "line 4\n"] {
+            w.write_all(line.as_bytes()).unwrap();
+        }
+        let read = |p: PathBuf| fs::read_to_string(p).unwrap();
+        assert_eq!(read(path.clone()), "line 4\n");
+        assert_eq!(read(w.rotated_path(1)), "line 3\n");
+        assert_eq!(read(w.rotated_path(2)), "line 2\n");
+        assert!(!w.rotated_path(3).exists());
+    }
+}
@@ -0,0 +1,580 @@
+//! User supplied per-event transforms, applied to the mapped event attrs
+//! before they're sent to Modality.
+//!
+//! Transforms are either a WASM module (`wasm` feature) or a Rhai
+//! script (`scripting` feature), the WASM module applies first.
+//!
+//! A transform receives the event attrs as a JSON object of attr keys
+//! (e.g. `event.name`) to values and returns the new JSON object, so it can
+//! rename, drop or add attrs.
+//! Strings, booleans and numbers map to their JSON counterparts, the other attr
+//! value types are given as strings. Attrs returned with an unchanged key and
+//! value keep their original attr value type.
+
+use crate::config::EventMappingConfig;
+use crate::diagnostics::{DropCounts, DropReason};
+use modality_api::{AttrVal, BigInt};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+#[derive(Debug, Error)]
+pub enum TransformError {
+    #[error("Failed to load the WASM transform module '{0}'. {1}")]
+    WasmLoad(PathBuf, String),
+
+    #[error("The WASM transform module '{0}' failed. {1}")]
+    WasmCall(PathBuf, String),
+
+    #[error("The WASM transform module '{0}' returned invalid attrs. {1}")]
+    InvalidOutput(PathBuf, String),
+
+    #[error("The `wasm-transform` setting requires a build with the 'wasm' feature")]
+    WasmNotSupported,
+
+    #[error("Failed to load the transform script '{0}'. {1}")]
+    ScriptLoad(PathBuf, String),
+
+    #[error("The transform script '{0}' failed. {1}")]
+    ScriptCall(PathBuf, String),
+
+    #[error("The `script-transform` setting requires a build with the 'scripting' feature")]
+    ScriptNotSupported,
+}
+
+/// The configured event transforms
+#[derive(Default)]
+pub struct EventTransform {
+    #[cfg(feature = "wasm")]
+    wasm: Option<wasm::WasmTransform>,
+    #[cfg(feature = "scripting")]
+    script: Option<script::ScriptTransform>,
+    /// Whether a failure was logged, the later ones are only counted
+    warned_failure: bool,
+}
+
+impl std::fmt::Debug for EventTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventTransform")
+            .field("is_empty", &self.is_empty())
+            .finish()
+    }
+}
+
+impl EventTransform {
+    pub fn from_config(cfg: &EventMappingConfig) -> Result<Self, TransformError> {
+        #[cfg(not(feature = "wasm"))]
+        if cfg.wasm_transform.is_some() {
+            return Err(TransformError::WasmNotSupported);
+        }
+        #[cfg(not(feature = "scripting"))]
+        if cfg.script_transform.is_some() {
+            return Err(TransformError::ScriptNotSupported);
+        }
+        Ok(Self {
+            #[cfg(feature = "wasm")]
+            wasm: cfg
+                .wasm_transform
+                .as_deref()
+                .map(wasm::WasmTransform::load)
+                .transpose()?,
+            #[cfg(feature = "scripting")]
+            script: cfg
+                .script_transform
+                .as_deref()
+                .map(|p| script::ScriptTransform::load(p, cfg.script_max_operations))
+                .transpose()?,
+            warned_failure: false,
+        })
+    }
+
+    #[allow(clippy::needless_return)]
+    pub fn is_empty(&self) -> bool {
+        #[cfg(feature = "wasm")]
+        if self.wasm.is_some() {
+            return false;
+        }
+        #[cfg(feature = "scripting")]
+        if self.script.is_some() {
+            return false;
+        }
+        true
+    }
+
+    /// Run the attrs of the event named `event_name` through the transforms
+    #[allow(unused_mut, unused_variables)]
+    pub fn apply(
+        &mut self,
+        event_name: Option<&str>,
+        mut attrs: BTreeMap<String, AttrVal>,
+    ) -> Result<BTreeMap<String, AttrVal>, TransformError> {
+        #[cfg(feature = "wasm")]
+        if let Some(w) = self.wasm.as_mut() {
+            attrs = w.apply(attrs)?;
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(s) = self.script.as_mut() {
+            attrs = s.apply(event_name, attrs)?;
+        }
+        Ok(attrs)
+    }
+
+    /// Run the attrs through the transforms like [`EventTransform::apply`], `None`
+    /// when a transform fails and the event is dropped, counted as `transform-error`
+    pub fn apply_or_drop(
+        &mut self,
+        event_name: Option<&str>,
+        attrs: BTreeMap<String, AttrVal>,
+        drops: &mut DropCounts,
+    ) -> Option<BTreeMap<String, AttrVal>> {
+        match self.apply(event_name, attrs) {
+            Ok(attrs) => Some(attrs),
+            Err(e) => {
+                if self.warned_failure {
+                    debug!("Dropping the event. {e}");
+                } else {
+                    warn!("Dropping the events the transform fails on. {e}");
+                    self.warned_failure = true;
+                }
+                drops.count(DropReason::TransformError);
+                None
+            }
+        }
+    }
+}
+
+/// The JSON representation of an attr value given to the transforms
+pub fn attr_val_to_json(v: &AttrVal) -> serde_json::Value {
+    match v {
+        AttrVal::String(s) => serde_json::Value::String(s.to_string()),
+        AttrVal::Integer(i) => (*i).into(),
+        AttrVal::Bool(b) => (*b).into(),
+        AttrVal::Float(_) => v
+            .to_string()
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(serde_json::Value::Number)
+            .unwrap_or_else(|| v.to_string().into()),
+        _ => v.to_string().into(),
+    }
+}
+
+/// The attr value of a JSON value returned by the transforms, `None` for
+/// nulls (the attr is dropped) and values that don't map to an attr value
+pub fn json_to_attr_val(v: &serde_json::Value) -> Option<AttrVal> {
+    use serde_json::Value;
+    match v {
+        Value::Null => None,
+        Value::Bool(b) => Some((*b).into()),
+        Value::String(s) => Some(s.clone().into()),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Some(i.into())
+            } else if let Some(u) = n.as_u64() {
+                Some(BigInt::new_attr_val(u.into()))
+            } else {
+                n.as_f64().map(AttrVal::from)
+            }
+        }
+        Value::Array(_) | Value::Object(_) => None,
+    }
+}
+
+/// Encode the attrs for a transform
+pub fn attrs_to_json(attrs: &BTreeMap<String, AttrVal>) -> serde_json::Value {
+    serde_json::Value::Object(
+        attrs
+            .iter()
+            .map(|(k, v)| (k.clone(), attr_val_to_json(v)))
+            .collect(),
+    )
+}
+
+/// Decode the attrs returned by a transform, keeping the original value of
+/// the attrs it didn't change
+pub fn attrs_from_json(
+    original: &BTreeMap<String, AttrVal>,
+    output: serde_json::Value,
+) -> Result<BTreeMap<String, AttrVal>, String> {
+    let obj = match output {
+        serde_json::Value::Object(obj) => obj,
+        _ => return Err("Expected a JSON object of attr keys to values".to_owned()),
+    };
+    let mut attrs = BTreeMap::new();
+    for (k, v) in obj.into_iter() {
+        if let Some(orig) = original.get(&k) {
+            if attr_val_to_json(orig) == v {
+                attrs.insert(k, orig.clone());
+                continue;
+            }
+        }
+        match json_to_attr_val(&v) {
+            Some(val) => {
+                attrs.insert(k, val);
+            }
+            None if v.is_null() => (),
+            None => warn!("Ignoring the transformed attr '{k}', its value isn't a scalar"),
+        }
+    }
+    Ok(attrs)
+}
+
+#[cfg(feature = "wasm")]
+mod wasm {
+    //! The module exports its `memory` and the functions:
+    //! * `alloc(len: i32) -> i32` — Allocate `len` bytes for the input
+    //! * `transform(ptr: i32, len: i32) -> i64` — Transform the UTF-8 JSON input at `ptr`,
+    //!   returning the output location as `(ptr << 32) | len`
+    use super::{attrs_from_json, attrs_to_json, TransformError};
+    use modality_api::AttrVal;
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+    use wasmtime::{Engine, Instance, Memory, Module, Store, TypedFunc};
+
+    /// Fuel each event's transform call may consume, so a runaway module
+    /// can't stall the ingest
+    const FUEL_PER_EVENT: u64 = 10_000_000;
+
+    pub struct WasmTransform {
+        path: PathBuf,
+        store: Store<()>,
+        memory: Memory,
+        alloc: TypedFunc<i32, i32>,
+        transform: TypedFunc<(i32, i32), i64>,
+    }
+
+    impl WasmTransform {
+        pub fn load(path: &Path) -> Result<Self, TransformError> {
+            let load_err =
+                |e: wasmtime::Error| TransformError::WasmLoad(path.to_owned(), e.to_string());
+            let mut config = wasmtime::Config::new();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config).map_err(load_err)?;
+            let module = Module::from_file(&engine, path).map_err(load_err)?;
+            let mut store = Store::new(&engine, ());
+            store.set_fuel(FUEL_PER_EVENT).map_err(load_err)?;
+            let instance = Instance::new(&mut store, &module, &[]).map_err(load_err)?;
+            let memory = instance.get_memory(&mut store, "memory").ok_or_else(|| {
+                TransformError::WasmLoad(
+                    path.to_owned(),
+                    "The module doesn't export its memory".to_owned(),
+                )
+            })?;
+            let alloc = instance
+                .get_typed_func::<i32, i32>(&mut store, "alloc")
+                .map_err(load_err)?;
+            let transform = instance
+                .get_typed_func::<(i32, i32), i64>(&mut store, "transform")
+                .map_err(load_err)?;
+            Ok(Self {
+                path: path.to_owned(),
+                store,
+                memory,
+                alloc,
+                transform,
+            })
+        }
+
+        pub fn apply(
+            &mut self,
+            attrs: BTreeMap<String, AttrVal>,
+        ) -> Result<BTreeMap<String, AttrVal>, TransformError> {
+            let call_err =
+                |e: wasmtime::Error| TransformError::WasmCall(self.path.clone(), e.to_string());
+            let input = attrs_to_json(&attrs).to_string();
+            self.store.set_fuel(FUEL_PER_EVENT).map_err(call_err)?;
+
+            let ptr = self
+                .alloc
+                .call(&mut self.store, input.len() as i32)
+                .map_err(call_err)?;
+            self.memory
+                .write(&mut self.store, ptr as usize, input.as_bytes())
+                .map_err(|e| TransformError::WasmCall(self.path.clone(), e.to_string()))?;
+            let out = self
+                .transform
+                .call(&mut self.store, (ptr, input.len() as i32))
+                .map_err(call_err)?;
+
+            let (out_ptr, out_len) = (
+                (out as u64 >> 32) as usize,
+                (out as u64 & 0xFFFF_FFFF) as usize,
+            );
+            let mut output = vec![0; out_len];
+            self.memory
+                .read(&self.store, out_ptr, &mut output)
+                .map_err(|e| TransformError::WasmCall(self.path.clone(), e.to_string()))?;
+            let output = serde_json::from_slice(&output)
+                .map_err(|e| TransformError::InvalidOutput(self.path.clone(), e.to_string()))?;
+            attrs_from_json(&attrs, output)
+                .map_err(|e| TransformError::InvalidOutput(self.path.clone(), e))
+        }
+    }
+}
+
+#[cfg(feature = "scripting")]
+mod script {
+    //! The script runs once per event with the event name in the `name`
+    //! variable and the attrs in the `attrs` object map, and mutates `attrs`
+    use super::{attrs_from_json, attrs_to_json, TransformError};
+    use modality_api::AttrVal;
+    use rhai::{Dynamic, Engine, Scope, AST};
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+
+    pub const DEFAULT_MAX_OPERATIONS: u64 = 100_000;
+
+    pub struct ScriptTransform {
+        path: PathBuf,
+        engine: Engine,
+        ast: AST,
+    }
+
+    impl ScriptTransform {
+        pub fn load(path: &Path, max_operations: Option<u64>) -> Result<Self, TransformError> {
+            let mut engine = Engine::new();
+            engine.set_max_operations(max_operations.unwrap_or(DEFAULT_MAX_OPERATIONS));
+            let ast = engine
+                .compile_file(path.to_owned())
+                .map_err(|e| TransformError::ScriptLoad(path.to_owned(), e.to_string()))?;
+            Ok(Self {
+                path: path.to_owned(),
+                engine,
+                ast,
+            })
+        }
+
+        pub fn apply(
+            &mut self,
+            event_name: Option<&str>,
+            attrs: BTreeMap<String, AttrVal>,
+        ) -> Result<BTreeMap<String, AttrVal>, TransformError> {
+            let call_err = |e: String| TransformError::ScriptCall(self.path.clone(), e);
+            let input: Dynamic = rhai::serde::to_dynamic(attrs_to_json(&attrs))
+                .map_err(|e| call_err(e.to_string()))?;
+            let mut scope = Scope::new();
+            scope.push_constant("name", event_name.unwrap_or_default().to_owned());
+            scope.push("attrs", input);
+            self.engine
+                .run_ast_with_scope(&mut scope, &self.ast)
+                .map_err(|e| call_err(e.to_string()))?;
+            let output: Dynamic = scope
+                .get_value("attrs")
+                .ok_or_else(|| call_err("The script removed the `attrs` variable".to_owned()))?;
+            let output: serde_json::Value =
+                rhai::serde::from_dynamic(&output).map_err(|e| call_err(e.to_string()))?;
+            attrs_from_json(&attrs, output).map_err(call_err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use modality_api::Nanoseconds;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn json_attrs_round_trip() {
+        let attrs: BTreeMap<String, AttrVal> = vec![
+            ("event.name".to_owned(), "foo".to_owned().into()),
+            ("event.count".to_owned(), 3_i64.into()),
+            ("event.timestamp".to_owned(), Nanoseconds::from(10).into()),
+            ("event.secret".to_owned(), "hunter2".to_owned().into()),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut output = attrs_to_json(&attrs);
+        let obj = output.as_object_mut().unwrap();
+        obj.remove("event.secret");
+        let count = obj.remove("event.count").unwrap();
+        obj.insert("event.total".to_owned(), count);
+        obj.insert("event.site".to_owned(), json!("rig-a"));
+        obj.insert("event.flag".to_owned(), json!(true));
+        obj.insert("event.dropped".to_owned(), json!(null));
+
+        let transformed = attrs_from_json(&attrs, output).unwrap();
+        assert_eq!(
+            transformed,
+            vec![
+                ("event.flag".to_owned(), true.into()),
+                ("event.name".to_owned(), "foo".to_owned().into()),
+                ("event.site".to_owned(), "rig-a".to_owned().into()),
+                // Unchanged, keeps its timestamp type
+                ("event.timestamp".to_owned(), Nanoseconds::from(10).into()),
+                ("event.total".to_owned(), 3_i64.into()),
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        assert!(attrs_from_json(&attrs, json!([])).is_err());
+    }
+
+    #[cfg(any(feature = "wasm", feature = "scripting"))]
+    fn write_transform(dir: &tempfile::TempDir, name: &str, contents: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[cfg(any(feature = "wasm", feature = "scripting"))]
+    fn event_attrs(count: i64) -> BTreeMap<String, AttrVal> {
+        vec![
+            ("event.name".to_owned(), "foo".to_owned().into()),
+            ("event.count".to_owned(), count.into()),
+            ("event.secret".to_owned(), "hunter2".to_owned().into()),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    /// A module ignoring its input and returning the `output` JSON
+    #[cfg(feature = "wasm")]
+    fn wasm_module(transform_body: &str, output: &str) -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 1024) "{}")
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "transform") (param i32 i32) (result i64)
+                    {transform_body}
+                    (i64.or (i64.shl (i64.const 1024) (i64.const 32)) (i64.const {}))))"#,
+            output.replace('"', "\\\""),
+            output.len()
+        )
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn wasm_transform() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = EventMappingConfig {
+            wasm_transform: write_transform(
+                &dir,
+                "transform.wat",
+                &wasm_module("", r#"{"event.name":"foo","event.site":"rig-a"}"#),
+            )
+            .into(),
+            ..Default::default()
+        };
+        let mut transform = EventTransform::from_config(&cfg).unwrap();
+        assert!(!transform.is_empty());
+        assert_eq!(
+            transform.apply(Some("foo"), event_attrs(1)).unwrap(),
+            vec![
+                ("event.name".to_owned(), "foo".to_owned().into()),
+                ("event.site".to_owned(), "rig-a".to_owned().into()),
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        for (name, module) in [
+            ("trap.wat", wasm_module("unreachable", "{}")),
+            ("fuel.wat", wasm_module("(loop br 0)", "{}")),
+        ] {
+            let cfg = EventMappingConfig {
+                wasm_transform: write_transform(&dir, name, &module).into(),
+                ..Default::default()
+            };
+            let mut transform = EventTransform::from_config(&cfg).unwrap();
+            assert!(matches!(
+                transform.apply(Some("foo"), event_attrs(1)),
+                Err(TransformError::WasmCall(..))
+            ));
+            let mut drops = DropCounts::default();
+            assert_eq!(
+                transform.apply_or_drop(Some("foo"), event_attrs(1), &mut drops),
+                None
+            );
+            assert_eq!(drops.get(DropReason::TransformError), 1);
+        }
+
+        let cfg = EventMappingConfig {
+            wasm_transform: write_transform(&dir, "invalid.wat", "(module").into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            EventTransform::from_config(&cfg),
+            Err(TransformError::WasmLoad(..))
+        ));
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn script_transform() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = EventMappingConfig {
+            script_transform: write_transform(
+                &dir,
+                "transform.rhai",
+                r#"
+                if attrs["event.count"] > 2 {
+                    throw "too many";
+                }
+                attrs.remove("event.secret");
+                if name == "foo" {
+                    attrs["event.site"] = "rig-a";
+                }
+                "#,
+            )
+            .into(),
+            ..Default::default()
+        };
+        let mut transform = EventTransform::from_config(&cfg).unwrap();
+        assert!(!transform.is_empty());
+        assert_eq!(
+            transform.apply(Some("foo"), event_attrs(1)).unwrap(),
+            vec![
+                ("event.count".to_owned(), 1_i64.into()),
+                ("event.name".to_owned(), "foo".to_owned().into()),
+                ("event.site".to_owned(), "rig-a".to_owned().into()),
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert!(matches!(
+            transform.apply(Some("foo"), event_attrs(3)),
+            Err(TransformError::ScriptCall(..))
+        ));
+
+        let mut drops = DropCounts::default();
+        assert!(transform
+            .apply_or_drop(Some("foo"), event_attrs(1), &mut drops)
+            .is_some());
+        assert_eq!(
+            transform.apply_or_drop(Some("foo"), event_attrs(3), &mut drops),
+            None
+        );
+        assert_eq!(
+            transform.apply_or_drop(Some("foo"), event_attrs(4), &mut drops),
+            None
+        );
+        assert_eq!(drops.get(DropReason::TransformError), 2);
+
+        let cfg = EventMappingConfig {
+            script_transform: write_transform(&dir, "loop.rhai", "loop {}").into(),
+            script_max_operations: 100.into(),
+            ..Default::default()
+        };
+        let mut transform = EventTransform::from_config(&cfg).unwrap();
+        assert!(matches!(
+            transform.apply(None, event_attrs(1)),
+            Err(TransformError::ScriptCall(..))
+        ));
+
+        let cfg = EventMappingConfig {
+            script_transform: write_transform(&dir, "invalid.rhai", "if {").into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            EventTransform::from_config(&cfg),
+            Err(TransformError::ScriptLoad(..))
+        ));
+    }
+}
@@ -1,4 +1,6 @@
+use crate::schema::did_you_mean;
 use derive_more::{Display, From, Into};
+use regex::Regex;
 use serde::Deserialize;
 use std::convert::TryFrom;
 use std::num::ParseIntError;
@@ -6,6 +8,21 @@ use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
 use std::sync::Arc;
 
+/// The error message of an invalid enum-like value, listing the expected
+/// values and suggesting the closest one
+fn invalid_value(what: &str, s: &str, expected: &[&str]) -> String {
+    let quoted: Vec<String> = expected.iter().map(|v| format!("'{v}'")).collect();
+    let mut msg = format!(
+        "Invalid {what} '{s}', expected one of {}",
+        quoted.join(", ")
+    );
+    let normalized = s.trim().to_lowercase();
+    if let Some(closest) = did_you_mean(&normalized, expected.iter().copied()) {
+        msg.push_str(&format!(". Did you mean '{closest}'?"));
+    }
+    msg
+}
+
 #[derive(Clone, Debug)]
 #[repr(transparent)]
 pub struct Interruptor(Arc<AtomicBool>);
@@ -30,6 +47,35 @@ impl Default for Interruptor {
     }
 }
 
+/// Shared pause state, toggled by the live collector's pause/resume signals
+#[derive(Clone, Debug)]
+#[repr(transparent)]
+pub struct Pauser(Arc<AtomicBool>);
+
+impl Pauser {
+    pub fn new() -> Self {
+        Pauser(Arc::new(AtomicBool::new(false)))
+    }
+
+    pub fn pause(&self) {
+        self.0.store(true, SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.0.store(false, SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(SeqCst)
+    }
+}
+
+impl Default for Pauser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(
     Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Deserialize, From, Into, Display,
 )]
@@ -110,3 +156,795 @@ impl FromStr for SessionNotFoundAction {
         ))
     }
 }
+
+/// CTF event class log levels, ordered from most to least severe.
+///
+/// The discriminants match the babeltrace2 `bt_event_class_log_level` values.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum EventLogLevel {
+    #[display(fmt = "emergency")]
+    Emergency = 0,
+    #[display(fmt = "alert")]
+    Alert = 1,
+    #[display(fmt = "critical")]
+    Critical = 2,
+    #[display(fmt = "error")]
+    Error = 3,
+    #[display(fmt = "warning")]
+    Warning = 4,
+    #[display(fmt = "notice")]
+    Notice = 5,
+    #[display(fmt = "info")]
+    Info = 6,
+    #[display(fmt = "debug_system")]
+    DebugSystem = 7,
+    #[display(fmt = "debug_program")]
+    DebugProgram = 8,
+    #[display(fmt = "debug_process")]
+    DebugProcess = 9,
+    #[display(fmt = "debug_module")]
+    DebugModule = 10,
+    #[display(fmt = "debug_unit")]
+    DebugUnit = 11,
+    #[display(fmt = "debug_function")]
+    DebugFunction = 12,
+    #[display(fmt = "debug_line")]
+    DebugLine = 13,
+    #[display(fmt = "debug")]
+    Debug = 14,
+}
+
+impl EventLogLevel {
+    /// The log level of the event's class, if it has one
+    pub fn from_event(event: &babeltrace2_sys::OwnedEvent) -> Option<Self> {
+        event.class_properties.log_level.map(Self::from)
+    }
+
+    /// Returns true if this level is at least as severe as `min`
+    pub fn is_at_least(self, min: EventLogLevel) -> bool {
+        self <= min
+    }
+}
+
+impl From<babeltrace2_sys::LogLevel> for EventLogLevel {
+    fn from(ll: babeltrace2_sys::LogLevel) -> Self {
+        use babeltrace2_sys::LogLevel as Bt;
+        use EventLogLevel::*;
+        match ll {
+            Bt::Emergency => Emergency,
+            Bt::Alert => Alert,
+            Bt::Critical => Critical,
+            Bt::Error => Error,
+            Bt::Warning => Warning,
+            Bt::Notice => Notice,
+            Bt::Info => Info,
+            Bt::DebugSystem => DebugSystem,
+            Bt::DebugProgram => DebugProgram,
+            Bt::DebugProcess => DebugProcess,
+            Bt::DebugModule => DebugModule,
+            Bt::DebugUnit => DebugUnit,
+            Bt::DebugFunction => DebugFunction,
+            Bt::DebugLine => DebugLine,
+            Bt::Debug => Debug,
+        }
+    }
+}
+
+impl TryFrom<String> for EventLogLevel {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for EventLogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use EventLogLevel::*;
+        let normalized = s.trim().to_lowercase().replace(['_', '-'], "");
+        Ok(match normalized.as_str() {
+            "emergency" | "emerg" => Emergency,
+            "alert" => Alert,
+            "critical" | "crit" => Critical,
+            "error" | "err" => Error,
+            "warning" | "warn" => Warning,
+            "notice" => Notice,
+            "info" => Info,
+            "debugsystem" => DebugSystem,
+            "debugprogram" => DebugProgram,
+            "debugprocess" => DebugProcess,
+            "debugmodule" => DebugModule,
+            "debugunit" => DebugUnit,
+            "debugfunction" => DebugFunction,
+            "debugline" => DebugLine,
+            "debug" => Debug,
+            _ => {
+                return Err(invalid_value(
+                    "event log level",
+                    s,
+                    &[
+                        "emergency",
+                        "alert",
+                        "critical",
+                        "error",
+                        "warning",
+                        "notice",
+                        "info",
+                        "debug-system",
+                        "debug-program",
+                        "debug-process",
+                        "debug-module",
+                        "debug-unit",
+                        "debug-function",
+                        "debug-line",
+                        "debug",
+                    ],
+                ))
+            }
+        })
+    }
+}
+
+/// Clock class origin handling, either forcing (or not) the origin to the
+/// Unix epoch, or picking it from the trace's clock classes
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Deserialize, Display)]
+#[serde(try_from = "UnixEpochOriginRepr")]
+pub enum UnixEpochOrigin {
+    #[display(fmt = "true")]
+    Enabled,
+    #[display(fmt = "false")]
+    Disabled,
+    #[display(fmt = "auto")]
+    Auto,
+}
+
+impl UnixEpochOrigin {
+    /// The value of the `force-clock-class-origin-unix-epoch` source component parameter,
+    /// `None` when it's resolved once the clock classes are known
+    pub fn force_param(self) -> Option<bool> {
+        match self {
+            UnixEpochOrigin::Enabled => Some(true),
+            UnixEpochOrigin::Disabled => Some(false),
+            UnixEpochOrigin::Auto => None,
+        }
+    }
+}
+
+impl From<bool> for UnixEpochOrigin {
+    fn from(force: bool) -> Self {
+        if force {
+            UnixEpochOrigin::Enabled
+        } else {
+            UnixEpochOrigin::Disabled
+        }
+    }
+}
+
+impl FromStr for UnixEpochOrigin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "true" => UnixEpochOrigin::Enabled,
+            "false" => UnixEpochOrigin::Disabled,
+            "auto" => UnixEpochOrigin::Auto,
+            _ => {
+                return Err(format!(
+                    "Invalid clock class origin '{s}', expected 'true', 'false', or 'auto'"
+                ))
+            }
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UnixEpochOriginRepr {
+    Bool(bool),
+    String(String),
+}
+
+impl TryFrom<UnixEpochOriginRepr> for UnixEpochOrigin {
+    type Error = String;
+
+    fn try_from(r: UnixEpochOriginRepr) -> Result<Self, Self::Error> {
+        match r {
+            UnixEpochOriginRepr::Bool(b) => Ok(b.into()),
+            UnixEpochOriginRepr::String(s) => s.parse(),
+        }
+    }
+}
+
+/// Semantic mapping profile, see the `profile` module
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum Profile {
+    #[default]
+    #[display(fmt = "none")]
+    None,
+    #[display(fmt = "linux-kernel")]
+    LinuxKernel,
+    #[display(fmt = "lttng-ust")]
+    LttngUst,
+    #[display(fmt = "zephyr")]
+    Zephyr,
+    #[display(fmt = "barectf-generic")]
+    BarectfGeneric,
+    #[display(fmt = "auto")]
+    Auto,
+}
+
+impl TryFrom<String> for Profile {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "none" => Profile::None,
+            "linux-kernel" => Profile::LinuxKernel,
+            "lttng-ust" => Profile::LttngUst,
+            "zephyr" => Profile::Zephyr,
+            "barectf-generic" => Profile::BarectfGeneric,
+            "auto" => Profile::Auto,
+            _ => {
+                return Err(invalid_value(
+                    "profile",
+                    s,
+                    &[
+                        "none",
+                        "auto",
+                        "linux-kernel",
+                        "lttng-ust",
+                        "zephyr",
+                        "barectf-generic",
+                    ],
+                ))
+            }
+        })
+    }
+}
+
+/// How to resolve a payload field and a (possibly renamed) context field
+/// mapping to the same event attr key
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum AttrConflictPolicy {
+    /// Keep the payload field's value
+    #[default]
+    #[display(fmt = "payload-wins")]
+    PayloadWins,
+    /// Keep the context field's value
+    #[display(fmt = "context-wins")]
+    ContextWins,
+    /// Keep both, moving the context field's value to `<key>.context`
+    #[display(fmt = "suffix-context")]
+    SuffixContext,
+    /// Keep both, moving the payload field's value to `<key>.payload`
+    #[display(fmt = "suffix-payload")]
+    SuffixPayload,
+}
+
+impl TryFrom<String> for AttrConflictPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for AttrConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "payload-wins" => AttrConflictPolicy::PayloadWins,
+            "context-wins" => AttrConflictPolicy::ContextWins,
+            "suffix-context" => AttrConflictPolicy::SuffixContext,
+            "suffix-payload" => AttrConflictPolicy::SuffixPayload,
+            _ => {
+                return Err(invalid_value(
+                    "attr conflict policy",
+                    s,
+                    &[
+                        "payload-wins",
+                        "context-wins",
+                        "suffix-context",
+                        "suffix-payload",
+                    ],
+                ))
+            }
+        })
+    }
+}
+
+/// What to do when an event fails to be ingested
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum IngestErrorPolicy {
+    /// Abort the run
+    #[default]
+    #[display(fmt = "fail")]
+    Fail,
+    /// Skip the event and continue
+    #[display(fmt = "skip")]
+    Skip,
+    /// Retry sending the event with backoff, skipping it once the retries are exhausted
+    #[display(fmt = "retry")]
+    Retry,
+}
+
+impl TryFrom<String> for IngestErrorPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for IngestErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "fail" => IngestErrorPolicy::Fail,
+            "skip" => IngestErrorPolicy::Skip,
+            "retry" => IngestErrorPolicy::Retry,
+            _ => {
+                return Err(invalid_value(
+                    "ingest error policy",
+                    s,
+                    &["fail", "skip", "retry"],
+                ))
+            }
+        })
+    }
+}
+
+/// What to do with the unknown keys of the plugin configuration
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum UnknownKeyPolicy {
+    /// Log a warning for each unknown key
+    #[default]
+    #[display(fmt = "warn")]
+    Warn,
+    /// Fail to load the configuration
+    #[display(fmt = "fail")]
+    Fail,
+    #[display(fmt = "ignore")]
+    Ignore,
+}
+
+impl TryFrom<String> for UnknownKeyPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for UnknownKeyPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "warn" => UnknownKeyPolicy::Warn,
+            "fail" => UnknownKeyPolicy::Fail,
+            "ignore" => UnknownKeyPolicy::Ignore,
+            _ => {
+                return Err(invalid_value(
+                    "unknown key policy",
+                    s,
+                    &["warn", "fail", "ignore"],
+                ))
+            }
+        })
+    }
+}
+
+/// What the LTTng collector does when no CTF metadata arrives within the metadata timeout
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum MetadataTimeoutAction {
+    /// Exit with an error
+    #[default]
+    #[display(fmt = "fail")]
+    Fail,
+    /// Exit with success, like a session that ended
+    #[display(fmt = "end")]
+    End,
+}
+
+impl TryFrom<String> for MetadataTimeoutAction {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for MetadataTimeoutAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "fail" => MetadataTimeoutAction::Fail,
+            "end" => MetadataTimeoutAction::End,
+            _ => {
+                return Err(invalid_value(
+                    "metadata timeout action",
+                    s,
+                    &["fail", "end"],
+                ))
+            }
+        })
+    }
+}
+
+/// Target attr value type of a payload field type coercion
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum FieldType {
+    #[display(fmt = "string")]
+    String,
+    #[display(fmt = "integer")]
+    Integer,
+    #[display(fmt = "float")]
+    Float,
+    #[display(fmt = "bool")]
+    Bool,
+    #[display(fmt = "timestamp")]
+    Timestamp,
+}
+
+impl TryFrom<String> for FieldType {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for FieldType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "string" => FieldType::String,
+            "integer" | "int" => FieldType::Integer,
+            "float" => FieldType::Float,
+            "bool" | "boolean" => FieldType::Bool,
+            "timestamp" => FieldType::Timestamp,
+            _ => {
+                return Err(invalid_value(
+                    "field type",
+                    s,
+                    &["string", "integer", "float", "bool", "timestamp"],
+                ))
+            }
+        })
+    }
+}
+
+/// Format of a string payload field parsed into a timestamp
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum TimestampFormat {
+    /// RFC 3339 / ISO-8601, e.g. `2023-11-14T22:13:20.123Z`
+    #[display(fmt = "rfc3339")]
+    Rfc3339,
+    #[display(fmt = "epoch-s")]
+    EpochSeconds,
+    #[display(fmt = "epoch-ms")]
+    EpochMillis,
+    #[display(fmt = "epoch-us")]
+    EpochMicros,
+    #[display(fmt = "epoch-ns")]
+    EpochNanos,
+    /// A `strftime`-like format string, e.g. `%d/%m/%Y %H:%M:%S`
+    #[display(fmt = "{_0}")]
+    Custom(String),
+}
+
+impl TryFrom<String> for TimestampFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "rfc3339" | "iso8601" => TimestampFormat::Rfc3339,
+            "epoch-s" => TimestampFormat::EpochSeconds,
+            "epoch-ms" => TimestampFormat::EpochMillis,
+            "epoch-us" => TimestampFormat::EpochMicros,
+            "epoch-ns" => TimestampFormat::EpochNanos,
+            _ if s.contains('%') => TimestampFormat::Custom(s.trim().to_owned()),
+            _ => {
+                return Err(format!(
+                    "{}, or a strftime-like format containing '%'",
+                    invalid_value(
+                        "timestamp format",
+                        s,
+                        &["rfc3339", "epoch-s", "epoch-ms", "epoch-us", "epoch-ns"]
+                    )
+                ))
+            }
+        })
+    }
+}
+
+/// One of `count` importer shards, specified as `index/count`. Each shard
+/// decodes the stream files whose position in the sorted stream files of the
+/// trace directory modulo `count` is `index`, see [`ShardDir`](crate::shard::ShardDir).
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Deserialize, Display)]
+#[serde(try_from = "String")]
+#[display(fmt = "{index}/{count}")]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    /// Whether the position `n` belongs to the shard
+    pub fn contains(&self, n: u64) -> bool {
+        n % self.count == self.index
+    }
+}
+
+impl TryFrom<String> for Shard {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid shard '{s}', expected 'index/count' with index < count");
+        let (index, count) = s.trim().split_once('/').ok_or_else(err)?;
+        let index: u64 = index.trim().parse().map_err(|_| err())?;
+        let count: u64 = count.trim().parse().map_err(|_| err())?;
+        if index >= count {
+            return Err(err());
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+/// What to do with a babeltrace message of a given type, see
+/// [`MessageHandlingConfig`](crate::config::MessageHandlingConfig)
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum MessageAction {
+    #[display(fmt = "ignore")]
+    Ignore,
+    /// Log the message at the info level
+    #[display(fmt = "log")]
+    Log,
+    /// Ingest the message as an event
+    #[display(fmt = "event")]
+    Event,
+}
+
+impl TryFrom<String> for MessageAction {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for MessageAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "ignore" => MessageAction::Ignore,
+            "log" => MessageAction::Log,
+            "event" => MessageAction::Event,
+            _ => {
+                return Err(invalid_value(
+                    "message action",
+                    s,
+                    &["ignore", "log", "event"],
+                ))
+            }
+        })
+    }
+}
+
+/// Where the plugin logs go
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum LogBackend {
+    /// stderr, or the `log-file` when set
+    #[default]
+    #[display(fmt = "stderr")]
+    Stderr,
+    /// The local syslog daemon, through `/dev/log`
+    #[display(fmt = "syslog")]
+    Syslog,
+    /// The systemd journal, through its native protocol
+    #[display(fmt = "journald")]
+    Journald,
+}
+
+impl TryFrom<String> for LogBackend {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for LogBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "stderr" => LogBackend::Stderr,
+            "syslog" => LogBackend::Syslog,
+            "journald" => LogBackend::Journald,
+            _ => {
+                return Err(invalid_value(
+                    "log backend",
+                    s,
+                    &["stderr", "syslog", "journald"],
+                ))
+            }
+        })
+    }
+}
+
+/// How the payload attrs of the streams merged with `merge-stream-id` are namespaced
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Deserialize, Display)]
+#[serde(try_from = "String")]
+pub enum MergeStreamNamespace {
+    /// `event.<field>`
+    #[default]
+    #[display(fmt = "none")]
+    None,
+    /// `event.stream<stream_id>.<field>`
+    #[display(fmt = "stream-id")]
+    StreamId,
+    /// `event.<stream_name>.<field>`, with the `.` and whitespace of the
+    /// stream name replaced by `_`
+    #[display(fmt = "stream-name")]
+    StreamName,
+}
+
+impl MergeStreamNamespace {
+    /// The attr key component of the originating stream's payload attrs
+    pub fn namespace(&self, stream_id: u64, stream_name: &str) -> Option<String> {
+        match self {
+            MergeStreamNamespace::None => None,
+            MergeStreamNamespace::StreamId => Some(format!("stream{stream_id}")),
+            MergeStreamNamespace::StreamName => Some(
+                stream_name
+                    .chars()
+                    .map(|c| {
+                        if c == '.' || c.is_whitespace() {
+                            '_'
+                        } else {
+                            c
+                        }
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl TryFrom<String> for MergeStreamNamespace {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for MergeStreamNamespace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "none" => MergeStreamNamespace::None,
+            "stream-id" => MergeStreamNamespace::StreamId,
+            "stream-name" => MergeStreamNamespace::StreamName,
+            _ => {
+                return Err(invalid_value(
+                    "merge stream namespace",
+                    s,
+                    &["none", "stream-id", "stream-name"],
+                ))
+            }
+        })
+    }
+}
+
+/// A stream name regular expression of the `timeline-name-patterns`,
+/// compiled when the configuration is loaded
+#[derive(Clone, Debug, Deserialize, Display)]
+#[serde(try_from = "String")]
+#[display(fmt = "{}", "_0.as_str()")]
+pub struct StreamNamePattern(Regex);
+
+impl StreamNamePattern {
+    pub fn regex(&self) -> &Regex {
+        &self.0
+    }
+}
+
+impl PartialEq for StreamNamePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for StreamNamePattern {}
+
+impl TryFrom<String> for StreamNamePattern {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for StreamNamePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s)
+            .map(StreamNamePattern)
+            .map_err(|e| format!("Invalid stream name pattern '{s}'. {e}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn shard_from_str() {
+        assert_eq!(
+            Shard::from_str("0/1").unwrap(),
+            Shard { index: 0, count: 1 }
+        );
+        assert_eq!(
+            Shard::from_str(" 2 / 3 ").unwrap(),
+            Shard { index: 2, count: 3 }
+        );
+        for s in ["3/3", "1/0", "1", "a/2", "-1/2", "1/2/3", ""] {
+            assert_eq!(
+                Shard::from_str(s),
+                Err(format!(
+                    "Invalid shard '{s}', expected 'index/count' with index < count"
+                ))
+            );
+        }
+        assert_eq!(Shard { index: 1, count: 4 }.to_string(), "1/4");
+        assert!(Shard { index: 1, count: 4 }.contains(5));
+        assert!(!Shard { index: 1, count: 4 }.contains(4));
+    }
+}